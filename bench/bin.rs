@@ -17,13 +17,17 @@ extern crate html5ever;
 use std::os;
 use test::test_main;
 
+mod util;
 mod tokenizer;
+mod tree_builder;
+mod rewrite;
 
 fn main() {
     let mut tests = vec!();
 
     tests.extend(tokenizer::tests());
-    // more to follow
+    tests.extend(tree_builder::tests());
+    tests.extend(rewrite::tests());
 
     test_main(os::args().as_slice(), tests);
 }
@@ -0,0 +1,147 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Benchmarks of the streaming rewrite path used by proxies and
+//! sanitizers: tokenize, filter the tokens, and write them back out
+//! as HTML.  Also the tree serializer on its own.
+
+use std::io::MemWriter;
+use std::default::Default;
+use std::vec::MoveItems;
+
+use test::{black_box, Bencher, TestDescAndFn, TDynBenchFn};
+
+use html5ever::tokenizer::{TokenSink, Token, Tokenizer, Tag, StartTag, EndTag};
+use html5ever::tokenizer::{DoctypeToken, TagToken, CommentToken, CharacterTokens};
+use html5ever::tokenizer::{WhitespaceTokens, NullCharacterToken, PassthroughToken};
+use html5ever::tokenizer::{ProcessingInstructionToken, EOFToken, ParseError};
+use html5ever::serialize::KeepNewlines;
+use html5ever::sink::rcdom::RcDom;
+use html5ever::{parse, serialize, text_to_html};
+
+use util::{read_input, workloads, bench_name, make_bench};
+
+// A filter in the style of a sanitizer: drop comments and event
+// handler attributes, and write everything else back out.
+struct Rewriter {
+    out: MemWriter,
+}
+
+impl Rewriter {
+    fn write_tag(&mut self, tag: Tag) {
+        match tag.kind {
+            StartTag => {
+                self.out.write_char('<').unwrap();
+                self.out.write_str(tag.name.as_slice()).unwrap();
+                for attr in tag.attrs.iter() {
+                    let name = attr.name.local.as_slice();
+                    if name.starts_with("on") {
+                        continue;
+                    }
+                    self.out.write_char(' ').unwrap();
+                    self.out.write_str(name).unwrap();
+                    self.out.write_str("=\"").unwrap();
+                    for c in attr.value.as_slice().chars() {
+                        match c {
+                            '&' => self.out.write_str("&amp;"),
+                            '"' => self.out.write_str("&quot;"),
+                            c => self.out.write_char(c),
+                        }.unwrap();
+                    }
+                    self.out.write_char('"').unwrap();
+                }
+                if tag.self_closing {
+                    self.out.write_str(" /").unwrap();
+                }
+                self.out.write_char('>').unwrap();
+            }
+            EndTag => {
+                self.out.write_str("</").unwrap();
+                self.out.write_str(tag.name.as_slice()).unwrap();
+                self.out.write_char('>').unwrap();
+            }
+        }
+    }
+}
+
+impl TokenSink for Rewriter {
+    fn process_token(&mut self, token: Token) {
+        match token {
+            TagToken(tag) => self.write_tag(tag),
+            CharacterTokens(s) | WhitespaceTokens(s)
+                => text_to_html(&mut self.out, s.as_slice(), KeepNewlines).unwrap(),
+            PassthroughToken(s) => self.out.write_str(s.as_slice()).unwrap(),
+            DoctypeToken(_) => self.out.write_str("<!DOCTYPE html>").unwrap(),
+            CommentToken(_) | ProcessingInstructionToken(..) | NullCharacterToken
+                | EOFToken | ParseError(_) => (),
+        }
+    }
+}
+
+struct RewriteBench {
+    input: Vec<String>,
+    len: uint,
+}
+
+impl TDynBenchFn for RewriteBench {
+    fn run(&self, bh: &mut Bencher) {
+        bh.iter(|| {
+            let input = self.input.clone();
+            // The output is about as long as the input.
+            let out = MemWriter::with_capacity(self.len);
+            let mut tok = Tokenizer::new(Rewriter { out: out }, Default::default());
+            for buf in input.into_iter() {
+                tok.feed(buf);
+            }
+            tok.end();
+            black_box(tok.unwrap().out.unwrap());
+        });
+    }
+}
+
+struct SerializeBench {
+    dom: RcDom,
+    len: uint,
+}
+
+impl TDynBenchFn for SerializeBench {
+    fn run(&self, bh: &mut Bencher) {
+        bh.iter(|| {
+            let mut out = MemWriter::with_capacity(self.len);
+            serialize(&mut out, &self.dom.document, Default::default())
+                .ok().expect("serialization failed");
+            black_box(out.unwrap());
+        });
+    }
+}
+
+fn input_len(input: &Vec<String>) -> uint {
+    input.iter().fold(0, |n, s| n + s.len())
+}
+
+pub fn tests() -> MoveItems<TestDescAndFn> {
+    let mut tests = vec!();
+
+    for (file, size) in workloads().into_iter() {
+        let input = read_input(file.as_slice(), size);
+        let len = input_len(&input);
+        tests.push(make_bench(bench_name("rewrite", file.as_slice(), size),
+            box RewriteBench { input: input, len: len }));
+    }
+
+    for (file, size) in workloads().into_iter() {
+        let input = read_input(file.as_slice(), size);
+        let len = input_len(&input);
+        let dom: RcDom = parse(input.into_iter(), Default::default());
+        tests.push(make_bench(bench_name("serialize", file.as_slice(), size),
+            box SerializeBench { dom: dom, len: len }));
+    }
+
+    tests.into_iter()
+}
@@ -7,15 +7,16 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::{io, os, cmp};
+use std::os;
 use std::default::Default;
 use std::vec::MoveItems;
 
-use test::{black_box, Bencher, TestDesc, TestDescAndFn};
-use test::{DynTestName, DynBenchFn, TDynBenchFn};
+use test::{black_box, Bencher, TestDescAndFn, TDynBenchFn};
 
 use html5ever::tokenizer::{TokenSink, Token, Tokenizer, TokenizerOpts};
 
+use util::{read_input, workloads, bench_name, make_bench};
+
 struct Sink;
 
 impl TokenSink for Sink {
@@ -37,30 +38,8 @@ struct Bench {
 impl Bench {
     fn new(name: &str, size: Option<uint>, clone_only: bool,
            opts: TokenizerOpts) -> Bench {
-        let mut path = os::self_exe_path().expect("can't get exe path");
-        path.push("../data/bench/");
-        path.push(name);
-        let mut file = io::File::open(&path).ok().expect("can't open file");
-
-        // Read the file and treat it as an infinitely repeating sequence of characters.
-        let file_input = file.read_to_string().ok().expect("can't read file");
-        let size = size.unwrap_or(file_input.len());
-        let mut stream = file_input.as_slice().chars().cycle();
-
-        // Break the input into chunks of 1024 chars (= a few kB).
-        // This simulates reading from the network.
-        let mut input = vec![];
-        let mut total = 0u;
-        while total < size {
-            // The by_ref() call is important, otherwise we get wrong results!
-            // See rust-lang/rust#18045.
-            let sz = cmp::min(1024, size - total);
-            input.push(stream.by_ref().take(sz).collect());
-            total += sz;
-        }
-
         Bench {
-            input: input,
+            input: read_input(name, size),
             clone_only: clone_only,
             opts: opts,
         }
@@ -89,26 +68,29 @@ impl TDynBenchFn for Bench {
     }
 }
 
-fn make_bench(name: &str, size: Option<uint>, clone_only: bool,
-              opts: TokenizerOpts) -> TestDescAndFn {
-    TestDescAndFn {
-        desc: TestDesc {
-            name: DynTestName([
-                "tokenize ".to_string(),
-                name.to_string(),
-                size.map_or("".to_string(), |s| format!(" size {:7u}", s)),
-                (if clone_only { " (clone only)" } else { "" }).to_string(),
-                (if opts.exact_errors { " (exact errors)" } else { "" }).to_string(),
-            ].concat().to_string()),
-            ignore: false,
-            should_fail: false,
-        },
-        testfn: DynBenchFn(box Bench::new(name, size, clone_only, opts)),
+// Tokenize once, and return how many strings and vectors the
+// tokenizer allocated.
+fn count_allocations(name: &str, size: Option<uint>) -> u64 {
+    let mut tok = Tokenizer::new(Sink, Default::default());
+    for buf in read_input(name, size).into_iter() {
+        tok.feed(buf);
     }
+    tok.end();
+    tok.allocations()
+}
+
+fn tokenize_bench(name: &str, size: Option<uint>, clone_only: bool,
+                  opts: TokenizerOpts) -> TestDescAndFn {
+    let full_name = [
+        bench_name("tokenize", name, size),
+        (if clone_only { " (clone only)" } else { "" }).to_string(),
+        (if opts.exact_errors { " (exact errors)" } else { "" }).to_string(),
+    ].concat().to_string();
+    make_bench(full_name, box Bench::new(name, size, clone_only, opts))
 }
 
 pub fn tests() -> MoveItems<TestDescAndFn> {
-    let mut tests = vec!(make_bench("lipsum.html", Some(1024*1024), true, Default::default()));
+    let mut tests = vec!(tokenize_bench("lipsum.html", Some(1024*1024), true, Default::default()));
 
     let mut opts_vec = vec!(Default::default());
     if os::getenv("BENCH_EXACT_ERRORS").is_some() {
@@ -119,22 +101,15 @@ pub fn tests() -> MoveItems<TestDescAndFn> {
     }
 
     for opts in opts_vec.iter() {
-        for &file in ["lipsum.html", "lipsum-zh.html", "strong.html"].iter() {
-            for &sz in [1024, 1024*1024].iter() {
-                tests.push(make_bench(file, Some(sz), false, opts.clone()));
-            }
-        }
-
-        for &file in ["tiny-fragment.html", "small-fragment.html", "medium-fragment.html"].iter() {
-            tests.push(make_bench(file, None, false, opts.clone()));
+        for (file, size) in workloads().into_iter() {
+            tests.push(tokenize_bench(file.as_slice(), size, false, opts.clone()));
         }
+    }
 
-        if os::getenv("BENCH_UNCOMMITTED").is_some() {
-            // Not checked into the repo, so don't include by default.
-            for &file in ["sina.com.cn.html", "wikipedia.html"].iter() {
-                let name = format!("uncommitted/{:s}", file);
-                tests.push(make_bench(name.as_slice(), None, false, opts.clone()));
-            }
+    if os::getenv("BENCH_ALLOCATIONS").is_some() {
+        for (file, size) in workloads().into_iter() {
+            println!("{}: {} allocations",
+                bench_name("tokenize", file.as_slice(), size), count_allocations(file.as_slice(), size));
         }
     }
 
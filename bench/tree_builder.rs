@@ -0,0 +1,101 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Benchmarks of the tree builder alone, fed with tokens recorded
+//! ahead of time, and of the whole parser.
+
+use std::default::Default;
+use std::vec::MoveItems;
+
+use test::{black_box, Bencher, TestDescAndFn, TDynBenchFn};
+
+use html5ever::tokenizer::{TokenSink, Token, Tokenizer, TokenizerOpts};
+use html5ever::tree_builder::TreeBuilder;
+use html5ever::sink::rcdom::RcDom;
+use html5ever::parse;
+
+use util::{read_input, workloads, bench_name, make_bench};
+
+struct Recorder {
+    tokens: Vec<Token>,
+}
+
+impl TokenSink for Recorder {
+    fn process_token(&mut self, token: Token) {
+        self.tokens.push(token);
+    }
+}
+
+// Tokenize without a tree builder.  The tokenizer never hears about
+// state changes, so e.g. the contents of <script> will be tokenized
+// as markup.  That's fine for measuring the tree builder.
+fn record_tokens(input: Vec<String>) -> Vec<Token> {
+    let opts = TokenizerOpts {
+        // Like the driver, so the tree builder sees the same tokens.
+        whitespace_tokens: true,
+        .. Default::default()
+    };
+    let mut tok = Tokenizer::new(Recorder { tokens: vec!() }, opts);
+    for buf in input.into_iter() {
+        tok.feed(buf);
+    }
+    tok.end();
+    tok.unwrap().tokens
+}
+
+struct TreeBuilderBench {
+    tokens: Vec<Token>,
+}
+
+impl TDynBenchFn for TreeBuilderBench {
+    fn run(&self, bh: &mut Bencher) {
+        bh.iter(|| {
+            // The clone is included in the time.  Compare with
+            // "tokenize ... (clone only)" to get a rough idea of its cost.
+            let tokens = self.tokens.clone();
+            let mut tb = TreeBuilder::new(RcDom::default(), Default::default());
+            for token in tokens.into_iter() {
+                tb.process_token(token);
+            }
+            black_box(tb.unwrap());
+        });
+    }
+}
+
+struct ParseBench {
+    input: Vec<String>,
+}
+
+impl TDynBenchFn for ParseBench {
+    fn run(&self, bh: &mut Bencher) {
+        bh.iter(|| {
+            let input = self.input.clone();
+            let dom: RcDom = parse(input.into_iter(), Default::default());
+            black_box(dom);
+        });
+    }
+}
+
+pub fn tests() -> MoveItems<TestDescAndFn> {
+    let mut tests = vec!();
+
+    for (file, size) in workloads().into_iter() {
+        let tokens = record_tokens(read_input(file.as_slice(), size));
+        tests.push(make_bench(bench_name("tree builder", file.as_slice(), size),
+            box TreeBuilderBench { tokens: tokens }));
+    }
+
+    for (file, size) in workloads().into_iter() {
+        let input = read_input(file.as_slice(), size);
+        tests.push(make_bench(bench_name("parse", file.as_slice(), size),
+            box ParseBench { input: input }));
+    }
+
+    tests.into_iter()
+}
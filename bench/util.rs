@@ -0,0 +1,86 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::{io, os, cmp};
+
+use test::{TestDesc, TestDescAndFn, DynTestName, DynBenchFn, TDynBenchFn};
+
+/// Read a file from `data/bench`, as a sequence of chunks.
+///
+/// If `size` is given, the file is repeated or truncated to that
+/// many characters.
+pub fn read_input(name: &str, size: Option<uint>) -> Vec<String> {
+    let mut path = os::self_exe_path().expect("can't get exe path");
+    path.push("../data/bench/");
+    path.push(name);
+    let mut file = io::File::open(&path).ok().expect("can't open file");
+
+    // Read the file and treat it as an infinitely repeating sequence of characters.
+    let file_input = file.read_to_string().ok().expect("can't read file");
+    let size = size.unwrap_or(file_input.len());
+    let mut stream = file_input.as_slice().chars().cycle();
+
+    // Break the input into chunks of 1024 chars (= a few kB).
+    // This simulates reading from the network.
+    let mut input = vec![];
+    let mut total = 0u;
+    while total < size {
+        // The by_ref() call is important, otherwise we get wrong results!
+        // See rust-lang/rust#18045.
+        let sz = cmp::min(1024, size - total);
+        input.push(stream.by_ref().take(sz).collect());
+        total += sz;
+    }
+    input
+}
+
+/// The inputs for each workload which doesn't depend on tokenizer
+/// options: a file name, and optionally a size in characters.
+pub fn workloads() -> Vec<(String, Option<uint>)> {
+    let mut out = vec!();
+    for &file in ["lipsum.html", "lipsum-zh.html", "strong.html"].iter() {
+        for &sz in [1024, 1024*1024].iter() {
+            out.push((file.to_string(), Some(sz)));
+        }
+    }
+
+    for &file in ["tiny-fragment.html", "small-fragment.html", "medium-fragment.html"].iter() {
+        out.push((file.to_string(), None));
+    }
+
+    if os::getenv("BENCH_UNCOMMITTED").is_some() {
+        // Not checked into the repo, so don't include by default.
+        for &file in ["sina.com.cn.html", "wikipedia.html"].iter() {
+            out.push((format!("uncommitted/{:s}", file), None));
+        }
+    }
+    out
+}
+
+/// The name of a benchmark, e.g. `tokenize lipsum.html size 1048576`.
+pub fn bench_name(what: &str, file: &str, size: Option<uint>) -> String {
+    [
+        what.to_string(),
+        " ".to_string(),
+        file.to_string(),
+        size.map_or("".to_string(), |s| format!(" size {:7u}", s)),
+    ].concat()
+}
+
+/// A benchmark with this name, to pass to the test runner.
+pub fn make_bench(name: String, testfn: Box<TDynBenchFn+'static>) -> TestDescAndFn {
+    TestDescAndFn {
+        desc: TestDesc {
+            name: DynTestName(name),
+            ignore: false,
+            should_fail: false,
+        },
+        testfn: DynBenchFn(testfn),
+    }
+}
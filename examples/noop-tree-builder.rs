@@ -15,12 +15,11 @@ use std::io;
 use std::default::Default;
 use std::string::String;
 use std::collections::hashmap::HashMap;
-use std::str::MaybeOwned;
 use string_cache::QualName;
 
 use html5ever::{parse_to, one_input};
 use html5ever::tokenizer::Attribute;
-use html5ever::tree_builder::{TreeSink, QuirksMode, NodeOrText};
+use html5ever::tree_builder::{TreeSink, QuirksMode, NodeOrText, Error};
 
 struct Sink {
     next_id: uint,
@@ -58,6 +57,10 @@ impl TreeSink<uint> for Sink {
         self.get_id()
     }
 
+    fn get_template_contents(&mut self, target: uint) -> uint {
+        target
+    }
+
     fn append_before_sibling(&mut self,
             _sibling: uint,
             _new_node: NodeOrText<uint>) -> Result<(), NodeOrText<uint>> {
@@ -66,13 +69,14 @@ impl TreeSink<uint> for Sink {
         Ok(())
     }
 
-    fn parse_error(&mut self, _msg: MaybeOwned<'static>) { }
+    fn parse_error(&mut self, _err: Error) { }
     fn set_quirks_mode(&mut self, _mode: QuirksMode) { }
     fn append(&mut self, _parent: uint, _child: NodeOrText<uint>) { }
 
     fn append_doctype_to_document(&mut self, _name: String, _public_id: String, _system_id: String) { }
     fn add_attrs_if_missing(&mut self, _target: uint, _attrs: Vec<Attribute>) { }
     fn remove_from_parent(&mut self, _target: uint) { }
+    fn reparent_children(&mut self, _node: uint, _new_parent: uint) { }
     fn mark_script_already_started(&mut self, _node: uint) { }
 }
 
@@ -65,7 +65,7 @@ fn main() {
     if !dom.errors.is_empty() {
         println!("\nParse errors:");
         for err in dom.errors.into_iter() {
-            println!("    {}", err);
+            println!("    {}", err.message());
         }
     }
 }
@@ -15,12 +15,12 @@ use std::io;
 use std::default::Default;
 use std::string::String;
 use std::collections::hashmap::HashMap;
-use std::str::MaybeOwned;
 use string_cache::QualName;
 
 use html5ever::{parse_to, one_input};
 use html5ever::tokenizer::Attribute;
 use html5ever::tree_builder::{TreeSink, QuirksMode, NodeOrText, AppendNode, AppendText};
+use html5ever::tree_builder::Error;
 
 struct Sink {
     next_id: uint,
@@ -36,8 +36,8 @@ impl Sink {
 }
 
 impl TreeSink<uint> for Sink {
-    fn parse_error(&mut self, msg: MaybeOwned<'static>) {
-        println!("Parse error: {:s}", msg);
+    fn parse_error(&mut self, err: Error) {
+        println!("Parse error: {}", err.message());
     }
 
     fn get_document(&mut self) -> uint {
@@ -69,6 +69,11 @@ impl TreeSink<uint> for Sink {
         id
     }
 
+    // Print the template itself as the parent of its contents.
+    fn get_template_contents(&mut self, target: uint) -> uint {
+        target
+    }
+
     fn append(&mut self, parent: uint, child: NodeOrText<uint>) {
         match child {
             AppendNode(n)
@@ -108,6 +113,10 @@ impl TreeSink<uint> for Sink {
         println!("Remove {:u} from parent", target);
     }
 
+    fn reparent_children(&mut self, node: uint, new_parent: uint) {
+        println!("Move children from {:u} to {:u}", node, new_parent);
+    }
+
     fn mark_script_already_started(&mut self, node: uint) {
         println!("Mark script {:u} as already started", node);
     }
@@ -65,7 +65,7 @@ impl TokenSink for TokenPrinter {
             }
             ParseError(err) => {
                 self.is_char(false);
-                println!("ERROR: {:s}", err);
+                println!("ERROR: {:s}", err.message());
             }
             _ => {
                 self.is_char(false);
@@ -0,0 +1,269 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! ARIA roles and landmarks of a parsed document.
+//!
+//! An element's role comes from its `role` attribute, or else from its
+//! name and attributes, following the implicit roles in the ARIA in
+//! HTML mapping.  Roles which depend on an accessible name, such as
+//! `<section>` as a `region`, use only `aria-label`, `aria-labelledby`
+//! and `title`, since computing names needs more than the tree.
+//!
+//! ## Example
+//!
+//! ```rust
+//! for landmark in landmarks(&dom.document).iter() {
+//!     println!("{}", landmark.role);
+//! }
+//! ```
+
+use core::prelude::*;
+
+use sink::common::Element;
+use sink::rcdom::{Handle, Traverse};
+use tokenizer::Attribute;
+use util::str::{split_html_space, AsciiExt};
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+use std::from_str::from_str;
+
+/// An element with a role.
+#[deriving(Clone)]
+pub struct ElementRole {
+    /// The element.
+    pub node: Handle,
+
+    /// The role, e.g. `navigation`.
+    pub role: String,
+
+    /// Did the role come from a `role` attribute?
+    pub explicit: bool,
+}
+
+impl ElementRole {
+    /// Is the role a landmark, like `main` or `navigation`?
+    pub fn is_landmark(&self) -> bool {
+        is_landmark(self.role.as_slice())
+    }
+}
+
+/// Is this role a landmark?
+pub fn is_landmark(role: &str) -> bool {
+    match role {
+        "banner" | "complementary" | "contentinfo" | "form" | "main"
+            | "navigation" | "region" | "search" => true,
+        _ => false,
+    }
+}
+
+fn get_attr<'a>(attrs: &'a [Attribute], name: &str) -> Option<&'a str> {
+    attrs.iter()
+        .find(|a| a.name.ns == ns!("") && a.name.local.as_slice() == name)
+        .map(|a| a.value.as_slice())
+}
+
+fn has_name(attrs: &[Attribute]) -> bool {
+    ["aria-label", "aria-labelledby", "title"].iter()
+        .any(|&n| get_attr(attrs, n).map_or(false, |v| !v.is_empty()))
+}
+
+// Is there an ancestor which stops `<header>` and `<footer>` from
+// being the page's banner and content info?
+fn in_sectioning(node: &Handle) -> bool {
+    node.ancestors().any(|a| match a.borrow().node {
+        Element(ref name, _) if name.ns == ns!(HTML) => match name.local.as_slice() {
+            "article" | "aside" | "main" | "nav" | "section" => true,
+            _ => false,
+        },
+        _ => false,
+    })
+}
+
+fn input_role(attrs: &[Attribute]) -> Option<&'static str> {
+    let ty = get_attr(attrs, "type").unwrap_or("text").to_ascii_lower();
+    let has_list = get_attr(attrs, "list").is_some();
+    Some(match ty.as_slice() {
+        "button" | "image" | "reset" | "submit" => "button",
+        "checkbox" => "checkbox",
+        "radio" => "radio",
+        "range" => "slider",
+        "number" => "spinbutton",
+        "search" if has_list => "combobox",
+        "search" => "searchbox",
+        "email" | "tel" | "text" | "url" if has_list => "combobox",
+        "email" | "tel" | "text" | "url" => "textbox",
+        "hidden" | "password" | "color" | "date" | "datetime-local" | "file"
+            | "month" | "time" | "week" => return None,
+        // Unknown types are text inputs.
+        _ if has_list => "combobox",
+        _ => "textbox",
+    })
+}
+
+fn select_role(attrs: &[Attribute]) -> &'static str {
+    let size = get_attr(attrs, "size").and_then(|s| from_str::<uint>(s.trim()));
+    if get_attr(attrs, "multiple").is_some() || size.map_or(false, |n| n > 1) {
+        "listbox"
+    } else {
+        "combobox"
+    }
+}
+
+/// The role an element has without a `role` attribute, if any.
+pub fn implicit_role(node: &Handle) -> Option<&'static str> {
+    let node_ref = node.borrow();
+    let (name, attrs) = match node_ref.node {
+        Element(ref name, ref attrs) if name.ns == ns!(HTML)
+            => (name.local.as_slice(), attrs.as_slice()),
+        _ => return None,
+    };
+
+    Some(match name {
+        "a" | "area" if get_attr(attrs, "href").is_some() => "link",
+        "article" => "article",
+        "aside" => "complementary",
+        "button" => "button",
+        "datalist" => "listbox",
+        "dd" => "definition",
+        "details" | "fieldset" | "optgroup" => "group",
+        "dfn" | "dt" => "term",
+        "dialog" => "dialog",
+        "figure" => "figure",
+        "footer" if !in_sectioning(node) => "contentinfo",
+        "form" => "form",
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => "heading",
+        "header" if !in_sectioning(node) => "banner",
+        "hr" => "separator",
+        "html" => "document",
+        "img" if get_attr(attrs, "alt") == Some("") => "presentation",
+        "img" => "img",
+        "input" => return input_role(attrs),
+        "li" => "listitem",
+        "main" => "main",
+        "math" => "math",
+        "menu" | "ol" | "ul" => "list",
+        "nav" => "navigation",
+        "option" => "option",
+        "output" => "status",
+        "progress" => "progressbar",
+        "section" if has_name(attrs) => "region",
+        "select" => select_role(attrs),
+        "table" => "table",
+        "tbody" | "tfoot" | "thead" => "rowgroup",
+        "td" => "cell",
+        "textarea" => "textbox",
+        "th" => "columnheader",
+        "tr" => "row",
+        _ => return None,
+    })
+}
+
+/// The role of an element: the first token of its `role` attribute, or
+/// else its implicit role.
+pub fn element_role(node: &Handle) -> Option<ElementRole> {
+    let explicit = match node.borrow().node {
+        Element(_, ref attrs) => get_attr(attrs.as_slice(), "role")
+            .and_then(|r| split_html_space(r).next())
+            .map(|r| r.to_ascii_lower()),
+        _ => return None,
+    };
+
+    match explicit {
+        Some(role) => Some(ElementRole {
+            node: node.clone(),
+            role: role,
+            explicit: true,
+        }),
+        None => implicit_role(node).map(|role| ElementRole {
+            node: node.clone(),
+            role: String::from_str(role),
+            explicit: false,
+        }),
+    }
+}
+
+/// List the elements below `root` which have a role, in tree order.
+pub fn roles(root: &Handle) -> Vec<ElementRole> {
+    let mut out = vec!();
+    for (node, _) in root.descendants() {
+        match element_role(&node) {
+            Some(r) => out.push(r),
+            None => (),
+        }
+    }
+    out
+}
+
+/// List the landmarks below `root`, in tree order.
+pub fn landmarks(root: &Handle) -> Vec<ElementRole> {
+    roles(root).into_iter().filter(|r| r.is_landmark()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{roles, landmarks, ElementRole};
+
+    use sink::rcdom::RcDom;
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    fn show(found: Vec<ElementRole>) -> Vec<String> {
+        found.into_iter().map(|r| {
+            let mut s = r.role;
+            if r.explicit {
+                s.push('!');
+            }
+            s
+        }).collect()
+    }
+
+    fn find(input: &str) -> Vec<String> {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        show(roles(&dom.document))
+    }
+
+    fn find_landmarks(input: &str) -> Vec<String> {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        show(landmarks(&dom.document))
+    }
+
+    fn v(xs: &[&str]) -> Vec<String> {
+        xs.iter().map(|x| String::from_str(*x)).collect()
+    }
+
+    static PAGE: &'static str = "<header>h</header>\
+        <nav><a href=/>x</a><a>y</a></nav>\
+        <main><article><header>a</header></article>\
+        <div role='Search button'>s</div></main><footer>f</footer>";
+
+    test_eq!(page, find(PAGE), v(["document", "banner", "navigation", "link", "main",
+        "article", "search!", "contentinfo"]))
+
+    test_eq!(page_landmarks, find_landmarks(PAGE), v(["banner", "navigation", "main",
+        "search!", "contentinfo"]))
+
+    test_eq!(inputs, find("<input><input type=CHECKBOX><input type=range>\
+        <input type=email list=x><input type=hidden><input type=image>"),
+        v(["document", "textbox", "checkbox", "slider", "combobox", "button"]))
+
+    test_eq!(selects, find("<select></select><select size=4></select><select multiple>"),
+        v(["document", "combobox", "listbox", "listbox"]))
+
+    test_eq!(named_section, find("<section>a</section><section aria-label=b>b</section>"),
+        v(["document", "region"]))
+
+    test_eq!(images, find("<img src=a><img src=b alt=''>"),
+        v(["document", "img", "presentation"]))
+}
@@ -0,0 +1,234 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Text and link density of each element, for finding the main
+//! content of a page.
+//!
+//! Readability-style extractors score blocks by how much text they
+//! hold and how much of that is links; navigation bars are mostly
+//! links, articles mostly aren't.  `block_stats` collects the counts
+//! for every element in one pass, leaving the scoring to you.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let best = block_stats(&dom.document).into_iter()
+//!     .filter(|s| s.link_density() < 0.25)
+//!     .max_by(|s| s.text_len);
+//! ```
+
+use core::prelude::*;
+
+use sink::common::{Text, Element};
+use sink::rcdom::Handle;
+use util::str::is_ascii_whitespace;
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+
+/// Counts for one element and everything inside it.
+#[deriving(Clone)]
+pub struct BlockStats {
+    /// The element.
+    pub node: Handle,
+
+    /// How far the element is below the root passed to `block_stats`,
+    /// counting its children as depth 1.
+    pub depth: uint,
+
+    /// Characters of text inside the element, not counting whitespace
+    /// or the contents of `<script>`, `<style>` and `<noscript>`.
+    pub text_len: uint,
+
+    /// How much of `text_len` is inside an `<a>`.
+    pub link_text_len: uint,
+
+    /// The number of `<a>` elements inside the element.
+    pub links: uint,
+
+    /// The number of elements which are children of the element.
+    pub child_elements: uint,
+
+    /// The number of non-whitespace text nodes which are children of
+    /// the element.
+    pub child_texts: uint,
+}
+
+impl BlockStats {
+    /// The fraction of the element's text which is inside links, or 0
+    /// if it has no text.
+    pub fn link_density(&self) -> f64 {
+        if self.text_len == 0 {
+            0.0
+        } else {
+            (self.link_text_len as f64) / (self.text_len as f64)
+        }
+    }
+}
+
+fn text_len(text: &str) -> uint {
+    text.chars().filter(|&c| !is_ascii_whitespace(c)).count()
+}
+
+// Counts which add up from an element's children.
+struct Totals {
+    text_len: uint,
+    link_text_len: uint,
+    links: uint,
+}
+
+// Add up the children of `node`, adding entries for any elements among
+// them.  Also returns the number of child elements and text nodes.
+fn visit_children(node: &Handle, depth: uint, in_link: bool, in_skipped: bool,
+        out: &mut Vec<BlockStats>) -> (Totals, uint, uint) {
+    let mut totals = Totals {
+        text_len: 0,
+        link_text_len: 0,
+        links: 0,
+    };
+    let mut child_elements = 0;
+    let mut child_texts = 0;
+
+    for child in node.borrow().children.iter() {
+        match child.borrow().node {
+            Text(ref text) => {
+                let n = text_len(text.as_slice());
+                if n > 0 {
+                    child_texts += 1;
+                }
+                if !in_skipped {
+                    totals.text_len += n;
+                    if in_link {
+                        totals.link_text_len += n;
+                    }
+                }
+            }
+
+            Element(..) => {
+                child_elements += 1;
+                let t = visit_elem(child, depth + 1, in_link, in_skipped, out);
+                totals.text_len += t.text_len;
+                totals.link_text_len += t.link_text_len;
+                totals.links += t.links;
+            }
+
+            _ => (),
+        }
+    }
+    (totals, child_elements, child_texts)
+}
+
+fn visit_elem(node: &Handle, depth: uint, in_link: bool, in_skipped: bool,
+        out: &mut Vec<BlockStats>) -> Totals {
+    let (is_link, skip) = match node.borrow().node {
+        Element(ref name, _) if name.ns == ns!(HTML) => match name.local {
+            atom!(a) => (true, false),
+            atom!(script) | atom!(style) | atom!(noscript) => (false, true),
+            _ => (false, false),
+        },
+        _ => (false, false),
+    };
+
+    // Take our place before the elements inside us, so that the
+    // results are in document order.
+    let i = out.len();
+    out.push(BlockStats {
+        node: node.clone(),
+        depth: depth,
+        text_len: 0,
+        link_text_len: 0,
+        links: 0,
+        child_elements: 0,
+        child_texts: 0,
+    });
+
+    let (mut totals, child_elements, child_texts)
+        = visit_children(node, depth, in_link || is_link, in_skipped || skip, out);
+
+    {
+        let stats = &mut out.as_mut_slice()[i];
+        stats.text_len = totals.text_len;
+        stats.link_text_len = totals.link_text_len;
+        stats.links = totals.links;
+        stats.child_elements = child_elements;
+        stats.child_texts = child_texts;
+    }
+
+    if is_link {
+        totals.links += 1;
+    }
+    totals
+}
+
+/// Count text and links for every element below `root`, in document
+/// order.  Each element's counts include everything inside it, so the
+/// whole tree is walked only once.
+pub fn block_stats(root: &Handle) -> Vec<BlockStats> {
+    let mut out = vec!();
+    visit_children(root, 0, false, false, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{block_stats, BlockStats};
+
+    use sink::common::Element;
+    use sink::rcdom::RcDom;
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    fn stats(input: &str) -> Vec<BlockStats> {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        block_stats(&dom.document)
+    }
+
+    // Counts for the first element with this id.
+    fn find(input: &str, id: &str) -> (uint, uint, uint, uint, uint) {
+        for s in stats(input).into_iter() {
+            let found = match s.node.borrow().node {
+                Element(_, ref attrs) => attrs.iter()
+                    .any(|a| a.name.local.as_slice() == "id" && a.value.as_slice() == id),
+                _ => false,
+            };
+            if found {
+                return (s.text_len, s.link_text_len, s.links, s.child_elements, s.child_texts);
+            }
+        }
+        fail!("no element with id {}", id);
+    }
+
+    static PAGE: &'static str = "<div id=nav><a href=/>Home</a> | <a href=/x>About us</a></div>\
+        <div id=main><p>Some words here.</p><p>More, <a href=y>see</a>.</p>\
+        <script>var lots = 'of code';</script></div>";
+
+    test_eq!(nav, find(PAGE, "nav"), (12, 11, 2, 2, 1))
+    test_eq!(main, find(PAGE, "main"), (23, 3, 1, 3, 0))
+
+    test_eq!(document_order, stats("<p id=a><b></b></p><i></i>").iter()
+        .map(|s| (s.depth, s.child_elements)).collect::<Vec<(uint, uint)>>(),
+        vec!((1, 2), (2, 0), (2, 2), (3, 1), (4, 0), (3, 0)))
+
+    #[test]
+    fn density() {
+        for s in stats(PAGE).iter() {
+            match s.node.borrow().node {
+                Element(ref name, _) if name.local == atom!(a)
+                    => assert_eq!(s.link_density(), 1.0),
+                Element(ref name, _) if name.local == atom!(head)
+                    => assert_eq!(s.link_density(), 0.0),
+                _ => (),
+            }
+        }
+    }
+}
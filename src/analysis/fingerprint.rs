@@ -0,0 +1,188 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Approximate fingerprints of document structure, for finding pages
+//! built from the same template.
+//!
+//! Only elements count: the document is read as the sequence of its
+//! elements in document order, each one a name and a depth.  Text,
+//! comments and attributes are ignored, so two articles on the same
+//! site should look nearly the same.
+//!
+//! `tag_shingles` gives the set of runs of `k` consecutive elements,
+//! for an exact similarity with `shingle_similarity`.  `simhash` folds
+//! them into 64 bits, where similar documents have fingerprints that
+//! differ in few bits.  These can be stored, and compared quickly with
+//! `hamming_distance`.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let a = simhash(&dom_a.document, 4);
+//! let b = simhash(&dom_b.document, 4);
+//! if hamming_distance(a, b) <= 3 {
+//!     // Probably the same template.
+//! }
+//! ```
+
+use core::prelude::*;
+
+use sink::common::Element;
+use sink::rcdom::{Handle, Traverse};
+use util::fnv::Fnv;
+
+use collections::vec::Vec;
+
+// One hash per element, from its name and its depth below the root.
+fn element_hashes(root: &Handle) -> Vec<u64> {
+    root.descendants().filter_map(|(node, depth)| match node.borrow().node {
+        Element(ref name, _) => {
+            let mut fnv = Fnv::new();
+            fnv.len(depth);
+            fnv.name(name);
+            Some(fnv.finish())
+        }
+        _ => None,
+    }).collect()
+}
+
+/// The set of `k`-shingles of the element sequence below `root`: a
+/// hash of each run of `k` consecutive elements, sorted and without
+/// duplicates.  A tree with fewer than `k` elements has one shingle,
+/// or none if it has no elements.
+///
+/// The hashes are the same between runs and platforms.
+///
+/// ## Failure
+///
+/// Fails if `k` is zero.
+pub fn tag_shingles(root: &Handle, k: uint) -> Vec<u64> {
+    assert!(k > 0, "shingle size must be at least 1");
+
+    let elems = element_hashes(root);
+    if elems.is_empty() {
+        return vec!();
+    }
+
+    let k = if elems.len() < k { elems.len() } else { k };
+    let mut shingles: Vec<u64> = elems.as_slice().windows(k).map(|run| {
+        let mut fnv = Fnv::new();
+        for &h in run.iter() {
+            fnv.u64(h);
+        }
+        fnv.finish()
+    }).collect();
+
+    shingles.sort();
+    shingles.dedup();
+    shingles
+}
+
+/// The Jaccard similarity of two sets from `tag_shingles`: the number
+/// of shingles they share over the number in either.  1 for identical
+/// sets, 0 for disjoint ones, and 1 if both are empty.
+pub fn shingle_similarity(a: &[u64], b: &[u64]) -> f64 {
+    let (mut i, mut j, mut both) = (0u, 0u, 0u);
+    while i < a.len() && j < b.len() {
+        if a[i] < b[j] {
+            i += 1;
+        } else if a[i] > b[j] {
+            j += 1;
+        } else {
+            both += 1;
+            i += 1;
+            j += 1;
+        }
+    }
+
+    let either = a.len() + b.len() - both;
+    if either == 0 {
+        1.0
+    } else {
+        (both as f64) / (either as f64)
+    }
+}
+
+/// A 64-bit simhash of the `k`-shingles of the tree below `root`.
+/// Each bit is set if most shingles have it set, so trees sharing most
+/// of their shingles differ in few bits.  See `tag_shingles`.
+pub fn simhash(root: &Handle, k: uint) -> u64 {
+    let mut votes = [0i64, ..64];
+    for &h in tag_shingles(root, k).iter() {
+        for bit in range(0u, 64) {
+            if h & (1 << bit) != 0 {
+                votes[bit] += 1;
+            } else {
+                votes[bit] -= 1;
+            }
+        }
+    }
+
+    let mut out = 0u64;
+    for bit in range(0u, 64) {
+        if votes[bit] > 0 {
+            out |= 1 << bit;
+        }
+    }
+    out
+}
+
+/// The number of bits which differ between two fingerprints.
+pub fn hamming_distance(a: u64, b: u64) -> uint {
+    (a ^ b).count_ones() as uint
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{tag_shingles, shingle_similarity, simhash, hamming_distance};
+
+    use sink::rcdom::RcDom;
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    fn dom(input: &str) -> RcDom {
+        parse(one_input(String::from_str(input)), Default::default())
+    }
+
+    fn shingles(input: &str, k: uint) -> Vec<u64> {
+        tag_shingles(&dom(input).document, k)
+    }
+
+    fn similarity(a: &str, b: &str, k: uint) -> f64 {
+        shingle_similarity(shingles(a, k).as_slice(), shingles(b, k).as_slice())
+    }
+
+    fn same_simhash(a: &str, b: &str) -> bool {
+        simhash(&dom(a).document, 3) == simhash(&dom(b).document, 3)
+    }
+
+    // html, head, body, p
+    test_eq!(count, shingles("<p>a", 2).len(), 3)
+    test_eq!(short, shingles("<p>a", 10).len(), 1)
+    test_eq!(repeats, shingles("<p>a<p>b<p>c", 1).len(), 4)
+
+    test_eq!(identical, similarity("<ul><li>a<li>b</ul>", "<ul><li>a<li>b</ul>", 3), 1.0)
+    test_eq!(text_ignored, similarity("<p class=x>a<!--b-->", "<p>c", 2), 1.0)
+    test_eq!(one_differs, similarity("<p>a", "<div>a", 1), 0.6)
+    test_eq!(depth_counts, similarity("<div><div></div></div>", "<div></div><div></div>", 1),
+        0.8)
+    test_eq!(both_empty, shingle_similarity(&[], &[]), 1.0)
+
+    test_eq!(simhash_text_ignored, same_simhash("<ul><li>a<li>b</ul>", "<ul><li>x<li>y</ul>"),
+        true)
+    test_eq!(simhash_differs, same_simhash("<ul><li>a<li>b</ul>", "<table><td>a</table>"),
+        false)
+
+    test_eq!(hamming_same, hamming_distance(0x1234, 0x1234), 0)
+    test_eq!(hamming_bits, hamming_distance(0xF0, 0x0F), 8)
+}
@@ -0,0 +1,207 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A stable hash of a parsed tree, for finding duplicate documents.
+//!
+//! The hash is 64-bit FNV-1a over a description of each node, so it
+//! doesn't depend on the platform or on when the process started, and
+//! it can be stored.  It is not cryptographic.
+//!
+//! Trees which differ only in attribute order hash the same.  With the
+//! options in `TreeHashOpts`, so do trees which differ only in comments
+//! or in whitespace.
+
+use core::prelude::*;
+
+use sink::common::{Document, Doctype, Text, Comment, Element};
+use sink::rcdom::Handle;
+use tokenizer::Attribute;
+use util::str::is_ascii_whitespace;
+use util::fnv::Fnv;
+
+use core::default::Default;
+use collections::vec::Vec;
+use collections::string::String;
+
+/// Options for `tree_hash`, with an impl for `Default`.
+#[deriving(Clone)]
+pub struct TreeHashOpts {
+    /// Leave comments out of the hash?  Text on either side of a
+    /// comment is then joined up.  Default: false
+    pub ignore_comments: bool,
+
+    /// Collapse each run of whitespace in text to a single space, and
+    /// leave out whitespace at the start and end of text and text which
+    /// is only whitespace?  Default: false
+    pub ignore_whitespace: bool,
+}
+
+impl Default for TreeHashOpts {
+    fn default() -> TreeHashOpts {
+        TreeHashOpts {
+            ignore_comments: false,
+            ignore_whitespace: false,
+        }
+    }
+}
+
+struct Hasher {
+    opts: TreeHashOpts,
+    fnv: Fnv,
+
+    // Adjacent text, which is hashed as one.
+    text: String,
+}
+
+impl Hasher {
+    fn flush_text(&mut self) {
+        let text = if self.opts.ignore_whitespace {
+            let mut out = String::new();
+            for word in self.text.as_slice().split(is_ascii_whitespace) {
+                if !word.is_empty() {
+                    if !out.is_empty() {
+                        out.push(' ');
+                    }
+                    out.push_str(word);
+                }
+            }
+            out
+        } else {
+            self.text.clone()
+        };
+        self.text.truncate(0);
+
+        if !text.is_empty() {
+            self.fnv.byte(b'T');
+            self.fnv.str(text.as_slice());
+        }
+    }
+
+    fn children(&mut self, node: &Handle) {
+        for child in node.borrow().children.iter() {
+            self.node(child);
+        }
+        self.flush_text();
+    }
+
+    fn node(&mut self, node: &Handle) {
+        match node.borrow().node {
+            Text(ref text) => {
+                self.text.push_str(text.as_slice());
+                return;
+            }
+            Comment(_) if self.opts.ignore_comments => return,
+            _ => (),
+        }
+
+        self.flush_text();
+        match node.borrow().node {
+            Document => {
+                self.fnv.byte(b'R');
+                self.children(node);
+            }
+
+            Doctype(ref name, ref public_id, ref system_id) => {
+                self.fnv.byte(b'D');
+                self.fnv.str(name.as_slice());
+                self.fnv.str(public_id.as_slice());
+                self.fnv.str(system_id.as_slice());
+            }
+
+            Comment(ref text) => {
+                self.fnv.byte(b'C');
+                self.fnv.str(text.as_slice());
+            }
+
+            Element(ref name, ref attrs) => {
+                self.fnv.byte(b'E');
+                self.fnv.name(name);
+
+                let mut attrs: Vec<&Attribute> = attrs.iter().collect();
+                attrs.sort_by(|a, b| a.name.cmp(&b.name));
+                self.fnv.len(attrs.len());
+                for attr in attrs.iter() {
+                    self.fnv.name(&attr.name);
+                    self.fnv.str(attr.value.as_slice());
+                }
+
+                self.children(node);
+                match node.borrow().template_contents {
+                    Some(ref contents) => {
+                        self.fnv.byte(b'F');
+                        self.children(contents);
+                    }
+                    None => (),
+                }
+                self.fnv.byte(b'e');
+            }
+
+            Text(_) => unreachable!(),
+        }
+    }
+}
+
+/// Hash the tree rooted at `root`.  Equal trees always have equal
+/// hashes, including between runs and platforms.
+pub fn tree_hash(root: &Handle, opts: TreeHashOpts) -> u64 {
+    let mut hasher = Hasher {
+        opts: opts,
+        fnv: Fnv::new(),
+        text: String::new(),
+    };
+    hasher.node(root);
+    hasher.flush_text();
+    hasher.fnv.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{tree_hash, TreeHashOpts};
+
+    use sink::rcdom::RcDom;
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::string::String;
+
+    fn hash(input: &str, ignore_comments: bool, ignore_whitespace: bool) -> u64 {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        tree_hash(&dom.document, TreeHashOpts {
+            ignore_comments: ignore_comments,
+            ignore_whitespace: ignore_whitespace,
+        })
+    }
+
+    fn same(a: &str, b: &str, ignore_comments: bool, ignore_whitespace: bool) -> bool {
+        hash(a, ignore_comments, ignore_whitespace) == hash(b, ignore_comments, ignore_whitespace)
+    }
+
+    test_eq!(implied_tags, same("<p>a", "<html><body><p>a</p></body></html>", false, false), true)
+    test_eq!(attr_order, same("<a x=1 y=2>", "<a y=2 x=1>", false, false), true)
+    test_eq!(attr_value, same("<a x=1>", "<a x=2>", false, false), false)
+    test_eq!(text_differs, same("<p>a", "<p>b", false, false), false)
+    test_eq!(text_vs_elem, same("<p>ab", "<p>a<b></b>", false, false), false)
+    test_eq!(name_boundary, same("<p>ab</p>", "<p>a</p><p>b</p>", false, false), false)
+
+    test_eq!(comments_kept, same("<p>a<!--x-->b", "<p>ab", false, false), false)
+    test_eq!(comments_ignored, same("<p>a<!--x-->b", "<p>ab", true, false), true)
+
+    test_eq!(whitespace_kept, same("<p> a  b\n", "<p>a b", false, false), false)
+    test_eq!(whitespace_ignored, same("<p> a  b\n", "<p>a b", false, true), true)
+    test_eq!(whitespace_nodes, same("<div>\n  <p>a</p>\n</div>", "<div><p>a</p></div>",
+        false, true), true)
+
+    test_eq!(template_contents, same("<template>a</template>", "<template>b</template>",
+        false, false), false)
+
+    // Stored hashes must stay valid, so this value must not change.
+    test_eq!(stable, hash("<!DOCTYPE html><p class=x>Hello</p>", false, false),
+        0x85e87e8f9b48f592)
+}
@@ -0,0 +1,168 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The language of each node, from `lang` and `xml:lang` attributes.
+//!
+//! A node's language is given by the nearest element at or above it
+//! with a language attribute.  `xml:lang` in the XML namespace wins over
+//! `lang`.  An empty value means the language is unknown, and stops the
+//! search; `None` is returned for it, as for a document which doesn't
+//! say.  The `Content-Language` pragma is not consulted.
+//!
+//! ## Example
+//!
+//! ```rust
+//! for (text, lang) in text_runs(&dom.document).into_iter() {
+//!     pipeline_for(lang).process(text);
+//! }
+//! ```
+
+use core::prelude::*;
+
+use sink::common::{Text, Element};
+use sink::rcdom::{Handle, Traverse};
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+// The language set on this node itself: `None` if it doesn't set one,
+// `Some(None)` if it sets the empty string.
+fn own_language(node: &Handle) -> Option<Option<String>> {
+    let node = node.borrow();
+    let attrs = match node.node {
+        Element(_, ref attrs) => attrs,
+        _ => return None,
+    };
+
+    let xml_lang = attrs.iter()
+        .find(|a| a.name.ns == ns!(XML) && a.name.local.as_slice() == "lang");
+    let lang = attrs.iter()
+        .find(|a| a.name.ns == ns!("") && a.name.local.as_slice() == "lang");
+    xml_lang.or(lang).map(|a| if a.value.is_empty() {
+        None
+    } else {
+        Some(a.value.clone())
+    })
+}
+
+/// The language of `node`, or `None` if it's not known.
+pub fn node_language(node: &Handle) -> Option<String> {
+    match own_language(node) {
+        Some(lang) => return lang,
+        None => (),
+    }
+    for ancestor in node.ancestors() {
+        match own_language(&ancestor) {
+            Some(lang) => return lang,
+            None => (),
+        }
+    }
+    None
+}
+
+fn skip_text(node: &Handle) -> bool {
+    match node.borrow().node {
+        Element(ref name, _) => name.ns == ns!(HTML) && match name.local {
+            atom!(script) | atom!(style) => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn collect(node: &Handle, lang: &Option<String>, out: &mut Vec<(String, Option<String>)>) {
+    for child in node.borrow().children.iter() {
+        match child.borrow().node {
+            Text(ref text) => {
+                let same = match out.last() {
+                    Some(&(_, ref last)) => last == lang,
+                    None => false,
+                };
+                if same {
+                    let (mut run, run_lang) = out.pop().unwrap();
+                    run.push_str(text.as_slice());
+                    out.push((run, run_lang));
+                } else {
+                    out.push((text.clone(), lang.clone()));
+                }
+                continue;
+            }
+            Element(..) => (),
+            _ => continue,
+        }
+
+        if skip_text(child) {
+            continue;
+        }
+        match own_language(child) {
+            Some(child_lang) => collect(child, &child_lang, out),
+            None => collect(child, lang, out),
+        }
+    }
+}
+
+/// The text below `root` in tree order, split into runs with the same
+/// language.  The text of `<script>` and `<style>` is left out.
+pub fn text_runs(root: &Handle) -> Vec<(String, Option<String>)> {
+    let mut out = vec!();
+    collect(root, &node_language(root), &mut out);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{node_language, text_runs};
+
+    use sink::rcdom::{RcDom, Handle, Traverse};
+    use sink::common::Text;
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    fn dom(input: &str) -> RcDom {
+        parse(one_input(String::from_str(input)), Default::default())
+    }
+
+    // The language of the first text node containing `text`.
+    fn lang_of(input: &str, text: &str) -> Option<String> {
+        let dom = dom(input);
+        let node: Handle = dom.document.descendants().map(|(n, _)| n)
+            .find(|n| match n.borrow().node {
+                Text(ref t) => t.as_slice().contains(text),
+                _ => false,
+            }).unwrap();
+        node_language(&node)
+    }
+
+    fn runs(input: &str) -> Vec<(String, Option<String>)> {
+        text_runs(&dom(input).document)
+    }
+
+    fn s(x: &str) -> String {
+        String::from_str(x)
+    }
+
+    test_eq!(inherited, lang_of("<html lang=fr><p>bonjour", "bonjour"), Some(s("fr")))
+    test_eq!(nearest, lang_of("<html lang=fr><div lang=de><p>hallo", "hallo"), Some(s("de")))
+    test_eq!(none, lang_of("<p>hello", "hello"), None)
+    test_eq!(empty_is_unknown, lang_of("<html lang=fr><p lang=''>??", "??"), None)
+    // Only `xml:lang` in the XML namespace counts, which the HTML
+    // parser never makes on HTML elements.
+    test_eq!(html_xml_lang, lang_of("<p lang=en xml:lang=fr>x", "x"), Some(s("en")))
+
+    test_eq!(runs_merged, runs("<html lang=en><p>Hello <b>world</b></p>\
+        <p lang=fr>Bonjour <i lang=''>?</i></p><script>x</script>"),
+        vec!((s("Hello world"), Some(s("en"))),
+             (s("Bonjour "), Some(s("fr"))),
+             (s("?"), None)))
+}
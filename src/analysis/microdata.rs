@@ -0,0 +1,347 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Structured data in a parsed document: microdata items, and elements
+//! carrying RDFa attributes.
+//!
+//! `items` follows the microdata algorithms from the spec, including
+//! `itemref`.  URL-valued properties such as `<a href>` are returned as
+//! written, since there is no base URL here to resolve them against.
+//!
+//! RDFa needs a full processor to turn into triples; `rdfa_elements`
+//! just finds the elements and attributes one would start from.
+//!
+//! ## Example
+//!
+//! ```rust
+//! for item in items(&dom.document).iter() {
+//!     if item.types.iter().any(|t| t.as_slice() == "http://schema.org/Product") {
+//!         println!("{}", item.properties);
+//!     }
+//! }
+//! ```
+
+use core::prelude::*;
+
+use sink::common::{Text, Element};
+use sink::rcdom::{Node, Handle, Traverse};
+use tokenizer::Attribute;
+use util::str::split_html_space;
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+/// The value of a microdata property.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum PropertyValue {
+    /// A string from an attribute, such as `href` or `content`, or the
+    /// text of the element.  An item which would contain itself through
+    /// `itemref` is the string `ERROR`, as in the spec's JSON output.
+    StringValue(String),
+
+    /// A nested item, from an element with `itemscope`.
+    ItemValue(Item),
+}
+
+/// A microdata item.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct Item {
+    /// The types from `itemtype`, usually URLs.
+    pub types: Vec<String>,
+
+    /// The global identifier from `itemid`, if any.
+    pub id: Option<String>,
+
+    /// The item's properties, named by `itemprop`, in tree order.  An
+    /// element with several names in its `itemprop` gives a property
+    /// for each.
+    pub properties: Vec<(String, PropertyValue)>,
+}
+
+/// An element with RDFa attributes.
+#[deriving(Clone)]
+pub struct RdfaElement {
+    /// The element.
+    pub node: Handle,
+
+    /// Its RDFa attributes, in source order.
+    pub attrs: Vec<Attribute>,
+}
+
+fn same(x: &Handle, y: &Handle) -> bool {
+    (&*x.borrow() as *const Node) == (&*y.borrow() as *const Node)
+}
+
+fn get_attr(node: &Handle, name: &str) -> Option<String> {
+    match node.borrow().node {
+        Element(_, ref attrs) => attrs.iter()
+            .find(|a| a.name.ns == ns!("") && a.name.local.as_slice() == name)
+            .map(|a| a.value.clone()),
+        _ => None,
+    }
+}
+
+fn has_attr(node: &Handle, name: &str) -> bool {
+    get_attr(node, name).is_some()
+}
+
+fn html_name(node: &Handle) -> Option<String> {
+    match node.borrow().node {
+        Element(ref name, _) if name.ns == ns!(HTML)
+            => Some(String::from_str(name.local.as_slice())),
+        _ => None,
+    }
+}
+
+fn text_content(node: &Handle) -> String {
+    let mut text = String::new();
+    for (child, _) in node.descendants() {
+        match child.borrow().node {
+            Text(ref t) => text.push_str(t.as_slice()),
+            _ => (),
+        }
+    }
+    text
+}
+
+fn split(value: Option<String>) -> Vec<String> {
+    match value {
+        Some(v) => split_html_space(v.as_slice()).map(|s| String::from_str(s)).collect(),
+        None => vec!(),
+    }
+}
+
+struct Extractor {
+    // All HTML elements in the tree being searched, in tree order.
+    elems: Vec<Handle>,
+
+    // Items whose properties are being found, to stop `itemref` loops.
+    memory: Vec<Handle>,
+}
+
+impl Extractor {
+    fn tree_index(&self, node: &Handle) -> uint {
+        self.elems.iter().position(|e| same(e, node)).unwrap_or(self.elems.len())
+    }
+
+    fn by_id(&self, id: &str) -> Option<Handle> {
+        self.elems.iter()
+            .find(|e| get_attr(*e, "id").as_ref().map_or(false, |v| v.as_slice() == id))
+            .map(|e| e.clone())
+    }
+
+    // The spec's "properties of an item" algorithm.
+    fn properties(&self, root: &Handle) -> Vec<Handle> {
+        let mut results = vec!();
+        let mut seen = vec!(root.clone());
+        let mut pending: Vec<Handle> = root.children_elements().collect();
+        for id in split(get_attr(root, "itemref")).iter() {
+            match self.by_id(id.as_slice()) {
+                Some(e) => pending.push(e),
+                None => (),
+            }
+        }
+
+        loop {
+            let candidate = match pending.pop() {
+                Some(c) => c,
+                None => break,
+            };
+            if seen.iter().any(|e| same(e, &candidate)) {
+                continue;
+            }
+            seen.push(candidate.clone());
+            if !has_attr(&candidate, "itemscope") {
+                pending.extend(candidate.children_elements());
+            }
+            if has_attr(&candidate, "itemprop") {
+                results.push(candidate);
+            }
+        }
+
+        results.sort_by(|a, b| self.tree_index(a).cmp(&self.tree_index(b)));
+        results
+    }
+
+    fn value(&mut self, elem: &Handle) -> PropertyValue {
+        if has_attr(elem, "itemscope") {
+            if self.memory.iter().any(|e| same(e, elem)) {
+                return StringValue(String::from_str("ERROR"));
+            }
+            return ItemValue(self.item(elem));
+        }
+
+        let attr = match html_name(elem) {
+            None => None,
+            Some(name) => match name.as_slice() {
+                "meta" => Some("content"),
+                "audio" | "embed" | "iframe" | "img" | "source" | "track" | "video"
+                    => Some("src"),
+                "a" | "area" | "link" => Some("href"),
+                "object" => Some("data"),
+                "data" | "meter" => Some("value"),
+                "time" if has_attr(elem, "datetime") => Some("datetime"),
+                _ => None,
+            },
+        };
+        StringValue(match attr {
+            Some(attr) => get_attr(elem, attr).unwrap_or(String::new()),
+            None => text_content(elem),
+        })
+    }
+
+    fn item(&mut self, root: &Handle) -> Item {
+        self.memory.push(root.clone());
+        let mut properties = vec!();
+        for elem in self.properties(root).iter() {
+            let value = self.value(elem);
+            for name in split(get_attr(elem, "itemprop")).into_iter() {
+                properties.push((name, value.clone()));
+            }
+        }
+        self.memory.pop();
+
+        Item {
+            types: split(get_attr(root, "itemtype")),
+            id: get_attr(root, "itemid"),
+            properties: properties,
+        }
+    }
+}
+
+/// Find the top-level microdata items below `root`: elements with
+/// `itemscope` and no `itemprop`, in tree order.  `itemref` can refer to
+/// any element below `root`.
+pub fn items(root: &Handle) -> Vec<Item> {
+    let mut ex = Extractor {
+        elems: root.descendants().map(|(node, _)| node)
+            .filter(|node| html_name(node).is_some()).collect(),
+        memory: vec!(),
+    };
+
+    let tops: Vec<Handle> = ex.elems.iter()
+        .filter(|e| has_attr(*e, "itemscope") && !has_attr(*e, "itemprop"))
+        .map(|e| e.clone())
+        .collect();
+    tops.iter().map(|e| ex.item(e)).collect()
+}
+
+static RDFA_ATTRS: &'static [&'static str] = &["about", "content", "datatype",
+    "inlist", "prefix", "property", "rel", "resource", "rev", "typeof", "vocab"];
+
+// Attributes which mark an element as using RDFa.  `rel`, `rev` and
+// `content` are common in plain HTML too.
+static RDFA_ONLY_ATTRS: &'static [&'static str] = &["about", "datatype", "inlist",
+    "prefix", "property", "resource", "typeof", "vocab"];
+
+/// Find the elements below `root` which use RDFa, in tree order, with
+/// their RDFa attributes.
+pub fn rdfa_elements(root: &Handle) -> Vec<RdfaElement> {
+    let mut out = vec!();
+    for (node, _) in root.descendants() {
+        let attrs: Vec<Attribute> = match node.borrow().node {
+            Element(_, ref attrs) => attrs.iter()
+                .filter(|a| a.name.ns == ns!("")
+                    && RDFA_ATTRS.contains(&a.name.local.as_slice()))
+                .map(|a| a.clone())
+                .collect(),
+            _ => continue,
+        };
+        if attrs.iter().any(|a| RDFA_ONLY_ATTRS.contains(&a.name.local.as_slice())) {
+            out.push(RdfaElement {
+                node: node.clone(),
+                attrs: attrs,
+            });
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{items, rdfa_elements, Item, PropertyValue, StringValue, ItemValue};
+
+    use sink::rcdom::RcDom;
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    fn parse_str(input: &str) -> RcDom {
+        parse(one_input(String::from_str(input)), Default::default())
+    }
+
+    fn s(x: &str) -> String {
+        String::from_str(x)
+    }
+
+    fn prop(name: &str, value: &str) -> (String, PropertyValue) {
+        (s(name), StringValue(s(value)))
+    }
+
+    #[test]
+    fn nested() {
+        let dom = parse_str("<div itemscope itemtype='http://schema.org/Person' itemid=p1>\
+            <span itemprop=name>Ann</span> <a itemprop=url href=/ann>home</a>\
+            <div itemprop=address itemscope><span itemprop=locality>Paris</span></div></div>");
+        assert_eq!(items(&dom.document), vec!(Item {
+            types: vec!(s("http://schema.org/Person")),
+            id: Some(s("p1")),
+            properties: vec!(
+                prop("name", "Ann"),
+                prop("url", "/ann"),
+                (s("address"), ItemValue(Item {
+                    types: vec!(),
+                    id: None,
+                    properties: vec!(prop("locality", "Paris")),
+                })),
+            ),
+        }));
+    }
+
+    #[test]
+    fn itemref() {
+        let dom = parse_str("<p id=x itemprop=b>2</p>\
+            <div itemscope itemref='x nowhere'><p itemprop=a>1</p></div>");
+        let found = items(&dom.document);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].properties, vec!(prop("b", "2"), prop("a", "1")));
+    }
+
+    #[test]
+    fn itemref_loop() {
+        let dom = parse_str("<div id=a itemscope itemref=a><p itemprop=n>x</p></div>");
+        let found = items(&dom.document);
+        assert_eq!(found[0].properties, vec!(prop("n", "x")));
+    }
+
+    #[test]
+    fn values() {
+        let dom = parse_str("<div itemscope><meta itemprop='a b' content=1>\
+            <time itemprop=c datetime=2014-01-01>New Year</time><time itemprop=d>now</time>\
+            <img itemprop=e src=x.png><data itemprop=f value=7>seven</data></div>");
+        assert_eq!(items(&dom.document)[0].properties, vec!(
+            prop("a", "1"), prop("b", "1"), prop("c", "2014-01-01"), prop("d", "now"),
+            prop("e", "x.png"), prop("f", "7")));
+    }
+
+    #[test]
+    fn rdfa() {
+        let dom = parse_str("<link rel=stylesheet href=x>\
+            <div vocab='http://schema.org/' typeof=Person>\
+            <span property=name>Ann</span></div>");
+        let found: Vec<Vec<String>> = rdfa_elements(&dom.document).into_iter()
+            .map(|e| e.attrs.into_iter().map(|a| a.value).collect())
+            .collect();
+        assert_eq!(found, vec!(vec!(s("http://schema.org/"), s("Person")), vec!(s("name"))));
+    }
+}
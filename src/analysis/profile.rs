@@ -0,0 +1,353 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Checking a parsed document against a restricted HTML profile.
+//!
+//! A `Profile` lists the HTML elements a dialect allows, the attributes
+//! allowed on each, and the URL schemes allowed in attributes like
+//! `href`.  It's written as plain text, so the same checker handles an
+//! email-safe subset, a site's own allowlist for user content, and so
+//! on.  Each line names an element followed by its attributes:
+//!
+//! ```text
+//! # Comments start with '#'.
+//! a href title
+//! img src alt width height
+//! * class style
+//! @schemes http https mailto
+//! ```
+//!
+//! `*` gives attributes allowed on every element, and `@schemes` the
+//! URL schemes; with no `@schemes` line any URL is allowed.  Relative
+//! URLs are always allowed, but a URL which looks like it has a scheme
+//! that doesn't parse is not.  Names are ASCII case-insensitive.  The
+//! parser always creates `<html>`, `<head>` and `<body>`, so a profile
+//! for whole documents should allow them.
+//!
+//! ## Example
+//!
+//! ```rust
+//! for v in check(&dom.document, &Profile::email()).iter() {
+//!     println!("{}", v.describe());
+//! }
+//! ```
+
+use core::prelude::*;
+
+use sink::common::Element;
+use sink::rcdom::Handle;
+use util::str::{split_html_space, AsciiExt};
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+use string_cache::{Atom, QualName};
+
+/// The profile for HTML email, allowing the markup which common mail
+/// clients display.  Scripts, forms, embedded content other than
+/// images, and event handler attributes are left out.
+pub static EMAIL_SAFE: &'static str = "
+html lang dir
+head
+title
+meta name content charset http-equiv
+style type media
+body bgcolor background text link vlink alink
+a href name target
+abbr
+b
+big
+blockquote cite
+br clear
+center
+cite
+code
+dd
+div align
+dl
+dt
+em
+font color face size
+h1 align
+h2 align
+h3 align
+h4 align
+h5 align
+h6 align
+hr align noshade size width
+i
+img src alt width height border align hspace vspace usemap
+li type value
+map name
+area shape coords href alt target
+ol type start
+p align
+pre
+q cite
+s
+small
+span
+strike
+strong
+sub
+sup
+table align bgcolor background border cellpadding cellspacing width height summary
+caption align
+colgroup span width
+col span width align valign
+thead align valign
+tbody align valign
+tfoot align valign
+tr align valign bgcolor height
+td align valign bgcolor background colspan rowspan width height nowrap
+th align valign bgcolor background colspan rowspan width height nowrap scope
+tt
+u
+ul type
+* class id style title dir lang
+@schemes http https mailto cid
+";
+
+// URL-valued attributes whose scheme is checked.
+static URL_ATTRS: &'static [&'static str] = &["action", "background", "cite", "formaction",
+    "href", "longdesc", "poster", "src", "usemap"];
+
+/// A way a document breaks a profile.
+#[deriving(Clone)]
+pub enum Violation {
+    /// An element the profile doesn't allow.  Nothing inside it is
+    /// checked.
+    DisallowedElement(Handle),
+
+    /// An attribute the profile doesn't allow on this element.
+    DisallowedAttribute(Handle, QualName),
+
+    /// A URL with a scheme the profile doesn't allow.
+    DisallowedUrl(Handle, QualName, String),
+}
+
+impl Violation {
+    /// The element with the problem.
+    pub fn node(&self) -> Handle {
+        match *self {
+            DisallowedElement(ref node) | DisallowedAttribute(ref node, _)
+                | DisallowedUrl(ref node, _, _) => node.clone(),
+        }
+    }
+
+    /// A short description, e.g. `<img onload> is not allowed`.
+    pub fn describe(&self) -> String {
+        let name = match self.node().borrow().node {
+            Element(ref name, _) => name.local.clone(),
+            _ => fail!("not an element!"),
+        };
+        match *self {
+            DisallowedElement(_) => format!("<{}> is not allowed", name.as_slice()),
+            DisallowedAttribute(_, ref attr) => format!("<{} {}> is not allowed",
+                name.as_slice(), attr.local.as_slice()),
+            DisallowedUrl(_, ref attr, ref url) => format!("<{} {}=\"{}\"> has a URL scheme \
+                which is not allowed", name.as_slice(), attr.local.as_slice(), url),
+        }
+    }
+}
+
+struct ElementRule {
+    name: Atom,
+    attrs: Vec<Atom>,
+}
+
+/// The elements, attributes and URL schemes a restricted dialect of
+/// HTML allows.
+pub struct Profile {
+    elements: Vec<ElementRule>,
+    global_attrs: Vec<Atom>,
+
+    // Lower case.  Empty to allow any scheme.
+    schemes: Vec<String>,
+}
+
+// The scheme of a URL, or `None` if it's relative.  As in the URL
+// parser, C0 controls and spaces at either end and tabs and newlines
+// anywhere are ignored, so `java\tscript:` is `javascript:`.  A colon
+// before any '/', '?' or '#' with no valid scheme before it is an
+// error: a browser might not see that URL as relative.
+fn url_scheme(url: &str) -> Result<Option<String>, ()> {
+    let url: String = url.trim_chars(|c: char| c <= ' ')
+        .chars().filter(|&c| c != '\t' && c != '\n' && c != '\r').collect();
+    let end = unwrap_or_return!(url.as_slice().find(|c: char| c == ':' || c == '/'
+        || c == '?' || c == '#'), Ok(None));
+    if url.as_slice().char_at(end) != ':' {
+        return Ok(None);
+    }
+
+    let scheme = url.as_slice().slice_to(end);
+    let mut chars = scheme.chars();
+    let valid = chars.next().map_or(false, |c| c.is_alphabetic() && c < '\x80')
+        && chars.all(|c| c < '\x80' && (c.is_alphanumeric() || c == '+' || c == '-' || c == '.'));
+    if valid {
+        Ok(Some(scheme.to_ascii_lower()))
+    } else {
+        Err(())
+    }
+}
+
+impl Profile {
+    /// Read a profile from its text form.  Every line is meaningful,
+    /// so there are no errors; a misspelled name just isn't allowed.
+    pub fn parse(spec: &str) -> Profile {
+        let mut profile = Profile {
+            elements: vec!(),
+            global_attrs: vec!(),
+            schemes: vec!(),
+        };
+        for line in spec.lines() {
+            let line = line.split('#').next().unwrap().to_ascii_lower();
+            let mut words = split_html_space(line.as_slice());
+            let first = match words.next() {
+                Some(first) => first,
+                None => continue,
+            };
+            match first {
+                "*" => profile.global_attrs.extend(words.map(|w| Atom::from_slice(w))),
+                "@schemes" => profile.schemes.extend(words.map(|w| String::from_str(w))),
+                name => profile.elements.push(ElementRule {
+                    name: Atom::from_slice(name),
+                    attrs: words.map(|w| Atom::from_slice(w)).collect(),
+                }),
+            }
+        }
+        profile
+    }
+
+    /// The `EMAIL_SAFE` profile.
+    pub fn email() -> Profile {
+        Profile::parse(EMAIL_SAFE)
+    }
+
+    fn rule<'a>(&'a self, name: &QualName) -> Option<&'a ElementRule> {
+        if name.ns != ns!(HTML) {
+            return None;
+        }
+        self.elements.iter().find(|r| r.name == name.local)
+    }
+
+    /// Does the profile allow this element?
+    pub fn allows_element(&self, name: &QualName) -> bool {
+        self.rule(name).is_some()
+    }
+
+    /// Does the profile allow this attribute on this element?
+    pub fn allows_attr(&self, elem: &QualName, attr: &QualName) -> bool {
+        if attr.ns != ns!("") {
+            return false;
+        }
+        self.global_attrs.contains(&attr.local)
+            || self.rule(elem).map_or(false, |r| r.attrs.contains(&attr.local))
+    }
+
+    /// Does the profile allow this URL?  If the profile limits the
+    /// schemes, a URL with a colon that doesn't follow a valid scheme
+    /// isn't allowed.
+    pub fn allows_url(&self, url: &str) -> bool {
+        if self.schemes.is_empty() {
+            return true;
+        }
+        match url_scheme(url) {
+            Ok(Some(ref scheme)) => self.schemes.contains(scheme),
+            Ok(None) => true,
+            Err(()) => false,
+        }
+    }
+}
+
+fn check_children(node: &Handle, profile: &Profile, out: &mut Vec<Violation>) {
+    for child in node.borrow().children.iter() {
+        let child_ref = child.borrow();
+        let (name, attrs) = match child_ref.node {
+            Element(ref name, ref attrs) => (name, attrs),
+            _ => continue,
+        };
+
+        if !profile.allows_element(name) {
+            out.push(DisallowedElement(child.clone()));
+            continue;
+        }
+
+        for attr in attrs.iter() {
+            if !profile.allows_attr(name, &attr.name) {
+                out.push(DisallowedAttribute(child.clone(), attr.name.clone()));
+            } else if attr.name.ns == ns!("")
+                    && URL_ATTRS.iter().any(|&a| a == attr.name.local.as_slice())
+                    && !profile.allows_url(attr.value.as_slice()) {
+                out.push(DisallowedUrl(child.clone(), attr.name.clone(), attr.value.clone()));
+            }
+        }
+
+        check_children(child, profile, out);
+    }
+}
+
+/// Check the elements below `root` against a profile, listing each
+/// violation in tree order.
+pub fn check(root: &Handle, profile: &Profile) -> Vec<Violation> {
+    let mut out = vec!();
+    check_children(root, profile, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{check, Profile};
+
+    use sink::rcdom::RcDom;
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    fn violations(input: &str, profile: &Profile) -> Vec<String> {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        check(&dom.document, profile).iter().map(|v| v.describe()).collect()
+    }
+
+    fn v(xs: &[&str]) -> Vec<String> {
+        xs.iter().map(|x| String::from_str(*x)).collect()
+    }
+
+    test_eq!(email_ok, violations("<table width=600><tr><td style='color:red'>\
+        <a href='https://example.com/'>Hi</a><img src=cid:logo alt=''></table>",
+        &Profile::email()), v([]))
+
+    test_eq!(email_bad, violations("<p onclick=x()>a<script>b()</script>\
+        <a href='javascript:evil()'>c</a><form><input></form>", &Profile::email()),
+        v(["<p onclick> is not allowed", "<script> is not allowed",
+           "<a href=\"javascript:evil()\"> has a URL scheme which is not allowed",
+           "<form> is not allowed"]))
+
+    test_eq!(custom, violations("<b title=x>a</b><I>b</I><svg></svg>",
+        &Profile::parse("html\nhead\nbody\n  B   # bold\ni\n")),
+        v(["<b title> is not allowed", "<svg> is not allowed"]))
+
+    test_eq!(relative_urls, Profile::email().allows_url("/a:b"), true)
+    test_eq!(scheme_case, Profile::email().allows_url(" HTTPS://x"), true)
+    test_eq!(any_scheme, Profile::parse("a href").allows_url("ftp://x"), true)
+    test_eq!(relative_query, Profile::email().allows_url("?a:b#c:d"), true)
+
+    test_eq!(scheme_with_tab, Profile::email().allows_url("java\tscript:alert(1)"), false)
+    test_eq!(scheme_with_newline, Profile::email().allows_url("java\nscript:alert(1)"), false)
+    test_eq!(scheme_with_cr, Profile::email().allows_url("java\r\nscript:alert(1)"), false)
+    test_eq!(leading_control, Profile::email().allows_url("\x01javascript:alert(1)"), false)
+    test_eq!(trailing_control, Profile::email().allows_url("javascript:alert(1)\x1f"), false)
+    test_eq!(bad_scheme, Profile::email().allows_url("java%73cript:alert(1)"), false)
+    test_eq!(bad_scheme_space, Profile::email().allows_url("java script:alert(1)"), false)
+    test_eq!(allowed_with_tab, Profile::email().allows_url("ht\ttps://x"), true)
+}
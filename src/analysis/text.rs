@@ -0,0 +1,207 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The text of a tree roughly as a browser would show it, for search
+//! indexing and the like.
+//!
+//! Concatenating text nodes, as `textContent` does, runs words together
+//! across block elements and keeps all the source's indentation.
+//! `text_content_collapsed` follows the default style sheet instead:
+//! whitespace collapses to single spaces except inside `<pre>` and
+//! `<textarea>`, block elements start new lines, `<br>` is a newline,
+//! and things which aren't rendered, like `<script>` and `<head>`, are
+//! left out.  No CSS is applied.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let body = dom.body().unwrap();
+//! index.add(url, text_content_collapsed(&body).as_slice());
+//! ```
+
+use core::prelude::*;
+
+use sink::common::{Text, Element};
+use sink::rcdom::{Handle, Traverse};
+use util::str::is_ascii_whitespace;
+
+use collections::MutableSeq;
+use collections::string::String;
+
+// How an HTML element affects the text around it.
+enum Display {
+    Inline,
+    Block,
+    Preformatted,
+    Cell,
+    LineBreak,
+    Hidden,
+}
+
+fn display(node: &Handle) -> Display {
+    match node.borrow().node {
+        Element(ref name, _) if name.ns == ns!(HTML) => match name.local.as_slice() {
+            "br" => LineBreak,
+
+            "head" | "script" | "style" | "template" | "noscript" => Hidden,
+
+            "pre" | "textarea" | "listing" | "plaintext" | "xmp" => Preformatted,
+
+            "td" | "th" => Cell,
+
+            "address" | "article" | "aside" | "blockquote" | "caption" | "center"
+                | "dd" | "details" | "dialog" | "dir" | "div" | "dl" | "dt" | "fieldset"
+                | "figcaption" | "figure" | "footer" | "form" | "h1" | "h2" | "h3"
+                | "h4" | "h5" | "h6" | "header" | "hgroup" | "hr" | "legend" | "li"
+                | "main" | "menu" | "nav" | "ol" | "p" | "section" | "summary"
+                | "table" | "tr" | "ul" => Block,
+
+            _ => Inline,
+        },
+        _ => Inline,
+    }
+}
+
+struct Collector {
+    out: String,
+
+    // A collapsed space is pending.
+    space: bool,
+
+    // A block boundary is pending.
+    newline: bool,
+}
+
+impl Collector {
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.as_slice().ends_with("\n")
+    }
+
+    // Write any pending space or line break, before some visible text.
+    fn flush(&mut self) {
+        if self.newline {
+            if !self.at_line_start() {
+                self.out.push('\n');
+            }
+        } else if self.space {
+            self.out.push(' ');
+        }
+        self.newline = false;
+        self.space = false;
+    }
+
+    fn text(&mut self, text: &str, preserve: bool) {
+        if preserve {
+            if !text.is_empty() {
+                self.flush();
+                self.out.push_str(text);
+            }
+            return;
+        }
+
+        for c in text.chars() {
+            if is_ascii_whitespace(c) {
+                if !self.at_line_start() {
+                    self.space = true;
+                }
+            } else {
+                self.flush();
+                self.out.push(c);
+            }
+        }
+    }
+
+    fn line_break(&mut self) {
+        self.newline = false;
+        self.space = false;
+        self.out.push('\n');
+    }
+
+    fn children(&mut self, node: &Handle, preserve: bool) {
+        for child in node.borrow().children.iter() {
+            match child.borrow().node {
+                Text(ref text) => {
+                    self.text(text.as_slice(), preserve);
+                    continue;
+                }
+                Element(..) => (),
+                _ => continue,
+            }
+
+            match display(child) {
+                Inline => self.children(child, preserve),
+                Hidden => (),
+                LineBreak => self.line_break(),
+                Cell => {
+                    self.text(" ", false);
+                    self.children(child, preserve);
+                    self.text(" ", false);
+                }
+                Block => self.block(child, preserve),
+                Preformatted => self.block(child, true),
+            }
+        }
+    }
+
+    fn block(&mut self, node: &Handle, preserve: bool) {
+        self.newline = true;
+        self.children(node, preserve);
+        self.newline = true;
+    }
+}
+
+fn is_preformatted(node: &Handle) -> bool {
+    match display(node) {
+        Preformatted => true,
+        _ => false,
+    }
+}
+
+/// The text inside `root`, with whitespace collapsed and lines broken
+/// as the default rendering would.  There is no leading or trailing
+/// whitespace, except inside preformatted text.
+pub fn text_content_collapsed(root: &Handle) -> String {
+    let preserve = is_preformatted(root) || root.ancestors().any(|n| is_preformatted(&n));
+
+    let mut collector = Collector {
+        out: String::new(),
+        space: false,
+        newline: false,
+    };
+    collector.children(root, preserve);
+    collector.out
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::text_content_collapsed;
+
+    use sink::rcdom::RcDom;
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::string::String;
+
+    fn text(input: &str) -> String {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        text_content_collapsed(&dom.document)
+    }
+
+    test_eq!(collapse, text("<p>  Hello,\n   <b>big</b>  world  </p>").as_slice(),
+        "Hello, big world")
+    test_eq!(blocks, text("<div>a<p>b</p>c</div><p>d").as_slice(), "a\nb\nc\nd")
+    test_eq!(line_breaks, text("<p>a<br>b<br><br>c").as_slice(), "a\nb\n\nc")
+    test_eq!(preformatted, text("<p>x</p><pre>  a\n   b</pre>").as_slice(), "x\n  a\n   b")
+    test_eq!(textarea, text("<textarea>  keep  </textarea>").as_slice(), "  keep  ")
+    test_eq!(hidden, text("<title>T</title><script>x()</script><p>y<style>p{}</style>")
+        .as_slice(), "y")
+    test_eq!(cells, text("<table><tr><td>a<td>b</table>").as_slice(), "a b")
+    test_eq!(empty, text("  ").as_slice(), "")
+}
@@ -0,0 +1,205 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Check whether a parsed document can be expressed as well-formed,
+//! namespace-well-formed XML.
+//!
+//! This looks at the tree, not at the source.  Implied end tags and
+//! unescaped `&` or `<` in the source don't matter, because a serializer
+//! will write explicit end tags and escape text.  What can't be fixed up
+//! on output is listed in `XmlProblem`.
+
+use core::prelude::*;
+
+use sink::common::{Doctype, Text, Comment, Element};
+use sink::rcdom::{Handle, Traverse};
+
+use collections::vec::Vec;
+use collections::string::String;
+
+use string_cache::QualName;
+
+/// A reason why a document is not expressible as XML.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum XmlProblem {
+    /// An element name which is not an XML `NCName`.
+    BadElementName(QualName),
+
+    /// An attribute name which is not an XML `NCName`, for example
+    /// `foo:bar` with no namespace.
+    BadAttributeName(QualName),
+
+    /// A character which XML does not allow, such as U+000C FORM FEED,
+    /// in text, a comment, or an attribute value.
+    BadChar(char),
+
+    /// A comment containing `--` or ending with `-`.
+    BadComment(String),
+
+    /// A `DOCTYPE` whose public identifier has characters outside
+    /// `PubidChar`, or whose system identifier has both kinds of quote.
+    BadDoctype,
+}
+
+fn is_xml_char(c: char) -> bool {
+    match c {
+        '\t' | '\n' | '\r' => true,
+        '\u0000'...'\u001f' | '\ufffe' | '\uffff' => false,
+        _ => true,
+    }
+}
+
+fn is_name_start_char(c: char) -> bool {
+    match c {
+        'A'...'Z' | '_' | 'a'...'z'
+        | '\u00c0'...'\u00d6' | '\u00d8'...'\u00f6' | '\u00f8'...'\u02ff'
+        | '\u0370'...'\u037d' | '\u037f'...'\u1fff' | '\u200c'...'\u200d'
+        | '\u2070'...'\u218f' | '\u2c00'...'\u2fef' | '\u3001'...'\ud7ff'
+        | '\uf900'...'\ufdcf' | '\ufdf0'...'\ufffd'
+        | '\U00010000'...'\U000effff' => true,
+        _ => false,
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    match c {
+        '-' | '.' | '0'...'9' | '\u00b7'
+        | '\u0300'...'\u036f' | '\u203f'...'\u2040' => true,
+        _ => is_name_start_char(c),
+    }
+}
+
+/// Is this a valid XML `NCName`, i.e. a name without a colon?
+pub fn is_ncname(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if is_name_start_char(c) => chars.all(is_name_char),
+        _ => false,
+    }
+}
+
+fn is_pubid_char(c: char) -> bool {
+    match c {
+        ' ' | '\r' | '\n' | 'a'...'z' | 'A'...'Z' | '0'...'9' => true,
+        _ => "-'()+,./:=?;!*#@$_%".contains_char(c),
+    }
+}
+
+fn check_chars(s: &str, problems: &mut Vec<XmlProblem>) {
+    match s.chars().find(|&c| !is_xml_char(c)) {
+        Some(c) => problems.push(BadChar(c)),
+        None => (),
+    }
+}
+
+fn check_node(node: &Handle, problems: &mut Vec<XmlProblem>) {
+    match node.borrow().node {
+        Element(ref name, ref attrs) => {
+            if !is_ncname(name.local.as_slice()) {
+                problems.push(BadElementName(name.clone()));
+            }
+            for attr in attrs.iter() {
+                // Attributes in the XML, XLink, or XMLNS namespaces
+                // are written with a prefix, which is fine.
+                if attr.name.ns == ns!("") && !is_ncname(attr.name.local.as_slice()) {
+                    problems.push(BadAttributeName(attr.name.clone()));
+                }
+                check_chars(attr.value.as_slice(), problems);
+            }
+        }
+
+        Text(ref text) => check_chars(text.as_slice(), problems),
+
+        Comment(ref text) => {
+            let s = text.as_slice();
+            if s.contains("--") || s.ends_with("-") {
+                problems.push(BadComment(text.clone()));
+            }
+            check_chars(s, problems);
+        }
+
+        Doctype(_, ref public_id, ref system_id) => {
+            if !public_id.as_slice().chars().all(is_pubid_char)
+                || (system_id.as_slice().contains_char('"')
+                    && system_id.as_slice().contains_char('\'')) {
+                problems.push(BadDoctype);
+            }
+        }
+
+        _ => (),
+    }
+}
+
+/// Find everything which prevents the tree rooted at `root` from
+/// being serialized as well-formed XML.  Returns an empty vector if
+/// the tree is XML-compatible.
+pub fn xml_problems(root: &Handle) -> Vec<XmlProblem> {
+    let mut problems = vec!();
+    check_node(root, &mut problems);
+    for (node, _) in root.descendants() {
+        check_node(&node, &mut problems);
+    }
+    problems
+}
+
+/// Can the tree rooted at `root` be serialized as well-formed XML?
+pub fn is_xml_compatible(root: &Handle) -> bool {
+    xml_problems(root).is_empty()
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{is_ncname, is_xml_char, xml_problems, is_xml_compatible, XmlProblem};
+    use super::{BadElementName, BadAttributeName, BadChar, BadComment, BadDoctype};
+
+    use sink::rcdom::RcDom;
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    use string_cache::{Atom, QualName};
+
+    fn problems(input: &str) -> Vec<XmlProblem> {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        xml_problems(&dom.document)
+    }
+
+    test_eq!(ncname_simple, is_ncname("div"), true)
+    test_eq!(ncname_hyphen, is_ncname("font-face"), true)
+    test_eq!(ncname_empty, is_ncname(""), false)
+    test_eq!(ncname_colon, is_ncname("foo:bar"), false)
+    test_eq!(ncname_leading_digit, is_ncname("1a"), false)
+    test_eq!(ncname_leading_hyphen, is_ncname("-a"), false)
+    test_eq!(ncname_quote, is_ncname("a\""), false)
+    test_eq!(ncname_non_ascii, is_ncname("\u00e9l\u00e9ment"), true)
+
+    test_eq!(xml_char_tab, is_xml_char('\t'), true)
+    test_eq!(xml_char_form_feed, is_xml_char('\x0c'), false)
+    test_eq!(xml_char_nonchar, is_xml_char('\uffff'), false)
+
+    #[test]
+    fn compatible() {
+        let dom: RcDom = parse(one_input(String::from_str(
+            "<!DOCTYPE html><p title='a<b'>x & y<br><svg xlink:href=z></svg>")),
+            Default::default());
+        assert!(is_xml_compatible(&dom.document));
+    }
+
+    test_eq!(element_name, problems("<a:b>x</a:b>"),
+        vec!(BadElementName(QualName::new(ns!(HTML), Atom::from_slice("a:b")))))
+
+    test_eq!(attr_name_char_and_comment, problems("<p foo:bar=x>a\x0cb<!--a--b-->"),
+        vec!(BadAttributeName(QualName::new(ns!(""), Atom::from_slice("foo:bar"))),
+            BadChar('\x0c'), BadComment(String::from_str("a--b"))))
+
+    test_eq!(doctype, problems("<!DOCTYPE html PUBLIC \"a{b\" \"\">"), vec!(BadDoctype))
+}
@@ -0,0 +1,301 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! What could go at a cursor position, for an editor's completions.
+//!
+//! `completion_context` parses the document up to the cursor and stops
+//! without signalling EOF, so the tokenizer is left in the state the
+//! cursor is in, and the stack of open elements holds the elements the
+//! cursor is inside.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let cx = completion_context(my_str, cursor, Default::default());
+//! match cx.context {
+//!     AttrNameContext => suggest_attrs(cx.tag, cx.attr_name),
+//!     TextContext => suggest_children(cx.open_elems.last()),
+//!     _ => (),
+//! }
+//! ```
+
+use core::prelude::*;
+
+use tokenizer::{Tokenizer, Attribute, TagKind, EndTag};
+use tokenizer::states;
+use tokenizer::states::State;
+use tree_builder::{TreeBuilder, TreeSink, QuirksMode, NodeOrText};
+use tree_builder::Error;
+use driver::ParseOpts;
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+use string_cache::QualName;
+
+/// Roughly where the cursor is in the markup.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum CursorContext {
+    /// In text, where a tag or character reference could start.
+    TextContext,
+
+    /// In the text of an element like `<script>` or `<textarea>`,
+    /// which can't contain tags.
+    RawTextContext,
+
+    /// Just after a `<`.
+    TagOpenContext,
+
+    /// In a tag name, or just after `</`.
+    TagNameContext,
+
+    /// In a tag, where an attribute name could start.
+    BeforeAttrNameContext,
+
+    /// In an attribute name.
+    AttrNameContext,
+
+    /// In a tag after an attribute name, where either `=` or another
+    /// attribute could follow.
+    AfterAttrNameContext,
+
+    /// After an attribute's `=`, or in its value.
+    AttrValueContext,
+
+    /// In a comment.
+    CommentContext,
+
+    /// In a doctype.
+    DoctypeContext,
+}
+
+/// What the parser knows at a cursor position.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct CompletionContext {
+    /// Where the cursor is in the markup.
+    pub context: CursorContext,
+
+    /// The tag the cursor is in, if any: its kind, and its name as far
+    /// as the cursor, lowercased.
+    pub tag: Option<(TagKind, String)>,
+
+    /// The attribute the cursor is in or after, if any: its name as far
+    /// as the cursor, lowercased.
+    pub attr_name: Option<String>,
+
+    /// The open elements, outermost first, including any the parser
+    /// implied.  A tag the cursor is in has not been opened yet.
+    pub open_elems: Vec<QualName>,
+
+    /// The tokenizer's state, for finer distinctions than `context`.
+    pub state: State,
+}
+
+// Remembers element names.  Handles are indices into `names`, and the
+// document is 0.
+struct NameSink {
+    names: Vec<Option<QualName>>,
+}
+
+impl NameSink {
+    fn new_node(&mut self, name: Option<QualName>) -> uint {
+        self.names.push(name);
+        self.names.len() - 1
+    }
+}
+
+impl TreeSink<uint> for NameSink {
+    fn get_document(&mut self) -> uint {
+        0
+    }
+
+    fn same_node(&self, x: uint, y: uint) -> bool {
+        x == y
+    }
+
+    fn elem_name(&self, target: uint) -> QualName {
+        self.names[target].clone().expect("not an element")
+    }
+
+    fn create_element(&mut self, name: QualName, _attrs: Vec<Attribute>) -> uint {
+        self.new_node(Some(name))
+    }
+
+    fn create_comment(&mut self, _text: String) -> uint {
+        self.new_node(None)
+    }
+
+    fn get_template_contents(&mut self, target: uint) -> uint {
+        target
+    }
+
+    fn append_before_sibling(&mut self,
+            _sibling: uint,
+            _new_node: NodeOrText<uint>) -> Result<(), NodeOrText<uint>> {
+        // No scripts run, so the sibling always has a parent.
+        Ok(())
+    }
+
+    fn parse_error(&mut self, _err: Error) { }
+    fn set_quirks_mode(&mut self, _mode: QuirksMode) { }
+    fn append(&mut self, _parent: uint, _child: NodeOrText<uint>) { }
+    fn append_doctype_to_document(&mut self, _name: String, _public_id: String, _system_id: String) { }
+    fn add_attrs_if_missing(&mut self, _target: uint, _attrs: Vec<Attribute>) { }
+    fn remove_from_parent(&mut self, _target: uint) { }
+    fn reparent_children(&mut self, _node: uint, _new_parent: uint) { }
+    fn mark_script_already_started(&mut self, _node: uint) { }
+}
+
+fn cursor_context(state: State) -> CursorContext {
+    match state {
+        states::Data => TextContext,
+
+        states::TagOpen => TagOpenContext,
+
+        states::EndTagOpen | states::TagName
+            | states::RawEndTagOpen(_) | states::RawEndTagName(_) => TagNameContext,
+
+        states::BeforeAttributeName | states::AfterAttributeValueQuoted
+            | states::SelfClosingStartTag => BeforeAttrNameContext,
+
+        states::AttributeName => AttrNameContext,
+
+        states::AfterAttributeName => AfterAttrNameContext,
+
+        states::BeforeAttributeValue | states::AttributeValue(_)
+            | states::AttributeValueTemplate(..) => AttrValueContext,
+
+        states::BogusComment | states::MarkupDeclarationOpen
+            | states::CommentStart | states::CommentStartDash
+            | states::Comment | states::CommentEndDash
+            | states::CommentEnd | states::CommentEndBang
+            | states::ProcessingInstruction => CommentContext,
+
+        states::Doctype | states::BeforeDoctypeName
+            | states::DoctypeName | states::AfterDoctypeName
+            | states::AfterDoctypeKeyword(_) | states::BeforeDoctypeIdentifier(_)
+            | states::DoctypeIdentifierDoubleQuoted(_)
+            | states::DoctypeIdentifierSingleQuoted(_)
+            | states::AfterDoctypeIdentifier(_)
+            | states::BetweenDoctypePublicAndSystemIdentifiers
+            | states::BogusDoctype => DoctypeContext,
+
+        // Raw text, script escapes, CDATA and template passthrough.
+        _ => RawTextContext,
+    }
+}
+
+/// Find out what could go at `cursor`, a byte offset into `input`.
+/// Only the input before the cursor is parsed.
+///
+/// ## Failure
+///
+/// Fails if `cursor` is not at a character boundary.
+pub fn completion_context(input: &str, cursor: uint, mut opts: ParseOpts)
+        -> CompletionContext {
+    // Saves the tree builder from checking for whitespace itself.
+    opts.tokenizer.whitespace_tokens = true;
+
+    let tb = TreeBuilder::new(NameSink { names: vec!(None) }, opts.tree_builder);
+    let mut tok = Tokenizer::new(tb, opts.tokenizer);
+    tok.feed(String::from_str(input.slice_to(cursor)));
+
+    // Don't call `end()`: the document goes on after the cursor.
+    let state = tok.state();
+    let context = cursor_context(state);
+
+    let tag = match (context, state) {
+        (TagNameContext, states::EndTagOpen) | (TagNameContext, states::RawEndTagOpen(_))
+            => Some((EndTag, String::new())),
+        (TagNameContext, _) | (BeforeAttrNameContext, _) | (AttrNameContext, _)
+            | (AfterAttrNameContext, _) | (AttrValueContext, _)
+            => Some((tok.current_tag_kind(), String::from_str(tok.current_tag_name()))),
+        _ => None,
+    };
+
+    let attr_name = match context {
+        AttrNameContext | AfterAttrNameContext | AttrValueContext
+            => Some(String::from_str(tok.current_attr_name())),
+        _ => None,
+    };
+
+    let open_elems = {
+        let tb = tok.sink();
+        tb.open_elems().iter().map(|&h| tb.sink().elem_name(h)).collect()
+    };
+
+    CompletionContext {
+        context: context,
+        tag: tag,
+        attr_name: attr_name,
+        open_elems: open_elems,
+        state: state,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{completion_context, CursorContext, TextContext, RawTextContext};
+    use super::{TagOpenContext, TagNameContext, AttrNameContext, AfterAttrNameContext};
+    use super::{AttrValueContext, CommentContext};
+
+    use tokenizer::{TagKind, StartTag, EndTag};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    fn context(input: &str) -> CursorContext {
+        completion_context(input, input.len(), Default::default()).context
+    }
+
+    fn tag(input: &str) -> Option<(TagKind, String)> {
+        completion_context(input, input.len(), Default::default()).tag
+    }
+
+    fn attr_name(input: &str) -> Option<String> {
+        completion_context(input, input.len(), Default::default()).attr_name
+    }
+
+    fn open_elems(input: &str, cursor: uint) -> Vec<String> {
+        completion_context(input, cursor, Default::default()).open_elems.into_iter()
+            .map(|n| String::from_str(n.local.as_slice())).collect()
+    }
+
+    fn strs(x: &[&str]) -> Vec<String> {
+        x.iter().map(|s| String::from_str(*s)).collect()
+    }
+
+    test_eq!(text, context("<p>abc &am"), TextContext)
+    test_eq!(tag_open, context("<p>abc <"), TagOpenContext)
+    test_eq!(tag_name, context("<p>abc <DI"), TagNameContext)
+    test_eq!(attr_name_ctx, context("<a hr"), AttrNameContext)
+    test_eq!(after_attr_name, context("<a href "), AfterAttrNameContext)
+    test_eq!(attr_value, context("<a href='x"), AttrValueContext)
+    test_eq!(comment, context("<!-- <p"), CommentContext)
+    test_eq!(script, context("<script>if (a <"), RawTextContext)
+
+    test_eq!(start_tag, tag("<p>abc <DI"), Some((StartTag, String::from_str("di"))))
+    test_eq!(end_tag_open, tag("<p>abc</"), Some((EndTag, String::new())))
+    test_eq!(no_tag, tag("<p>abc"), None)
+    test_eq!(attr_tag, tag("<a b=c d"), Some((StartTag, String::from_str("a"))))
+
+    test_eq!(attr_name_so_far, attr_name("<a HR"), Some(String::from_str("hr")))
+    test_eq!(attr_name_in_value, attr_name("<a href=\"x"), Some(String::from_str("href")))
+
+    test_eq!(implied_elems, open_elems("<ul>\n <li>", 10),
+        strs(&["html", "body", "ul", "li"]))
+
+    test_eq!(cursor_in_middle, open_elems("<div><span>x</span></div>", 11),
+        strs(&["html", "body", "div", "span"]))
+
+    test_eq!(in_head, open_elems("<title>a", 8), strs(&["html", "head", "title"]))
+}
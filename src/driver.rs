@@ -8,21 +8,118 @@
 // except according to those terms.
 
 //! High-level interface to the parser.
+//!
+//! These functions will not `fail!()` on any input, provided that the
+//! tree builder's `ignore_missing_rules` option is left enabled and the
+//! `TreeSink` itself doesn't fail.  The external test suite checks this
+//! on the html5lib inputs and on randomly mutated copies of them.
 
 use core::prelude::*;
 
-use tokenizer::{TokenizerOpts, Tokenizer, TokenSink};
-use tree_builder::{TreeBuilderOpts, TreeBuilder, TreeSink};
+use tokenizer::{TokenizerOpts, Tokenizer, TokenSink, Token};
+#[cfg(feature = "tree_builder")]
+use tokenizer::decoder_for;
+#[cfg(feature = "tree_builder")]
+use encoding::prescan;
+use tokenizer::{CharacterTokens, WhitespaceTokens};
+use tokenizer::states::{RawData, Rcdata};
 
-use core::default::Default;
+use core::char::from_u32;
 use core::option;
+use collections::vec::Vec;
 use collections::string::String;
 
+#[cfg(feature = "tree_builder")]
+use tokenizer::{Attribute, Span, TokenPart};
+#[cfg(feature = "tree_builder")]
+use tokenizer::states::State;
+#[cfg(feature = "tree_builder")]
+use tree_builder::{TreeBuilderOpts, TreeBuilder, TreeSink, QuirksMode, NodeOrText};
+#[cfg(feature = "tree_builder")]
+use tree_builder::{ElementKind, TreeBuilderBuffers};
+#[cfg(feature = "tree_builder")]
+use tokenizer::TokenizerBuffers;
+#[cfg(feature = "tree_builder")]
+use tree_builder::Error;
+
+#[cfg(feature = "tree_builder")]
+use core::cell::Cell;
+#[cfg(feature = "tree_builder")]
+use core::mem::replace;
+#[cfg(feature = "tree_builder")]
+use core::default::Default;
+
+#[cfg(feature = "tree_builder")]
+use string_cache::QualName;
+
 /// Convenience function to turn a single `String` into an iterator.
 pub fn one_input(x: String) -> option::Item<String> {
     Some(x).into_iter()
 }
 
+/// Iterator adapter which decodes chunks of UTF-16 into parser input.
+/// See `utf16_input`.
+pub struct Utf16Input<It> {
+    inner: It,
+    pending: Option<u32>,
+    done: bool,
+}
+
+/// Decode chunks of UTF-16 code units, for use as parser input.
+///
+/// A surrogate pair may be split across chunks.  Any surrogate which
+/// isn't part of a pair becomes U+FFFD REPLACEMENT CHARACTER, as
+/// numeric character references to surrogates do, so the parser and
+/// serializer only ever see valid Unicode.
+pub fn utf16_input<It: Iterator<Vec<u16>>>(input: It) -> Utf16Input<It> {
+    Utf16Input {
+        inner: input,
+        pending: None,
+        done: false,
+    }
+}
+
+impl<It> Utf16Input<It> {
+    fn decode(&mut self, units: &[u16]) -> String {
+        let mut out = String::with_capacity(units.len());
+        for &u in units.iter() {
+            let u = u as u32;
+            match self.pending.take() {
+                Some(hi) if u >= 0xDC00 && u <= 0xDFFF => {
+                    let c = 0x10000 + ((hi - 0xD800) << 10) + (u - 0xDC00);
+                    out.push(from_u32(c).expect("bad surrogate pair"));
+                    continue;
+                }
+                Some(_) => out.push('\ufffd'),
+                None => (),
+            }
+
+            match u {
+                0xD800...0xDBFF => self.pending = Some(u),
+                0xDC00...0xDFFF => out.push('\ufffd'),
+                _ => out.push(from_u32(u).expect("not a surrogate")),
+            }
+        }
+        out
+    }
+}
+
+impl<It: Iterator<Vec<u16>>> Iterator<String> for Utf16Input<It> {
+    fn next(&mut self) -> Option<String> {
+        if self.done {
+            return None;
+        }
+        match self.inner.next() {
+            Some(units) => Some(self.decode(units.as_slice())),
+            None => {
+                // A high surrogate at the very end.
+                self.done = true;
+                self.pending.take().map(|_| String::from_char(1, '\ufffd'))
+            }
+        }
+    }
+}
+
 /// Tokenize and send results to a `TokenSink`.
 ///
 /// ## Example
@@ -36,25 +133,211 @@ pub fn tokenize_to<
         It: Iterator<String>
     >(
         sink: Sink,
-        mut input: It,
+        input: It,
         opts: TokenizerOpts) -> Sink {
 
     let mut tok = Tokenizer::new(sink, opts);
+    feed_all(&mut tok, input);
+    tok.unwrap()
+}
+
+// Feed the tokenizer all of the input, then end it.  Nothing here runs
+// scripts, so if the sink suspends tokenizing, resume straight away.
+fn feed_all<Sink: TokenSink, It: Iterator<String>>(tok: &mut Tokenizer<Sink>, mut input: It) {
     for s in input {
         tok.feed(s);
+        resume_all(tok);
+    }
+    tok.end();
+    resume_all(tok);
+}
+
+// Like `feed_all`, for bytes.  A `<meta>` declaration in the first chunk
+// chooses the decoder, unless there's a byte order mark.
+#[cfg(feature = "tree_builder")]
+fn feed_all_bytes<Sink: TokenSink, It: Iterator<Vec<u8>>>(tok: &mut Tokenizer<Sink>,
+                                                         mut input: It) {
+    let mut first = true;
+    for b in input {
+        if first {
+            first = false;
+            match prescan(b.as_slice()).and_then(|e| decoder_for(e)) {
+                Some(decoder) => tok.set_decoder(decoder),
+                None => (),
+            }
+        }
+        tok.feed_bytes(b);
+        resume_all(tok);
     }
     tok.end();
+    resume_all(tok);
+}
+
+fn resume_all<Sink: TokenSink>(tok: &mut Tokenizer<Sink>) {
+    while tok.is_suspended() {
+        tok.resume();
+    }
+}
+
+/// Tokenize `len` bytes of `buffer` starting at `start`, and send
+/// results to a `TokenSink`.  Spans are offsets into the whole of
+/// `buffer`, plus the `span_offset` option.  The `track_spans` option
+/// is always turned on.
+///
+/// The buffer is tokenized in place, without copying the region.
+///
+/// ## Example
+///
+/// ```rust
+/// let mut sink = MySink;
+/// tokenize_region_to(&mut sink, email_body, start, len, Default::default());
+/// ```
+///
+/// ## Failure
+///
+/// Fails if the region doesn't start and end at character boundaries.
+pub fn tokenize_region_to<Sink: TokenSink>(
+        sink: Sink,
+        mut buffer: String,
+        start: uint,
+        len: uint,
+        mut opts: TokenizerOpts) -> Sink {
+
+    opts.track_spans = true;
+    opts.span_offset += start;
+    buffer.truncate(start + len);
+    let mut tok = Tokenizer::new(sink, opts);
+    tok.feed_from(buffer, start);
+    // The region was the whole of the input.
+    feed_all(&mut tok, None.into_iter());
     tok.unwrap()
 }
 
+// Collects the text of an RCDATA fragment.
+struct RcdataText {
+    text: String,
+}
+
+impl TokenSink for RcdataText {
+    fn process_token(&mut self, token: Token) {
+        match token {
+            CharacterTokens(b) | WhitespaceTokens(b) => self.text.push_str(b.as_slice()),
+            _ => (),
+        }
+    }
+}
+
+/// Parse a fragment in the context of a `<title>` or `<textarea>`
+/// element, and return its text.
+///
+/// These are RCDATA elements: character references are decoded, but
+/// tags are not recognized.  Because the context element's end tag is
+/// never "appropriate" in a fragment, `</title>` is text too.  This is
+/// what setting `innerHTML` on such an element would produce.
+pub fn parse_rcdata_fragment<It: Iterator<String>>(
+        mut input: It,
+        mut opts: TokenizerOpts) -> String {
+
+    opts.initial_state = Some(RawData(Rcdata));
+    opts.last_start_tag_name = None;
+
+    // The text is about as long as the input.
+    let text = String::with_capacity(opts.input_size_hint.unwrap_or(0));
+    let mut tok = Tokenizer::new(RcdataText { text: text }, opts);
+    for s in input {
+        tok.feed(s);
+    }
+    tok.end();
+    tok.unwrap().text
+}
+
 /// All-encompassing options struct for the parser.
+#[cfg(feature = "tree_builder")]
 #[deriving(Clone, Default)]
 pub struct ParseOpts {
     /// Tokenizer options.
+    ///
+    /// The parse functions always turn on `whitespace_tokens`, which
+    /// saves the tree builder from checking for whitespace itself and
+    /// doesn't change the tree.  The fragment parsers also set
+    /// `initial_state` from the context element, and clear
+    /// `last_start_tag_name`.
     pub tokenizer: TokenizerOpts,
 
     /// Tree builder options.
     pub tree_builder: TreeBuilderOpts,
+
+    /// Roughly how many bytes of input to expect, if known.  This sets
+    /// the tokenizer's option of the same name, and is passed to
+    /// `TreeSink::input_size_hint` so that the sink can size its storage
+    /// up front.  Default: None, or the tokenizer's option if that's set
+    pub input_size_hint: Option<uint>,
+}
+
+#[cfg(feature = "tree_builder")]
+impl ParseOpts {
+    /// Start building options from the defaults, like
+    /// `TokenizerOpts::builder`.
+    pub fn builder() -> ParseOptsBuilder {
+        ParseOptsBuilder {
+            opts: Default::default(),
+        }
+    }
+}
+
+/// Builds `ParseOpts`.
+#[cfg(feature = "tree_builder")]
+#[deriving(Clone)]
+pub struct ParseOptsBuilder {
+    opts: ParseOpts,
+}
+
+#[cfg(feature = "tree_builder")]
+impl ParseOptsBuilder {
+    pub fn tokenizer(mut self, opts: TokenizerOpts) -> ParseOptsBuilder {
+        self.opts.tokenizer = opts;
+        self
+    }
+
+    pub fn tree_builder(mut self, opts: TreeBuilderOpts) -> ParseOptsBuilder {
+        self.opts.tree_builder = opts;
+        self
+    }
+
+    pub fn input_size_hint(mut self, bytes: uint) -> ParseOptsBuilder {
+        self.opts.input_size_hint = Some(bytes);
+        self
+    }
+
+    pub fn build(self) -> ParseOpts {
+        self.opts
+    }
+}
+
+/// Information about how a document was interpreted.
+#[cfg(feature = "tree_builder")]
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct DocumentMetadata {
+    /// The character encoding the input was decoded from, as in
+    /// `tokenizer::decoder_for`, or `None` if the input was already
+    /// Unicode.
+    pub encoding_used: Option<String>,
+
+    /// The document's quirks mode.
+    pub quirks_mode: QuirksMode,
+
+    /// The number of parse errors reported.
+    pub errors_count: uint,
+
+    /// Was any content truncated by the length limits in
+    /// `TokenizerOpts`?
+    pub truncated: bool,
+
+    /// Did the input seem to be cut off, ending inside a tag, comment
+    /// or doctype, or with elements open which need an end tag?  Set
+    /// the `truncated_input` options to report this instead of parse
+    /// errors.
+    pub eof_truncated: bool,
 }
 
 /// Parse and send results to a `TreeSink`.
@@ -65,28 +348,127 @@ pub struct ParseOpts {
 /// let mut sink = MySink;
 /// parse_to(&mut sink, one_input(my_str), Default::default());
 /// ```
+#[cfg(feature = "tree_builder")]
 pub fn parse_to<
         Handle: Clone,
         Sink: TreeSink<Handle>,
         It: Iterator<String>
     >(
         sink: Sink,
-        mut input: It,
+        input: It,
         opts: ParseOpts) -> Sink {
 
-    let tb = TreeBuilder::new(sink, opts.tree_builder);
-    let mut tok = Tokenizer::new(tb, opts.tokenizer);
-    for s in input {
-        tok.feed(s);
+    let (sink, _) = parse_to_with_metadata(sink, input, opts);
+    sink
+}
+
+/// Like `parse_to`, but also return `DocumentMetadata`.
+#[cfg(feature = "tree_builder")]
+pub fn parse_to_with_metadata<
+        Handle: Clone,
+        Sink: TreeSink<Handle>,
+        It: Iterator<String>
+    >(
+        sink: Sink,
+        mut input: It,
+        opts: ParseOpts) -> (Sink, DocumentMetadata) {
+
+    parse_with(sink, opts, &mut Pool::new(), |tok| feed_all(tok, input.by_ref()))
+}
+
+/// Like `parse_to_with_metadata`, but for bytes in any encoding the
+/// tokenizer has a decoder for.  A byte order mark decides the encoding,
+/// or else a `<meta>` declaration in the first chunk, as found by
+/// `encoding::prescan`.  The default is UTF-8.  The encoding chosen is
+/// returned as `encoding_used`.
+#[cfg(feature = "tree_builder")]
+pub fn parse_bytes_to_with_metadata<
+        Handle: Clone,
+        Sink: TreeSink<Handle>,
+        It: Iterator<Vec<u8>>
+    >(
+        sink: Sink,
+        mut input: It,
+        opts: ParseOpts) -> (Sink, DocumentMetadata) {
+
+    parse_with(sink, opts, &mut Pool::new(), |tok| feed_all_bytes(tok, input.by_ref()))
+}
+
+// What `BatchParser` keeps from one document for the next.  A new
+// `Pool` is empty, and allocates nothing.
+#[cfg(feature = "tree_builder")]
+struct Pool<Handle> {
+    tokenizer: Option<TokenizerBuffers>,
+    tree_builder: Option<TreeBuilderBuffers<Handle>>,
+
+    // The tokenizer's `template_delimiters` option, which is moved
+    // into each document's options rather than cloned.
+    template_delimiters: Vec<(String, String)>,
+}
+
+#[cfg(feature = "tree_builder")]
+impl<Handle> Pool<Handle> {
+    fn new() -> Pool<Handle> {
+        Pool {
+            tokenizer: None,
+            tree_builder: None,
+            template_delimiters: vec!(),
+        }
+    }
+}
+
+#[cfg(feature = "tree_builder")]
+fn parse_with<Handle: Clone, Sink: TreeSink<Handle>>(
+        mut sink: Sink,
+        mut opts: ParseOpts,
+        pool: &mut Pool<Handle>,
+        feed: |&mut Tokenizer<TreeBuilder<Handle, Sink>>|) -> (Sink, DocumentMetadata) {
+
+    // Saves the tree builder from checking for whitespace itself.
+    opts.tokenizer.whitespace_tokens = true;
+    apply_size_hint(&mut sink, &mut opts);
+
+    let tb_buffers = pool.tree_builder.take().unwrap_or_else(|| TreeBuilderBuffers::new());
+    let tb = TreeBuilder::new_with_buffers(sink, opts.tree_builder, tb_buffers);
+    let tok_buffers = pool.tokenizer.take().unwrap_or_else(|| TokenizerBuffers::new());
+    let mut tok = Tokenizer::new_with_buffers(tb, opts.tokenizer, tok_buffers);
+    feed(&mut tok);
+
+    let truncated = tok.truncated();
+    let tok_eof_truncated = tok.eof_truncated();
+    let encoding_used = tok.encoding_used().map(|e| String::from_str(e));
+    let (tb, tok_opts, tok_buffers) = tok.unwrap_with_buffers();
+    let metadata = DocumentMetadata {
+        encoding_used: encoding_used,
+        quirks_mode: tb.quirks_mode(),
+        errors_count: tb.errors_count(),
+        truncated: truncated,
+        eof_truncated: tok_eof_truncated || tb.eof_truncated(),
+    };
+    let (sink, tb_buffers) = tb.unwrap_with_buffers();
+    pool.tokenizer = Some(tok_buffers);
+    pool.tree_builder = Some(tb_buffers);
+    pool.template_delimiters = tok_opts.template_delimiters;
+    (sink, metadata)
+}
+
+// Pass the `input_size_hint` option on to the tokenizer and the sink.
+#[cfg(feature = "tree_builder")]
+fn apply_size_hint<Handle, Sink: TreeSink<Handle>>(sink: &mut Sink, opts: &mut ParseOpts) {
+    match opts.input_size_hint.or(opts.tokenizer.input_size_hint) {
+        Some(bytes) => {
+            opts.tokenizer.input_size_hint = Some(bytes);
+            sink.input_size_hint(bytes);
+        }
+        None => (),
     }
-    tok.end();
-    tok.unwrap().unwrap()
 }
 
 /// Results which can be extracted from a `TreeSink`.
 ///
 /// Implement this for your parse tree data type so that it
 /// can be returned by `parse()`.
+#[cfg(feature = "tree_builder")]
 pub trait ParseResult<Sink> {
     fn get_result(sink: Sink) -> Self;
 }
@@ -98,6 +480,7 @@ pub trait ParseResult<Sink> {
 /// ```rust
 /// let dom: RcDom = parse(one_input(my_str), Default::default());
 /// ```
+#[cfg(feature = "tree_builder")]
 pub fn parse<
         Handle: Clone,
         Sink: Default + TreeSink<Handle>,
@@ -110,3 +493,869 @@ pub fn parse<
     let sink = parse_to(Default::default(), input, opts);
     ParseResult::get_result(sink)
 }
+
+/// Like `parse`, but also return `DocumentMetadata`.
+#[cfg(feature = "tree_builder")]
+pub fn parse_with_metadata<
+        Handle: Clone,
+        Sink: Default + TreeSink<Handle>,
+        Output: ParseResult<Sink>,
+        It: Iterator<String>
+    >(
+        input: It,
+        opts: ParseOpts) -> (Output, DocumentMetadata) {
+
+    let (sink, metadata) = parse_to_with_metadata(Default::default(), input, opts);
+    (ParseResult::get_result(sink), metadata)
+}
+
+/// Like `parse_with_metadata`, but for bytes.  See
+/// `parse_bytes_to_with_metadata`.
+#[cfg(feature = "tree_builder")]
+pub fn parse_bytes_with_metadata<
+        Handle: Clone,
+        Sink: Default + TreeSink<Handle>,
+        Output: ParseResult<Sink>,
+        It: Iterator<Vec<u8>>
+    >(
+        input: It,
+        opts: ParseOpts) -> (Output, DocumentMetadata) {
+
+    let (sink, metadata) = parse_bytes_to_with_metadata(Default::default(), input, opts);
+    (ParseResult::get_result(sink), metadata)
+}
+
+/// Parses many documents one after another with the same options, as
+/// a crawler does.
+///
+/// Interned atoms and the character reference table are already shared
+/// by every parse in the process.  The batch keeps the buffers which the
+/// tokenizer and tree builder grow, such as the input queue and the
+/// stack of open elements, and hands them on to the next document, so
+/// that once they're big enough, parsing another document doesn't grow
+/// them again.  They're emptied in between, and each document gets a
+/// fresh tokenizer and tree builder around them, so nothing from one
+/// document, such as an unclosed `<script>` or the quirks mode, carries
+/// over to the next.
+///
+/// ## Example
+///
+/// ```rust
+/// let mut batch = BatchParser::new(Default::default());
+/// for page in pages {
+///     let (dom, metadata): (RcDom, DocumentMetadata)
+///         = batch.parse(one_input(page));
+/// }
+/// ```
+#[cfg(feature = "tree_builder")]
+pub struct BatchParser<Handle> {
+    // The options, less `template_delimiters`, which are in `pool`.
+    opts: ParseOpts,
+    pool: Pool<Handle>,
+    documents: uint,
+}
+
+#[cfg(feature = "tree_builder")]
+impl<Handle: Clone> BatchParser<Handle> {
+    pub fn new(mut opts: ParseOpts) -> BatchParser<Handle> {
+        let mut pool = Pool::new();
+        pool.template_delimiters = replace(&mut opts.tokenizer.template_delimiters, vec!());
+        BatchParser {
+            opts: opts,
+            pool: pool,
+            documents: 0,
+        }
+    }
+
+    /// Parse the next document, like `parse_to_with_metadata`.
+    pub fn parse_to<
+            Sink: TreeSink<Handle>,
+            It: Iterator<String>
+        >(
+            &mut self,
+            sink: Sink,
+            mut input: It) -> (Sink, DocumentMetadata) {
+
+        self.documents += 1;
+        let mut opts = self.opts.clone();
+        opts.tokenizer.template_delimiters = replace(&mut self.pool.template_delimiters, vec!());
+        parse_with(sink, opts, &mut self.pool, |tok| feed_all(tok, input.by_ref()))
+    }
+
+    /// Parse the next document, like `parse_with_metadata`.
+    pub fn parse<
+            Sink: Default + TreeSink<Handle>,
+            Output: ParseResult<Sink>,
+            It: Iterator<String>
+        >(
+            &mut self,
+            input: It) -> (Output, DocumentMetadata) {
+
+        let (sink, metadata) = self.parse_to(Default::default(), input);
+        (ParseResult::get_result(sink), metadata)
+    }
+
+    /// How many documents have been parsed?
+    pub fn documents(&self) -> uint {
+        self.documents
+    }
+}
+
+/// Parse a fragment of HTML as if it were the content of an element
+/// named `context`, and send results to a `TreeSink`.  This is the
+/// fragment parsing algorithm used by `innerHTML`.
+///
+/// The document gets a single `<html>` element, and the fragment's
+/// nodes are the children of that.  The context element itself is
+/// created with the sink but never added to the tree.
+///
+/// ## Example
+///
+/// ```rust
+/// let sink = parse_fragment_to(MySink::new(), one_input(my_str), qualname!(HTML, td),
+///     Default::default());
+/// ```
+#[cfg(feature = "tree_builder")]
+pub fn parse_fragment_to<
+        Handle: Clone,
+        Sink: TreeSink<Handle>,
+        It: Iterator<String>
+    >(
+        mut sink: Sink,
+        input: It,
+        context: QualName,
+        mut opts: ParseOpts) -> Sink {
+
+    opts.tokenizer.whitespace_tokens = true;
+
+    let context_elem = sink.create_element(context, vec!());
+    let tb = TreeBuilder::new_for_fragment(sink, context_elem, None, opts.tree_builder);
+    opts.tokenizer.initial_state = Some(tb.tokenizer_state_for_context_elem());
+    opts.tokenizer.last_start_tag_name = None;
+
+    let mut tok = Tokenizer::new(tb, opts.tokenizer);
+    feed_all(&mut tok, input);
+    tok.unwrap().unwrap()
+}
+
+/// Parse a fragment into a type which implements `ParseResult`.  See
+/// `parse_fragment_to`.
+///
+/// ## Example
+///
+/// ```rust
+/// let dom: RcDom = parse_fragment(one_input(my_str), qualname!(HTML, td), Default::default());
+/// ```
+#[cfg(feature = "tree_builder")]
+pub fn parse_fragment<
+        Handle: Clone,
+        Sink: Default + TreeSink<Handle>,
+        Output: ParseResult<Sink>,
+        It: Iterator<String>
+    >(
+        input: It,
+        context: QualName,
+        opts: ParseOpts) -> Output {
+
+    let sink = parse_fragment_to(Default::default(), input, context, opts);
+    ParseResult::get_result(sink)
+}
+
+/// Time spent in each phase of parsing a document, in nanoseconds.
+#[cfg(feature = "tree_builder")]
+#[deriving(Clone, PartialEq, Eq, Default, Show)]
+pub struct ParseTimings {
+    /// Decoding input bytes to Unicode.  Zero when the input is
+    /// already a sequence of `String`s.
+    pub decode_ns: u64,
+
+    /// The tokenizer, not counting the tree builder or sink.
+    pub tokenize_ns: u64,
+
+    /// The tree builder, not counting the sink.
+    pub tree_build_ns: u64,
+
+    /// The `TreeSink`.
+    pub sink_ns: u64,
+}
+
+// Times calls into the tree builder.
+#[cfg(feature = "tree_builder")]
+struct TimedTokenSink<Sink> {
+    inner: Sink,
+    ns: u64,
+}
+
+#[cfg(feature = "tree_builder")]
+impl<Sink: TokenSink> TokenSink for TimedTokenSink<Sink> {
+    fn process_token(&mut self, token: Token) {
+        let (_, dt) = time!(self.inner.process_token(token));
+        self.ns += dt;
+    }
+
+    fn process_token_with_span(&mut self, token: Token, span: Span) {
+        let (_, dt) = time!(self.inner.process_token_with_span(token, span));
+        self.ns += dt;
+    }
+
+    fn process_token_part(&mut self, part: TokenPart, span: Span) {
+        self.inner.process_token_part(part, span);
+    }
+
+    fn query_state_change(&mut self) -> Option<State> {
+        self.inner.query_state_change()
+    }
+
+    fn query_suspend(&mut self) -> bool {
+        self.inner.query_suspend()
+    }
+
+    fn adjusted_current_node_is_foreign(&self) -> bool {
+        self.inner.adjusted_current_node_is_foreign()
+    }
+}
+
+// Times calls into the tree sink.  The counter is a `Cell` because
+// some `TreeSink` methods take `&self`.
+#[cfg(feature = "tree_builder")]
+struct TimedTreeSink<Sink> {
+    inner: Sink,
+    ns: Cell<u64>,
+}
+
+#[cfg(feature = "tree_builder")]
+impl<Sink> TimedTreeSink<Sink> {
+    fn add(&self, dt: u64) {
+        self.ns.set(self.ns.get() + dt);
+    }
+}
+
+#[cfg(feature = "tree_builder")]
+impl<Handle, Sink: TreeSink<Handle>> TreeSink<Handle> for TimedTreeSink<Sink> {
+    fn parse_error(&mut self, err: Error) {
+        let (_, dt) = time!(self.inner.parse_error(err));
+        self.add(dt);
+    }
+
+    fn get_document(&mut self) -> Handle {
+        let (r, dt) = time!(self.inner.get_document());
+        self.add(dt);
+        r
+    }
+
+    fn same_node(&self, x: Handle, y: Handle) -> bool {
+        let (r, dt) = time!(self.inner.same_node(x, y));
+        self.add(dt);
+        r
+    }
+
+    fn elem_name(&self, target: Handle) -> QualName {
+        let (r, dt) = time!(self.inner.elem_name(target));
+        self.add(dt);
+        r
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        let (_, dt) = time!(self.inner.set_quirks_mode(mode));
+        self.add(dt);
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle {
+        let (r, dt) = time!(self.inner.create_element(name, attrs));
+        self.add(dt);
+        r
+    }
+
+    fn create_element_of_kind(&mut self, kind: ElementKind, is: Option<String>,
+            name: QualName, attrs: Vec<Attribute>) -> Handle {
+        let (r, dt) = time!(self.inner.create_element_of_kind(kind, is, name, attrs));
+        self.add(dt);
+        r
+    }
+
+    fn create_comment(&mut self, text: String) -> Handle {
+        let (r, dt) = time!(self.inner.create_comment(text));
+        self.add(dt);
+        r
+    }
+
+    fn get_template_contents(&mut self, target: Handle) -> Handle {
+        let (r, dt) = time!(self.inner.get_template_contents(target));
+        self.add(dt);
+        r
+    }
+
+    fn append(&mut self, parent: Handle, child: NodeOrText<Handle>) {
+        let (_, dt) = time!(self.inner.append(parent, child));
+        self.add(dt);
+    }
+
+    fn append_before_sibling(&mut self,
+            sibling: Handle,
+            new_node: NodeOrText<Handle>) -> Result<(), NodeOrText<Handle>> {
+        let (r, dt) = time!(self.inner.append_before_sibling(sibling, new_node));
+        self.add(dt);
+        r
+    }
+
+    fn append_based_on_parent_node(&mut self,
+            element: Handle,
+            prev_element: Handle,
+            child: NodeOrText<Handle>) {
+        let (_, dt) = time!(self.inner.append_based_on_parent_node(element, prev_element, child));
+        self.add(dt);
+    }
+
+    fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String) {
+        let (_, dt) = time!(self.inner.append_doctype_to_document(name, public_id, system_id));
+        self.add(dt);
+    }
+
+    fn add_attrs_if_missing(&mut self, target: Handle, attrs: Vec<Attribute>) {
+        let (_, dt) = time!(self.inner.add_attrs_if_missing(target, attrs));
+        self.add(dt);
+    }
+
+    fn remove_from_parent(&mut self, target: Handle) {
+        let (_, dt) = time!(self.inner.remove_from_parent(target));
+        self.add(dt);
+    }
+
+    fn reparent_children(&mut self, node: Handle, new_parent: Handle) {
+        let (_, dt) = time!(self.inner.reparent_children(node, new_parent));
+        self.add(dt);
+    }
+
+    fn mark_script_already_started(&mut self, node: Handle) {
+        let (_, dt) = time!(self.inner.mark_script_already_started(node));
+        self.add(dt);
+    }
+
+    fn complete_script(&mut self, node: Handle) {
+        let (_, dt) = time!(self.inner.complete_script(node));
+        self.add(dt);
+    }
+
+    fn input_size_hint(&mut self, bytes: uint) {
+        let (_, dt) = time!(self.inner.input_size_hint(bytes));
+        self.add(dt);
+    }
+
+    fn account_attrs(&mut self, name: &QualName, bytes: uint) {
+        let (_, dt) = time!(self.inner.account_attrs(name, bytes));
+        self.add(dt);
+    }
+
+    fn account_text(&mut self, parent: Handle, bytes: uint) {
+        let (_, dt) = time!(self.inner.account_text(parent, bytes));
+        self.add(dt);
+    }
+
+    fn set_text_span(&mut self, parent: Handle, span: Span) {
+        let (_, dt) = time!(self.inner.set_text_span(parent, span));
+        self.add(dt);
+    }
+
+    fn set_node_span(&mut self, node: Handle, span: Span) {
+        let (_, dt) = time!(self.inner.set_node_span(node, span));
+        self.add(dt);
+    }
+
+    fn set_end_span(&mut self, elem: Handle, span: Span) {
+        let (_, dt) = time!(self.inner.set_end_span(elem, span));
+        self.add(dt);
+    }
+}
+
+/// Like `parse_to`, but also measure the time spent in each phase.
+///
+/// Timing every call into the tree builder and sink has a small
+/// cost, so use `parse_to` when you don't need the numbers.
+#[cfg(feature = "tree_builder")]
+pub fn parse_to_timed<
+        Handle: Clone,
+        Sink: TreeSink<Handle>,
+        It: Iterator<String>
+    >(
+        sink: Sink,
+        input: It,
+        mut opts: ParseOpts) -> (Sink, ParseTimings) {
+
+    opts.tokenizer.whitespace_tokens = true;
+
+    let mut sink = TimedTreeSink { inner: sink, ns: Cell::new(0) };
+    apply_size_hint(&mut sink, &mut opts);
+    let tb = TimedTokenSink {
+        inner: TreeBuilder::new(sink, opts.tree_builder),
+        ns: 0,
+    };
+
+    // Don't count the sink calls made while creating the tree builder.
+    tb.inner.sink().ns.set(0);
+
+    let mut tok = Tokenizer::new(tb, opts.tokenizer);
+    let (_, total) = time!(feed_all(&mut tok, input));
+
+    let tb = tok.unwrap();
+    let sink = tb.inner.unwrap();
+    let sink_ns = sink.ns.get();
+    let timings = ParseTimings {
+        decode_ns: 0,
+        tokenize_ns: total - tb.ns,
+        tree_build_ns: tb.ns - sink_ns,
+        sink_ns: sink_ns,
+    };
+    (sink.inner, timings)
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use collections::vec::Vec;
+    use collections::string::String;
+    use core::default::Default;
+
+    use super::{parse_rcdata_fragment, one_input, utf16_input};
+    use tokenizer::TokenizerOpts;
+
+    #[cfg(feature = "tree_builder")]
+    use super::{ParseOpts, DocumentMetadata, BatchParser, parse, parse_with_metadata};
+    #[cfg(feature = "tree_builder")]
+    use super::parse_bytes_with_metadata;
+    #[cfg(feature = "tree_builder")]
+    use super::parse_fragment;
+    #[cfg(feature = "tree_builder")]
+    use sink::rcdom::RcDom;
+    #[cfg(feature = "tree_builder")]
+    use sink::common::{Element, Text};
+    #[cfg(feature = "tree_builder")]
+    use serialize::serialize;
+    #[cfg(feature = "tree_builder")]
+    use std::io::MemWriter;
+    #[cfg(feature = "tree_builder")]
+    use string_cache::QualName;
+    #[cfg(feature = "tree_builder")]
+    use tokenizer::Tokenizer;
+    #[cfg(feature = "tree_builder")]
+    use super::apply_size_hint;
+    #[cfg(feature = "tree_builder")]
+    use tree_builder::{TreeBuilder, TreeBuilderOpts};
+    #[cfg(feature = "tree_builder")]
+    use tree_builder::{TokenizerErr, TreeBuilderErr, NoElementToClose};
+    #[cfg(feature = "tree_builder")]
+    use tokenizer::{DuplicateAttribute, Severity, Informational, Recoverable, Fatal};
+    #[cfg(feature = "tree_builder")]
+    use super::parse_to;
+    #[cfg(feature = "tree_builder")]
+    use tree_builder::{TreeSink, NodeOrText, QuirksMode, Error};
+    #[cfg(feature = "tree_builder")]
+    use tokenizer::Attribute;
+
+    fn rcdata(s: &str) -> String {
+        parse_rcdata_fragment(one_input(String::from_str(s)), Default::default())
+    }
+
+    fn utf16(chunks: Vec<Vec<u16>>) -> String {
+        parse_rcdata_fragment(utf16_input(chunks.into_iter()), Default::default())
+    }
+
+    test_eq!(rcdata_decodes_entities, rcdata("a &amp; b &lt;").as_slice(), "a & b <")
+    test_eq!(rcdata_ignores_tags, rcdata("<b>x</b>").as_slice(), "<b>x</b>")
+    test_eq!(rcdata_end_tag_is_text, rcdata("x</title>y").as_slice(), "x</title>y")
+    test_eq!(rcdata_no_comments, rcdata("<!--x-->").as_slice(), "<!--x-->")
+    test_eq!(rcdata_newlines, rcdata("a\r\nb").as_slice(), "a\nb")
+    test_eq!(rcdata_longest_name, rcdata("&notin; &notit; &not &amp").as_slice(),
+        "\u2209 \u00acit; \u00ac &")
+    test_eq!(rcdata_two_code_points, rcdata("&NotEqualTilde;").as_slice(), "\u2242\u0338")
+    test_eq!(rcdata_numeric_refs, rcdata("&#65;&#x42&#X43;&#x80;&#150;&#x81;&#0;&#x110000;\
+        &#99999999999;&#x1F;&#;&#x;").as_slice(),
+        "ABC\u20ac\u2013\u0081\ufffd\ufffd\ufffd\x1f&#;&#x;")
+    test_eq!(rcdata_surrogate_ref, rcdata("&#xD800;&#56320;").as_slice(), "\ufffd\ufffd")
+    test_eq!(rcdata_size_hint, parse_rcdata_fragment(one_input(String::from_str("a &amp; b")),
+        TokenizerOpts::builder().input_size_hint(9).build()).as_slice(), "a & b")
+
+    #[cfg(feature = "tree_builder")]
+    fn metadata(s: &str, truncated_input: bool) -> DocumentMetadata {
+        let mut opts: ParseOpts = Default::default();
+        opts.tokenizer.truncated_input = truncated_input;
+        opts.tree_builder.truncated_input = truncated_input;
+        let (_, metadata): (RcDom, DocumentMetadata)
+            = parse_with_metadata(one_input(String::from_str(s)), opts);
+        metadata
+    }
+
+    #[cfg(feature = "tree_builder")]
+    fn encoding_used(bytes: &[u8]) -> Option<String> {
+        let (_, metadata): (RcDom, DocumentMetadata)
+            = parse_bytes_with_metadata(Some(bytes.to_vec()).into_iter(), Default::default());
+        metadata.encoding_used
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn encoding() {
+        assert_eq!(metadata("<p>caf\u00e9", false).encoding_used, None);
+        assert_eq!(encoding_used(b"<p>caf\xc3\xa9"), Some(String::from_str("utf-8")));
+        assert_eq!(encoding_used(b"\xff\xfe<\x00p\x00>\x00"),
+            Some(String::from_str("utf-16le")));
+        assert_eq!(encoding_used(b"<meta charset=windows-1252><p>\xe9"),
+            Some(String::from_str("windows-1252")));
+        assert_eq!(encoding_used(b"\xef\xbb\xbf<meta charset=windows-1252>"),
+            Some(String::from_str("utf-8")));
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn complete_document() {
+        let m = metadata("<!DOCTYPE html><p>a<li>b", false);
+        assert!(!m.eof_truncated);
+        assert_eq!(m.errors_count, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn cut_off_in_tag() {
+        let m = metadata("<!DOCTYPE html><p><a href='x", false);
+        assert!(m.eof_truncated);
+        assert!(m.errors_count > 0);
+
+        let m = metadata("<!DOCTYPE html><p><a href='x", true);
+        assert!(m.eof_truncated);
+        assert_eq!(m.errors_count, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn cut_off_in_element() {
+        let m = metadata("<!DOCTYPE html><div><span>x", false);
+        assert!(m.eof_truncated);
+        assert!(m.errors_count > 0);
+
+        let m = metadata("<!DOCTYPE html><div><span>x", true);
+        assert!(m.eof_truncated);
+        assert_eq!(m.errors_count, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn other_eof_errors_not_truncation() {
+        // A missing DOCTYPE is only noticed at EOF here.
+        let m = metadata("", false);
+        assert!(!m.eof_truncated);
+        assert_eq!(m.errors_count, 1);
+
+        let m = metadata("<!DOCTYPE html><script>x", false);
+        assert!(m.eof_truncated);
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn other_errors_kept() {
+        let m = metadata("<!DOCTYPE html></x><div>", true);
+        assert!(m.eof_truncated);
+        assert_eq!(m.errors_count, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn structured_errors() {
+        let dom: RcDom = parse(one_input(String::from_str("<!DOCTYPE html><p a a>x</p></p>")),
+            Default::default());
+        assert_eq!(dom.errors.len(), 2u);
+        match dom.errors[0] {
+            TokenizerErr(ref e) => assert_eq!(e.code, DuplicateAttribute),
+            ref e => fail!("expected a tokenizer error, got {}", e),
+        }
+        match dom.errors[1] {
+            TreeBuilderErr(ref e) => {
+                assert_eq!(e.code, NoElementToClose(atom!(p)));
+                assert_eq!(e.span, None);
+                assert_eq!(e.context, None);
+            }
+            ref e => fail!("expected a tree builder error, got {}", e),
+        }
+        assert_eq!(dom.errors[1].summary(), "No matching tag to close");
+        assert_eq!(dom.errors[1].message().as_slice(), "No <p> tag to close");
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn exact_error_context() {
+        let mut opts: ParseOpts = Default::default();
+        opts.tree_builder.exact_errors = true;
+        let dom: RcDom = parse(one_input(String::from_str(
+            "<!DOCTYPE html><div><b>x</li></b></div>")), opts);
+        assert_eq!(dom.errors.len(), 1u);
+        match dom.errors[0] {
+            TreeBuilderErr(ref e) => {
+                assert_eq!(e.code, NoElementToClose(atom!(li)));
+                assert_eq!(e.context, Some(String::from_str(
+                    "insertion mode InBody, open elements: html body div b")));
+            }
+            ref e => fail!("expected a tree builder error, got {}", e),
+        }
+        assert_eq!(dom.errors[0].message().as_slice(),
+            "No <li> tag to close (insertion mode InBody, open elements: html body div b)");
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn tree_builder_error_severity() {
+        let severities = |min| {
+            let mut opts: ParseOpts = Default::default();
+            opts.tree_builder.min_error_severity = min;
+            let dom: RcDom = parse(one_input(String::from_str("<!DOCTYPE html><p a a>x</p></p><b>y")),
+                opts);
+            dom.errors.iter().map(|e| e.severity()).collect::<Vec<Severity>>()
+        };
+        assert_eq!(severities(Some(Informational)), vec!(Recoverable, Recoverable, Fatal));
+        assert_eq!(severities(Some(Fatal)), vec!(Fatal));
+        assert_eq!(severities(None), vec!());
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn input_size_hint() {
+        let serialized = |opts: ParseOpts| {
+            let dom: RcDom = parse(one_input(String::from_str(
+                "<!DOCTYPE html>\n<p>a\n<b>b</b>\n")), opts);
+            let mut out = MemWriter::new();
+            serialize(&mut out, &dom.document, Default::default()).unwrap();
+            String::from_utf8(out.unwrap()).unwrap()
+        };
+        let mut hinted = ParseOpts::builder().input_size_hint(1 << 20).build();
+        hinted.tokenizer.track_lines = true;
+        assert_eq!(serialized(hinted), serialized(Default::default()));
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn input_size_hint_saves_allocations() {
+        let input = "<p title='one &amp; two &amp; three'>a</p>";
+        let allocations = |opts: ParseOpts| {
+            let (mut sink, mut opts) = (RcDom::default(), opts);
+            apply_size_hint(&mut sink, &mut opts);
+            let mut tok = Tokenizer::new(TreeBuilder::new(sink, opts.tree_builder),
+                opts.tokenizer);
+            tok.feed(String::from_str(input));
+            tok.end();
+            tok.allocations()
+        };
+        let hinted = ParseOpts::builder().input_size_hint(input.len()).build();
+        assert!(allocations(hinted) < allocations(Default::default()));
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn batch_isolates_documents() {
+        let mut batch = BatchParser::new(Default::default());
+        let (_, m): (RcDom, DocumentMetadata)
+            = batch.parse(one_input(String::from_str("<script>x")));
+        assert!(m.errors_count > 0);
+
+        let (dom, m): (RcDom, DocumentMetadata)
+            = batch.parse(one_input(String::from_str("<!DOCTYPE html><p>a")));
+        assert_eq!(m.errors_count, 0);
+        assert!(!m.eof_truncated);
+        assert_eq!(dom.body().unwrap().borrow().children.len(), 1);
+        assert_eq!(batch.documents(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn batch_reuses_buffers() {
+        let mut opts: ParseOpts = Default::default();
+        opts.tokenizer.template_delimiters
+            = vec!((String::from_str("{{"), String::from_str("}}")));
+        let mut batch = BatchParser::new(opts);
+        assert!(batch.pool.tokenizer.is_none());
+        assert!(batch.pool.tree_builder.is_none());
+
+        // The delimiters are handed to each document and back again.
+        for _ in range(0u, 2) {
+            let (dom, _): (RcDom, DocumentMetadata)
+                = batch.parse(one_input(String::from_str("<p>{{<b>}}</p>")));
+            let mut out = MemWriter::new();
+            serialize(&mut out, &dom.body().unwrap(), Default::default()).unwrap();
+            assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(),
+                "<p>{{&lt;b&gt;}}</p>");
+            assert!(batch.pool.tokenizer.is_some());
+            assert!(batch.pool.tree_builder.is_some());
+            assert_eq!(batch.pool.template_delimiters.len(), 1);
+            assert!(batch.opts.tokenizer.template_delimiters.is_empty());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn suspend_after_scripts() {
+        let tb = TreeBuilder::new(RcDom::default(), TreeBuilderOpts {
+            suspend_after_scripts: true,
+            .. Default::default()
+        });
+        let mut tok = Tokenizer::new(tb, Default::default());
+        tok.feed(String::from_str("<script>w()</script><p>a<svg><script/></svg>b"));
+        assert!(tok.is_suspended());
+        tok.insert(String::from_str("<i>x</i>"));
+        tok.resume();
+        assert!(tok.is_suspended());
+        tok.resume();
+        assert!(!tok.is_suspended());
+        tok.end();
+
+        let dom = tok.unwrap().unwrap();
+        let mut out = MemWriter::new();
+        serialize(&mut out, &dom.body().unwrap(), Default::default()).unwrap();
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(),
+            "<i>x</i><p>a<svg><script></script></svg>b</p>");
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn parse_resumes_after_scripts() {
+        let mut opts: ParseOpts = Default::default();
+        opts.tree_builder.suspend_after_scripts = true;
+        let dom: RcDom = parse(one_input(String::from_str(
+            "<script>a</script><p>b<svg><script/></svg><p>c")), opts);
+        assert_eq!(dom.body().unwrap().borrow().children.len(), 2);
+    }
+
+    #[cfg(feature = "tree_builder")]
+    fn fragment(s: &str, context: QualName) -> String {
+        let dom: RcDom = parse_fragment(one_input(String::from_str(s)), context,
+            Default::default());
+        let root = dom.document.borrow().children[0].clone();
+        let mut out = MemWriter::new();
+        serialize(&mut out, &root, Default::default()).unwrap();
+        String::from_utf8(out.unwrap()).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn fragment_in_body() {
+        assert_eq!(fragment("<p>a<p>b", qualname!(HTML, div)).as_slice(), "<p>a</p><p>b</p>");
+        assert_eq!(fragment("<title>t</title>x", qualname!(HTML, div)).as_slice(),
+            "<title>t</title>x");
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn fragment_in_table() {
+        assert_eq!(fragment("<td>a", qualname!(HTML, tr)).as_slice(), "<td>a</td>");
+        assert_eq!(fragment("<tr><td>a", qualname!(HTML, table)).as_slice(),
+            "<tbody><tr><td>a</td></tr></tbody>");
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn fragment_tokenizer_state() {
+        assert_eq!(fragment("a &amp; <b>", qualname!(HTML, textarea)).as_slice(),
+            "a &amp; &lt;b&gt;");
+        assert_eq!(fragment("<b>x</b>", qualname!(HTML, plaintext)).as_slice(),
+            "&lt;b&gt;x&lt;/b&gt;");
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn plaintext_to_eof() {
+        let dom: RcDom = parse(one_input(String::from_str(
+            "<p>a<plaintext>b</plaintext><i>&amp;\r\n\0")), Default::default());
+        let body = dom.body().unwrap();
+        let body = body.borrow();
+        assert_eq!(body.children.len(), 2);
+        let plaintext = body.children[1].borrow();
+        match plaintext.node {
+            Element(ref name, _) => assert_eq!(*name, qualname!(HTML, plaintext)),
+            _ => fail!("not an element"),
+        }
+        assert_eq!(plaintext.children.len(), 1);
+        match plaintext.children[0].borrow().node {
+            Text(ref t) => assert_eq!(t.as_slice(), "b</plaintext><i>&amp;\n\ufffd"),
+            _ => fail!("not text"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn fragment_in_svg() {
+        assert_eq!(fragment("<g viewbox=a><p>x", QualName::new(ns!(SVG), atom!(svg))).as_slice(),
+            "<g viewBox=\"a\"><p>x</p></g>");
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn fragment_in_math() {
+        assert_eq!(fragment("<mi definitionurl=a>x", QualName::new(ns!(MathML), atom!(math)))
+            .as_slice(), "<mi definitionURL=\"a\">x</mi>");
+    }
+
+    // Records the size accounting hooks.  Handles are indices into
+    // `names`, and the document is 0.
+    #[cfg(feature = "tree_builder")]
+    struct Accounting {
+        names: Vec<Option<QualName>>,
+        attrs: Vec<(String, uint)>,
+        text: Vec<(uint, uint)>,
+    }
+
+    #[cfg(feature = "tree_builder")]
+    impl TreeSink<uint> for Accounting {
+        fn get_document(&mut self) -> uint { 0 }
+        fn same_node(&self, x: uint, y: uint) -> bool { x == y }
+        fn elem_name(&self, target: uint) -> QualName { self.names[target].clone().unwrap() }
+
+        fn create_element(&mut self, name: QualName, _attrs: Vec<Attribute>) -> uint {
+            self.names.push(Some(name));
+            self.names.len() - 1
+        }
+
+        fn create_comment(&mut self, _text: String) -> uint {
+            self.names.push(None);
+            self.names.len() - 1
+        }
+
+        fn account_attrs(&mut self, name: &QualName, bytes: uint) {
+            self.attrs.push((String::from_str(name.local.as_slice()), bytes));
+        }
+
+        fn account_text(&mut self, parent: uint, bytes: uint) {
+            self.text.push((parent, bytes));
+        }
+
+        fn get_template_contents(&mut self, target: uint) -> uint { target }
+        fn append_before_sibling(&mut self, _sibling: uint, _new_node: NodeOrText<uint>)
+            -> Result<(), NodeOrText<uint>> { Ok(()) }
+        fn parse_error(&mut self, _err: Error) { }
+        fn set_quirks_mode(&mut self, _mode: QuirksMode) { }
+        fn append(&mut self, _parent: uint, _child: NodeOrText<uint>) { }
+        fn append_doctype_to_document(&mut self, _name: String, _public_id: String,
+            _system_id: String) { }
+        fn add_attrs_if_missing(&mut self, _target: uint, _attrs: Vec<Attribute>) { }
+        fn remove_from_parent(&mut self, _target: uint) { }
+        fn reparent_children(&mut self, _node: uint, _new_parent: uint) { }
+        fn mark_script_already_started(&mut self, _node: uint) { }
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn size_accounting() {
+        let sink = Accounting { names: vec!(None), attrs: vec!(), text: vec!() };
+        let sink = parse_to(sink, one_input(String::from_str(
+            "<p title=abc data-x='12345'>hello<img src=xyz>")), Default::default());
+        let attrs: Vec<(&str, uint)> = sink.attrs.iter()
+            .map(|&(ref n, b)| (n.as_slice(), b)).collect();
+        assert_eq!(attrs, vec!(("html", 0), ("head", 0), ("body", 0), ("p", 8), ("img", 3)));
+        // The text goes in the <p>, which is the fifth node.
+        assert_eq!(sink.text, vec!((4, 5)));
+    }
+
+    test_eq!(utf16_plain, utf16(vec!(vec!(0x61, 0xe9))).as_slice(), "a\u00e9")
+    test_eq!(utf16_pair, utf16(vec!(vec!(0xD83D, 0xDE00))).as_slice(), "\U0001f600")
+    test_eq!(utf16_split_pair, utf16(vec!(vec!(0x61, 0xD83D), vec!(0xDE00))).as_slice(),
+        "a\U0001f600")
+    test_eq!(utf16_lone_low, utf16(vec!(vec!(0x61, 0xDE00, 0x62))).as_slice(), "a\ufffdb")
+    test_eq!(utf16_lone_high, utf16(vec!(vec!(0xD83D, 0x62))).as_slice(), "\ufffdb")
+    test_eq!(utf16_high_at_end, utf16(vec!(vec!(0x61, 0xD83D))).as_slice(), "a\ufffd")
+    test_eq!(utf16_two_highs, utf16(vec!(vec!(0xD83D), vec!(0xD83D, 0xDE00))).as_slice(),
+        "\ufffd\U0001f600")
+}
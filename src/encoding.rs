@@ -0,0 +1,381 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Determining the character encoding of a byte stream.
+//!
+//! Encodings are identified by their names in the Encoding Standard,
+//! e.g. `"windows-1252"`.  This module doesn't decode anything; see
+//! `tokenizer::decoder_for` and `Tokenizer::feed_bytes` for that.
+//! `feed_bytes` does its own BOM sniffing.
+
+use core::prelude::*;
+
+use util::str::{AsciiExt, ASCII_LOWER_MAP};
+
+use collections::vec::Vec;
+use collections::string::String;
+
+// Each encoding's name, followed by its labels.
+static ENCODINGS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("utf-8", &["unicode-1-1-utf-8", "unicode11utf8", "unicode20utf8", "utf-8", "utf8",
+        "x-unicode20utf8"]),
+    ("ibm866", &["866", "cp866", "csibm866", "ibm866"]),
+    ("iso-8859-2", &["csisolatin2", "iso-8859-2", "iso-ir-101", "iso8859-2", "iso88592",
+        "iso_8859-2", "iso_8859-2:1987", "l2", "latin2"]),
+    ("iso-8859-3", &["csisolatin3", "iso-8859-3", "iso-ir-109", "iso8859-3", "iso88593",
+        "iso_8859-3", "iso_8859-3:1988", "l3", "latin3"]),
+    ("iso-8859-4", &["csisolatin4", "iso-8859-4", "iso-ir-110", "iso8859-4", "iso88594",
+        "iso_8859-4", "iso_8859-4:1988", "l4", "latin4"]),
+    ("iso-8859-5", &["csisolatincyrillic", "cyrillic", "iso-8859-5", "iso-ir-144",
+        "iso8859-5", "iso88595", "iso_8859-5", "iso_8859-5:1988"]),
+    ("iso-8859-6", &["arabic", "asmo-708", "csiso88596e", "csiso88596i",
+        "csisolatinarabic", "ecma-114", "iso-8859-6", "iso-8859-6-e", "iso-8859-6-i",
+        "iso-ir-127", "iso8859-6", "iso88596", "iso_8859-6", "iso_8859-6:1987"]),
+    ("iso-8859-7", &["csisolatingreek", "ecma-118", "elot_928", "greek", "greek8",
+        "iso-8859-7", "iso-ir-126", "iso8859-7", "iso88597", "iso_8859-7",
+        "iso_8859-7:1987", "sun_eu_greek"]),
+    ("iso-8859-8", &["csiso88598e", "csisolatinhebrew", "hebrew", "iso-8859-8",
+        "iso-8859-8-e", "iso-ir-138", "iso8859-8", "iso88598", "iso_8859-8",
+        "iso_8859-8:1988", "visual"]),
+    ("iso-8859-8-i", &["csiso88598i", "iso-8859-8-i", "logical"]),
+    ("iso-8859-10", &["csisolatin6", "iso-8859-10", "iso-ir-157", "iso8859-10",
+        "iso885910", "l6", "latin6"]),
+    ("iso-8859-13", &["iso-8859-13", "iso8859-13", "iso885913"]),
+    ("iso-8859-14", &["iso-8859-14", "iso8859-14", "iso885914"]),
+    ("iso-8859-15", &["csisolatin9", "iso-8859-15", "iso8859-15", "iso885915",
+        "iso_8859-15", "l9"]),
+    ("iso-8859-16", &["iso-8859-16"]),
+    ("koi8-r", &["cskoi8r", "koi", "koi8", "koi8-r", "koi8_r"]),
+    ("koi8-u", &["koi8-ru", "koi8-u"]),
+    ("macintosh", &["csmacintosh", "mac", "macintosh", "x-mac-roman"]),
+    ("windows-874", &["dos-874", "iso-8859-11", "iso8859-11", "iso885911", "tis-620",
+        "windows-874"]),
+    ("windows-1250", &["cp1250", "windows-1250", "x-cp1250"]),
+    ("windows-1251", &["cp1251", "windows-1251", "x-cp1251"]),
+    ("windows-1252", &["ansi_x3.4-1968", "ascii", "cp1252", "cp819", "csisolatin1",
+        "ibm819", "iso-8859-1", "iso-ir-100", "iso8859-1", "iso88591", "iso_8859-1",
+        "iso_8859-1:1987", "l1", "latin1", "us-ascii", "windows-1252", "x-cp1252"]),
+    ("windows-1253", &["cp1253", "windows-1253", "x-cp1253"]),
+    ("windows-1254", &["cp1254", "csisolatin5", "iso-8859-9", "iso-ir-148", "iso8859-9",
+        "iso88599", "iso_8859-9", "iso_8859-9:1989", "l5", "latin5", "windows-1254",
+        "x-cp1254"]),
+    ("windows-1255", &["cp1255", "windows-1255", "x-cp1255"]),
+    ("windows-1256", &["cp1256", "windows-1256", "x-cp1256"]),
+    ("windows-1257", &["cp1257", "windows-1257", "x-cp1257"]),
+    ("windows-1258", &["cp1258", "windows-1258", "x-cp1258"]),
+    ("x-mac-cyrillic", &["x-mac-cyrillic", "x-mac-ukrainian"]),
+    ("gbk", &["chinese", "csgb2312", "csiso58gb231280", "gb2312", "gb_2312",
+        "gb_2312-80", "gbk", "iso-ir-58", "x-gbk"]),
+    ("gb18030", &["gb18030"]),
+    ("big5", &["big5", "big5-hkscs", "cn-big5", "csbig5", "x-x-big5"]),
+    ("euc-jp", &["cseucpkdfmtjapanese", "euc-jp", "x-euc-jp"]),
+    ("iso-2022-jp", &["csiso2022jp", "iso-2022-jp"]),
+    ("shift_jis", &["csshiftjis", "ms932", "ms_kanji", "shift-jis", "shift_jis", "sjis",
+        "windows-31j", "x-sjis"]),
+    ("euc-kr", &["cseuckr", "csksc56011987", "euc-kr", "iso-ir-149", "korean",
+        "ks_c_5601-1987", "ks_c_5601-1989", "ksc5601", "ksc_5601", "windows-949"]),
+    ("replacement", &["csiso2022kr", "hz-gb-2312", "iso-2022-cn", "iso-2022-cn-ext",
+        "iso-2022-kr"]),
+    ("utf-16be", &["unicodefffe", "utf-16be"]),
+    ("utf-16le", &["csunicode", "iso-10646-ucs-2", "ucs-2", "unicode", "unicodefeff",
+        "utf-16", "utf-16le"]),
+    ("x-user-defined", &["x-user-defined"]),
+];
+
+fn is_space(b: u8) -> bool {
+    match b {
+        b'\t' | b'\n' | b'\x0C' | b'\r' | b' ' => true,
+        _ => false,
+    }
+}
+
+/// Get the name of the encoding with this label, ignoring ASCII case
+/// and surrounding whitespace.
+pub fn encoding_for_label(label: &str) -> Option<&'static str> {
+    let label = label.trim_chars(|c: char| c < '\x80' && is_space(c as u8));
+    for &(name, labels) in ENCODINGS.iter() {
+        if labels.iter().any(|l| l.eq_ignore_ascii_case(label)) {
+            return Some(name);
+        }
+    }
+    None
+}
+
+fn starts_with_ignore_case(bytes: &[u8], pat: &[u8]) -> bool {
+    bytes.len() >= pat.len() && bytes.slice_to(pat.len()).eq_ignore_ascii_case(pat)
+}
+
+fn find(bytes: &[u8], pat: &[u8]) -> Option<uint> {
+    if bytes.len() < pat.len() {
+        return None;
+    }
+    range(0, bytes.len() - pat.len() + 1).find(|&i| bytes.slice_from(i).starts_with(pat))
+}
+
+// The "algorithm for extracting a character encoding from a meta
+// element", applied to the `content` attribute.
+fn encoding_from_content(content: &[u8]) -> Option<&'static str> {
+    let mut pos = 0;
+    loop {
+        let rest = content.slice_from(pos);
+        let i = unwrap_or_return!(
+            range(0, rest.len()).find(|&i| starts_with_ignore_case(rest.slice_from(i), b"charset")),
+            None);
+        pos += i + 7;
+
+        while pos < content.len() && is_space(content[pos]) {
+            pos += 1;
+        }
+        if pos >= content.len() || content[pos] != b'=' {
+            continue;
+        }
+        pos += 1;
+        while pos < content.len() && is_space(content[pos]) {
+            pos += 1;
+        }
+        if pos >= content.len() {
+            return None;
+        }
+
+        let rest = content.slice_from(pos);
+        let value = match rest[0] {
+            q @ b'"' | q @ b'\'' => {
+                let end = unwrap_or_return!(rest.slice_from(1).position_elem(&q), None);
+                rest.slice(1, end + 1)
+            }
+            _ => {
+                let end = rest.iter().position(|&b| is_space(b) || b == b';')
+                    .unwrap_or(rest.len());
+                rest.slice_to(end)
+            }
+        };
+        return encoding_for_label(String::from_utf8_lossy(value).as_slice());
+    }
+}
+
+// The "get an attribute" algorithm.  Returns the name and value, and
+// advances `pos`, or returns `None` if there are no more attributes.
+// Also returns `None` if we run out of input.
+fn get_attribute(bytes: &[u8], pos: &mut uint) -> Option<(Vec<u8>, Vec<u8>)> {
+    macro_rules! byte ( () => (
+        if *pos < bytes.len() { bytes[*pos] } else { return None }
+    ))
+
+    while is_space(byte!()) || byte!() == b'/' {
+        *pos += 1;
+    }
+    if byte!() == b'>' {
+        return None;
+    }
+
+    let mut name = vec!();
+    let mut value = vec!();
+    loop {
+        match byte!() {
+            b'=' if !name.is_empty() => {
+                *pos += 1;
+                break;
+            }
+            b if is_space(b) => {
+                while is_space(byte!()) {
+                    *pos += 1;
+                }
+                if byte!() != b'=' {
+                    return Some((name, value));
+                }
+                *pos += 1;
+                break;
+            }
+            b'/' | b'>' => return Some((name, value)),
+            b => name.push(ASCII_LOWER_MAP[b as uint]),
+        }
+        *pos += 1;
+    }
+
+    while is_space(byte!()) {
+        *pos += 1;
+    }
+    match byte!() {
+        q @ b'"' | q @ b'\'' => loop {
+            *pos += 1;
+            match byte!() {
+                b if b == q => {
+                    *pos += 1;
+                    return Some((name, value));
+                }
+                b => value.push(ASCII_LOWER_MAP[b as uint]),
+            }
+        },
+        b'>' => return Some((name, value)),
+        b => {
+            value.push(ASCII_LOWER_MAP[b as uint]);
+            *pos += 1;
+        }
+    }
+    loop {
+        match byte!() {
+            b if is_space(b) || b == b'>' => return Some((name, value)),
+            b => value.push(ASCII_LOWER_MAP[b as uint]),
+        }
+        *pos += 1;
+    }
+}
+
+// Handle a `<meta` tag, with `pos` just after the name.  Returns the
+// encoding it declares, if any.
+fn prescan_meta(bytes: &[u8], pos: &mut uint) -> Option<&'static str> {
+    let mut seen: Vec<Vec<u8>> = vec!();
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+
+    loop {
+        let (name, value) = match get_attribute(bytes, pos) {
+            Some(attr) => attr,
+            None => break,
+        };
+        if seen.contains(&name) {
+            continue;
+        }
+
+        {
+            let name_is = |s: &str| name.as_slice() == s.as_bytes();
+            if name_is("http-equiv") {
+                if value.as_slice() == b"content-type" {
+                    got_pragma = true;
+                }
+            } else if name_is("content") {
+                // Only if we haven't already set charset.
+                if need_pragma.is_none() {
+                    match encoding_from_content(value.as_slice()) {
+                        Some(enc) => {
+                            charset = Some(enc);
+                            need_pragma = Some(true);
+                        }
+                        None => (),
+                    }
+                }
+            } else if name_is("charset") {
+                // An unknown label still stops `content` from setting charset.
+                let label = String::from_utf8_lossy(value.as_slice());
+                charset = encoding_for_label(label.as_slice());
+                need_pragma = Some(false);
+            }
+        }
+        seen.push(name);
+    }
+
+    let need_pragma = unwrap_or_return!(need_pragma, None);
+    if need_pragma && !got_pragma {
+        return None;
+    }
+    match charset {
+        Some("utf-16be") | Some("utf-16le") => Some("utf-8"),
+        Some("x-user-defined") => Some("windows-1252"),
+        c => c,
+    }
+}
+
+/// Look for a byte order mark at the start of a byte stream.  Returns
+/// the encoding it indicates and its length in bytes.  A BOM overrides
+/// any other way of choosing the encoding, so check this first.
+pub fn sniff_bom(bytes: &[u8]) -> Option<(&'static str, uint)> {
+    match bytes {
+        [0xEF, 0xBB, 0xBF, ..] => Some(("utf-8", 3)),
+        [0xFE, 0xFF, ..] => Some(("utf-16be", 2)),
+        [0xFF, 0xFE, ..] => Some(("utf-16le", 2)),
+        _ => None,
+    }
+}
+
+/// Prescan the first 1024 bytes of a document for a `<meta>` tag
+/// declaring its character encoding, as in the spec's "prescan a byte
+/// stream to determine its encoding".
+///
+/// Returns the name of the encoding, or `None` if there's no usable
+/// declaration.  The result is tentative: the parser would normally
+/// restart if a declaration later in the document disagrees.
+pub fn prescan(bytes: &[u8]) -> Option<&'static str> {
+    let bytes = if bytes.len() > 1024 { bytes.slice_to(1024) } else { bytes };
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let rest = bytes.slice_from(pos);
+        if rest.starts_with(b"<!--") {
+            pos += 2 + unwrap_or_return!(find(rest.slice_from(2), b"-->"), None) + 3;
+            continue;
+        }
+
+        if starts_with_ignore_case(rest, b"<meta")
+                && rest.len() > 5 && (is_space(rest[5]) || rest[5] == b'/') {
+            pos += 6;
+            match prescan_meta(bytes, &mut pos) {
+                Some(enc) => return Some(enc),
+                None => (),
+            }
+            continue;
+        }
+
+        let tag_start = match rest {
+            [b'<', b'/', c, ..] | [b'<', c, ..] if (c | 0x20) >= b'a' && (c | 0x20) <= b'z' => true,
+            _ => false,
+        };
+        if tag_start {
+            pos += unwrap_or_return!(
+                rest.iter().position(|&b| is_space(b) || b == b'>'), None);
+            while get_attribute(bytes, &mut pos).is_some() {
+                // skip attributes
+            }
+            if pos >= bytes.len() {
+                return None;
+            }
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            pos += unwrap_or_return!(rest.iter().position(|&b| b == b'>'), None);
+        }
+        pos += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use collections::vec::Vec;
+    use super::{prescan, encoding_for_label, sniff_bom};
+
+    test_eq!(label_plain, encoding_for_label("utf-8"), Some("utf-8"))
+    test_eq!(label_case_space, encoding_for_label(" Latin1\n"), Some("windows-1252"))
+    test_eq!(label_unknown, encoding_for_label("utf-9"), None)
+
+    test_eq!(bom_utf8, sniff_bom(b"\xef\xbb\xbf<p>"), Some(("utf-8", 3)))
+    test_eq!(bom_utf16be, sniff_bom(b"\xfe\xff\x00<"), Some(("utf-16be", 2)))
+    test_eq!(bom_utf16le, sniff_bom(b"\xff\xfe<\x00"), Some(("utf-16le", 2)))
+    test_eq!(bom_partial, sniff_bom(b"\xef\xbb"), None)
+    test_eq!(bom_none, sniff_bom(b"<p>"), None)
+
+    test_eq!(meta_charset, prescan(b"<meta charset=iso-8859-2>"), Some("iso-8859-2"))
+    test_eq!(meta_quoted, prescan(b"<META CHARSET='Shift_JIS'>"), Some("shift_jis"))
+    test_eq!(meta_pragma, prescan(
+        b"<meta http-equiv=content-type content='text/html; charset=koi8-r'>"), Some("koi8-r"))
+    test_eq!(meta_no_pragma, prescan(b"<meta content='text/html; charset=koi8-r'>"), None)
+    test_eq!(meta_utf16, prescan(b"<meta charset=utf-16le>"), Some("utf-8"))
+    test_eq!(meta_in_comment, prescan(b"<!-- <meta charset=big5> -->"), None)
+    test_eq!(meta_after_tag, prescan(b"<p title='<meta charset=big5>'><meta charset=gbk>"),
+        Some("gbk"))
+    test_eq!(meta_unknown_first, prescan(b"<meta charset=x-nope><meta charset=gbk>"),
+        Some("gbk"))
+    test_eq!(no_meta, prescan(b"<html><head><title>x</title>"), None)
+
+    #[test]
+    fn meta_too_late() {
+        let mut bytes = Vec::from_elem(1024, b' ');
+        bytes.push_all(b"<meta charset=gbk>");
+        assert_eq!(prescan(bytes.as_slice()), None);
+    }
+}
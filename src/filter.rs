@@ -0,0 +1,366 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Dropping or extracting whole elements from a token stream.
+//!
+//! A `FilterSink` sits between the tokenizer and another `TokenSink`,
+//! and asks a `SubtreeFilter` about each start tag.  An element can be
+//! dropped along with everything inside it, or passed to the filter
+//! instead of the sink, e.g. to remove all `<iframe>`s from a page while
+//! collecting its `<script>`s.
+//!
+//! There is no tree builder here, so an element ends at the matching
+//! end tag, counting any nested elements of the same name.  Void
+//! elements like `<img>` end with their start tag, and so do
+//! self-closing tags like `<svg/>` in foreign content.  An element is
+//! foreign if it's `<svg>` or `<math>`, or if the sink's
+//! `adjusted_current_node_is_foreign` says so, as a tree builder's
+//! does.  Elements whose end tag is often left out, like `<p>` or
+//! `<li>`, will run on to the next matching end tag or EOF.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let sink = FilterSink::new(MyFilter, MySink, true);
+//! let (filter, sink) = tokenize_to(sink, one_input(my_str), Default::default()).unwrap();
+//! ```
+
+use core::prelude::*;
+
+use tokenizer::{Token, TokenSink, Tag, TagToken, StartTag, EndTag, ParseError, EOFToken, Span};
+use tokenizer::states::{State, raw_text_state};
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+
+use string_cache::Atom;
+
+/// What a `FilterSink` does with an element.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum SubtreeAction {
+    /// Pass the element on, and ask about the elements inside it.
+    KeepSubtree,
+
+    /// Drop the element and everything inside it.
+    DropSubtree,
+
+    /// Give the element and everything inside it to
+    /// `SubtreeFilter::extracted`, rather than to the sink.
+    ExtractSubtree,
+}
+
+/// Decides which elements a `FilterSink` drops or extracts.
+pub trait SubtreeFilter {
+    /// What to do with the element this start tag begins.  Not asked
+    /// about elements inside one being dropped or extracted.
+    fn action(&mut self, tag: &Tag) -> SubtreeAction;
+
+    /// Receive the tokens of an extracted element: its start tag,
+    /// everything inside it, and its end tag, unless EOF came first.
+    /// Parse errors go to the sink as usual.
+    fn extracted(&mut self, _tokens: Vec<Token>) {
+    }
+}
+
+// An element being dropped or extracted.
+struct Matched {
+    name: Atom,
+
+    // Number of elements with this name open, including the first.
+    depth: uint,
+
+    // Is it an SVG or MathML element, whose self-closing tags end it?
+    foreign: bool,
+
+    extract: bool,
+    tokens: Vec<Token>,
+}
+
+fn is_void(name: &Atom) -> bool {
+    match *name {
+        atom!(area) | atom!(base) | atom!(basefont) | atom!(bgsound) | atom!(br)
+        | atom!(col) | atom!(embed) | atom!(frame) | atom!(hr) | atom!(img)
+        | atom!(input) | atom!(keygen) | atom!(link) | atom!(menuitem)
+        | atom!(meta) | atom!(param) | atom!(source) | atom!(track) | atom!(wbr)
+            => true,
+        _ => false,
+    }
+}
+
+/// A `TokenSink` which drops or extracts elements chosen by a
+/// `SubtreeFilter`, and passes everything else on to another sink.
+///
+/// Like `ChannelSink`, this switches the tokenizer into a raw text
+/// state after e.g. `<script>` based on the tag name alone, so that
+/// the text of a dropped element can't end it early.  For tags it
+/// passes on, the sink's own state changes take priority.  Token parts
+/// are not passed on.
+pub struct FilterSink<F, Sink> {
+    filter: F,
+    sink: Sink,
+    scripting_enabled: bool,
+    matched: Option<Matched>,
+    next_state: Option<State>,
+}
+
+impl<F: SubtreeFilter, Sink: TokenSink> FilterSink<F, Sink> {
+    /// Create a `FilterSink` passing tokens to `sink`.
+    /// `scripting_enabled` should match the tree builder's option of
+    /// the same name, if there is a tree builder.
+    pub fn new(filter: F, sink: Sink, scripting_enabled: bool) -> FilterSink<F, Sink> {
+        FilterSink {
+            filter: filter,
+            sink: sink,
+            scripting_enabled: scripting_enabled,
+            matched: None,
+            next_state: None,
+        }
+    }
+
+    pub fn unwrap(self) -> (F, Sink) {
+        (self.filter, self.sink)
+    }
+
+    pub fn sink<'a>(&'a self) -> &'a Sink {
+        &self.sink
+    }
+
+    pub fn sink_mut<'a>(&'a mut self) -> &'a mut Sink {
+        &mut self.sink
+    }
+
+    // Would an element with this name, starting here, be foreign?
+    fn is_foreign(&self, name: &Atom) -> bool {
+        *name == atom!(svg) || *name == atom!(math)
+            || self.sink.adjusted_current_node_is_foreign()
+    }
+
+    // End the element being dropped or extracted.
+    fn finish(&mut self) {
+        match self.matched.take() {
+            Some(m) => if m.extract {
+                self.filter.extracted(m.tokens);
+            },
+            None => (),
+        }
+    }
+
+    // Return the token if it should go to the sink.
+    fn filter_token(&mut self, token: Token) -> Option<Token> {
+        let pass = match token {
+            TagToken(ref tag) if tag.kind == StartTag => {
+                self.next_state = raw_text_state(&tag.name, self.scripting_enabled);
+                false
+            }
+            ParseError(_) => true,
+            EOFToken => {
+                self.finish();
+                true
+            }
+            _ => false,
+        };
+        if pass {
+            return Some(token);
+        }
+
+        let done = if self.matched.is_none() {
+            let start = match token {
+                TagToken(ref tag) if tag.kind == StartTag
+                    => Some((self.filter.action(tag), tag.name.clone(), tag.self_closing)),
+                _ => None,
+            };
+            let (action, name, self_closing) = match start {
+                Some(x) => x,
+                None => return Some(token),
+            };
+            if action == KeepSubtree {
+                return Some(token);
+            }
+            let foreign = self.is_foreign(&name);
+            let done = is_void(&name) || (self_closing && foreign);
+            self.matched = Some(Matched {
+                name: name,
+                depth: 1,
+                foreign: foreign,
+                extract: action == ExtractSubtree,
+                tokens: vec!(),
+            });
+            done
+        } else {
+            let m = self.matched.as_mut().unwrap();
+            match token {
+                TagToken(ref tag) if tag.name == m.name => match tag.kind {
+                    StartTag if tag.self_closing && m.foreign => (),
+                    StartTag => m.depth += 1,
+                    EndTag => m.depth -= 1,
+                },
+                _ => (),
+            }
+            m.depth == 0
+        };
+
+        {
+            let m = self.matched.as_mut().unwrap();
+            if m.extract {
+                m.tokens.push(token);
+            }
+        }
+        if done {
+            self.finish();
+        }
+        None
+    }
+}
+
+impl<F: SubtreeFilter, Sink: TokenSink> TokenSink for FilterSink<F, Sink> {
+    fn process_token(&mut self, token: Token) {
+        match self.filter_token(token) {
+            Some(token) => self.sink.process_token(token),
+            None => (),
+        }
+    }
+
+    fn process_token_with_span(&mut self, token: Token, span: Span) {
+        match self.filter_token(token) {
+            Some(token) => self.sink.process_token_with_span(token, span),
+            None => (),
+        }
+    }
+
+    fn query_state_change(&mut self) -> Option<State> {
+        let ours = self.next_state.take();
+        self.sink.query_state_change().or(ours)
+    }
+
+    fn query_suspend(&mut self) -> bool {
+        self.sink.query_suspend()
+    }
+
+    fn adjusted_current_node_is_foreign(&self) -> bool {
+        self.sink.adjusted_current_node_is_foreign()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{FilterSink, SubtreeFilter, SubtreeAction};
+    use super::{KeepSubtree, DropSubtree, ExtractSubtree};
+
+    use tokenizer::{Token, TokenSink, Tag, TagToken, EndTag};
+    use tokenizer::{CharacterTokens, WhitespaceTokens, CommentToken};
+    use driver::{tokenize_to, one_input};
+
+    use core::default::Default;
+    use collections::MutableSeq;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    struct Names {
+        drop: &'static str,
+        extract: &'static str,
+        subtrees: Vec<String>,
+    }
+
+    impl SubtreeFilter for Names {
+        fn action(&mut self, tag: &Tag) -> SubtreeAction {
+            let name = tag.name.as_slice();
+            if name == self.drop {
+                DropSubtree
+            } else if name == self.extract {
+                ExtractSubtree
+            } else {
+                KeepSubtree
+            }
+        }
+
+        fn extracted(&mut self, tokens: Vec<Token>) {
+            self.subtrees.push(show(tokens));
+        }
+    }
+
+    struct Collect(Vec<Token>);
+
+    impl TokenSink for Collect {
+        fn process_token(&mut self, token: Token) {
+            let Collect(ref mut tokens) = *self;
+            tokens.push(token);
+        }
+    }
+
+    // Tags without attributes, text, and comments, for readable tests.
+    fn show(tokens: Vec<Token>) -> String {
+        let mut out = String::new();
+        for token in tokens.into_iter() {
+            match token {
+                TagToken(tag) => {
+                    out.push_str(if tag.kind == EndTag { "</" } else { "<" });
+                    out.push_str(tag.name.as_slice());
+                    out.push('>');
+                }
+                CharacterTokens(s) | WhitespaceTokens(s) => out.push_str(s.as_slice()),
+                CommentToken(s) => {
+                    out.push_str("<!--");
+                    out.push_str(s.as_slice());
+                    out.push_str("-->");
+                }
+                _ => (),
+            }
+        }
+        out
+    }
+
+    fn filter(input: &str, drop: &'static str, extract: &'static str)
+            -> (String, Vec<String>) {
+        let names = Names {
+            drop: drop,
+            extract: extract,
+            subtrees: vec!(),
+        };
+        let sink = FilterSink::new(names, Collect(vec!()), true);
+        let sink = tokenize_to(sink, one_input(String::from_str(input)), Default::default());
+        let (names, Collect(tokens)) = sink.unwrap();
+        (show(tokens), names.subtrees)
+    }
+
+    fn s(x: &str) -> String {
+        String::from_str(x)
+    }
+
+    test_eq!(keep, filter("<p>a</p><!--c-->", "iframe", "script"),
+        (s("<p>a</p><!--c-->"), vec!()))
+
+    test_eq!(drop_raw, filter("a<iframe src=x><p>b</iframe>c", "iframe", ""),
+        (s("ac"), vec!()))
+
+    test_eq!(drop_nested, filter("<div>a<div>b</div>c</div>d<div>e", "div", ""),
+        (s("d"), vec!()))
+
+    test_eq!(drop_void, filter("a<img src=x>b", "img", ""), (s("ab"), vec!()))
+
+    test_eq!(drop_self_closing_foreign, filter("a<svg/>b<p>c", "svg", ""),
+        (s("ab<p>c"), vec!()))
+
+    test_eq!(drop_self_closing_html, filter("<div/>a</div>b", "div", ""), (s("b"), vec!()))
+
+    test_eq!(extract_nested_self_closing, filter("<svg><svg/>x</svg>y", "", "svg"),
+        (s("y"), vec!(s("<svg><svg>x</svg>"))))
+
+    test_eq!(extract_script, filter("x<script>if (a<b) {}</script>y", "", "script"),
+        (s("xy"), vec!(s("<script>if (a<b) {}</script>"))))
+
+    test_eq!(extract_each, filter("<b>1</b>-<b>2<b>3</b></b>", "", "b"),
+        (s("-"), vec!(s("<b>1</b>"), s("<b>2<b>3</b></b>"))))
+
+    test_eq!(extract_at_eof, filter("a<template><p>x", "", "template"),
+        (s("a"), vec!(s("<template><p>x"))))
+
+    test_eq!(inner_ignored, filter("<div><iframe></iframe></div>", "iframe", "div"),
+        (s(""), vec!(s("<div><iframe></iframe></div>"))))
+}
@@ -0,0 +1,275 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Foldable regions of a document, for an editor's outline and code
+//! folding.
+//!
+//! `folding_regions` parses the document with span tracking on, so an
+//! element's region runs from its start tag to wherever the tree
+//! builder actually closed it.  An `<li>` or `<p>` without an end tag
+//! ends before the tag which implied its end, and an unclosed element
+//! ends with the document.
+//!
+//! ## Example
+//!
+//! ```rust
+//! for region in folding_regions(my_str, Default::default()).iter() {
+//!     add_fold(region.span.start, region.span.end);
+//! }
+//! ```
+
+use core::prelude::*;
+
+use tokenizer::{Attribute, Span};
+use tree_builder::{TreeSink, QuirksMode, NodeOrText};
+use tree_builder::Error;
+use driver::{ParseOpts, parse_to, one_input};
+
+use util::str::is_ascii_whitespace;
+
+use core::cmp::Equal;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+use string_cache::QualName;
+
+/// What a `FoldRegion` holds.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum FoldKind {
+    /// An element with this name.
+    ElementFold(QualName),
+
+    /// A comment.
+    CommentFold,
+}
+
+/// A region of the input which an editor could fold.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct FoldRegion {
+    pub kind: FoldKind,
+
+    /// The element's start tag, or the whole comment.
+    pub start_tag: Span,
+
+    /// The element's end tag, or `None` if it was closed without one.
+    /// Always `None` for a comment.
+    pub end_tag: Option<Span>,
+
+    /// The whole region.  For an element closed without an end tag,
+    /// this stops before any whitespace preceding the point where it
+    /// was closed.
+    pub span: Span,
+}
+
+struct Node {
+    name: Option<QualName>,
+    start: Option<Span>,
+    end: Option<Span>,
+}
+
+// Records the spans of each node.  Handles are indices into `nodes`,
+// and the document is 0.
+struct FoldSink {
+    nodes: Vec<Node>,
+}
+
+impl FoldSink {
+    fn new_node(&mut self, name: Option<QualName>) -> uint {
+        self.nodes.push(Node {
+            name: name,
+            start: None,
+            end: None,
+        });
+        self.nodes.len() - 1
+    }
+}
+
+impl TreeSink<uint> for FoldSink {
+    fn get_document(&mut self) -> uint {
+        0
+    }
+
+    fn same_node(&self, x: uint, y: uint) -> bool {
+        x == y
+    }
+
+    fn elem_name(&self, target: uint) -> QualName {
+        self.nodes[target].name.clone().expect("not an element")
+    }
+
+    fn create_element(&mut self, name: QualName, _attrs: Vec<Attribute>) -> uint {
+        self.new_node(Some(name))
+    }
+
+    fn create_comment(&mut self, _text: String) -> uint {
+        self.new_node(None)
+    }
+
+    fn get_template_contents(&mut self, target: uint) -> uint {
+        target
+    }
+
+    fn append_before_sibling(&mut self,
+            _sibling: uint,
+            _new_node: NodeOrText<uint>) -> Result<(), NodeOrText<uint>> {
+        // No scripts run, so the sibling always has a parent.
+        Ok(())
+    }
+
+    fn set_node_span(&mut self, node: uint, span: Span) {
+        self.nodes.get_mut(node).start = Some(span);
+    }
+
+    fn set_end_span(&mut self, elem: uint, span: Span) {
+        self.nodes.get_mut(elem).end = Some(span);
+    }
+
+    fn parse_error(&mut self, _err: Error) { }
+    fn set_quirks_mode(&mut self, _mode: QuirksMode) { }
+    fn append(&mut self, _parent: uint, _child: NodeOrText<uint>) { }
+    fn append_doctype_to_document(&mut self, _name: String, _public_id: String, _system_id: String) { }
+    fn add_attrs_if_missing(&mut self, _target: uint, _attrs: Vec<Attribute>) { }
+    fn remove_from_parent(&mut self, _target: uint) { }
+    fn reparent_children(&mut self, _node: uint, _new_parent: uint) { }
+    fn mark_script_already_started(&mut self, _node: uint) { }
+}
+
+// Back up over whitespace before `end`, but not past `min`.
+fn trim_end(input: &str, min: uint, mut end: uint) -> uint {
+    let bytes = input.as_bytes();
+    while end > min && is_ascii_whitespace(bytes[end - 1] as char) {
+        end -= 1;
+    }
+    end
+}
+
+/// Find the foldable regions of a document.  These are the comments,
+/// and the elements which have a start tag and some content.  Elements
+/// the parser implied, like a `<tbody>` with no tag of its own, and
+/// void elements like `<br>` are left out.
+///
+/// Regions are sorted by where they start, and an element comes before
+/// any regions inside it.  The tokenizer's `track_spans` option is
+/// always turned on.
+pub fn folding_regions(input: &str, mut opts: ParseOpts) -> Vec<FoldRegion> {
+    opts.tokenizer.track_spans = true;
+    let document = Node {
+        name: None,
+        start: None,
+        end: None,
+    };
+    let sink = parse_to(FoldSink { nodes: vec!(document) },
+        one_input(String::from_str(input)), opts);
+
+    let mut out = vec!();
+    for node in sink.nodes.into_iter() {
+        let start = match node.start {
+            Some(s) if s.start < s.end => s,
+            _ => continue,
+        };
+
+        let name = match node.name {
+            None => {
+                out.push(FoldRegion {
+                    kind: CommentFold,
+                    start_tag: start.clone(),
+                    end_tag: None,
+                    span: start,
+                });
+                continue;
+            }
+            Some(name) => name,
+        };
+
+        let (end_tag, end) = match node.end {
+            // Closed by its own start tag.
+            Some(ref e) if *e == start => continue,
+            Some(ref e) if e.start < e.end => (Some(e.clone()), e.end),
+            Some(ref e) => (None, trim_end(input, start.end, e.start)),
+            // Still open at the end of input.
+            None => (None, trim_end(input, start.end, input.len())),
+        };
+
+        out.push(FoldRegion {
+            kind: ElementFold(name),
+            span: Span { start: start.start, end: end },
+            start_tag: start,
+            end_tag: end_tag,
+        });
+    }
+
+    out.sort_by(|a, b| match a.span.start.cmp(&b.span.start) {
+        Equal => b.span.end.cmp(&a.span.end),
+        o => o,
+    });
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{folding_regions, ElementFold, CommentFold, FoldSink, Node};
+    use driver::{ParseOpts, parse_to, one_input};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    // The name of each region, and its text.
+    fn regions(input: &str) -> Vec<(String, String)> {
+        folding_regions(input, Default::default()).into_iter().map(|r| {
+            let name = match r.kind {
+                ElementFold(name) => String::from_str(name.local.as_slice()),
+                CommentFold => String::from_str("comment"),
+            };
+            (name, String::from_str(input.slice(r.span.start, r.span.end)))
+        }).collect()
+    }
+
+    fn r(name: &str, text: &str) -> (String, String) {
+        (String::from_str(name), String::from_str(text))
+    }
+
+    test_eq!(nested, regions("<div>\n<p>a</p>\n</div>"), vec!(
+        r("div", "<div>\n<p>a</p>\n</div>"), r("p", "<p>a</p>")))
+
+    test_eq!(implied_end, regions("<ul>\n <li>a\n <li>b\n</ul>"), vec!(
+        r("ul", "<ul>\n <li>a\n <li>b\n</ul>"), r("li", "<li>a"), r("li", "<li>b")))
+
+    test_eq!(comment, regions("<!-- a\nb -->x"), vec!(r("comment", "<!-- a\nb -->")))
+
+    test_eq!(unclosed, regions("<div>abc  "), vec!(r("div", "<div>abc")))
+
+    test_eq!(void_skipped, regions("<p>a<br>b</p>"), vec!(r("p", "<p>a<br>b</p>")))
+
+    #[test]
+    fn open_at_eof_never_closed() {
+        let mut opts: ParseOpts = Default::default();
+        opts.tokenizer.track_spans = true;
+        let document = Node { name: None, start: None, end: None };
+        let sink = parse_to(FoldSink { nodes: vec!(document) },
+            one_input(String::from_str("<p>a</p><div>b")), opts);
+        let end = |name| sink.nodes.iter()
+            .find(|n| n.name.as_ref().map(|q| q.local.as_slice()) == Some(name))
+            .map(|n| n.end.clone())
+            .expect("element not found");
+        assert!(end("p").is_some());
+        assert_eq!(end("div"), None);
+        assert_eq!(end("body"), None);
+    }
+
+    #[test]
+    fn end_tag_span() {
+        let regions = folding_regions("<b>x</b>", Default::default());
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].start_tag.end, 3);
+        assert_eq!(regions[0].end_tag.as_ref().map(|s| s.start), Some(4));
+    }
+}
@@ -14,8 +14,9 @@ use core::prelude::*;
 use for_c::common::{LifetimeBuf, AsLifetimeBuf, h5e_buf, c_bool};
 
 use tokenizer::{TokenSink, Token, Doctype, Tag, ParseError, DoctypeToken};
-use tokenizer::{CommentToken, CharacterTokens, NullCharacterToken};
-use tokenizer::{TagToken, StartTag, EndTag, EOFToken, Tokenizer};
+use tokenizer::{CommentToken, CharacterTokens, WhitespaceTokens, NullCharacterToken};
+use tokenizer::{TagToken, StartTag, EndTag, PassthroughToken, EOFToken, Tokenizer};
+use tokenizer::ProcessingInstructionToken;
 
 use core::mem;
 use core::default::Default;
@@ -96,16 +97,20 @@ impl TokenSink for *mut h5e_token_sink {
                 call!(do_comment, text.get());
             }
 
-            CharacterTokens(text) => {
+            CharacterTokens(text) | WhitespaceTokens(text) | PassthroughToken(text) => {
                 let text = text.as_lifetime_buf();
                 call!(do_chars, text.get());
             }
 
             NullCharacterToken => call!(do_null_char),
 
+            // Not emitted with the default tokenizer options.
+            ProcessingInstructionToken(..) => (),
+
             EOFToken => call!(do_eof),
 
-            ParseError(msg) => {
+            ParseError(err) => {
+                let msg = err.message();
                 let msg = msg.as_lifetime_buf();
                 call!(do_error, msg.get());
             }
@@ -0,0 +1,284 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Classifying HTML source for syntax highlighting.
+//!
+//! `highlight` runs the tokenizer with `track_spans` on, so the
+//! classes agree with how a browser reads the markup, e.g. for
+//! `<a title="x>y">` or an unclosed comment.  There is no tree builder,
+//! so raw text elements like `<script>` are recognized by name alone.
+//!
+//! ## Example
+//!
+//! ```rust
+//! for (class, span) in highlight(my_str, Default::default()).into_iter() {
+//!     paint(class, my_str.slice(span.start, span.end));
+//! }
+//! ```
+
+use core::prelude::*;
+
+use tokenizer::{Tokenizer, TokenizerOpts, TokenSink, Token, Span, TokenPart};
+use tokenizer::{TagToken, StartTag, CommentToken, DoctypeToken};
+use tokenizer::{ProcessingInstructionToken, ParseError};
+use tokenizer::{TagNamePart, AttrNamePart, AttrValuePart, CharRefPart};
+use tokenizer::states::{State, raw_text_state};
+
+use util::str::is_ascii_whitespace;
+
+use core::mem::replace;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+/// The class of a span of source, for syntax highlighting.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum HighlightClass {
+    /// Anything in a tag which isn't a name or a value, e.g. `<`,
+    /// `</`, `=`, `/>` and `>`.
+    PunctuationClass,
+
+    /// A tag name.
+    TagNameClass,
+
+    /// An attribute name.
+    AttrNameClass,
+
+    /// An attribute value, including any quotes.
+    AttrValueClass,
+
+    /// A character reference, e.g. `&amp;`, in text or an attribute
+    /// value.  Text which only looks like one is not classified.
+    EntityClass,
+
+    /// A comment, including a bogus comment or processing instruction.
+    CommentClass,
+
+    /// A doctype.
+    DoctypeClass,
+}
+
+// Collects classified spans.  Punctuation is not yet split at
+// whitespace, because we don't have the input.
+struct Classifier {
+    spans: Vec<(HighlightClass, Span)>,
+    parts: Vec<(TokenPart, Span)>,
+    next_state: Option<State>,
+}
+
+impl Classifier {
+    fn push(&mut self, class: HighlightClass, start: uint, end: uint) {
+        if start < end {
+            self.spans.push((class, Span { start: start, end: end }));
+        }
+    }
+
+    fn tag(&mut self, span: Span) {
+        // A character reference is reported before the attribute value
+        // containing it, so sort by where they start.
+        let mut parts = replace(&mut self.parts, vec!());
+        parts.sort_by(|&(_, ref a), &(_, ref b)| a.start.cmp(&b.start));
+
+        let mut pos = span.start;
+        let mut value_end = None;
+        for &(part, ref s) in parts.iter() {
+            match (part, value_end) {
+                (CharRefPart, Some(end)) if s.end <= end => {
+                    self.push(AttrValueClass, pos, s.start);
+                    self.push(EntityClass, s.start, s.end);
+                    pos = s.end;
+                    continue;
+                }
+                (_, Some(end)) => {
+                    self.push(AttrValueClass, pos, end);
+                    pos = end;
+                    value_end = None;
+                }
+                _ => (),
+            }
+
+            self.push(PunctuationClass, pos, s.start);
+            pos = s.start;
+            match part {
+                AttrValuePart => value_end = Some(s.end),
+                _ => {
+                    let class = match part {
+                        TagNamePart => TagNameClass,
+                        AttrNamePart => AttrNameClass,
+                        _ => EntityClass,
+                    };
+                    self.push(class, s.start, s.end);
+                    pos = s.end;
+                }
+            }
+        }
+
+        match value_end {
+            Some(end) => {
+                self.push(AttrValueClass, pos, end);
+                pos = end;
+            }
+            None => (),
+        }
+        self.push(PunctuationClass, pos, span.end);
+    }
+}
+
+impl TokenSink for Classifier {
+    fn process_token(&mut self, _token: Token) {
+        unreachable!();  // we always track spans
+    }
+
+    fn process_token_with_span(&mut self, token: Token, span: Span) {
+        match token {
+            // Errors arrive in the middle of other tokens.
+            ParseError(_) => return,
+
+            TagToken(tag) => {
+                if tag.kind == StartTag {
+                    self.next_state = raw_text_state(&tag.name, true);
+                }
+                self.tag(span);
+            }
+
+            CommentToken(_) | ProcessingInstructionToken(..)
+                => self.push(CommentClass, span.start, span.end),
+
+            DoctypeToken(_) => self.push(DoctypeClass, span.start, span.end),
+
+            // Character references in text, and the parts of a tag
+            // which was cut off by EOF.
+            _ => for &(part, ref s) in self.parts.iter() {
+                if part == CharRefPart {
+                    self.spans.push((EntityClass, s.clone()));
+                }
+            },
+        }
+        self.parts.truncate(0);
+    }
+
+    fn process_token_part(&mut self, part: TokenPart, span: Span) {
+        self.parts.push((part, span));
+    }
+
+    fn query_state_change(&mut self) -> Option<State> {
+        self.next_state.take()
+    }
+}
+
+/// Classify the spans of `input` which should be highlighted.  The
+/// result is in order and the spans don't overlap.  Text, and
+/// whitespace between the parts of a tag, is not classified.
+///
+/// The `track_spans` option is always turned on.
+pub fn highlight(input: &str, mut opts: TokenizerOpts) -> Vec<(HighlightClass, Span)> {
+    opts.track_spans = true;
+    let classifier = Classifier {
+        spans: vec!(),
+        parts: vec!(),
+        next_state: None,
+    };
+    let mut tok = Tokenizer::new(classifier, opts);
+    tok.feed(String::from_str(input));
+    tok.end();
+
+    let mut out = vec!();
+    for (class, span) in tok.unwrap().spans.into_iter() {
+        if class != PunctuationClass {
+            out.push((class, span));
+            continue;
+        }
+
+        // Split punctuation into runs of non-whitespace.
+        let mut start = None;
+        let text = input.slice(span.start, span.end);
+        for (i, c) in text.char_indices() {
+            match (is_ascii_whitespace(c), start) {
+                (true, Some(s)) => {
+                    out.push((PunctuationClass, Span { start: s, end: span.start + i }));
+                    start = None;
+                }
+                (false, None) => start = Some(span.start + i),
+                _ => (),
+            }
+        }
+        match start {
+            Some(s) => out.push((PunctuationClass, Span { start: s, end: span.end })),
+            None => (),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{highlight, HighlightClass, PunctuationClass, TagNameClass, AttrNameClass};
+    use super::{AttrValueClass, EntityClass, CommentClass, DoctypeClass};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    // The classified text, for readable tests.
+    fn classes(input: &str) -> Vec<(HighlightClass, String)> {
+        highlight(input, Default::default()).into_iter()
+            .map(|(c, s)| (c, String::from_str(input.slice(s.start, s.end))))
+            .collect()
+    }
+
+    fn c(class: HighlightClass, s: &str) -> (HighlightClass, String) {
+        (class, String::from_str(s))
+    }
+
+    test_eq!(text_only, classes("hello world"), vec!())
+
+    test_eq!(simple_tag, classes("<p>x</p>"), vec!(
+        c(PunctuationClass, "<"), c(TagNameClass, "p"), c(PunctuationClass, ">"),
+        c(PunctuationClass, "</"), c(TagNameClass, "p"), c(PunctuationClass, ">")))
+
+    test_eq!(attributes, classes("<a href = \"x\" id=y hidden/>"), vec!(
+        c(PunctuationClass, "<"), c(TagNameClass, "a"),
+        c(AttrNameClass, "href"), c(PunctuationClass, "="), c(AttrValueClass, "\"x\""),
+        c(AttrNameClass, "id"), c(PunctuationClass, "="), c(AttrValueClass, "y"),
+        c(AttrNameClass, "hidden"), c(PunctuationClass, "/>")))
+
+    test_eq!(entity_in_value, classes("<a title='x&amp;y'>"), vec!(
+        c(PunctuationClass, "<"), c(TagNameClass, "a"),
+        c(AttrNameClass, "title"), c(PunctuationClass, "="),
+        c(AttrValueClass, "'x"), c(EntityClass, "&amp;"), c(AttrValueClass, "y'"),
+        c(PunctuationClass, ">")))
+
+    test_eq!(entity_in_text, classes("a &lt; b &c"), vec!(c(EntityClass, "&lt;")))
+
+    test_eq!(gt_in_value, classes("<a title=\"x>y\">"), vec!(
+        c(PunctuationClass, "<"), c(TagNameClass, "a"),
+        c(AttrNameClass, "title"), c(PunctuationClass, "="),
+        c(AttrValueClass, "\"x>y\""), c(PunctuationClass, ">")))
+
+    test_eq!(comment_and_doctype, classes("<!DOCTYPE html>\n<!-- x -->"), vec!(
+        c(DoctypeClass, "<!DOCTYPE html>"), c(CommentClass, "<!-- x -->")))
+
+    test_eq!(script_is_raw, classes("<script>a<b</script>"), vec!(
+        c(PunctuationClass, "<"), c(TagNameClass, "script"), c(PunctuationClass, ">"),
+        c(PunctuationClass, "</"), c(TagNameClass, "script"), c(PunctuationClass, ">")))
+
+    test_eq!(not_a_tag, classes("1 <2 <p>"), vec!(
+        c(PunctuationClass, "<"), c(TagNameClass, "p"), c(PunctuationClass, ">")))
+
+    test_eq!(crlf, classes("a\r\n<b\r\nc=d>"), vec!(
+        c(PunctuationClass, "<"), c(TagNameClass, "b"),
+        c(AttrNameClass, "c"), c(PunctuationClass, "="), c(AttrValueClass, "d"),
+        c(PunctuationClass, ">")))
+
+    test_eq!(multibyte, classes("é<x-é é=é>"), vec!(
+        c(PunctuationClass, "<"), c(TagNameClass, "x-é"),
+        c(AttrNameClass, "é"), c(PunctuationClass, "="), c(AttrValueClass, "é"),
+        c(PunctuationClass, ">")))
+}
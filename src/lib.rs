@@ -55,35 +55,98 @@ extern crate phf;
 extern crate time;
 
 pub use tokenizer::Attribute;
-pub use driver::{one_input, ParseOpts, parse_to, parse};
-
+pub use driver::{one_input, parse_rcdata_fragment, utf16_input};
+
+#[cfg(feature = "tree_builder")]
+pub use driver::{ParseOpts, ParseOptsBuilder, parse_to, parse};
+#[cfg(feature = "tree_builder")]
+pub use driver::{DocumentMetadata, parse_to_with_metadata, parse_with_metadata};
+#[cfg(feature = "tree_builder")]
+pub use driver::{parse_bytes_to_with_metadata, parse_bytes_with_metadata};
+#[cfg(feature = "tree_builder")]
+pub use driver::{parse_fragment_to, parse_fragment};
+#[cfg(feature = "tree_builder")]
+pub use driver::BatchParser;
+
+#[cfg(feature = "tree_builder")]
 #[cfg(not(for_c))]
-pub use serialize::serialize;
+pub use serialize::{serialize, serialize_to_string, text_to_html};
 
 mod macros;
 
 mod util {
     #![macro_escape]
 
+    // Some of these are only used by the tree builder.
+    #[cfg_attr(not(feature = "tree_builder"), allow(dead_code))]
     pub mod str;
     pub mod smallcharset;
+    pub mod tempbuf;
+
+    #[cfg(feature = "tree_builder")]
+    #[cfg(not(for_c))]
+    pub mod fnv;
 }
 
 pub mod tokenizer;
+
+// Everything which needs the tree builder is behind this feature, on
+// by default.  Without it we build only the tokenizer.
+#[cfg(feature = "tree_builder")]
 pub mod tree_builder;
 
+#[cfg(feature = "tree_builder")]
 #[cfg(not(for_c))]
 pub mod serialize;
 
 /// Consumers of the parser API.
+#[cfg(feature = "tree_builder")]
 #[cfg(not(for_c))]
 pub mod sink {
     pub mod common;
     pub mod rcdom;
     pub mod owned_dom;
+    pub mod channel;
+    pub mod walk;
+    pub mod validating;
 }
 
 pub mod driver;
+pub mod markdown;
+pub mod highlight;
+pub mod resync;
+pub mod filter;
+pub mod prefetch;
+pub mod token_stream;
+#[cfg(not(for_c))]
+pub mod token_export;
+
+#[cfg(feature = "tree_builder")]
+pub mod folding;
+#[cfg(feature = "tree_builder")]
+pub mod completion;
+#[cfg(feature = "tree_builder")]
+pub mod text_spans;
+pub mod encoding;
+
+#[cfg(feature = "testing")]
+#[cfg(not(for_c))]
+pub mod testing;
+
+/// Analyses of parsed documents.
+#[cfg(feature = "tree_builder")]
+#[cfg(not(for_c))]
+pub mod analysis {
+    pub mod xml;
+    pub mod hash;
+    pub mod fingerprint;
+    pub mod density;
+    pub mod microdata;
+    pub mod aria;
+    pub mod lang;
+    pub mod text;
+    pub mod profile;
+}
 
 #[cfg(for_c)]
 pub mod for_c {
@@ -0,0 +1,209 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Recognizing the start of a CommonMark HTML block.
+//!
+//! See the "HTML blocks" section of the CommonMark spec.  The kinds
+//! of block are listed in the same order as the spec's seven start
+//! conditions.
+
+use core::prelude::*;
+
+use tokenizer::{Tokenizer, TokenSink, Token, Tag, TagToken};
+use tokenizer::{CharacterTokens, WhitespaceTokens, EOFToken};
+
+use util::str::{is_ascii_whitespace, is_ascii_alnum, lower_ascii_letter, AsciiExt};
+
+use core::default::Default;
+use collections::string::String;
+
+/// The kind of HTML block a line starts, which determines how
+/// the block ends.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum HtmlBlockKind {
+    /// `<script`, `<pre`, or `<style`.
+    RawTextBlock,
+
+    /// `<!--`
+    CommentBlock,
+
+    /// `<?`
+    ProcessingInstructionBlock,
+
+    /// `<!` followed by a letter.
+    DeclarationBlock,
+
+    /// `<![CDATA[`
+    CdataBlock,
+
+    /// A start or end tag for one of the block-level elements.
+    BlockTagBlock,
+
+    /// Any other complete start or end tag, alone on the line.
+    CompleteTagBlock,
+}
+
+impl HtmlBlockKind {
+    /// The text which ends the block, if the block doesn't end at
+    /// a blank line.  For `RawTextBlock` the block ends at any of
+    /// `</script>`, `</pre>`, or `</style>`; this returns `None`.
+    pub fn end_marker(&self) -> Option<&'static str> {
+        match *self {
+            CommentBlock => Some("-->"),
+            ProcessingInstructionBlock => Some("?>"),
+            DeclarationBlock => Some(">"),
+            CdataBlock => Some("]]>"),
+            RawTextBlock | BlockTagBlock | CompleteTagBlock => None,
+        }
+    }
+
+    /// Can a block of this kind interrupt a paragraph?
+    pub fn can_interrupt_paragraph(&self) -> bool {
+        *self != CompleteTagBlock
+    }
+}
+
+static BLOCK_TAGS: &'static [&'static str] = &[
+    "address", "article", "aside", "base", "basefont", "blockquote", "body",
+    "caption", "center", "col", "colgroup", "dd", "details", "dialog", "dir",
+    "div", "dl", "dt", "fieldset", "figcaption", "figure", "footer", "form",
+    "frame", "frameset", "h1", "h2", "h3", "h4", "h5", "h6", "head", "header",
+    "hr", "html", "iframe", "legend", "li", "link", "main", "menu", "menuitem",
+    "meta", "nav", "noframes", "ol", "optgroup", "option", "p", "param",
+    "section", "source", "summary", "table", "tbody", "td", "tfoot", "th",
+    "thead", "title", "tr", "track", "ul"];
+
+// Split off a tag name made of ASCII letters and digits.
+fn split_name<'a>(s: &'a str) -> (&'a str, &'a str) {
+    let end = s.find(|c: char| !is_ascii_alnum(c)).unwrap_or(s.len());
+    (s.slice_to(end), s.slice_from(end))
+}
+
+// Records whether the input was exactly one tag, optionally followed
+// by whitespace.
+struct OneTag {
+    tag: Option<Tag>,
+    ok: bool,
+}
+
+impl TokenSink for OneTag {
+    fn process_token(&mut self, token: Token) {
+        match token {
+            TagToken(tag) => if self.tag.is_none() {
+                self.tag = Some(tag);
+                return;
+            },
+            CharacterTokens(ref s) | WhitespaceTokens(ref s)
+                if self.tag.is_some() && s.as_slice().chars().all(is_ascii_whitespace)
+                => return,
+            EOFToken => return,
+            _ => (),
+        }
+        self.ok = false;
+    }
+}
+
+fn is_complete_tag(line: &str) -> bool {
+    let mut tok = Tokenizer::new(OneTag { tag: None, ok: true }, Default::default());
+    tok.feed(String::from_str(line));
+    tok.end();
+    let OneTag { tag, ok } = tok.unwrap();
+    match tag {
+        Some(ref tag) if ok => {
+            let name = tag.name.as_slice();
+            let mut chars = name.chars();
+            let start_ok = chars.next().map_or(false, |c| lower_ascii_letter(c).is_some());
+            start_ok
+                && chars.all(|c| c == '-' || is_ascii_alnum(c))
+                && name != "script" && name != "style" && name != "pre"
+        }
+        _ => false,
+    }
+}
+
+/// Does this line start a CommonMark HTML block, and if so, which kind?
+///
+/// The line should not include its line ending.
+pub fn html_block_start(line: &str) -> Option<HtmlBlockKind> {
+    // Up to three spaces of indentation are allowed.
+    let indent = line.chars().take(3).take_while(|&c| c == ' ').count();
+    let line = line.slice_from(indent);
+    if !line.starts_with("<") {
+        return None;
+    }
+    let rest = line.slice_from(1);
+
+    if rest.starts_with("!--") {
+        return Some(CommentBlock);
+    }
+    if rest.starts_with("?") {
+        return Some(ProcessingInstructionBlock);
+    }
+    if rest.starts_with("![CDATA[") {
+        return Some(CdataBlock);
+    }
+    if rest.starts_with("!") {
+        return match rest.slice_from(1).chars().next() {
+            Some(c) if lower_ascii_letter(c).is_some() => Some(DeclarationBlock),
+            _ => None,
+        };
+    }
+
+    let name_ends = |s: &str| match s.chars().next() {
+        None | Some('>') => true,
+        Some(c) => is_ascii_whitespace(c),
+    };
+
+    let (name, after) = split_name(rest);
+    let name = name.to_ascii_lower();
+    if (name.as_slice() == "script" || name.as_slice() == "pre" || name.as_slice() == "style")
+            && name_ends(after) {
+        return Some(RawTextBlock);
+    }
+
+    let (name, after) = if rest.starts_with("/") {
+        let (name, after) = split_name(rest.slice_from(1));
+        (name.to_ascii_lower(), after)
+    } else {
+        (name, after)
+    };
+    if BLOCK_TAGS.contains(&name.as_slice()) && (name_ends(after) || after.starts_with("/>")) {
+        return Some(BlockTagBlock);
+    }
+
+    if is_complete_tag(line) {
+        return Some(CompleteTagBlock);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{html_block_start, RawTextBlock, CommentBlock, ProcessingInstructionBlock};
+    use super::{DeclarationBlock, CdataBlock, BlockTagBlock, CompleteTagBlock};
+
+    test_eq!(not_html, html_block_start("hello"), None)
+    test_eq!(too_indented, html_block_start("    <div>"), None)
+    test_eq!(script, html_block_start("<script type=x>"), Some(RawTextBlock))
+    test_eq!(pre_upper, html_block_start("  <PRE>"), Some(RawTextBlock))
+    test_eq!(comment, html_block_start("<!-- x"), Some(CommentBlock))
+    test_eq!(pi, html_block_start("<?php"), Some(ProcessingInstructionBlock))
+    test_eq!(doctype, html_block_start("<!DOCTYPE html>"), Some(DeclarationBlock))
+    test_eq!(cdata, html_block_start("<![CDATA["), Some(CdataBlock))
+    test_eq!(div, html_block_start("<div class=x"), Some(BlockTagBlock))
+    test_eq!(end_div, html_block_start("</div>"), Some(BlockTagBlock))
+    test_eq!(hr_self_closing, html_block_start("<hr/>"), Some(BlockTagBlock))
+    test_eq!(divx, html_block_start("<divx"), None)
+    test_eq!(span, html_block_start("<span title='a'>  "), Some(CompleteTagBlock))
+    test_eq!(end_span, html_block_start("</span>"), Some(CompleteTagBlock))
+    test_eq!(span_then_text, html_block_start("<span>text"), None)
+    test_eq!(unclosed_attr, html_block_start("<span title='a>"), None)
+}
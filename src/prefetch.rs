@@ -0,0 +1,290 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Finding the resources a browser's preload scanner would fetch early.
+//!
+//! Browsers look ahead through a page for resources they can start
+//! loading before the parser reaches them.  `scan` does the same with
+//! only the tokenizer, for tools like a CDN adding preload headers.
+//! Character data is dropped by the tokenizer without being decoded,
+//! and the contents of `<script>`, `<style>` and other raw text
+//! elements are skipped without looking for tags.
+//!
+//! The resources found are `<img src>` and every candidate in `<img
+//! srcset>`, `<script src>` for JavaScript, and `<link href>` with a
+//! `rel` of `stylesheet` or `preload`.  Nothing inside a `<template>`
+//! counts.  URLs are given as written, without resolving them; the
+//! first `<base href>` is given too, for doing that.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let found = scan(page.as_slice());
+//! for res in found.resources.iter() {
+//!     add_preload_header(&mut response, &found.base, res);
+//! }
+//! ```
+
+use core::prelude::*;
+
+use tokenizer::{Token, TokenSink, TokenizerOpts, Tag, TagToken, StartTag, EndTag, Attribute};
+use tokenizer::states::{State, raw_text_state};
+use driver::{tokenize_to, one_input};
+use util::str::{is_ascii_whitespace, split_html_space, AsciiExt};
+
+use core::default::Default;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+/// What a resource is fetched for.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum ResourceKind {
+    Image,
+    Script,
+    Stylesheet,
+
+    /// `<link rel=preload>`, with its `as` attribute in lower case.
+    Preload(Option<String>),
+}
+
+/// A resource a preload scanner would fetch.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct Resource {
+    /// The URL as written, minus any leading and trailing whitespace.
+    pub url: String,
+    pub kind: ResourceKind,
+}
+
+/// What `scan` found in a document.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct Prefetch {
+    /// The `href` of the first `<base>` with one.
+    pub base: Option<String>,
+
+    /// The resources, in document order.
+    pub resources: Vec<Resource>,
+}
+
+fn get_attr<'a>(attrs: &'a [Attribute], name: &str) -> Option<&'a str> {
+    attrs.iter()
+        .find(|a| a.name.ns == ns!("") && a.name.local.as_slice() == name)
+        .map(|a| a.value.as_slice())
+}
+
+// Would a `<script>` with this `type` be run as JavaScript?
+fn is_script_type(ty: Option<&str>) -> bool {
+    let ty = match ty {
+        None => return true,
+        Some(ty) => ty.to_ascii_lower(),
+    };
+    let essence = ty.as_slice().split(';').next().unwrap().trim_chars(is_ascii_whitespace);
+    match essence {
+        "" | "module" | "text/javascript" | "application/javascript" | "text/ecmascript"
+            | "application/ecmascript" | "application/x-javascript"
+            | "application/x-ecmascript" | "text/jscript" | "text/x-javascript" => true,
+        _ => false,
+    }
+}
+
+// The URLs of the candidates in a `srcset`, skipping their descriptors.
+fn srcset_urls<'a>(value: &'a str) -> Vec<&'a str> {
+    let mut out = vec!();
+    let mut rest = value;
+    loop {
+        rest = rest.trim_left_chars(|c: char| c == ',' || is_ascii_whitespace(c));
+        if rest.is_empty() {
+            return out;
+        }
+
+        let end = rest.find(is_ascii_whitespace).unwrap_or(rest.len());
+        let url = rest.slice_to(end);
+        rest = rest.slice_from(end);
+
+        // A URL ending in a comma has no descriptors.
+        let trimmed = url.trim_right_chars(',');
+        out.push(trimmed);
+        if trimmed.len() < url.len() {
+            continue;
+        }
+
+        // Skip the descriptors, up to a comma outside parentheses.
+        let mut in_parens = false;
+        let mut end = rest.len();
+        for (i, c) in rest.char_indices() {
+            match c {
+                '(' => in_parens = true,
+                ')' => in_parens = false,
+                ',' if !in_parens => {
+                    end = i;
+                    break;
+                }
+                _ => (),
+            }
+        }
+        rest = rest.slice_from(end);
+    }
+}
+
+/// A `TokenSink` which collects the resources a preload scanner would
+/// find.  Turn on the tokenizer's `discard_text` option when using it
+/// directly; `scan` does this.
+pub struct PrefetchSink {
+    found: Prefetch,
+
+    // Number of `<template>`s open.
+    template_depth: uint,
+
+    next_state: Option<State>,
+}
+
+impl PrefetchSink {
+    /// Create a `PrefetchSink` which has found nothing yet.
+    pub fn new() -> PrefetchSink {
+        PrefetchSink {
+            found: Prefetch {
+                base: None,
+                resources: vec!(),
+            },
+            template_depth: 0,
+            next_state: None,
+        }
+    }
+
+    /// Get what was found.
+    pub fn unwrap(self) -> Prefetch {
+        self.found
+    }
+
+    fn push(&mut self, url: &str, kind: ResourceKind) {
+        let url = url.trim_chars(is_ascii_whitespace);
+        if !url.is_empty() {
+            self.found.resources.push(Resource {
+                url: String::from_str(url),
+                kind: kind,
+            });
+        }
+    }
+
+    fn start_tag(&mut self, tag: &Tag) {
+        let attrs = tag.attrs.as_slice();
+        match tag.name {
+            atom!(base) => if self.found.base.is_none() {
+                self.found.base = get_attr(attrs, "href").map(|h| String::from_str(h));
+            },
+
+            atom!(img) => {
+                match get_attr(attrs, "src") {
+                    Some(src) => self.push(src, Image),
+                    None => (),
+                }
+                match get_attr(attrs, "srcset") {
+                    Some(srcset) => for url in srcset_urls(srcset).into_iter() {
+                        self.push(url, Image);
+                    },
+                    None => (),
+                }
+            }
+
+            atom!(script) => match get_attr(attrs, "src") {
+                Some(src) if is_script_type(get_attr(attrs, "type")) => self.push(src, Script),
+                _ => (),
+            },
+
+            atom!(link) => {
+                let href = unwrap_or_return!(get_attr(attrs, "href"), ());
+                let rel = get_attr(attrs, "rel").unwrap_or("").to_ascii_lower();
+                let has_rel = |name: &str| split_html_space(rel.as_slice()).any(|r| r == name);
+                if has_rel("stylesheet") && !has_rel("alternate") {
+                    self.push(href, Stylesheet);
+                } else if has_rel("preload") {
+                    let as_ = get_attr(attrs, "as").map(|a| a.to_ascii_lower());
+                    self.push(href, Preload(as_));
+                }
+            }
+
+            _ => (),
+        }
+    }
+}
+
+impl TokenSink for PrefetchSink {
+    fn process_token(&mut self, token: Token) {
+        let tag = match token {
+            TagToken(tag) => tag,
+            _ => return,
+        };
+        match tag.kind {
+            StartTag => {
+                // Scripting is assumed, so `<noscript>` is skipped too.
+                self.next_state = raw_text_state(&tag.name, true);
+                if tag.name == atom!(template) {
+                    self.template_depth += 1;
+                } else if self.template_depth == 0 {
+                    self.start_tag(&tag);
+                }
+            }
+            EndTag => if tag.name == atom!(template) && self.template_depth > 0 {
+                self.template_depth -= 1;
+            },
+        }
+    }
+
+    fn query_state_change(&mut self) -> Option<State> {
+        self.next_state.take()
+    }
+}
+
+/// Find the resources a preload scanner would fetch from a document.
+pub fn scan(input: &str) -> Prefetch {
+    let opts = TokenizerOpts {
+        discard_text: true,
+        .. Default::default()
+    };
+    tokenize_to(PrefetchSink::new(), one_input(String::from_str(input)), opts).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{scan, srcset_urls, Resource, ResourceKind, Image, Script, Stylesheet, Preload};
+
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    fn found(input: &str) -> Vec<(String, ResourceKind)> {
+        scan(input).resources.into_iter().map(|Resource { url, kind }| (url, kind)).collect()
+    }
+
+    fn r(url: &str, kind: ResourceKind) -> (String, ResourceKind) {
+        (String::from_str(url), kind)
+    }
+
+    test_eq!(images, found("<p>a &amp; b<img src=' a.png '><img src=''><img>"),
+        vec!(r("a.png", Image)))
+
+    test_eq!(srcset, srcset_urls(" a.png 1x,b.png, c(1).png 100w (x, y), d.png"),
+        vec!("a.png", "b.png", "c(1).png", "d.png"))
+
+    test_eq!(scripts, found("<script src=a.js></script><script src=b.js type=module>\
+        </script><script src=c.tmpl type=text/template></script>\
+        <script src=d.js type='Text/JavaScript; charset=utf-8'></script>"),
+        vec!(r("a.js", Script), r("b.js", Script), r("d.js", Script)))
+
+    test_eq!(links, found("<link rel=StyleSheet href=a.css><link rel='alternate stylesheet' \
+        href=b.css><link rel=preload href=c.woff as=Font><link rel=icon href=d.ico>"),
+        vec!(r("a.css", Stylesheet), r("c.woff", Preload(Some(String::from_str("font"))))))
+
+    test_eq!(skipped, found("<script>document.write('<img src=a.png>')</script>\
+        <noscript><img src=b.png></noscript><template><img src=c.png></template>\
+        <img src=d.png>"), vec!(r("d.png", Image)))
+
+    test_eq!(base, scan("<base target=_top><base href=/x/><base href=/y/>").base,
+        Some(String::from_str("/x/")))
+}
@@ -0,0 +1,142 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Finding places to restart tokenizing in corrupted input.
+//!
+//! A log or dump with a chunk missing can leave the tokenizer inside an
+//! attribute value or comment for the rest of the input.  If you know
+//! where the damage is, `next_tag_boundary` finds the next place after
+//! it where some ordinary markup starts, so the rest can be tokenized as
+//! a separate segment.
+//!
+//! This is a heuristic.  It can't tell a tag in the text of a
+//! `<script>` from a real one.
+//!
+//! ## Example
+//!
+//! ```rust
+//! match next_tag_boundary(my_str, damage_end) {
+//!     Some(i) => tokenize_segment(my_str.slice_from(i)),
+//!     None => (),
+//! }
+//! ```
+
+use core::prelude::*;
+
+use util::str::lower_ascii_letter;
+
+fn is_space(b: u8) -> bool {
+    match b {
+        b'\t' | b'\n' | b'\x0C' | b'\r' | b' ' => true,
+        _ => false,
+    }
+}
+
+fn is_name_char(b: u8) -> bool {
+    match b {
+        b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'_' | b':' | b'.' => true,
+        _ => false,
+    }
+}
+
+fn starts_with_ignore_case(bytes: &[u8], pat: &[u8]) -> bool {
+    bytes.len() >= pat.len() && bytes.slice_to(pat.len()).eq_ignore_ascii_case(pat)
+}
+
+// Does a plausible tag name, attributes and `>` start at `i`?  Any `<`
+// outside a quoted value means this isn't a tag.
+fn plausible_tag_rest(bytes: &[u8], mut i: uint) -> bool {
+    match bytes.get(i) {
+        Some(&b) if lower_ascii_letter(b as char).is_some() => (),
+        _ => return false,
+    }
+    while i < bytes.len() && is_name_char(bytes[i]) {
+        i += 1;
+    }
+
+    let mut after_equals = false;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'>' => return true,
+            b'<' => return false,
+            q @ b'"' | q @ b'\'' if after_equals => {
+                match bytes.slice_from(i + 1).iter().position(|&b| b == q) {
+                    Some(n) => i += n + 1,
+                    None => return false,
+                }
+                after_equals = false;
+            }
+            b'=' => after_equals = true,
+            b if is_space(b) => (),
+            _ => after_equals = false,
+        }
+        i += 1;
+    }
+
+    // We don't know how the tag would end.
+    false
+}
+
+/// Is there a plausible tag, comment or doctype at byte offset `i`?
+pub fn is_tag_boundary(input: &str, i: uint) -> bool {
+    let bytes = input.as_bytes();
+    if bytes.get(i) != Some(&b'<') {
+        return false;
+    }
+
+    let rest = bytes.slice_from(i + 1);
+    if rest.starts_with(b"!--") || starts_with_ignore_case(rest, b"!doctype") {
+        true
+    } else if rest.starts_with(b"/") {
+        plausible_tag_rest(rest, 1)
+    } else {
+        plausible_tag_rest(rest, 0)
+    }
+}
+
+/// Find the first position at or after byte offset `from` where the
+/// tokenizer could restart in the data state: a `<` which begins a
+/// comment, a doctype, or a tag with a name, well-formed attributes
+/// and a closing `>`.
+pub fn next_tag_boundary(input: &str, from: uint) -> Option<uint> {
+    let bytes = input.as_bytes();
+    let mut i = from;
+    while i < bytes.len() {
+        match bytes.slice_from(i).iter().position(|&b| b == b'<') {
+            None => return None,
+            Some(n) => i += n,
+        }
+        if is_tag_boundary(input, i) {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{next_tag_boundary, is_tag_boundary};
+
+    test_eq!(start_tag, next_tag_boundary("x=\"y\">abc <p class=a>", 0), Some(10))
+    test_eq!(end_tag, next_tag_boundary("garbage</div >", 0), Some(7))
+    test_eq!(comment, next_tag_boundary("a < b <!-- c", 0), Some(6))
+    test_eq!(doctype, next_tag_boundary("é<!DOCTYPE html>", 0), Some(2))
+    test_eq!(from_offset, next_tag_boundary("<a><b>", 1), Some(3))
+    test_eq!(none, next_tag_boundary("1 < 2 and 3 <4", 0), None)
+
+    test_eq!(quoted_lt, is_tag_boundary("<a title='<x>'>", 0), true)
+    test_eq!(unquoted_lt, is_tag_boundary("<a title=<x>", 0), false)
+    test_eq!(unclosed_quote, is_tag_boundary("<a title='x>", 0), false)
+    test_eq!(no_gt, is_tag_boundary("<a href=x", 0), false)
+    test_eq!(digit_name, is_tag_boundary("<3>", 0), false)
+    test_eq!(bare_end, is_tag_boundary("</>", 0), false)
+    test_eq!(self_closing, is_tag_boundary("<br/>", 0), true)
+}
@@ -0,0 +1,268 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Generating HTML from code, without a template language.
+//!
+//! An `Html` value is a small tree of elements, text and comments.
+//! It's written out by the same `Serializer` as a parsed tree, so text
+//! and attribute values are escaped the same way, and read back as
+//! given.  Some things which can't be written at all are an
+//! `InvalidInput` error rather than broken output: an element or
+//! attribute name the tokenizer wouldn't see as one, children of a
+//! void element like `<br>`, a comment containing `-->`, or raw text
+//! like a `<script>` containing its own end tag.
+//!
+//! Nothing else about the structure is checked.  Nesting which the
+//! tree builder would rearrange, like a `<div>` inside a `<p>`, is
+//! written as given, and won't parse back into the same tree.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let note = elem("p").attr("class", "note")
+//!     .text("1 < 2")
+//!     .child(elem("a").attr("href", "/more?a=1&b=2").text("More"));
+//! try!(note.write(&mut out, Default::default()));
+//! ```
+
+use core::prelude::*;
+
+use super::{Serializable, Serializer, SerializeOpts, serialize, is_void};
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+use std::io::{Writer, IoResult, IoError, InvalidInput};
+
+use string_cache::{Atom, QualName};
+
+/// A piece of HTML to generate.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum Html {
+    /// An element, with its attributes and children.
+    HtmlElement(QualName, Vec<(QualName, String)>, Vec<Html>),
+
+    /// Text, which is escaped as needed.
+    HtmlText(String),
+
+    HtmlComment(String),
+
+    /// `<!DOCTYPE name>`.
+    HtmlDoctype(String),
+
+    /// A list of siblings, with nothing around them.
+    HtmlFragment(Vec<Html>),
+}
+
+/// An HTML element with no attributes or children yet.
+pub fn elem(name: &str) -> Html {
+    elem_ns(QualName::new(ns!(HTML), Atom::from_slice(name)))
+}
+
+/// An element in any namespace, e.g. SVG.
+pub fn elem_ns(name: QualName) -> Html {
+    HtmlElement(name, vec!(), vec!())
+}
+
+pub fn text(text: &str) -> Html {
+    HtmlText(String::from_str(text))
+}
+
+pub fn comment(text: &str) -> Html {
+    HtmlComment(String::from_str(text))
+}
+
+pub fn doctype(name: &str) -> Html {
+    HtmlDoctype(String::from_str(name))
+}
+
+pub fn fragment(children: Vec<Html>) -> Html {
+    HtmlFragment(children)
+}
+
+impl Html {
+    /// Add an attribute to an element.
+    ///
+    /// ## Failure
+    ///
+    /// Fails if this isn't an element.
+    pub fn attr(mut self, name: &str, value: &str) -> Html {
+        match self {
+            HtmlElement(_, ref mut attrs, _) => attrs.push((
+                QualName::new(ns!(""), Atom::from_slice(name)), String::from_str(value))),
+            _ => fail!("attr() on something other than an element"),
+        }
+        self
+    }
+
+    /// Add a child to an element or fragment.
+    ///
+    /// ## Failure
+    ///
+    /// Fails if this is something else.
+    pub fn child(mut self, child: Html) -> Html {
+        match self {
+            HtmlElement(_, _, ref mut children) | HtmlFragment(ref mut children)
+                => children.push(child),
+            _ => fail!("child() on something other than an element or fragment"),
+        }
+        self
+    }
+
+    /// Add a text child to an element or fragment.
+    pub fn text(self, s: &str) -> Html {
+        self.child(text(s))
+    }
+
+    /// Write out this HTML, including the element itself.
+    pub fn write<Wr: Writer>(&self, writer: &mut Wr, opts: SerializeOpts) -> IoResult<()> {
+        serialize(writer, &Whole(self), opts)
+    }
+}
+
+// Serializes the element itself, where `serialize` would only do its
+// children.
+struct Whole<'a>(&'a Html);
+
+impl<'a> Serializable for Whole<'a> {
+    fn serialize<'wr, Wr: Writer>(&self,
+            serializer: &mut Serializer<'wr, Wr>,
+            _incl_self: bool) -> IoResult<()> {
+        let Whole(html) = *self;
+        html.serialize(serializer, true)
+    }
+}
+
+fn invalid(desc: &'static str) -> IoResult<()> {
+    Err(IoError {
+        kind: InvalidInput,
+        desc: desc,
+        detail: None,
+    })
+}
+
+// Would the tokenizer take all of this as a name, and nothing more?
+fn is_name(name: &str, is_elem: bool) -> bool {
+    let mut chars = name.chars();
+    let first_ok = match chars.next() {
+        None => false,
+        Some(c) if is_elem => c.is_alphabetic() && c < '\x80',
+        Some(_) => true,
+    };
+    first_ok && !name.chars().any(|c| match c {
+        '\t' | '\n' | '\x0C' | '\r' | ' ' | '/' | '>' | '=' | '"' | '\'' | '<' | '\0' => true,
+        _ => false,
+    })
+}
+
+// Would this comment text end the comment early, or run into the
+// `-->` after it?
+fn is_comment_text(text: &str) -> bool {
+    !(text.starts_with(">") || text.starts_with("->") || text.contains("-->")
+        || text.contains("--!>") || text.ends_with("<!-") || text.ends_with("-"))
+}
+
+impl Serializable for Html {
+    fn serialize<'wr, Wr: Writer>(&self,
+            serializer: &mut Serializer<'wr, Wr>,
+            incl_self: bool) -> IoResult<()> {
+
+        match (incl_self, self) {
+            (_, &HtmlElement(ref name, ref attrs, ref children)) => {
+                if incl_self {
+                    if !is_name(name.local.as_slice(), true) {
+                        return invalid("not a valid element name");
+                    }
+                    if !attrs.iter().all(|&(ref n, _)| is_name(n.local.as_slice(), false)) {
+                        return invalid("not a valid attribute name");
+                    }
+                    if is_void(name) && !children.is_empty() {
+                        return invalid("void element with children");
+                    }
+                    try!(serializer.start_elem(name.clone(),
+                        attrs.iter().map(|&(ref n, ref v)| (n, v.as_slice()))));
+                }
+                for child in children.iter() {
+                    try!(child.serialize(serializer, true));
+                }
+                if incl_self {
+                    try!(serializer.end_elem(name.clone()));
+                }
+                Ok(())
+            }
+
+            (_, &HtmlFragment(ref children)) => {
+                for child in children.iter() {
+                    try!(child.serialize(serializer, true));
+                }
+                Ok(())
+            }
+
+            (false, _) => Ok(()),
+
+            (true, &HtmlText(ref text)) => serializer.write_text(text.as_slice()),
+
+            (true, &HtmlComment(ref text)) => if is_comment_text(text.as_slice()) {
+                serializer.write_comment(text.as_slice())
+            } else {
+                invalid("comment text would end the comment")
+            },
+
+            (true, &HtmlDoctype(ref name)) => if is_name(name.as_slice(), false) {
+                serializer.write_doctype(name.as_slice())
+            } else {
+                invalid("not a valid doctype name")
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{Html, elem, elem_ns, text, comment, doctype, fragment};
+
+    use core::default::Default;
+    use collections::string::String;
+    use std::io::{MemWriter, IoResult};
+
+    fn html(h: Html) -> IoResult<String> {
+        let mut out = MemWriter::new();
+        try!(h.write(&mut out, Default::default()));
+        Ok(String::from_utf8(out.unwrap()).unwrap())
+    }
+
+    #[test]
+    fn escaping() {
+        let h = elem("p").attr("title", "a\"b&c").text("1 < 2")
+            .child(elem("br")).child(comment(" c "));
+        assert_eq!(html(h).unwrap().as_slice(),
+            "<p title=\"a&quot;b&amp;c\">1 &lt; 2<br><!-- c --></p>");
+    }
+
+    #[test]
+    fn page() {
+        let h = fragment(vec!(doctype("html"),
+            elem("html").child(elem("body").child(elem("script").text("if (a<b) f()")))));
+        assert_eq!(html(h).unwrap().as_slice(),
+            "<!DOCTYPE html><html><body><script>if (a<b) f()</script></body></html>");
+    }
+
+    #[test]
+    fn unparseable() {
+        assert!(html(elem("a b")).is_err());
+        assert!(html(elem("1")).is_err());
+        assert!(html(elem("a").attr("x>", "")).is_err());
+        assert!(html(comment("a-->b")).is_err());
+        assert!(html(elem("script").text("</script>")).is_err());
+        assert!(html(text("</script>")).is_ok());
+        assert!(html(elem("br").text("x")).is_err());
+        assert!(html(elem_ns(qualname!(SVG, br)).text("x")).is_ok());
+    }
+}
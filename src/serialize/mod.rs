@@ -9,13 +9,25 @@
 
 use core::prelude::*;
 
-use std::io::{Writer, IoResult};
+use std::io::{Writer, IoResult, IoError, InvalidInput, MemWriter};
 use core::default::Default;
+use core::mem::replace;
 use collections::MutableSeq;
 use collections::vec::Vec;
+use collections::string::String;
+
+use util::str::{AsciiExt, is_ascii_whitespace};
+use tokenizer::states::{RawKind, Rawtext, ScriptData, ScriptDataEscaped};
+use tokenizer::states::{Escaped, DoubleEscaped};
 
 use string_cache::{Atom, QualName};
 
+pub use self::stream::{StreamSink, StreamOpts};
+
+mod stream;
+
+pub mod build;
+
 //§ serializing-html-fragments
 pub trait Serializable {
     fn serialize<'wr, Wr: Writer>(&self, serializer: &mut Serializer<'wr, Wr>, incl_self: bool) -> IoResult<()>;
@@ -24,20 +36,476 @@ pub trait Serializable {
 pub fn serialize<Wr: Writer, T: Serializable>
     (writer: &mut Wr, node: &T, opts: SerializeOpts) -> IoResult<()> {
 
-    let mut ser = Serializer::new(writer, opts);
-    node.serialize(&mut ser, false)
+    match opts.line_ending {
+        Lf => {
+            let mut ser = Serializer::new(writer, opts);
+            node.serialize(&mut ser, false)
+        }
+        CrLf => {
+            let mut writer = CrLfWriter::new(writer);
+            let mut ser = Serializer::new(&mut writer, opts);
+            node.serialize(&mut ser, false)
+        }
+    }
+}
+
+/// Serialize into a new string, with room for `size_hint` bytes up
+/// front.  The length of the input the tree was parsed from is a good
+/// guess.
+pub fn serialize_to_string<T: Serializable>
+    (node: &T, opts: SerializeOpts, size_hint: uint) -> IoResult<String> {
+
+    let mut out = MemWriter::with_capacity(size_hint);
+    try!(serialize(&mut out, node, opts));
+    Ok(String::from_utf8(out.unwrap()).unwrap())
+}
+
+/// Which line endings to write.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum LineEnding {
+    /// `\n`, which is what the parser turns every line ending into.
+    Lf,
+
+    /// `\r\n`.
+    CrLf,
+}
+
+impl LineEnding {
+    /// The line ending `input` uses, for writing a document back with
+    /// its own line endings: `CrLf` if its first line ends with `\r\n`,
+    /// and otherwise `Lf`.  Only the first line ending is looked at, so
+    /// a document which mixes them gets that one throughout.
+    pub fn of(input: &str) -> LineEnding {
+        match input.find('\n') {
+            Some(i) if i > 0 && input.as_bytes()[i - 1] == b'\r' => CrLf,
+            _ => Lf,
+        }
+    }
+}
+
+/// A `Writer` which turns each `\n` into `\r\n`, for using a
+/// `Serializer` or `StreamSink` directly with `CrLf` line endings.
+pub struct CrLfWriter<'wr, Wr: 'wr> {
+    inner: &'wr mut Wr,
+}
+
+impl<'wr, Wr: Writer> CrLfWriter<'wr, Wr> {
+    pub fn new(inner: &'wr mut Wr) -> CrLfWriter<'wr, Wr> {
+        CrLfWriter {
+            inner: inner,
+        }
+    }
+}
+
+impl<'wr, Wr: Writer> Writer for CrLfWriter<'wr, Wr> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        for (i, line) in buf.split(|&b| b == b'\n').enumerate() {
+            if i > 0 {
+                try!(self.inner.write(b"\r\n"));
+            }
+            try!(self.inner.write(line));
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
 }
 
 pub struct SerializeOpts {
     /// Is scripting enabled?
     pub scripting_enabled: bool,
+
+    /// The parser drops a newline immediately after a `<pre>`,
+    /// `<textarea>`, or `<listing>` start tag.  Write an extra newline
+    /// there if the content starts with one, so that it survives a
+    /// round trip?  Default: true
+    pub restore_initial_newline: bool,
+
+    /// Pretty-print, putting block-level elements on their own lines
+    /// and indenting their content by this many spaces per level?
+    /// Whitespace-only text next to those line breaks is dropped.  The
+    /// content of `<pre>`, `<textarea>`, `<script>` and the like is
+    /// never changed.  Default: None
+    pub indent: Option<uint>,
+
+    /// When pretty-printing, put inline elements such as `<b>` on their
+    /// own lines too?  This can add visible spaces between words.
+    /// Default: false
+    pub break_inline_elements: bool,
+
+    /// Put each attribute on its own line if a start tag would be
+    /// longer than this many bytes.  The attributes are indented like
+    /// the element's children when pretty-printing, and by four spaces
+    /// otherwise.  Default: None
+    pub wrap_attrs_at: Option<uint>,
+
+    /// Write attributes sorted by name, rather than in document order?
+    /// Default: false
+    pub sort_attrs: bool,
+
+    /// Write characters which can't be seen, like U+200B ZERO WIDTH
+    /// SPACE and control characters, as numeric character references?
+    /// This doesn't apply inside `<script>` and the like, or to
+    /// comments.  Default: false
+    pub escape_invisible: bool,
+
+    /// The line endings to write, in text, attribute values and
+    /// comments as well as between pretty-printed lines.  The parser
+    /// turns every line ending into `\n`; use `LineEnding::of` on the
+    /// input to write its own line endings back.  Only `serialize`
+    /// applies this; wrap the writer in a `CrLfWriter` to get the same
+    /// from a `Serializer`.  Default: Lf
+    pub line_ending: LineEnding,
 }
 
 impl Default for SerializeOpts {
     fn default() -> SerializeOpts {
         SerializeOpts {
             scripting_enabled: true,
+            restore_initial_newline: true,
+            indent: None,
+            break_inline_elements: false,
+            wrap_attrs_at: None,
+            sort_attrs: false,
+            escape_invisible: false,
+            line_ending: Lf,
+        }
+    }
+}
+
+impl SerializeOpts {
+    /// Options for HTML which is kept in version control, so that a
+    /// change to the document gives a small, readable diff.  Attributes
+    /// are sorted, start tags longer than 80 bytes get one attribute
+    /// per line, and invisible characters are escaped.  Unlike
+    /// pretty-printing, this doesn't change any whitespace in the text.
+    pub fn diffable() -> SerializeOpts {
+        SerializeOpts {
+            wrap_attrs_at: Some(80),
+            sort_attrs: true,
+            escape_invisible: true,
+            .. Default::default()
+        }
+    }
+}
+
+// Write a run of text which contains no other special characters,
+// escaping U+00A0.
+fn write_nbsp_escaped<Wr: Writer>(writer: &mut Wr, text: &str) -> IoResult<()> {
+    for (i, piece) in text.split('\xA0').enumerate() {
+        if i > 0 {
+            try!(writer.write_str("&nbsp;"));
+        }
+        try!(writer.write_str(piece));
+    }
+    Ok(())
+}
+
+// Characters with no visible form, which `escape_invisible` writes as
+// references.  This leaves out tab, newline and form feed.
+fn is_invisible(c: char) -> bool {
+    match c {
+        '\x00'...'\x08' | '\x0B' | '\x0D'...'\x1F' | '\x7F'...'\x9F' | '\xAD'
+            | '\u200B'...'\u200F' | '\u2028'...'\u202E' | '\u2060'...'\u2064'
+            | '\u2066'...'\u2069' | '\uFEFF' => true,
+        _ => false,
+    }
+}
+
+// Like `write_escaped`, but also writing invisible characters as
+// hexadecimal references, e.g. `&#x200B;`.
+fn write_escaped_invisible<Wr: Writer>(writer: &mut Wr, text: &str, attr_mode: bool)
+        -> IoResult<()> {
+    let mut rest = text;
+    loop {
+        let n = rest.find(is_invisible).unwrap_or(rest.len());
+        try!(write_escaped(writer, rest.slice_to(n), attr_mode));
+        if n == rest.len() {
+            return Ok(());
+        }
+        let c = rest.char_at(n);
+        try!(writer.write_str(format!("&#x{:X};", c as u32).as_slice()));
+        rest = rest.slice_from(n + c.len_utf8_bytes());
+    }
+}
+
+fn write_escaped<Wr: Writer>(writer: &mut Wr, text: &str, attr_mode: bool) -> IoResult<()> {
+    // Same scanner as the tokenizer uses for its input.
+    let set = if attr_mode {
+        small_char_set!('&' '"')
+    } else {
+        small_char_set!('&' '<' '>')
+    };
+
+    let mut rest = text;
+    loop {
+        let n = set.nonmember_prefix_len(rest);
+        try!(write_nbsp_escaped(writer, rest.slice_to(n)));
+        if n == rest.len() {
+            return Ok(());
         }
+        try!(writer.write_str(match rest.as_bytes()[n] {
+            b'&' => "&amp;",
+            b'"' => "&quot;",
+            b'<' => "&lt;",
+            _ => "&gt;",
+        }));
+        rest = rest.slice_from(n + 1);
+    }
+}
+
+// Elements with no end tag, whose children aren't written.
+fn is_void(name: &QualName) -> bool {
+    name.ns == ns!(HTML) && match name.local {
+        atom!(area) | atom!(base) | atom!(basefont) | atom!(bgsound) | atom!(br)
+        | atom!(col) | atom!(embed) | atom!(frame) | atom!(hr) | atom!(img)
+        | atom!(input) | atom!(keygen) | atom!(link) | atom!(menuitem)
+        | atom!(meta) | atom!(param) | atom!(source) | atom!(track) | atom!(wbr)
+            => true,
+        _ => false,
+    }
+}
+
+// Write an attribute's name, with a prefix if it's in one of the
+// namespaces which foreign content uses, e.g. `xlink:href`.
+fn write_attr_name<Wr: Writer>(writer: &mut Wr, name: &QualName) -> IoResult<()> {
+    match name.ns {
+        ns!("") => (),
+        ns!(XML) => try!(writer.write_str("xml:")),
+        ns!(XMLNS) => if name.local.as_slice() != "xmlns" {
+            try!(writer.write_str("xmlns:"));
+        },
+        ns!(XLink) => try!(writer.write_str("xlink:")),
+        // FIXME: the spec leaves other namespaces up to us
+        _ => (),
+    }
+    writer.write_str(name.local.as_slice())
+}
+
+// Write `="value"`, after an attribute's name.
+fn write_attr_value<Wr: Writer>(writer: &mut Wr, value: &str, escape_invisible: bool)
+        -> IoResult<()> {
+    try!(writer.write_str("=\""));
+    if escape_invisible {
+        try!(write_escaped_invisible(writer, value, true));
+    } else {
+        try!(write_escaped(writer, value, true));
+    }
+    writer.write_char('"')
+}
+
+/// What to do with newlines when converting plain text to HTML.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum NewlineMode {
+    /// Leave newlines as they are.
+    KeepNewlines,
+
+    /// Replace each newline with `<br>`.
+    NewlinesToBr,
+
+    /// Wrap each run of text separated by blank lines in `<p>`, and
+    /// replace the remaining newlines with `<br>`.
+    NewlinesToParagraphs,
+}
+
+fn write_lines<Wr: Writer>(writer: &mut Wr, text: &str) -> IoResult<()> {
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            try!(writer.write_str("<br>\n"));
+        }
+        try!(write_escaped(writer, line, false));
+    }
+    Ok(())
+}
+
+/// Write plain text as a HTML fragment, escaping it the same way the
+/// serializer escapes text nodes.
+pub fn text_to_html<Wr: Writer>(writer: &mut Wr, text: &str, newlines: NewlineMode)
+        -> IoResult<()> {
+    let text = text.replace("\r\n", "\n").replace("\r", "\n");
+    let text = text.as_slice();
+    match newlines {
+        KeepNewlines => write_escaped(writer, text, false),
+        NewlinesToBr => write_lines(writer, text),
+        NewlinesToParagraphs => {
+            let mut first = true;
+            for para in text.split_str("\n\n") {
+                let para = para.trim_chars('\n');
+                if para.is_empty() {
+                    continue;
+                }
+                if !first {
+                    try!(writer.write_char('\n'));
+                }
+                first = false;
+                try!(writer.write_str("<p>"));
+                try!(write_lines(writer, para));
+                try!(writer.write_str("</p>"));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// How text inside an element is serialized.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum TextEscaping {
+    /// Escape `&`, `<`, `>`, and U+00A0.  This includes the RCDATA
+    /// elements `<title>` and `<textarea>`, where escaping `<` also
+    /// takes care of the end tag.
+    EscapeText,
+
+    /// Write the text as it is, e.g. inside `<script>` or `<style>`.
+    /// The text must not contain the element's end tag.
+    RawText,
+}
+
+/// How text inside a HTML element with this name is serialized.
+pub fn text_escaping(parent: &Atom, scripting_enabled: bool) -> TextEscaping {
+    match *parent {
+        atom!(style) | atom!(script) | atom!(xmp)
+        | atom!(iframe) | atom!(noembed) | atom!(noframes)
+        | atom!(plaintext) => RawText,
+
+        atom!(noscript) if scripting_enabled => RawText,
+
+        _ => EscapeText,
+    }
+}
+
+/// Would the tokenizer end the element `name` somewhere in this raw
+/// text, at `</name` case-insensitively, followed by whitespace, `/`,
+/// or `>`?  Inside `<script>` this follows the script data escape
+/// states, so the `</script>` in `<!--<script>x</script>-->` doesn't
+/// count.  Nothing ends a `<plaintext>`.
+pub fn contains_end_tag(text: &str, name: &str) -> bool {
+    match RawTextScan::new(name) {
+        Some(mut scan) => scan.scan(text, name, true).is_err(),
+        None => false,
+    }
+}
+
+// Is `name` at the start of `bytes`, followed by what ends a tag name?
+fn tag_name_at(bytes: &[u8], name: &[u8]) -> bool {
+    bytes.len() > name.len() && bytes.slice_to(name.len()).eq_ignore_ascii_case(name)
+        && match bytes[name.len()] {
+            b'\t' | b'\n' | b'\x0C' | b' ' | b'/' | b'>' => true,
+            _ => false,
+        }
+}
+
+/// Where the tokenizer would be after some of an element's raw text,
+/// so that text written in pieces can be checked as a whole.
+struct RawTextScan {
+    kind: RawKind,
+
+    /// Dashes in a row, which with `>` end a script data escape.
+    dashes: uint,
+}
+
+impl RawTextScan {
+    fn new(name: &str) -> Option<RawTextScan> {
+        let kind = if name.eq_ignore_ascii_case("plaintext") {
+            return None;
+        } else if name.eq_ignore_ascii_case("script") {
+            ScriptData
+        } else {
+            Rawtext
+        };
+        Some(RawTextScan {
+            kind: kind,
+            dashes: 0,
+        })
+    }
+
+    /// Scan more of the text of the element `name`.  Returns `Err` if
+    /// the element would end.  Otherwise returns how much was scanned:
+    /// unless `complete`, we stop at a `<` which the following text
+    /// might turn into a tag.
+    fn scan(&mut self, text: &str, name: &str, complete: bool) -> Result<uint, ()> {
+        let bytes = text.as_bytes();
+        let name = name.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            let rest = bytes.slice_from(i);
+            match rest[0] {
+                b'<' => {
+                    // `</name` and one more byte is the longest we need.
+                    if !complete && rest.len() < name.len() + 3 {
+                        return Ok(i);
+                    }
+                    self.dashes = 0;
+                    let end_tag = rest.len() > 2 && rest[1] == b'/'
+                        && tag_name_at(rest.slice_from(2), name);
+                    let kind = self.kind;
+                    match kind {
+                        ScriptDataEscaped(DoubleEscaped) if end_tag => {
+                            self.kind = ScriptDataEscaped(Escaped);
+                            i += name.len() + 3;
+                            continue;
+                        }
+                        ScriptDataEscaped(DoubleEscaped) => (),
+                        _ if end_tag => return Err(()),
+                        ScriptData if rest.starts_with(b"<!--") => {
+                            self.kind = ScriptDataEscaped(Escaped);
+                            self.dashes = 2;
+                            i += 4;
+                            continue;
+                        }
+                        ScriptDataEscaped(Escaped) if tag_name_at(rest.slice_from(1), name) => {
+                            self.kind = ScriptDataEscaped(DoubleEscaped);
+                            i += name.len() + 2;
+                            continue;
+                        }
+                        _ => (),
+                    }
+                }
+                b'-' => self.dashes += 1,
+                b'>' if self.dashes >= 2 => {
+                    let kind = self.kind;
+                    match kind {
+                        ScriptDataEscaped(_) => self.kind = ScriptData,
+                        _ => (),
+                    }
+                    self.dashes = 0;
+                }
+                _ => self.dashes = 0,
+            }
+            i += 1;
+        }
+        Ok(i)
+    }
+
+    /// Check the rest of the text, before the element's end tag.
+    fn finish(&mut self, tail: &str, name: &str) -> Result<(), ()> {
+        try!(self.scan(tail, name, true));
+        match self.kind {
+            // Our end tag would only end the double escape.
+            ScriptDataEscaped(DoubleEscaped) => Err(()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Does pretty-printing put this HTML element on its own line?
+fn is_block(name: &Atom) -> bool {
+    match *name {
+        atom!(address) | atom!(article) | atom!(aside) | atom!(base)
+        | atom!(blockquote) | atom!(body) | atom!(caption) | atom!(col)
+        | atom!(colgroup) | atom!(dd) | atom!(details) | atom!(dialog)
+        | atom!(div) | atom!(dl) | atom!(dt) | atom!(fieldset)
+        | atom!(figcaption) | atom!(figure) | atom!(footer) | atom!(form)
+        | atom!(h1) | atom!(h2) | atom!(h3) | atom!(h4) | atom!(h5) | atom!(h6)
+        | atom!(head) | atom!(header) | atom!(hgroup) | atom!(hr) | atom!(html)
+        | atom!(li) | atom!(link) | atom!(main) | atom!(meta) | atom!(nav)
+        | atom!(noscript) | atom!(ol) | atom!(option) | atom!(p) | atom!(pre)
+        | atom!(script) | atom!(section) | atom!(select) | atom!(style)
+        | atom!(table) | atom!(tbody) | atom!(td) | atom!(tfoot) | atom!(th)
+        | atom!(thead) | atom!(title) | atom!(tr) | atom!(ul)
+            => true,
+        _ => false,
     }
 }
 
@@ -45,6 +513,47 @@ struct ElemInfo {
     html_name: Option<Atom>,
     ignore_children: bool,
     processed_first_child: bool,
+
+    /// The end of the raw text written so far in this element, in case
+    /// an end tag is split across text nodes, and how the text before
+    /// it was tokenized.
+    raw_tail: String,
+    raw_scan: Option<RawTextScan>,
+
+    /// Is pretty-printing off inside this element, e.g. in a `<pre>`?
+    preserve_space: bool,
+
+    /// Was the element put on its own line?
+    block: bool,
+
+    /// Should whitespace at the start of the next text be dropped,
+    /// because it would begin a line?
+    trim_space: bool,
+
+    /// Did the last thing written in this element end a line, so that
+    /// inline content needs a new one?
+    after_block: bool,
+
+    /// Did the element get any children on their own lines?
+    has_block_child: bool,
+}
+
+impl ElemInfo {
+    fn new(html_name: Option<Atom>, ignore_children: bool, preserve_space: bool,
+            block: bool) -> ElemInfo {
+        ElemInfo {
+            html_name: html_name,
+            ignore_children: ignore_children,
+            processed_first_child: false,
+            raw_tail: String::new(),
+            raw_scan: None,
+            preserve_space: preserve_space,
+            block: block,
+            trim_space: block,
+            after_block: false,
+            has_block_child: false,
+        }
+    }
 }
 
 pub type AttrRef<'a> = (&'a QualName, &'a str);
@@ -53,105 +562,231 @@ pub struct Serializer<'wr, Wr:'wr> {
     writer: &'wr mut Wr,
     opts: SerializeOpts,
     stack: Vec<ElemInfo>,
+
+    /// Has nothing been written yet?  Pretty-printing doesn't start
+    /// the output with a newline.
+    at_start: bool,
 }
 
 impl<'wr, Wr: Writer> Serializer<'wr, Wr> {
-    fn new(writer: &'wr mut Wr, opts: SerializeOpts) -> Serializer<'wr, Wr> {
+    /// Create a serializer, for writing a tree which isn't
+    /// `Serializable`, or a stream of tokens.  Calls to `start_elem`
+    /// and `end_elem` must be balanced.
+    pub fn new(writer: &'wr mut Wr, opts: SerializeOpts) -> Serializer<'wr, Wr> {
         Serializer {
             writer: writer,
             opts: opts,
-            stack: vec!(ElemInfo {
-                html_name: None,
-                ignore_children: false,
-                processed_first_child: false,
-            }),
+            stack: vec!(ElemInfo::new(None, false, false, true)),
+            at_start: true,
+        }
+    }
+
+    /// Are we pretty-printing here, i.e. not inside a `<pre>` etc.?
+    fn pretty(&self) -> bool {
+        self.opts.indent.is_some() && !self.stack.last().map_or(false, |p| p.preserve_space)
+    }
+
+    /// How deeply children of the current element are nested.
+    fn depth(&self) -> uint {
+        self.stack.len() - 1
+    }
+
+    /// Start a new line, indented to `depth`.
+    fn newline(&mut self, depth: uint) -> IoResult<()> {
+        if self.at_start {
+            return Ok(());
+        }
+        try!(self.writer.write_char('\n'));
+        for _ in range(0, self.opts.indent.unwrap_or(0) * depth) {
+            try!(self.writer.write_char(' '));
+        }
+        Ok(())
+    }
+
+    /// Get ready to write something inline: start a new line if the
+    /// last thing in the current element was on its own line.
+    fn before_inline(&mut self) -> IoResult<()> {
+        if self.pretty() && self.parent().after_block {
+            let depth = self.depth();
+            try!(self.newline(depth));
         }
+        self.at_start = false;
+        let parent = self.parent();
+        parent.trim_space = false;
+        parent.after_block = false;
+        Ok(())
+    }
+
+    /// Put something on its own line.
+    fn before_block(&mut self) -> IoResult<()> {
+        let depth = self.depth();
+        try!(self.newline(depth));
+        self.at_start = false;
+        self.parent().has_block_child = true;
+        Ok(())
+    }
+
+    /// Note that something on its own line has ended.
+    fn end_line(&mut self) {
+        let parent = self.parent();
+        parent.trim_space = true;
+        parent.after_block = true;
+    }
+
+    fn write_attrs<'a, AttrIter: Iterator<AttrRef<'a>>>(&mut self, attrs: AttrIter,
+            name_len: uint) -> IoResult<()> {
+        if !self.opts.sort_attrs && self.opts.wrap_attrs_at.is_none() {
+            // Nothing to reorder or measure, so write them straight out.
+            for (name, value) in attrs {
+                try!(self.writer.write_char(' '));
+                try!(write_attr_name(&mut *self.writer, name));
+                try!(write_attr_value(&mut *self.writer, value, self.opts.escape_invisible));
+            }
+            return Ok(());
+        }
+
+        // Each attribute's name, and the whole attribute as written.
+        let mut parts = vec!();
+        for (name, value) in attrs {
+            let mut part = MemWriter::new();
+            try!(write_attr_name(&mut part, name));
+            let name_end = part.get_ref().len();
+            try!(write_attr_value(&mut part, value, self.opts.escape_invisible));
+            parts.push((name_end, String::from_utf8(part.unwrap()).unwrap()));
+        }
+
+        if self.opts.sort_attrs {
+            parts.sort_by(|&(a_end, ref a), &(b_end, ref b)|
+                a.as_slice().slice_to(a_end).cmp(&b.as_slice().slice_to(b_end)));
+        }
+
+        let len = parts.iter().fold(name_len + 2, |n, &(_, ref p)| n + p.len() + 1);
+        let wrap = self.opts.wrap_attrs_at.map_or(false, |w| len > w);
+        let depth = self.depth() + 1;
+        for &(_, ref part) in parts.iter() {
+            if !wrap {
+                try!(self.writer.write_char(' '));
+            } else if self.opts.indent.is_some() {
+                // Indent the attributes like the element's children.
+                try!(self.newline(depth));
+            } else {
+                try!(self.writer.write_str("\n    "));
+            }
+            try!(self.writer.write_str(part.as_slice()));
+        }
+        Ok(())
     }
 
     fn parent<'a>(&'a mut self) -> &'a mut ElemInfo {
         self.stack.last_mut().expect("no parent ElemInfo")
     }
 
-    fn write_escaped(&mut self, text: &str, attr_mode: bool) -> IoResult<()> {
-        for c in text.chars() {
-            try!(match c {
-                '&' => self.writer.write_str("&amp;"),
-                '\xA0' => self.writer.write_str("&nbsp;"),
-                '"' if attr_mode => self.writer.write_str("&quot;"),
-                '<' if !attr_mode => self.writer.write_str("&lt;"),
-                '>' if !attr_mode => self.writer.write_str("&gt;"),
-                c => self.writer.write_char(c),
-            });
+    fn write_escaped(&mut self, text: &str) -> IoResult<()> {
+        if self.opts.escape_invisible {
+            write_escaped_invisible(&mut *self.writer, text, false)
+        } else {
+            write_escaped(&mut *self.writer, text, false)
         }
-        Ok(())
     }
 
     pub fn start_elem<'a, AttrIter: Iterator<AttrRef<'a>>>(
         &mut self,
         name: QualName,
-        mut attrs: AttrIter) -> IoResult<()> {
+        attrs: AttrIter) -> IoResult<()> {
 
         let html_name = match name.ns {
             ns!(HTML) => Some(name.local.clone()),
-            _ => fail!("FIXME: Handle qualified tag names"),
+            _ => None,
         };
 
         if self.parent().ignore_children {
-            self.stack.push(ElemInfo {
-                html_name: html_name,
-                ignore_children: true,
-                processed_first_child: false,
-            });
+            self.stack.push(ElemInfo::new(html_name, true, true, false));
             return Ok(());
         }
 
+        let pretty = self.pretty();
+        let block = pretty && (self.opts.break_inline_elements
+            || html_name.as_ref().map_or(false, |n| is_block(n)));
+        if block {
+            try!(self.before_block());
+        } else {
+            try!(self.before_inline());
+        }
+
         try!(self.writer.write_char('<'));
         try!(self.writer.write_str(name.local.as_slice()));
-        for (name, value) in attrs {
-            try!(self.writer.write_char(' '));
-            // FIXME: qualified names
-            assert!(name.ns == ns!(""));
-            try!(self.writer.write_str(name.local.as_slice()));
-            try!(self.writer.write_str("=\""));
-            try!(self.write_escaped(value, true));
-            try!(self.writer.write_char('"'));
-        }
+        try!(self.write_attrs(attrs, name.local.as_slice().len()));
         try!(self.writer.write_char('>'));
 
-        let ignore_children = name.ns == ns!(HTML) && match name.local {
-            atom!(area) | atom!(base) | atom!(basefont) | atom!(bgsound) | atom!(br)
-            | atom!(col) | atom!(embed) | atom!(frame) | atom!(hr) | atom!(img)
-            | atom!(input) | atom!(keygen) | atom!(link) | atom!(menuitem)
-            | atom!(meta) | atom!(param) | atom!(source) | atom!(track) | atom!(wbr)
-                => true,
-            _ => false,
-        };
+        let ignore_children = is_void(&name);
 
         self.parent().processed_first_child = true;
 
-        self.stack.push(ElemInfo {
-            html_name: html_name,
-            ignore_children: ignore_children,
-            processed_first_child: false,
-        });
+        let scripting_enabled = self.opts.scripting_enabled;
+        let preserve_space = !pretty || match html_name {
+            Some(atom!(pre)) | Some(atom!(textarea)) | Some(atom!(listing)) => true,
+            Some(ref name) => text_escaping(name, scripting_enabled) == RawText,
+            None => false,
+        };
+        self.stack.push(ElemInfo::new(html_name, ignore_children, preserve_space, block));
 
         Ok(())
     }
 
     pub fn end_elem(&mut self, name: QualName) -> IoResult<()> {
-        let info = self.stack.pop().expect("no ElemInfo");
+        let mut info = self.stack.pop().expect("no ElemInfo");
+        match info.raw_scan {
+            Some(ref mut scan) => {
+                if scan.finish(info.raw_tail.as_slice(), name.local.as_slice()).is_err() {
+                    return Err(IoError {
+                        kind: InvalidInput,
+                        desc: "raw text would stop its element from ending",
+                        detail: None,
+                    });
+                }
+            }
+            None => (),
+        }
+
         if info.ignore_children {
+            // A void element, or one inside a void element.
+            if info.block {
+                self.end_line();
+            }
             return Ok(());
         }
 
-        // FIXME: Handle qualified tag names
+        if info.has_block_child {
+            let depth = self.depth();
+            try!(self.newline(depth));
+        }
+
         try!(self.writer.write_str("</"));
         try!(self.writer.write_str(name.local.as_slice()));
-        self.writer.write_char('>')
+        try!(self.writer.write_char('>'));
+
+        if info.block {
+            self.end_line();
+        }
+        Ok(())
     }
 
     pub fn write_text(&mut self, text: &str) -> IoResult<()> {
-        let prepend_lf = text.starts_with("\n") && {
+        // Drop whitespace at the start of a line when pretty-printing.
+        let text = if self.pretty() && self.parent().trim_space {
+            let text = text.trim_left_chars(is_ascii_whitespace);
+            if text.is_empty() {
+                return Ok(());
+            }
+            text
+        } else {
+            text
+        };
+        if !text.is_empty() {
+            try!(self.before_inline());
+        }
+
+        let prepend_lf = self.opts.restore_initial_newline && text.starts_with("\n") && {
             let parent = self.parent();
             !parent.processed_first_child && match parent.html_name {
                 Some(atom!(pre)) | Some(atom!(textarea)) | Some(atom!(listing)) => true,
@@ -163,32 +798,303 @@ impl<'wr, Wr: Writer> Serializer<'wr, Wr> {
             try!(self.writer.write_char('\n'));
         }
 
-        let escape = match self.parent().html_name {
-            Some(atom!(style)) | Some(atom!(script)) | Some(atom!(xmp))
-            | Some(atom!(iframe)) | Some(atom!(noembed)) | Some(atom!(noframes))
-            | Some(atom!(plaintext)) => false,
-
-            Some(atom!(noscript)) => !self.opts.scripting_enabled,
-
-            _ => true,
+        let scripting_enabled = self.opts.scripting_enabled;
+        let raw_name = match self.parent().html_name {
+            Some(ref name) if text_escaping(name, scripting_enabled) == RawText
+                => Some(name.clone()),
+            _ => None,
+        };
+        let name = match raw_name {
+            Some(name) => name,
+            None => return self.write_escaped(text),
         };
 
-        if escape {
-            self.write_escaped(text, false)
-        } else {
-            self.writer.write_str(text)
+        // Check the new text together with the end of what we wrote
+        // before, which might have been the start of a tag.
+        {
+            let parent = self.parent();
+            if parent.raw_scan.is_none() {
+                parent.raw_scan = RawTextScan::new(name.as_slice());
+            }
+            match parent.raw_scan {
+                Some(ref mut scan) => {
+                    let mut check = replace(&mut parent.raw_tail, String::new());
+                    check.push_str(text);
+                    let done = match scan.scan(check.as_slice(), name.as_slice(), false) {
+                        Ok(done) => done,
+                        Err(()) => return Err(IoError {
+                            kind: InvalidInput,
+                            desc: "raw text contains its element's end tag",
+                            detail: None,
+                        }),
+                    };
+                    parent.raw_tail = String::from_str(check.as_slice().slice_from(done));
+                }
+                None => (),
+            }
         }
+
+        self.writer.write_str(text)
     }
 
     pub fn write_comment(&mut self, text: &str) -> IoResult<()> {
+        try!(self.before_inline());
         try!(self.writer.write_str("<!--"));
         try!(self.writer.write_str(text));
         self.writer.write_str("-->")
     }
 
     pub fn write_doctype(&mut self, name: &str) -> IoResult<()> {
+        if self.pretty() {
+            try!(self.before_block());
+            self.end_line();
+        }
         try!(self.writer.write_str("<!DOCTYPE "));
         try!(self.writer.write_str(name));
-        self.writer.write_char('\n')
+        self.writer.write_char('>')
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use std::io::MemWriter;
+    use collections::vec::Vec;
+    use collections::string::String;
+    use super::{contains_end_tag, write_escaped, serialize, serialize_to_string};
+    use super::{Serializer, SerializeOpts};
+    use super::{text_to_html, NewlineMode, KeepNewlines, NewlinesToBr, NewlinesToParagraphs};
+    use super::{LineEnding, Lf, CrLf};
+    use super::AttrRef;
+
+    use sink::rcdom::RcDom;
+    use driver::{parse, one_input, utf16_input};
+
+    use core::default::Default;
+
+    fn escape(text: &str, attr_mode: bool) -> String {
+        let mut out = MemWriter::new();
+        write_escaped(&mut out, text, attr_mode).unwrap();
+        String::from_utf8(out.unwrap()).unwrap()
+    }
+
+    test_eq!(escape_text, escape("a<b>&\"c\"\xA0", false),
+        String::from_str("a&lt;b&gt;&amp;\"c\"&nbsp;"))
+    test_eq!(escape_attr, escape("a<b>&\"c\"\xA0", true),
+        String::from_str("a<b>&amp;&quot;c&quot;&nbsp;"))
+    test_eq!(escape_nothing, escape("\u00e9t\u00e9", false), String::from_str("\u00e9t\u00e9"))
+
+    fn to_html(text: &str, newlines: NewlineMode) -> String {
+        let mut out = MemWriter::new();
+        text_to_html(&mut out, text, newlines).unwrap();
+        String::from_utf8(out.unwrap()).unwrap()
+    }
+
+    test_eq!(text_keep_newlines, to_html("a<b>\r\nc & d", KeepNewlines).as_slice(),
+        "a&lt;b&gt;\nc &amp; d")
+    test_eq!(text_newlines_to_br, to_html("a\r\nb\rc<", NewlinesToBr).as_slice(),
+        "a<br>\nb<br>\nc&lt;")
+    test_eq!(text_newlines_to_paragraphs, to_html("\n\na & b\nc\n\n\n\nd\n",
+        NewlinesToParagraphs).as_slice(), "<p>a &amp; b<br>\nc</p>\n<p>d</p>")
+
+    test_eq!(end_tag_plain, contains_end_tag("a</script>", "script"), true)
+    test_eq!(end_tag_upper, contains_end_tag("</SCRIPT x", "script"), true)
+    test_eq!(end_tag_slash, contains_end_tag("</script/", "script"), true)
+    test_eq!(end_tag_longer_name, contains_end_tag("</scripts>", "script"), false)
+    test_eq!(end_tag_unfinished, contains_end_tag("</script", "script"), false)
+    test_eq!(end_tag_other, contains_end_tag("</style>", "script"), false)
+    test_eq!(end_tag_non_ascii, contains_end_tag("</\u00e9t\u00e9>", "script"), false)
+    test_eq!(end_tag_escaped, contains_end_tag("<!--x</script>-->", "script"), true)
+    test_eq!(end_tag_double_escaped, contains_end_tag("<!--<script>x</script>-->",
+        "script"), false)
+    test_eq!(end_tag_after_escape, contains_end_tag("<!--<script>x</script>--></script>",
+        "script"), true)
+    test_eq!(end_tag_empty_escape, contains_end_tag("<!--><script></script>", "script"), true)
+    test_eq!(end_tag_no_escape_in_style, contains_end_tag("<!--<style></style>", "style"), true)
+    test_eq!(end_tag_plaintext, contains_end_tag("</plaintext>", "plaintext"), false)
+
+    // Lone surrogates in UTF-16 input, and references to surrogates,
+    // are replaced before they reach the DOM, so the output is UTF-8.
+    #[test]
+    fn escape_replacement() {
+        let ascii = |s: &str| s.chars().map(|c| c as u16).collect::<Vec<u16>>();
+        let mut input = ascii("<p title=x");
+        input.push(0xD800);
+        input.push_all(ascii(">a").as_slice());
+        input.push(0xDC00);
+        input.push_all(ascii("b&#xD800;").as_slice());
+
+        let dom: RcDom = parse(utf16_input(Some(input).into_iter()), Default::default());
+        let mut out = MemWriter::new();
+        serialize(&mut out, &dom.document, Default::default()).unwrap();
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(),
+            "<html><head></head><body><p title=\"x\ufffd\">a\ufffdb\ufffd</p></body></html>");
+    }
+
+    test_eq!(double_escaped_script, round_trip(
+        "<script><!--<script>x</script>--></script>").as_slice(),
+        "<html><head><script><!--<script>x</script>--></script></head><body></body></html>")
+
+    test_eq!(attrs_in_order, round_trip(
+        "<p title='a&amp;\"b' id=x><svg xlink:href=y>").as_slice(),
+        "<html><head></head><body><p title=\"a&amp;&quot;b\" id=\"x\">\
+        <svg xlink:href=\"y\"></svg></p></body></html>")
+
+    #[test]
+    fn raw_text_in_pieces() {
+        let mut out = MemWriter::new();
+        let mut ser = Serializer::new(&mut out, Default::default());
+        ser.start_elem(qualname!(HTML, script), None::<AttrRef>.into_iter()).unwrap();
+        ser.write_text("<!--<scr").unwrap();
+        ser.write_text("ipt>x</scr").unwrap();
+        ser.write_text("ipt>--><").unwrap();
+        assert!(ser.write_text("/script>").is_err());
+    }
+
+    #[test]
+    fn script_left_double_escaped() {
+        let mut out = MemWriter::new();
+        let mut ser = Serializer::new(&mut out, Default::default());
+        ser.start_elem(qualname!(HTML, script), None::<AttrRef>.into_iter()).unwrap();
+        ser.write_text("<!--<script>x").unwrap();
+        assert!(ser.end_elem(qualname!(HTML, script)).is_err());
+    }
+
+    fn pretty(input: &str, break_inline_elements: bool, wrap_attrs_at: Option<uint>) -> String {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        let mut out = MemWriter::new();
+        serialize(&mut out, &dom.document, SerializeOpts {
+            indent: Some(2),
+            break_inline_elements: break_inline_elements,
+            wrap_attrs_at: wrap_attrs_at,
+            .. Default::default()
+        }).unwrap();
+        String::from_utf8(out.unwrap()).unwrap()
+    }
+
+    test_eq!(pretty_blocks, pretty("<title>T</title><div>\n<p>Hello <b>world</b></p><p>x</p>\
+        </div><pre>  keep\n</pre>", false, None).as_slice(), "<html>\n\
+        \x20 <head>\n\
+        \x20   <title>T</title>\n\
+        \x20 </head>\n\
+        \x20 <body>\n\
+        \x20   <div>\n\
+        \x20     <p>Hello <b>world</b></p>\n\
+        \x20     <p>x</p>\n\
+        \x20   </div>\n\
+        \x20   <pre>  keep\n</pre>\n\
+        \x20 </body>\n\
+        </html>")
+
+    test_eq!(pretty_mixed, pretty("<div>intro<p>a</p> tail</div>", false, None).as_slice(),
+        "<html>\n  <head></head>\n  <body>\n    <div>intro\n      <p>a</p>\n      tail\n\
+        \x20   </div>\n  </body>\n</html>")
+
+    test_eq!(pretty_inline_breaks, pretty("<p>a<b>b</b></p>", true, None).as_slice(),
+        "<html>\n  <head></head>\n  <body>\n    <p>a\n      <b>b</b>\n    </p>\n\
+        \x20 </body>\n</html>")
+
+    test_eq!(pretty_wrap_attrs, pretty("<p><a href=/x title=y>z</a></p>", false, Some(10))
+        .as_slice(), "<html>\n  <head></head>\n  <body>\n    <p><a\n        href=\"/x\"\n\
+        \x20       title=\"y\">z</a></p>\n  </body>\n</html>")
+
+    test_eq!(pretty_short_attrs, pretty("<p><a href=/x>z</a></p>", false, Some(20))
+        .as_slice(), "<html>\n  <head></head>\n  <body>\n    <p><a href=\"/x\">z</a></p>\n\
+        \x20 </body>\n</html>")
+
+    fn diffable(input: &str) -> String {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        let mut out = MemWriter::new();
+        serialize(&mut out, &dom.document, SerializeOpts::diffable()).unwrap();
+        String::from_utf8(out.unwrap()).unwrap()
+    }
+
+    test_eq!(diffable_sorted, diffable("<p title=t class=c data-x=1 data=2>\n a</p>").as_slice(),
+        "<html><head></head><body><p class=\"c\" data=\"2\" data-x=\"1\" title=\"t\">\n a</p>\
+        </body></html>")
+
+    test_eq!(diffable_wrapped, diffable("<a href=https://example.com/a/long/path/to/a/page/with/more/segments \
+        title='A link'>x</a>").as_slice(), "<html><head></head><body><a\n    \
+        href=\"https://example.com/a/long/path/to/a/page/with/more/segments\"\n    title=\"A link\">x</a>\
+        </body></html>")
+
+    test_eq!(diffable_invisible, diffable("<p title='a\u200Bb'>c\u00ADd\u00A0e\u00E9\
+        <script>'\u200B'</script>").as_slice(), "<html><head></head><body><p \
+        title=\"a&#x200B;b\">c&#xAD;d&nbsp;e\u00E9<script>'\u200B'</script></p></body></html>")
+
+    #[test]
+    fn crlf() {
+        let dom: RcDom = parse(one_input(String::from_str("<p title='a\r\nb'>c\rd\n</p>\
+            <!--e\nf--><pre>\n\ng</pre>")), Default::default());
+        let mut out = MemWriter::new();
+        serialize(&mut out, &dom.document, SerializeOpts {
+            line_ending: CrLf,
+            .. Default::default()
+        }).unwrap();
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(), "<html><head></head>\
+            <body><p title=\"a\r\nb\">c\r\nd\r\n</p><!--e\r\nf--><pre>\r\n\r\ng</pre>\
+            </body></html>");
+    }
+
+    test_eq!(line_ending_crlf, LineEnding::of("<p>\r\na\nb"), CrLf)
+    test_eq!(line_ending_lf, LineEnding::of("<p>\na\r\nb"), Lf)
+    test_eq!(line_ending_none, LineEnding::of("<p>a"), Lf)
+    test_eq!(line_ending_at_start, LineEnding::of("\na"), Lf)
+
+    fn round_trip(input: &str) -> String {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        let mut out = MemWriter::new();
+        serialize(&mut out, &dom.document, Default::default()).unwrap();
+        String::from_utf8(out.unwrap()).unwrap()
+    }
+
+    test_eq!(foreign, round_trip("<svg viewbox='0 0 1 1'><use xlink:href=#a /></svg>").as_slice(),
+        "<html><head></head><body><svg viewBox=\"0 0 1 1\"><use xlink:href=\"#a\"></use></svg>\
+        </body></html>")
+
+    test_eq!(cdata, round_trip("<svg><![CDATA[a<b]]></svg><![CDATA[c]]>").as_slice(),
+        "<html><head></head><body><svg>a&lt;b</svg><!--[CDATA[c]]--></body></html>")
+
+    #[test]
+    fn to_string_reserves() {
+        let input = "<p>a<b>b</b>";
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        let out = serialize_to_string(&dom.document, Default::default(), 1024).unwrap();
+        assert_eq!(out, round_trip(input));
+        assert!(out.capacity() >= 1024);
+    }
+
+    test_eq!(attr_char_refs, round_trip("<a href='?a=1&not=2&notin;&amp=3&copy&ampx' \
+        title=\"&\">&not=2&ampx</a>").as_slice(), "<html><head></head><body>\
+        <a href=\"?a=1&amp;not=2\u2209&amp;amp=3\u00a9&amp;ampx\" title=\"&amp;\">\u00ac=2&amp;x</a>\
+        </body></html>")
+
+    test_eq!(misnested_formatting, round_trip("<b><i>text</b>more</i>").as_slice(),
+        "<html><head></head><body><b><i>text</i></b><i>more</i></body></html>")
+
+    test_eq!(formatting_around_block, round_trip("<a>1<p>2</a>3</p>").as_slice(),
+        "<html><head></head><body><a>1</a><p><a>2</a>3</p></body></html>")
+
+    test_eq!(foster_parented, round_trip("<table>a<tr>b<td>c</td>d<div>e</div></table>")
+        .as_slice(), "<html><head></head><body>abd<div>e</div><table><tbody><tr><td>c</td>\
+        </tr></tbody></table></body></html>")
+
+    test_eq!(foster_parented_formatting, round_trip("<table><b><tr><td>a</b>b</table>c")
+        .as_slice(), "<html><head></head><body><b></b><table><tbody><tr><td>ab</td></tr></tbody>\
+        </table><b>c</b></body></html>")
+
+    test_eq!(template, round_trip("<template><tr><td>a</template>b").as_slice(),
+        "<html><head><template><tr><td>a</td></tr></template></head><body>b</body></html>")
+
+    #[test]
+    fn template_contents_not_in_tree() {
+        let dom: RcDom = parse(one_input(String::from_str("<template><p>a</template>")),
+            Default::default());
+        let html = dom.document.borrow().children[0].clone();
+        let head = html.borrow().children[0].clone();
+        let template = head.borrow().children[0].clone();
+        assert!(template.borrow().children.is_empty());
+        let contents = template.borrow().template_contents.clone().unwrap();
+        assert_eq!(contents.borrow().children.len(), 1);
     }
 }
@@ -0,0 +1,347 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Writing HTML out as it's tokenized, for a streaming proxy.
+//!
+//! A `StreamSink` serializes each token as soon as it arrives, and
+//! flushes its writer whenever an element near the top of the document
+//! ends, so a client gets each finished section of the page while the
+//! rest is still being parsed.  Put a `FilterSink` in front of it to
+//! drop or extract whole elements on the way through.
+//!
+//! Like `FilterSink`, this works on tokens without a tree builder.  An
+//! end tag closes the most recent open element with its name, and any
+//! elements opened after it; end tags which match nothing don't change
+//! the depth.  Elements whose end tag is often left out, like `<p>` or
+//! `<li>`, stay open until an element enclosing them ends.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let mut out = BufferedWriter::new(socket);
+//! let sink = FilterSink::new(NoIframes, StreamSink::new(&mut out, Default::default()), true);
+//! let (_, sink) = tokenize_to(sink, upstream, Default::default()).unwrap();
+//! try!(sink.result());
+//! ```
+
+use core::prelude::*;
+
+use super::{write_escaped, write_attr_name};
+
+use tokenizer::{Token, TokenSink, Doctype, DoctypeToken, TagToken, StartTag, EndTag};
+use tokenizer::{CommentToken, CharacterTokens, WhitespaceTokens, NullCharacterToken};
+use tokenizer::{PassthroughToken, ProcessingInstructionToken, EOFToken, ParseError};
+use tokenizer::states::{State, RawData, Rawtext, ScriptData, Plaintext, raw_text_state};
+
+use core::default::Default;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use std::io::{Writer, IoResult, IoError};
+
+use string_cache::Atom;
+
+/// Options for a `StreamSink`.
+#[deriving(Clone)]
+pub struct StreamOpts {
+    /// Is scripting enabled?  This decides whether `<noscript>` holds
+    /// raw text.
+    pub scripting_enabled: bool,
+
+    /// Flush after an end tag which leaves no more than this many
+    /// elements open.  With the default of 2, that's the end of each
+    /// child of `<body>`, and of `<head>`.  Everything is flushed at
+    /// EOF regardless.  Default: 2
+    pub flush_depth: uint,
+}
+
+impl Default for StreamOpts {
+    fn default() -> StreamOpts {
+        StreamOpts {
+            scripting_enabled: true,
+            flush_depth: 2,
+        }
+    }
+}
+
+fn is_void(name: &Atom) -> bool {
+    match *name {
+        atom!(area) | atom!(base) | atom!(basefont) | atom!(bgsound) | atom!(br)
+        | atom!(col) | atom!(embed) | atom!(frame) | atom!(hr) | atom!(img)
+        | atom!(input) | atom!(keygen) | atom!(link) | atom!(menuitem)
+        | atom!(meta) | atom!(param) | atom!(source) | atom!(track) | atom!(wbr)
+            => true,
+        _ => false,
+    }
+}
+
+fn write_doctype<Wr: Writer>(writer: &mut Wr, doctype: &Doctype) -> IoResult<()> {
+    try!(writer.write_str("<!DOCTYPE"));
+    match doctype.name {
+        Some(ref name) => {
+            try!(writer.write_char(' '));
+            try!(writer.write_str(name.as_slice()));
+        }
+        None => (),
+    }
+    match doctype.public_id {
+        Some(ref id) => {
+            try!(writer.write_str(" PUBLIC \""));
+            try!(writer.write_str(id.as_slice()));
+            try!(writer.write_char('"'));
+        }
+        None => (),
+    }
+    match doctype.system_id {
+        Some(ref id) => {
+            if doctype.public_id.is_none() {
+                try!(writer.write_str(" SYSTEM"));
+            }
+            try!(writer.write_str(" \""));
+            try!(writer.write_str(id.as_slice()));
+            try!(writer.write_char('"'));
+        }
+        None => (),
+    }
+    writer.write_char('>')
+}
+
+/// A `TokenSink` which writes each token back out as HTML.
+///
+/// It switches the tokenizer into a raw text state after e.g.
+/// `<script>` based on the tag name alone, as `ChannelSink` does.  The
+/// first error from the writer stops any more output, and is returned
+/// by `result`.
+pub struct StreamSink<'wr, Wr: 'wr> {
+    writer: &'wr mut Wr,
+    opts: StreamOpts,
+
+    // Names of the open elements, as far as we can tell.
+    open: Vec<Atom>,
+
+    // Is the text we're in written as it is, e.g. inside `<script>`?
+    raw: bool,
+
+    next_state: Option<State>,
+    error: Option<IoError>,
+}
+
+impl<'wr, Wr: Writer> StreamSink<'wr, Wr> {
+    /// Create a `StreamSink` writing to `writer`.
+    pub fn new(writer: &'wr mut Wr, opts: StreamOpts) -> StreamSink<'wr, Wr> {
+        StreamSink {
+            writer: writer,
+            opts: opts,
+            open: vec!(),
+            raw: false,
+            next_state: None,
+            error: None,
+        }
+    }
+
+    /// The first error from the writer, if there was one.
+    pub fn result(self) -> IoResult<()> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn write_token(&mut self, token: Token) -> IoResult<()> {
+        match token {
+            TagToken(tag) => match tag.kind {
+                StartTag => {
+                    let state = raw_text_state(&tag.name, self.opts.scripting_enabled);
+                    self.raw = match state {
+                        Some(RawData(Rawtext)) | Some(RawData(ScriptData))
+                            | Some(Plaintext) => true,
+                        _ => false,
+                    };
+                    self.next_state = state;
+
+                    try!(self.writer.write_char('<'));
+                    try!(self.writer.write_str(tag.name.as_slice()));
+                    for attr in tag.attrs.iter() {
+                        try!(self.writer.write_char(' '));
+                        try!(write_attr_name(&mut *self.writer, &attr.name));
+                        try!(self.writer.write_str("=\""));
+                        try!(write_escaped(&mut *self.writer, attr.value.as_slice(), true));
+                        try!(self.writer.write_char('"'));
+                    }
+                    if tag.self_closing {
+                        try!(self.writer.write_str(" /"));
+                    } else if !is_void(&tag.name) {
+                        self.open.push(tag.name);
+                    }
+                    self.writer.write_char('>')
+                }
+
+                EndTag => {
+                    self.raw = false;
+                    try!(self.writer.write_str("</"));
+                    try!(self.writer.write_str(tag.name.as_slice()));
+                    try!(self.writer.write_char('>'));
+
+                    match self.open.iter().rposition(|n| *n == tag.name) {
+                        Some(i) => {
+                            self.open.truncate(i);
+                            if self.open.len() <= self.opts.flush_depth {
+                                try!(self.writer.flush());
+                            }
+                        }
+                        None => (),
+                    }
+                    Ok(())
+                }
+            },
+
+            CharacterTokens(text) | WhitespaceTokens(text) => if self.raw {
+                self.writer.write_str(text.as_slice())
+            } else {
+                write_escaped(&mut *self.writer, text.as_slice(), false)
+            },
+
+            // Template delimiters and what's between them go out as
+            // they came in.
+            PassthroughToken(text) => self.writer.write_str(text.as_slice()),
+
+            CommentToken(text) => {
+                try!(self.writer.write_str("<!--"));
+                try!(self.writer.write_str(text.as_slice()));
+                self.writer.write_str("-->")
+            }
+
+            ProcessingInstructionToken(target, data) => {
+                try!(self.writer.write_str("<?"));
+                try!(self.writer.write_str(target.as_slice()));
+                if !data.is_empty() {
+                    try!(self.writer.write_char(' '));
+                    try!(self.writer.write_str(data.as_slice()));
+                }
+                self.writer.write_str("?>")
+            }
+
+            DoctypeToken(doctype) => write_doctype(&mut *self.writer, &doctype),
+
+            EOFToken => self.writer.flush(),
+
+            // The tree builder would drop these in most places.
+            NullCharacterToken | ParseError(_) => Ok(()),
+        }
+    }
+}
+
+impl<'wr, Wr: Writer> TokenSink for StreamSink<'wr, Wr> {
+    fn process_token(&mut self, token: Token) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.write_token(token) {
+            Ok(()) => (),
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    fn query_state_change(&mut self) -> Option<State> {
+        self.next_state.take()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{StreamSink, StreamOpts};
+
+    use filter::{FilterSink, SubtreeFilter, SubtreeAction, KeepSubtree, DropSubtree};
+    use tokenizer::Tag;
+    use driver::{tokenize_to, one_input};
+
+    use core::default::Default;
+    use collections::MutableSeq;
+    use collections::vec::Vec;
+    use collections::string::String;
+    use std::io::{Writer, IoResult};
+
+    // Remembers what had been written at each flush.
+    struct FlushLog {
+        out: Vec<u8>,
+        flushed: Vec<String>,
+    }
+
+    impl Writer for FlushLog {
+        fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+            self.out.push_all(buf);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> IoResult<()> {
+            self.flushed.push(String::from_utf8(self.out.clone()).unwrap());
+            Ok(())
+        }
+    }
+
+    fn stream(input: &str, flush_depth: uint) -> Vec<String> {
+        let mut log = FlushLog {
+            out: vec!(),
+            flushed: vec!(),
+        };
+        {
+            let sink = StreamSink::new(&mut log, StreamOpts {
+                flush_depth: flush_depth,
+                .. Default::default()
+            });
+            let sink = tokenize_to(sink, one_input(String::from_str(input)), Default::default());
+            sink.result().unwrap();
+        }
+        log.flushed
+    }
+
+    fn v(xs: &[&str]) -> Vec<String> {
+        xs.iter().map(|x| String::from_str(*x)).collect()
+    }
+
+    test_eq!(flush_points, stream("<!DOCTYPE html><html><body><div><p>a</p></div><ul><li>b</ul>",
+        2), v(["<!DOCTYPE html><html><body><div><p>a</p></div>",
+               "<!DOCTYPE html><html><body><div><p>a</p></div><ul><li>b</ul>",
+               "<!DOCTYPE html><html><body><div><p>a</p></div><ul><li>b</ul>"]))
+
+    test_eq!(flush_shallow, stream("<div><p>a</p></div><div>b</div>", 0),
+        v(["<div><p>a</p></div>", "<div><p>a</p></div><div>b</div>",
+           "<div><p>a</p></div><div>b</div>"]))
+
+    test_eq!(escaping, stream("<p title='a\"&amp;b'>1 &lt; 2<script>if (a<b) x()</script>",
+        0), v(["<p title=\"a&quot;&amp;b\">1 &lt; 2<script>if (a<b) x()</script>"]))
+
+    test_eq!(void_and_self_closing, stream("<div><br><img src=x><svg/></div>", 0),
+        v(["<div><br><img src=\"x\"><svg /></div>", "<div><br><img src=\"x\"><svg /></div>"]))
+
+    struct NoIframes;
+
+    impl SubtreeFilter for NoIframes {
+        fn action(&mut self, tag: &Tag) -> SubtreeAction {
+            if tag.name == atom!(iframe) { DropSubtree } else { KeepSubtree }
+        }
+    }
+
+    #[test]
+    fn filtered() {
+        let mut log = FlushLog {
+            out: vec!(),
+            flushed: vec!(),
+        };
+        {
+            let sink = FilterSink::new(NoIframes,
+                StreamSink::new(&mut log, Default::default()), true);
+            let sink = tokenize_to(sink,
+                one_input(String::from_str("<p>a<iframe><p>x</iframe>b</p>")), Default::default());
+            let (_, sink) = sink.unwrap();
+            sink.result().unwrap();
+        }
+        assert_eq!(log.flushed, v(["<p>ab</p>", "<p>ab</p>"]));
+    }
+}
@@ -0,0 +1,131 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Run the tokenizer and the tree builder in different tasks.
+//!
+//! A `ChannelSink` sends each token over a channel as soon as it's
+//! produced.  On the other end, `build_from_channel` feeds the tokens
+//! into a `TreeBuilder`.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let (tx, rx) = channel();
+//! spawn(proc() {
+//!     tokenize_to(ChannelSink::new(tx, true), one_input(my_str), Default::default());
+//! });
+//! let dom: RcDom = build_from_channel(rx, Default::default(), Default::default());
+//! ```
+
+use core::prelude::*;
+
+use tokenizer::{Token, TokenSink, TagToken, StartTag, EOFToken};
+use tokenizer::states::{State, raw_text_state};
+use tree_builder::{TreeBuilder, TreeBuilderOpts, TreeSink};
+
+use std::comm::{Sender, Receiver};
+
+/// A `TokenSink` which sends tokens over a channel.
+///
+/// The tree builder normally tells the tokenizer when to switch
+/// into a raw text state, e.g. after `<script>`.  It can't do that
+/// from across a channel, so `ChannelSink` switches state based on
+/// the tag name alone.  This is right except in a few corner cases,
+/// e.g. a `<title>` inside a `<table>`.
+pub struct ChannelSink {
+    sender: Sender<Token>,
+    scripting_enabled: bool,
+    next_state: Option<State>,
+}
+
+impl ChannelSink {
+    /// Create a `ChannelSink` sending to `sender`.  `scripting_enabled`
+    /// should match the tree builder's option of the same name.
+    pub fn new(sender: Sender<Token>, scripting_enabled: bool) -> ChannelSink {
+        ChannelSink {
+            sender: sender,
+            scripting_enabled: scripting_enabled,
+            next_state: None,
+        }
+    }
+}
+
+impl TokenSink for ChannelSink {
+    fn process_token(&mut self, token: Token) {
+        match token {
+            TagToken(ref tag) if tag.kind == StartTag => {
+                self.next_state = raw_text_state(&tag.name, self.scripting_enabled);
+            }
+            _ => (),
+        }
+
+        // If the receiver has gone away, nobody wants the tokens.
+        let _ = self.sender.send_opt(token);
+    }
+
+    fn query_state_change(&mut self) -> Option<State> {
+        self.next_state.take()
+    }
+}
+
+/// Build a tree from tokens received over a channel, for example
+/// from a `ChannelSink`.  Returns once `EOFToken` is received or
+/// the sending end hangs up.
+pub fn build_from_channel<
+        Handle: Clone,
+        Sink: TreeSink<Handle>
+    >(
+        receiver: Receiver<Token>,
+        sink: Sink,
+        opts: TreeBuilderOpts) -> Sink {
+
+    let mut tb = TreeBuilder::new(sink, opts);
+    for token in receiver.iter() {
+        let eof = token == EOFToken;
+        tb.process_token(token);
+        if eof {
+            break;
+        }
+    }
+    tb.unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{ChannelSink, build_from_channel};
+
+    use sink::rcdom::RcDom;
+    use serialize::serialize;
+    use driver::{tokenize_to, one_input};
+
+    use std::comm::channel;
+    use std::task::spawn;
+    use std::io::MemWriter;
+    use collections::string::String;
+
+    use core::default::Default;
+
+    #[test]
+    fn across_tasks() {
+        let (tx, rx) = channel();
+        spawn(proc() {
+            tokenize_to(ChannelSink::new(tx, true),
+                one_input(String::from_str("<title><b></title><script>a<b</script><p>x")),
+                Default::default());
+        });
+        let dom = build_from_channel(rx, RcDom::default(), Default::default());
+
+        let mut out = MemWriter::new();
+        serialize(&mut out, &dom.document, Default::default()).unwrap();
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(),
+            "<html><head><title>&lt;b&gt;</title><script>a<b</script></head>\
+            <body><p>x</p></body></html>");
+    }
+}
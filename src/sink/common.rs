@@ -7,12 +7,17 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use core::prelude::*;
+
 use tokenizer::Attribute;
+use util::str::split_html_space;
 
 use collections::vec::Vec;
 use collections::string::String;
 use string_cache::QualName;
 
+pub use util::str::SpaceSeparated;
+
 /// The different kinds of nodes in the DOM.
 #[deriving(Show)]
 pub enum NodeEnum {
@@ -32,3 +37,40 @@ pub enum NodeEnum {
     Element(QualName, Vec<Attribute>),
 }
 
+impl NodeEnum {
+    /// Get the value of the un-namespaced attribute with the given
+    /// local name.  Returns `None` for non-element nodes.
+    pub fn get_attr<'a>(&'a self, name: &str) -> Option<&'a str> {
+        match *self {
+            Element(_, ref attrs) => attrs.iter()
+                .find(|a| a.name.ns == ns!("") && a.name.local.as_slice() == name)
+                .map(|a| a.value.as_slice()),
+            _ => None,
+        }
+    }
+
+    /// The element's ID, i.e. the value of its `id` attribute, if
+    /// present and non-empty.
+    pub fn id<'a>(&'a self) -> Option<&'a str> {
+        match self.get_attr("id") {
+            Some("") | None => None,
+            id => id,
+        }
+    }
+
+    /// Iterate over the tokens of the element's `class` attribute.
+    ///
+    /// The attribute is split on ASCII whitespace, as the spec requires.
+    /// Other whitespace, such as U+00A0 NO-BREAK SPACE, is part of a
+    /// class name.
+    pub fn classes<'a>(&'a self) -> SpaceSeparated<'a> {
+        split_html_space(self.get_attr("class").unwrap_or(""))
+    }
+
+    /// Does the element's `class` attribute contain the given class?
+    ///
+    /// Comparison is case-sensitive, as in a no-quirks document.
+    pub fn has_class(&self, class: &str) -> bool {
+        self.classes().any(|c| c == class)
+    }
+}
@@ -37,7 +37,6 @@ use alloc::boxed::Box;
 use collections::{MutableSeq, Set, MutableSet};
 use collections::vec::Vec;
 use collections::string::String;
-use collections::str::MaybeOwned;
 use std::io::{Writer, IoResult};
 use std::collections::HashSet;
 
@@ -48,6 +47,7 @@ struct SquishyNode {
     node: NodeEnum,
     parent: Handle,
     children: Vec<Handle>,
+    template_contents: Handle,
 }
 
 impl SquishyNode {
@@ -56,6 +56,7 @@ impl SquishyNode {
             node: node,
             parent: Handle::null(),
             children: vec!(),
+            template_contents: Handle::null(),
         }
     }
 }
@@ -147,10 +148,13 @@ fn append_to_existing_text(mut prev: Handle, text: &str) -> bool {
     }
 }
 
+// Average bytes of HTML per node, for `input_size_hint`.
+static TYPICAL_NODE_LEN: uint = 32;
+
 pub struct Sink {
     nodes: Vec<Box<UnsafeCell<SquishyNode>>>,
     document: Handle,
-    errors: Vec<MaybeOwned<'static>>,
+    errors: Vec<tree_builder::Error>,
     quirks_mode: QuirksMode,
 }
 
@@ -176,8 +180,12 @@ impl Sink {
 }
 
 impl TreeSink<Handle> for Sink {
-    fn parse_error(&mut self, msg: MaybeOwned<'static>) {
-        self.errors.push(msg);
+    fn parse_error(&mut self, err: tree_builder::Error) {
+        self.errors.push(err);
+    }
+
+    fn input_size_hint(&mut self, bytes: uint) {
+        self.nodes.reserve_additional(bytes / TYPICAL_NODE_LEN);
     }
 
     fn get_document(&mut self) -> Handle {
@@ -200,7 +208,17 @@ impl TreeSink<Handle> for Sink {
     }
 
     fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle {
-        self.new_node(Element(name, attrs))
+        let template = name == qualname!(HTML, template);
+        let mut node = self.new_node(Element(name, attrs));
+        if template {
+            node.template_contents = self.new_node(Document);
+        }
+        node
+    }
+
+    fn get_template_contents(&mut self, target: Handle) -> Handle {
+        assert!(!target.template_contents.is_null(), "not a template element");
+        target.template_contents
     }
 
     fn create_comment(&mut self, text: String) -> Handle {
@@ -279,6 +297,14 @@ impl TreeSink<Handle> for Sink {
         target.parent = Handle::null();
     }
 
+    fn reparent_children(&mut self, mut node: Handle, new_parent: Handle) {
+        let children = mem::replace(&mut node.children, vec!());
+        for mut child in children.into_iter() {
+            child.parent = Handle::null();
+            append(new_parent, child);
+        }
+    }
+
     fn mark_script_already_started(&mut self, _node: Handle) { }
 }
 
@@ -286,11 +312,12 @@ pub struct Node {
     pub node: NodeEnum,
     _parent_not_accessible: uint,
     pub children: Vec<Box<Node>>,
+    pub template_contents: Option<Box<Node>>,
 }
 
 pub struct OwnedDom {
     pub document: Box<Node>,
-    pub errors: Vec<MaybeOwned<'static>>,
+    pub errors: Vec<tree_builder::Error>,
     pub quirks_mode: QuirksMode,
 }
 
@@ -301,6 +328,9 @@ impl ParseResult<Sink> for OwnedDom {
             for &child in node.deref().children.iter() {
                 walk(live, child);
             }
+            if !node.template_contents.is_null() {
+                walk(live, node.template_contents);
+            }
         }
 
         // Collect addresses of all the nodes that made it into the final tree.
@@ -319,7 +349,7 @@ impl ParseResult<Sink> for OwnedDom {
             }
         }
 
-        let old_addrs = addrs_of!(sink.document: node, parent, children);
+        let old_addrs = addrs_of!(sink.document: node, parent, children, template_contents);
 
         // Transmute the root to a Node, finalizing the transfer of ownership.
         let document = unsafe {
@@ -327,7 +357,7 @@ impl ParseResult<Sink> for OwnedDom {
         };
 
         // FIXME: do this assertion statically
-        let new_addrs = addrs_of!(document: node, _parent_not_accessible, children);
+        let new_addrs = addrs_of!(document: node, _parent_not_accessible, children, template_contents);
         assert_eq!(old_addrs, new_addrs);
 
         OwnedDom {
@@ -350,7 +380,12 @@ impl Serializable for Node {
                         attrs.iter().map(|at| (&at.name, at.value.as_slice()))));
                 }
 
-                for child in self.children.iter() {
+                // A template's children are in its template contents.
+                let children = match self.template_contents {
+                    Some(ref contents) => &contents.children,
+                    None => &self.children,
+                };
+                for child in children.iter() {
                     try!(child.serialize(serializer, true));
                 }
 
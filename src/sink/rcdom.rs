@@ -21,20 +21,25 @@ use tree_builder::{TreeSink, QuirksMode, NodeOrText, AppendNode, AppendText};
 use tree_builder;
 use serialize::{Serializable, Serializer};
 use driver::ParseResult;
+use util::str::split_html_space;
 
 use core::cell::RefCell;
 use core::default::Default;
+use core::mem::replace;
 use alloc::rc::{Rc, Weak};
 use collections::MutableSeq;
 use collections::vec::Vec;
 use collections::string::String;
-use collections::str::MaybeOwned;
 use std::io::{Writer, IoResult};
 
 use string_cache::QualName;
 
 /// A DOM node.
 pub struct Node {
+    /// Numbered in creation order, starting with 0 for the `Document`.
+    /// Unique within one `RcDom`, and unchanged when the node moves.
+    pub id: uint,
+
     pub node: NodeEnum,
     pub parent: Option<WeakHandle>,
     pub children: Vec<Handle>,
@@ -43,15 +48,23 @@ pub struct Node {
     ///
     /// Not meaningful for nodes other than HTML `<script>`.
     pub script_already_started: bool,
+
+    /// The template contents, a `Document` node holding the children
+    /// of an HTML `<template>`.
+    ///
+    /// `None` for every other node.
+    pub template_contents: Option<Handle>,
 }
 
 impl Node {
-    fn new(node: NodeEnum) -> Node {
+    fn new(id: uint, node: NodeEnum) -> Node {
         Node {
+            id: id,
             node: node,
             parent: None,
             children: vec!(),
             script_already_started: false,
+            template_contents: None,
         }
     }
 }
@@ -67,10 +80,6 @@ fn same_node(x: &Handle, y: &Handle) -> bool {
     (&*x.borrow() as *const Node) == (&*y.borrow() as *const Node)
 }
 
-fn new_node(node: NodeEnum) -> Handle {
-    Rc::new(RefCell::new(Node::new(node)))
-}
-
 fn append(new_parent: &Handle, child: Handle) {
     new_parent.borrow_mut().children.push(child.clone());
     let parent = &mut child.borrow_mut().parent;
@@ -109,21 +118,300 @@ fn remove_from_parent(target: &Handle) {
     (*child).parent = None;
 }
 
+fn is_element(target: &Handle) -> bool {
+    match target.borrow().node {
+        Element(..) => true,
+        _ => false,
+    }
+}
+
+// Is this an HTML element with one of the given local names?
+fn is_html_element(target: &Handle, names: &[&str]) -> bool {
+    match target.borrow().node {
+        Element(QualName { ns: ns!(HTML), ref local }, _) => names.contains(&local.as_slice()),
+        _ => false,
+    }
+}
+
+/// Convenience methods for walking the tree from a `Handle`.
+///
+/// The iterators returned here hold `Handle`s rather than borrowing
+/// the tree.  If the tree is modified during iteration, the traversal
+/// order is unspecified.
+///
+/// Finding a node's siblings means finding it among its parent's
+/// children, so walking a list of siblings one `next_sibling` at a time
+/// takes time quadratic in their number.  Iterate over the parent's
+/// `children` instead, or use `descendants`.
+pub trait Traverse {
+    /// The parent of this node, if any.
+    fn parent(&self) -> Option<Handle>;
+
+    /// The sibling immediately following this node.
+    fn next_sibling(&self) -> Option<Handle>;
+
+    /// The sibling immediately preceding this node.
+    fn prev_sibling(&self) -> Option<Handle>;
+
+    /// The first following sibling which is an element.
+    fn next_sibling_element(&self) -> Option<Handle>;
+
+    /// The last preceding sibling which is an element.
+    fn prev_sibling_element(&self) -> Option<Handle>;
+
+    /// Iterate over the children of this node which are elements.
+    fn children_elements(&self) -> ChildElements;
+
+    /// Iterate over the ancestors of this node, starting with its parent.
+    fn ancestors(&self) -> Ancestors;
+
+    /// Iterate over the descendants of this node in tree order (pre-order),
+    /// along with their depth below this node.  Children have depth 1.
+    fn descendants(&self) -> Descendants;
+}
+
+impl Traverse for Handle {
+    fn parent(&self) -> Option<Handle> {
+        self.borrow().parent.as_ref()
+            .map(|p| p.upgrade().expect("dangling weak pointer"))
+    }
+
+    fn next_sibling(&self) -> Option<Handle> {
+        let (parent, i) = unwrap_or_return!(get_parent_and_index(self), None);
+        let parent = parent.borrow();
+        parent.children.as_slice().get(i+1).map(|h| h.clone())
+    }
+
+    fn prev_sibling(&self) -> Option<Handle> {
+        let (parent, i) = unwrap_or_return!(get_parent_and_index(self), None);
+        if i == 0 {
+            return None;
+        }
+        let parent = parent.borrow();
+        Some(parent.children[i-1].clone())
+    }
+
+    fn next_sibling_element(&self) -> Option<Handle> {
+        let (parent, i) = unwrap_or_return!(get_parent_and_index(self), None);
+        let parent = parent.borrow();
+        parent.children.slice_from(i+1).iter().find(|n| is_element(*n)).map(|n| n.clone())
+    }
+
+    fn prev_sibling_element(&self) -> Option<Handle> {
+        let (parent, i) = unwrap_or_return!(get_parent_and_index(self), None);
+        let parent = parent.borrow();
+        parent.children.slice_to(i).iter().rev().find(|n| is_element(*n)).map(|n| n.clone())
+    }
+
+    fn children_elements(&self) -> ChildElements {
+        ChildElements {
+            parent: self.clone(),
+            next: 0,
+        }
+    }
+
+    fn ancestors(&self) -> Ancestors {
+        Ancestors {
+            next: self.parent(),
+        }
+    }
+
+    fn descendants(&self) -> Descendants {
+        Descendants {
+            next: first_child(self).map(|c| (c, 1)),
+        }
+    }
+}
+
+/// Iterator over the element children of a node.  See `Traverse`.
+pub struct ChildElements {
+    parent: Handle,
+    next: uint,
+}
+
+impl Iterator<Handle> for ChildElements {
+    fn next(&mut self) -> Option<Handle> {
+        let parent = self.parent.borrow();
+        while self.next < parent.children.len() {
+            let child = &parent.children[self.next];
+            self.next += 1;
+            if is_element(child) {
+                return Some(child.clone());
+            }
+        }
+        None
+    }
+}
+
+/// Iterator over the ancestors of a node.  See `Traverse`.
+pub struct Ancestors {
+    next: Option<Handle>,
+}
+
+impl Iterator<Handle> for Ancestors {
+    fn next(&mut self) -> Option<Handle> {
+        let node = unwrap_or_return!(self.next.take(), None);
+        self.next = node.parent();
+        Some(node)
+    }
+}
+
+fn first_child(node: &Handle) -> Option<Handle> {
+    node.borrow().children.as_slice().get(0).map(|c| c.clone())
+}
+
+/// Pre-order iterator over the descendants of a node, with their
+/// depth.  See `Traverse`.
+///
+/// This follows parent and sibling links from the node it last
+/// returned, so it allocates nothing.  Don't move nodes while
+/// iterating.
+pub struct Descendants {
+    // The next node to return, with its depth.
+    next: Option<(Handle, uint)>,
+}
+
+impl Iterator<(Handle, uint)> for Descendants {
+    fn next(&mut self) -> Option<(Handle, uint)> {
+        let (node, depth) = unwrap_or_return!(self.next.take(), None);
+        self.next = match first_child(&node) {
+            Some(child) => Some((child, depth + 1)),
+            None => {
+                // Climb to the nearest ancestor with a next sibling,
+                // but never above the node where iteration began,
+                // which is at depth 0.
+                let mut cur = node.clone();
+                let mut d = depth;
+                let mut next = None;
+                while d > 0 {
+                    match cur.next_sibling() {
+                        Some(sibling) => {
+                            next = Some((sibling, d));
+                            break;
+                        }
+                        None => {
+                            cur = cur.parent().expect("descendant without a parent");
+                            d -= 1;
+                        }
+                    }
+                }
+                next
+            }
+        };
+        Some((node, depth))
+    }
+}
+
 /// The DOM itself; the result of parsing.
 pub struct RcDom {
     /// The `Document` itself.
     pub document: Handle,
 
     /// Errors that occurred during parsing.
-    pub errors: Vec<MaybeOwned<'static>>,
+    pub errors: Vec<tree_builder::Error>,
 
     /// The document's quirks mode.
     pub quirks_mode: QuirksMode,
+
+    /// The `id` of the next node to be created.
+    next_id: uint,
+}
+
+impl RcDom {
+    fn new_node(&mut self, node: NodeEnum) -> Handle {
+        let id = self.next_id;
+        self.next_id += 1;
+        Rc::new(RefCell::new(Node::new(id, node)))
+    }
+
+    /// The number of nodes created so far, including any which were
+    /// removed from the tree.  Every `id` is less than this.
+    pub fn node_count(&self) -> uint {
+        self.next_id
+    }
+
+    /// Find a node in the document by its `id`.  Nodes which aren't in
+    /// the document, such as template contents, aren't found.
+    pub fn node_by_id(&self, id: uint) -> Option<Handle> {
+        if self.document.borrow().id == id {
+            return Some(self.document.clone());
+        }
+        self.document.descendants()
+            .map(|(node, _)| node)
+            .find(|node| node.borrow().id == id)
+    }
+}
+
+/// Accessors following the spec's definitions on `Document`.
+impl RcDom {
+    /// The document element, i.e. the first element child of the document.
+    pub fn document_element(&self) -> Option<Handle> {
+        self.document.children_elements().next()
+    }
+
+    /// The DOCTYPE node, if any.
+    pub fn doctype(&self) -> Option<Handle> {
+        let document = self.document.borrow();
+        document.children.iter()
+            .find(|c| match c.borrow().node {
+                Doctype(..) => true,
+                _ => false,
+            })
+            .map(|c| c.clone())
+    }
+
+    // The first child of the `<html>` document element with one of the
+    // given names.
+    fn html_child(&self, names: &[&str]) -> Option<Handle> {
+        let html = unwrap_or_return!(self.document_element(), None);
+        if !is_html_element(&html, &["html"]) {
+            return None;
+        }
+        html.children_elements().find(|c| is_html_element(c, names))
+    }
+
+    /// The first `<head>` child of the `<html>` document element.
+    pub fn head(&self) -> Option<Handle> {
+        self.html_child(&["head"])
+    }
+
+    /// The first `<body>` or `<frameset>` child of the `<html>` document
+    /// element.
+    pub fn body(&self) -> Option<Handle> {
+        self.html_child(&["body", "frameset"])
+    }
+
+    /// The text of the first HTML `<title>` element in tree order, with
+    /// ASCII whitespace stripped and collapsed to single spaces.
+    pub fn title(&self) -> Option<String> {
+        // FIXME: SVG documents take the title from the `<svg:title>` child
+        // of the document element.
+        let title = unwrap_or_return!(self.document.descendants()
+            .map(|(node, _)| node)
+            .find(|node| is_html_element(node, &["title"])), None);
+
+        let mut text = String::new();
+        for child in title.borrow().children.iter() {
+            match child.borrow().node {
+                Text(ref t) => text.push_str(t.as_slice()),
+                _ => (),
+            }
+        }
+
+        let mut result = String::new();
+        for word in split_html_space(text.as_slice()) {
+            if !result.is_empty() {
+                result.push(' ');
+            }
+            result.push_str(word);
+        }
+        Some(result)
+    }
 }
 
 impl TreeSink<Handle> for RcDom {
-    fn parse_error(&mut self, msg: MaybeOwned<'static>) {
-        self.errors.push(msg);
+    fn parse_error(&mut self, err: tree_builder::Error) {
+        self.errors.push(err);
     }
 
     fn get_document(&mut self) -> Handle {
@@ -146,11 +434,21 @@ impl TreeSink<Handle> for RcDom {
     }
 
     fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle {
-        new_node(Element(name, attrs))
+        let template = name == qualname!(HTML, template);
+        let node = self.new_node(Element(name, attrs));
+        if template {
+            let contents = self.new_node(Document);
+            node.borrow_mut().template_contents = Some(contents);
+        }
+        node
+    }
+
+    fn get_template_contents(&mut self, target: Handle) -> Handle {
+        target.borrow().template_contents.clone().expect("not a template element")
     }
 
     fn create_comment(&mut self, text: String) -> Handle {
-        new_node(Comment(text))
+        self.new_node(Comment(text))
     }
 
     fn append(&mut self, parent: Handle, child: NodeOrText<Handle>) {
@@ -163,10 +461,11 @@ impl TreeSink<Handle> for RcDom {
             _ => (),
         }
 
-        append(&parent, match child {
-            AppendText(text) => new_node(Text(text)),
+        let child = match child {
+            AppendText(text) => self.new_node(Text(text)),
             AppendNode(node) => node
-        });
+        };
+        append(&parent, child);
     }
 
     fn append_before_sibling(&mut self,
@@ -176,7 +475,7 @@ impl TreeSink<Handle> for RcDom {
 
         let child = match (child, i) {
             // No previous node.
-            (AppendText(text), 0) => new_node(Text(text)),
+            (AppendText(text), 0) => self.new_node(Text(text)),
 
             // Look for a text node before the insertion point.
             (AppendText(text), i) => {
@@ -185,7 +484,7 @@ impl TreeSink<Handle> for RcDom {
                 if append_to_existing_text(prev, text.as_slice()) {
                     return Ok(());
                 }
-                new_node(Text(text))
+                self.new_node(Text(text))
             }
 
             // The tree builder promises we won't have a text node after
@@ -205,7 +504,8 @@ impl TreeSink<Handle> for RcDom {
     }
 
     fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String) {
-        append(&self.document, new_node(Doctype(name, public_id, system_id)));
+        let doctype = self.new_node(Doctype(name, public_id, system_id));
+        append(&self.document, doctype);
     }
 
     fn add_attrs_if_missing(&mut self, target: Handle, mut attrs: Vec<Attribute>) {
@@ -226,6 +526,14 @@ impl TreeSink<Handle> for RcDom {
         remove_from_parent(&target);
     }
 
+    fn reparent_children(&mut self, node: Handle, new_parent: Handle) {
+        let children = replace(&mut node.borrow_mut().children, vec!());
+        for child in children.into_iter() {
+            child.borrow_mut().parent = None;
+            append(&new_parent, child);
+        }
+    }
+
     fn mark_script_already_started(&mut self, node: Handle) {
         node.borrow_mut().script_already_started = true;
     }
@@ -234,9 +542,10 @@ impl TreeSink<Handle> for RcDom {
 impl Default for RcDom {
     fn default() -> RcDom {
         RcDom {
-            document: new_node(Document),
+            document: Rc::new(RefCell::new(Node::new(0, Document))),
             errors: vec!(),
             quirks_mode: tree_builder::NoQuirks,
+            next_id: 1,
         }
     }
 }
@@ -257,7 +566,13 @@ impl Serializable for Handle {
                         attrs.iter().map(|at| (&at.name, at.value.as_slice()))));
                 }
 
-                for handle in node.children.iter() {
+                // A template's children are in its template contents.
+                let contents = node.template_contents.as_ref().map(|c| c.borrow());
+                let children = match contents {
+                    Some(ref contents) => &contents.children,
+                    None => &node.children,
+                };
+                for handle in children.iter() {
                     try!(handle.clone().serialize(serializer, true));
                 }
 
@@ -284,3 +599,90 @@ impl Serializable for Handle {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{RcDom, Handle, Traverse, same_node};
+    use sink::common::{Element, Text};
+
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    fn ids(input: &str) -> (RcDom, Vec<uint>) {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        let ids = dom.document.descendants().map(|(node, _)| node.borrow().id).collect();
+        (dom, ids)
+    }
+
+    #[test]
+    fn ids_in_creation_order() {
+        let (dom, ids) = ids("<!DOCTYPE html><p>a<i>b</i>");
+        assert_eq!(dom.document.borrow().id, 0);
+        assert_eq!(ids, vec!(1, 2, 3, 4, 5, 6, 7, 8));
+        assert_eq!(dom.node_count(), 9);
+    }
+
+    fn names(nodes: Vec<(Handle, uint)>) -> Vec<(String, uint)> {
+        nodes.into_iter().map(|(node, depth)| (match node.borrow().node {
+            Element(ref name, _) => String::from_str(name.local.as_slice()),
+            Text(ref text) => text.clone(),
+            _ => String::from_str("?"),
+        }, depth)).collect()
+    }
+
+    fn s(x: &str) -> String {
+        String::from_str(x)
+    }
+
+    #[test]
+    fn descendants_stay_below_root() {
+        let dom: RcDom = parse(one_input(s("<div><p>a<i>b</i></p>c</div><p>d")),
+            Default::default());
+        let div = dom.body().unwrap().children_elements().next().unwrap();
+        assert_eq!(names(div.descendants().collect()),
+            vec!((s("p"), 1), (s("a"), 2), (s("i"), 2), (s("b"), 3), (s("c"), 1)));
+        assert_eq!(names(div.borrow().children[1].descendants().collect()), vec!());
+    }
+
+    #[test]
+    fn wide_descendants() {
+        let mut input = String::new();
+        for _ in range(0u, 10000) {
+            input.push_str("<br>");
+        }
+        let dom: RcDom = parse(one_input(input), Default::default());
+        let body = dom.body().unwrap();
+        assert_eq!(body.descendants().count(), 10000);
+        assert!(body.descendants().all(|(_, depth)| depth == 1));
+    }
+
+    #[test]
+    fn sibling_elements() {
+        let dom: RcDom = parse(one_input(s("<p>a<i>b</i>c<b>d</b>e")), Default::default());
+        let p = dom.body().unwrap().children_elements().next().unwrap();
+        let (i, b) = (p.borrow().children[1].clone(), p.borrow().children[3].clone());
+        assert!(same_node(&i.next_sibling_element().unwrap(), &b));
+        assert!(same_node(&b.prev_sibling_element().unwrap(), &i));
+        assert!(b.next_sibling_element().is_none());
+        assert!(i.prev_sibling_element().is_none());
+        assert!(same_node(&i.next_sibling().unwrap(), &p.borrow().children[2]));
+        assert!(p.borrow().children[0].prev_sibling().is_none());
+    }
+
+    #[test]
+    fn ids_survive_moves() {
+        // The adoption agency algorithm moves nodes around and clones
+        // the <b>.
+        let (dom, ids) = ids("<b>1<p>2</b>3");
+        for (i, &id) in ids.iter().enumerate() {
+            assert!(!ids.slice_to(i).contains(&id));
+            assert!(id < dom.node_count());
+            assert_eq!(dom.node_by_id(id).unwrap().borrow().id, id);
+        }
+        assert!(dom.node_by_id(dom.node_count()).is_none());
+    }
+}
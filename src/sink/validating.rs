@@ -0,0 +1,451 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A `TreeSink` adapter which checks the tree builder's promises.
+//!
+//! `ValidatingSink` passes every call through to another sink, while
+//! keeping its own record of what each node is and where it's
+//! attached.  It fails as soon as a call breaks one of the invariants
+//! documented on `TreeSink`, e.g. appending a node which already has a
+//! parent, or builds a tree the DOM doesn't allow, e.g. a second
+//! doctype, children of a comment, or empty text.  It also fails when
+//! the sink's answers disagree with the record, e.g.
+//! `append_before_sibling` refusing a sibling which has a parent.
+//!
+//! Every lookup is a linear search using `same_node`, so this is for
+//! tests and debugging, not production.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let sink = parse_to(ValidatingSink::new(MySink::new()), one_input(page), Default::default());
+//! let my_sink = sink.unwrap();
+//! ```
+
+use core::prelude::*;
+
+use tokenizer::{Attribute, Span};
+use tree_builder::{TreeSink, QuirksMode, NodeOrText, AppendNode, AppendText, ElementKind};
+use tree_builder::Error;
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+use string_cache::QualName;
+
+#[deriving(PartialEq, Eq)]
+enum Kind {
+    DocumentNode,
+    ElementNode,
+    CommentNode,
+
+    // The template contents of a `<template>`.
+    ContentsNode,
+}
+
+// What we know about one node.
+struct Record<Handle> {
+    handle: Handle,
+    parent: Option<Handle>,
+    kind: Kind,
+    template_contents: Option<Handle>,
+}
+
+/// Wraps a `TreeSink` and fails if the tree builder or the sink breaks
+/// an invariant.  See the module documentation.
+pub struct ValidatingSink<Handle, Sink> {
+    inner: Sink,
+    nodes: Vec<Record<Handle>>,
+    seen_doctype: bool,
+}
+
+impl<Handle: Clone, Sink: TreeSink<Handle>> ValidatingSink<Handle, Sink> {
+    pub fn new(inner: Sink) -> ValidatingSink<Handle, Sink> {
+        ValidatingSink {
+            inner: inner,
+            nodes: vec!(),
+            seen_doctype: false,
+        }
+    }
+
+    /// Get back the wrapped sink.
+    pub fn unwrap(self) -> Sink {
+        self.inner
+    }
+
+    fn same(&self, x: &Handle, y: &Handle) -> bool {
+        self.inner.same_node(x.clone(), y.clone())
+    }
+
+    fn find(&self, node: &Handle) -> Option<uint> {
+        self.nodes.iter().position(|r| self.same(&r.handle, node))
+    }
+
+    fn index(&self, node: &Handle, what: &str) -> uint {
+        match self.find(node) {
+            Some(i) => i,
+            None => fail!("{}: node was never created", what),
+        }
+    }
+
+    fn add(&mut self, node: Handle, kind: Kind, what: &str) {
+        if self.find(&node).is_some() {
+            fail!("{}: returned an existing node", what);
+        }
+        self.nodes.push(Record {
+            handle: node,
+            parent: None,
+            kind: kind,
+            template_contents: None,
+        });
+    }
+
+    fn kind_of(&self, node: &Handle, what: &str) -> Kind {
+        self.nodes[self.index(node, what)].kind
+    }
+
+    fn parent_of(&self, node: &Handle, what: &str) -> Option<Handle> {
+        self.nodes[self.index(node, what)].parent.clone()
+    }
+
+    fn set_parent(&mut self, node: &Handle, parent: Option<Handle>, what: &str) {
+        let i = self.index(node, what);
+        self.nodes.get_mut(i).parent = parent;
+    }
+
+    // Is `node` the same as `other` or one of its ancestors?
+    fn is_inclusive_ancestor(&self, node: &Handle, other: &Handle) -> bool {
+        let mut cur = Some(other.clone());
+        loop {
+            let c = unwrap_or_return!(cur, false);
+            if self.same(node, &c) {
+                return true;
+            }
+            cur = match self.find(&c) {
+                Some(i) => self.nodes[i].parent.clone(),
+                None => None,
+            };
+        }
+    }
+
+    // Does the document have an element child other than `except`?
+    fn has_document_element(&self, except: Option<&Handle>) -> bool {
+        self.nodes.iter().any(|r| r.kind == ElementNode
+            && match r.parent {
+                Some(ref p) => self.kind_of(p, "document element") == DocumentNode,
+                None => false,
+            }
+            && !except.map_or(false, |e| self.same(&r.handle, e)))
+    }
+
+    // Check that `child` may be inserted under `parent`.
+    fn check_insert(&self, parent: &Handle, child: &NodeOrText<Handle>, what: &str) {
+        let parent_kind = self.kind_of(parent, what);
+        if parent_kind == CommentNode {
+            fail!("{}: a comment can't have children", what);
+        }
+        match *child {
+            AppendNode(ref child) => {
+                if self.is_inclusive_ancestor(child, parent) {
+                    fail!("{}: would make a node its own ancestor", what);
+                }
+                if parent_kind == DocumentNode && self.kind_of(child, what) == ElementNode
+                        && self.has_document_element(Some(child)) {
+                    fail!("{}: a second document element", what);
+                }
+            }
+            AppendText(ref text) => {
+                if text.is_empty() {
+                    fail!("{}: empty text", what);
+                }
+                if parent_kind == DocumentNode {
+                    fail!("{}: text in the Document", what);
+                }
+            }
+        }
+    }
+}
+
+impl<Handle: Clone, Sink: TreeSink<Handle>> TreeSink<Handle> for ValidatingSink<Handle, Sink> {
+    fn parse_error(&mut self, err: Error) {
+        self.inner.parse_error(err)
+    }
+
+    fn get_document(&mut self) -> Handle {
+        let doc = self.inner.get_document();
+        if self.find(&doc).is_none() {
+            self.add(doc.clone(), DocumentNode, "get_document");
+        }
+        doc
+    }
+
+    fn same_node(&self, x: Handle, y: Handle) -> bool {
+        self.inner.same_node(x, y)
+    }
+
+    fn elem_name(&self, target: Handle) -> QualName {
+        if self.kind_of(&target, "elem_name") != ElementNode {
+            fail!("elem_name: not an element");
+        }
+        self.inner.elem_name(target)
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.inner.set_quirks_mode(mode)
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle {
+        let elem = self.inner.create_element(name, attrs);
+        self.add(elem.clone(), ElementNode, "create_element");
+        elem
+    }
+
+    fn create_element_of_kind(&mut self, kind: ElementKind, is: Option<String>,
+            name: QualName, attrs: Vec<Attribute>) -> Handle {
+        let elem = self.inner.create_element_of_kind(kind, is, name, attrs);
+        self.add(elem.clone(), ElementNode, "create_element_of_kind");
+        elem
+    }
+
+    fn create_comment(&mut self, text: String) -> Handle {
+        let comment = self.inner.create_comment(text);
+        self.add(comment.clone(), CommentNode, "create_comment");
+        comment
+    }
+
+    fn get_template_contents(&mut self, target: Handle) -> Handle {
+        let i = self.index(&target, "get_template_contents");
+        if self.nodes[i].kind != ElementNode {
+            fail!("get_template_contents: not an element");
+        }
+        let contents = self.inner.get_template_contents(target);
+        let old = self.nodes[i].template_contents.clone();
+        match old {
+            Some(ref old) => if !self.same(old, &contents) {
+                fail!("get_template_contents: returned a different node than before");
+            },
+            None => {
+                self.nodes.get_mut(i).template_contents = Some(contents.clone());
+                self.add(contents.clone(), ContentsNode, "get_template_contents");
+            }
+        }
+        contents
+    }
+
+    fn append(&mut self, parent: Handle, child: NodeOrText<Handle>) {
+        self.check_insert(&parent, &child, "append");
+        let node = match child {
+            AppendNode(ref node) => Some(node.clone()),
+            _ => None,
+        };
+        match node {
+            Some(ref node) => if self.parent_of(node, "append").is_some() {
+                fail!("append: child already has a parent");
+            },
+            None => (),
+        }
+
+        self.inner.append(parent.clone(), child);
+        match node {
+            Some(ref node) => self.set_parent(node, Some(parent), "append"),
+            None => (),
+        }
+    }
+
+    fn append_before_sibling(&mut self,
+            sibling: Handle,
+            new_node: NodeOrText<Handle>) -> Result<(), NodeOrText<Handle>> {
+        let parent = self.parent_of(&sibling, "append_before_sibling");
+        match parent {
+            Some(ref parent) => self.check_insert(parent, &new_node, "append_before_sibling"),
+            None => (),
+        }
+        let node = match new_node {
+            AppendNode(ref node) => Some(node.clone()),
+            _ => None,
+        };
+
+        let result = self.inner.append_before_sibling(sibling, new_node);
+        match (&result, parent) {
+            (&Ok(()), None)
+                => fail!("append_before_sibling: inserted next to a node with no parent"),
+            (&Err(_), Some(_))
+                => fail!("append_before_sibling: refused a sibling which has a parent"),
+            (&Ok(()), Some(parent)) => match node {
+                Some(ref node) => self.set_parent(node, Some(parent), "append_before_sibling"),
+                None => (),
+            },
+            (&Err(_), None) => (),
+        }
+        result
+    }
+
+    fn append_based_on_parent_node(&mut self,
+            element: Handle,
+            prev_element: Handle,
+            child: NodeOrText<Handle>) {
+        let parent = self.parent_of(&element, "append_based_on_parent_node")
+            .unwrap_or(prev_element.clone());
+        self.check_insert(&parent, &child, "append_based_on_parent_node");
+        let node = match child {
+            AppendNode(ref node) => Some(node.clone()),
+            _ => None,
+        };
+
+        self.inner.append_based_on_parent_node(element, prev_element, child);
+        match node {
+            Some(ref node) => self.set_parent(node, Some(parent), "append_based_on_parent_node"),
+            None => (),
+        }
+    }
+
+    fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String) {
+        if self.seen_doctype {
+            fail!("append_doctype_to_document: a second doctype");
+        }
+        if self.has_document_element(None) {
+            fail!("append_doctype_to_document: doctype after the document element");
+        }
+        self.seen_doctype = true;
+        self.inner.append_doctype_to_document(name, public_id, system_id)
+    }
+
+    fn add_attrs_if_missing(&mut self, target: Handle, attrs: Vec<Attribute>) {
+        if self.kind_of(&target, "add_attrs_if_missing") != ElementNode {
+            fail!("add_attrs_if_missing: not an element");
+        }
+        self.inner.add_attrs_if_missing(target, attrs)
+    }
+
+    fn remove_from_parent(&mut self, target: Handle) {
+        self.set_parent(&target, None, "remove_from_parent");
+        self.inner.remove_from_parent(target)
+    }
+
+    fn reparent_children(&mut self, node: Handle, new_parent: Handle) {
+        if self.kind_of(&new_parent, "reparent_children") == CommentNode {
+            fail!("reparent_children: a comment can't have children");
+        }
+        if self.is_inclusive_ancestor(&node, &new_parent) {
+            fail!("reparent_children: would make a node its own ancestor");
+        }
+        for i in range(0, self.nodes.len()) {
+            let moved = match self.nodes[i].parent {
+                Some(ref p) => self.same(p, &node),
+                None => false,
+            };
+            if moved {
+                self.nodes.get_mut(i).parent = Some(new_parent.clone());
+            }
+        }
+        self.inner.reparent_children(node, new_parent)
+    }
+
+    fn mark_script_already_started(&mut self, node: Handle) {
+        self.inner.mark_script_already_started(node)
+    }
+
+    fn complete_script(&mut self, node: Handle) {
+        self.inner.complete_script(node)
+    }
+
+    fn input_size_hint(&mut self, bytes: uint) {
+        self.inner.input_size_hint(bytes)
+    }
+
+    fn account_attrs(&mut self, name: &QualName, bytes: uint) {
+        self.inner.account_attrs(name, bytes)
+    }
+
+    fn account_text(&mut self, parent: Handle, bytes: uint) {
+        self.inner.account_text(parent, bytes)
+    }
+
+    fn set_text_span(&mut self, parent: Handle, span: Span) {
+        self.inner.set_text_span(parent, span)
+    }
+
+    fn set_node_span(&mut self, node: Handle, span: Span) {
+        self.inner.set_node_span(node, span)
+    }
+
+    fn set_end_span(&mut self, elem: Handle, span: Span) {
+        self.inner.set_end_span(elem, span)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::ValidatingSink;
+
+    use sink::rcdom::{RcDom, Handle};
+    use tree_builder::{TreeSink, AppendNode, AppendText};
+    use driver::{parse_to, one_input};
+
+    use core::default::Default;
+    use collections::string::String;
+
+    fn checked(input: &str) -> RcDom {
+        let sink: ValidatingSink<Handle, RcDom> = parse_to(ValidatingSink::new(Default::default()),
+            one_input(String::from_str(input)), Default::default());
+        sink.unwrap()
+    }
+
+    #[test]
+    fn tree_builder_keeps_invariants() {
+        for input in ["<b>1<p>2</b>3</p>",
+                "<a><div><a>x</a></div></a>",
+                "<table><tr>a<td>b</td>c<b>d</table>e",
+                "<template><td>a</td></template><frameset>",
+                "<body><div></body><body a=b>"].iter() {
+            checked(*input);
+        }
+    }
+
+    #[test]
+    #[should_fail]
+    fn append_attached_node() {
+        let mut sink: ValidatingSink<Handle, RcDom> = ValidatingSink::new(Default::default());
+        let doc = sink.get_document();
+        let p = sink.create_element(qualname!(HTML, p), vec!());
+        let div = sink.create_element(qualname!(HTML, div), vec!());
+        sink.append(doc, AppendNode(p.clone()));
+        sink.append(div, AppendNode(p));
+    }
+
+    #[test]
+    #[should_fail]
+    fn second_doctype() {
+        let mut sink: ValidatingSink<Handle, RcDom> = ValidatingSink::new(Default::default());
+        sink.get_document();
+        sink.append_doctype_to_document(String::from_str("html"), String::new(), String::new());
+        sink.append_doctype_to_document(String::from_str("html"), String::new(), String::new());
+    }
+
+    #[test]
+    #[should_fail]
+    fn second_document_element() {
+        let mut sink: ValidatingSink<Handle, RcDom> = ValidatingSink::new(Default::default());
+        let doc = sink.get_document();
+        let html = sink.create_element(qualname!(HTML, html), vec!());
+        let body = sink.create_element(qualname!(HTML, body), vec!());
+        sink.append(doc.clone(), AppendNode(html));
+        sink.append(doc, AppendNode(body));
+    }
+
+    #[test]
+    #[should_fail]
+    fn text_in_comment() {
+        let mut sink: ValidatingSink<Handle, RcDom> = ValidatingSink::new(Default::default());
+        let comment = sink.create_comment(String::from_str("x"));
+        sink.append(comment, AppendText(String::from_str("y")));
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Turning an `RcDom` tree back into tokens.
+//!
+//! This is the reverse of tree building.  `walk_tokens` sends a
+//! `TokenSink` the tokens for each node, in document order, so a token
+//! filter written for the streaming path can also run over a tree which
+//! was already built.  Every element gets explicit start and end tags,
+//! as the serializer would write them.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let dom: RcDom = parse(one_input(my_str), Default::default());
+//! let filter = tokenize_dom(MyFilter::new(), &dom);
+//! ```
+
+use core::prelude::*;
+
+use sink::common::{Document, Doctype, Text, Comment, Element};
+use sink::rcdom::{Handle, RcDom};
+use tokenizer;
+use tokenizer::{TokenSink, Tag, TagToken, StartTag, EndTag};
+use tokenizer::{DoctypeToken, CharacterTokens, CommentToken, EOFToken};
+
+use collections::string::String;
+
+use string_cache::QualName;
+
+fn is_void(name: &QualName) -> bool {
+    name.ns == ns!(HTML) && match name.local {
+        atom!(area) | atom!(base) | atom!(basefont) | atom!(bgsound) | atom!(br)
+        | atom!(col) | atom!(embed) | atom!(frame) | atom!(hr) | atom!(img)
+        | atom!(input) | atom!(keygen) | atom!(link) | atom!(menuitem)
+        | atom!(meta) | atom!(param) | atom!(source) | atom!(track) | atom!(wbr)
+            => true,
+        _ => false,
+    }
+}
+
+fn starts_with_newline(name: &QualName, children: &[Handle]) -> bool {
+    let drops_newline = name.ns == ns!(HTML) && match name.local {
+        atom!(pre) | atom!(textarea) | atom!(listing) => true,
+        _ => false,
+    };
+    if !drops_newline {
+        return false;
+    }
+    match children.head() {
+        Some(child) => match child.borrow().node {
+            Text(ref text) => text.as_slice().starts_with("\n"),
+            _ => false,
+        },
+        None => false,
+    }
+}
+
+fn non_empty(s: &String) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.clone())
+    }
+}
+
+/// Send `sink` the tokens for the children of `node`, and for `node`
+/// itself if `incl_self` is set.
+///
+/// Void elements get no end tag.  A foreign element with no children,
+/// like an SVG `<path>`, gets a self-closing start tag and no end tag.
+/// Tags carry only the local name of an element.
+///
+/// ## Failure
+///
+/// Fails if `incl_self` is set and `node` is a document.
+pub fn walk_tokens<Sink: TokenSink>(sink: &mut Sink, node: &Handle, incl_self: bool) {
+    let node = node.borrow();
+    match (incl_self, &node.node) {
+        (_, &Element(ref name, ref attrs)) => {
+            let self_closing = name.ns != ns!(HTML) && node.children.is_empty();
+            if incl_self {
+                sink.process_token(TagToken(Tag {
+                    kind: StartTag,
+                    name: name.local.clone(),
+                    self_closing: self_closing,
+                    attrs: attrs.clone(),
+                }));
+            }
+
+            // The tree builder drops a newline right after these start
+            // tags, so add one to keep any newline in the content.
+            if incl_self && starts_with_newline(name, node.children.as_slice()) {
+                sink.process_token(CharacterTokens(String::from_str("\n")));
+            }
+
+            for child in node.children.iter() {
+                walk_tokens(sink, child, true);
+            }
+
+            // A `<template>`'s content is written out as its children.
+            match node.template_contents {
+                Some(ref contents) => walk_tokens(sink, contents, false),
+                None => (),
+            }
+
+            if incl_self && !self_closing && !is_void(name) {
+                sink.process_token(TagToken(Tag {
+                    kind: EndTag,
+                    name: name.local.clone(),
+                    self_closing: false,
+                    attrs: vec!(),
+                }));
+            }
+        }
+
+        (false, &Document) => for child in node.children.iter() {
+            walk_tokens(sink, child, true);
+        },
+
+        (false, _) => (),
+
+        (true, &Doctype(ref name, ref public_id, ref system_id))
+            => sink.process_token(DoctypeToken(tokenizer::Doctype {
+                name: Some(name.clone()),
+                public_id: non_empty(public_id),
+                system_id: non_empty(system_id),
+                force_quirks: false,
+            })),
+
+        (true, &Text(ref text)) => sink.process_token(CharacterTokens(text.clone())),
+        (true, &Comment(ref text)) => sink.process_token(CommentToken(text.clone())),
+
+        (true, &Document) => fail!("Can't walk Document node itself"),
+    }
+}
+
+/// Send `sink` the tokens for a whole document, followed by an
+/// `EOFToken`.
+pub fn tokenize_dom<Sink: TokenSink>(mut sink: Sink, dom: &RcDom) -> Sink {
+    walk_tokens(&mut sink, &dom.document, false);
+    sink.process_token(EOFToken);
+    sink
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::tokenize_dom;
+
+    use sink::rcdom::RcDom;
+    use tokenizer::{Token, TokenSink, TagToken, EndTag, CharacterTokens};
+    use tree_builder::TreeBuilder;
+    use serialize::serialize;
+    use driver::{parse, one_input};
+
+    use core::default::Default;
+    use collections::MutableSeq;
+    use collections::vec::Vec;
+    use collections::string::String;
+    use std::io::MemWriter;
+
+    fn to_html(dom: &RcDom) -> String {
+        let mut out = MemWriter::new();
+        serialize(&mut out, &dom.document, Default::default()).unwrap();
+        String::from_utf8(out.unwrap()).unwrap()
+    }
+
+    // Parse, then build a second tree from the walked tokens.
+    fn round_trip(input: &str) -> (String, String) {
+        let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+        let tb = tokenize_dom(TreeBuilder::new(RcDom::default(), Default::default()), &dom);
+        (to_html(&dom), to_html(&tb.unwrap()))
+    }
+
+    struct Names(Vec<String>);
+
+    impl TokenSink for Names {
+        fn process_token(&mut self, token: Token) {
+            let Names(ref mut names) = *self;
+            match token {
+                TagToken(tag) => {
+                    let mut name = String::from_str(if tag.kind == EndTag { "/" } else { "" });
+                    name.push_str(tag.name.as_slice());
+                    names.push(name);
+                }
+                CharacterTokens(s) => names.push(s),
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for input in ["<!DOCTYPE html><title>x</title><p class=a>b<br>c<!--d-->",
+                "<table><tr><td>1<td>2</table>",
+                "<ul><li>a<li>b</ul><pre>\n\nx</pre>"].iter() {
+            let (a, b) = round_trip(*input);
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn template_contents() {
+        let dom: RcDom = parse(one_input(String::from_str(
+            "<template><p>a</p></template>")), Default::default());
+        let Names(names) = tokenize_dom(Names(vec!()), &dom);
+        let expected: Vec<String> = ["html", "head", "template", "p", "a", "/p",
+            "/template", "/head", "body", "/body", "/html"]
+            .iter().map(|s| String::from_str(*s)).collect();
+        assert_eq!(names, expected);
+
+        let (a, b) = round_trip("<template><td>x</td></template>");
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn void_has_no_end_tag() {
+        let dom: RcDom = parse(one_input(String::from_str("<p>a<br>b")), Default::default());
+        let Names(names) = tokenize_dom(Names(vec!()), &dom);
+        let expected: Vec<String> = ["html", "head", "/head", "body", "p",
+            "a", "br", "b", "/p", "/body", "/html"]
+            .iter().map(|s| String::from_str(*s)).collect();
+        assert_eq!(names, expected);
+    }
+}
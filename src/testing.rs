@@ -0,0 +1,464 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Helpers for testing code which consumes the parser's output: that
+//! output doesn't depend on how the input is split into chunks, that
+//! a document parses to the expected elements or markup, and that a
+//! tree matches a snapshot kept in a file.
+//!
+//! Only built with the `testing` Cargo feature.
+//!
+//! ## Example
+//!
+//! ```rust
+//! check_chunking(my_str, all_splits(my_str, 3), |input| {
+//!     let dom: RcDom = parse(input.into_iter(), Default::default());
+//!     my_serialize(dom)
+//! });
+//!
+//! assert_parses_to!("<p>a<p>b", "<p>a</p><p>b</p>");
+//! assert_elements!("<table><td>x", ["html", "head", "body", "table", "tbody", "tr", "td"]);
+//!
+//! let dom: RcDom = parse(one_input(page), Default::default());
+//! check_snapshot(&Path::new("tests/snapshots/page.tree"), dump_tree(&dom.document).as_slice());
+//! ```
+//!
+//! Run with `HTML5EVER_UPDATE_SNAPSHOTS` set in the environment to
+//! write the snapshots instead of checking them.
+
+use core::prelude::*;
+
+use tokenizer::{Token, TokenSink, Tokenizer, TokenizerOpts};
+use tokenizer::{CharacterTokens, WhitespaceTokens, NullCharacterToken};
+
+#[cfg(feature = "tree_builder")]
+use sink::common::{Document, Doctype, Text, Comment, Element};
+#[cfg(feature = "tree_builder")]
+use sink::rcdom::{RcDom, Handle, Traverse};
+#[cfg(feature = "tree_builder")]
+use driver::{parse, one_input};
+#[cfg(feature = "tree_builder")]
+use serialize::serialize;
+
+use core::fmt::Show;
+use core::mem::replace;
+#[cfg(feature = "tree_builder")]
+use core::default::Default;
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+use std::io::File;
+use std::os;
+use std::path::Path;
+#[cfg(feature = "tree_builder")]
+use std::io::MemWriter;
+
+/// Return all ways of splitting the string into at most `n`
+/// possibly-empty pieces, at character boundaries.
+///
+/// The number of splits grows as `s.len()` to the power `n - 1`,
+/// so keep `n` small.
+pub fn all_splits(s: &str, n: uint) -> Vec<Vec<String>> {
+    if n <= 1 {
+        return vec!(vec!(s.to_string()));
+    }
+
+    let mut points: Vec<uint> = s.char_indices().map(|(n,_)| n).collect();
+    points.push(s.len());
+
+    let mut out = vec!();
+    for p in points.into_iter() {
+        let y = s.slice_from(p);
+        for mut x in all_splits(s.slice_to(p), n-1).into_iter() {
+            x.push(y.to_string());
+            out.push(x);
+        }
+    }
+
+    out.extend(all_splits(s, n-1).into_iter());
+    out
+}
+
+/// Split the string into non-empty pieces at pseudo-random character
+/// boundaries.  The same `seed` always gives the same pieces.
+pub fn random_split(s: &str, seed: u32) -> Vec<String> {
+    // xorshift, which must not start at zero.
+    let mut x = if seed == 0 { 1 } else { seed };
+    let mut out = vec!();
+    let mut rest = s;
+    while !rest.is_empty() {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        let mut n = 1 + (x as uint) % rest.len();
+        while !rest.is_char_boundary(n) {
+            n += 1;
+        }
+        out.push(rest.slice_to(n).to_string());
+        rest = rest.slice_from(n);
+    }
+    out
+}
+
+/// Run `f` on the whole input and on each of `splits`, and `fail!()`
+/// unless every result is equal.
+pub fn check_chunking<T: PartialEq + Show>(
+        input: &str,
+        splits: Vec<Vec<String>>,
+        f: |Vec<String>| -> T) {
+
+    let expected = f(vec!(input.to_string()));
+    for split in splits.into_iter() {
+        let result = f(split.clone());
+        if result != expected {
+            fail!("\ninput: {}\nsplit: {}\ngot: {}\nexpected: {}\n",
+                input, split, result, expected);
+        }
+    }
+}
+
+/// A `TokenSink` which records tokens, merging adjacent character
+/// tokens so that the result doesn't depend on chunking.
+pub struct TokenCollector {
+    tokens: Vec<Token>,
+    current_str: String,
+}
+
+impl TokenCollector {
+    pub fn new() -> TokenCollector {
+        TokenCollector {
+            tokens: vec!(),
+            current_str: String::new(),
+        }
+    }
+
+    fn finish_str(&mut self) {
+        if self.current_str.len() > 0 {
+            let s = replace(&mut self.current_str, String::new());
+            self.tokens.push(CharacterTokens(s));
+        }
+    }
+
+    /// Get the tokens seen so far.  `WhitespaceTokens` and
+    /// `NullCharacterToken`s are included in the text of `CharacterTokens`.
+    pub fn unwrap(mut self) -> Vec<Token> {
+        self.finish_str();
+        self.tokens
+    }
+}
+
+impl TokenSink for TokenCollector {
+    fn process_token(&mut self, token: Token) {
+        match token {
+            CharacterTokens(b) | WhitespaceTokens(b)
+                => self.current_str.push_str(b.as_slice()),
+            NullCharacterToken => self.current_str.push('\0'),
+            token => {
+                self.finish_str();
+                self.tokens.push(token);
+            }
+        }
+    }
+}
+
+/// Tokenize the input, split in each of the given ways, and `fail!()`
+/// unless the tokens are always the same.
+pub fn check_tokenizer_chunking(input: &str, splits: Vec<Vec<String>>, opts: TokenizerOpts) {
+    check_chunking(input, splits, |chunks| {
+        let mut tok = Tokenizer::new(TokenCollector::new(), opts.clone());
+        for chunk in chunks.into_iter() {
+            tok.feed(chunk);
+        }
+        tok.end();
+        tok.unwrap().unwrap()
+    });
+}
+
+/// Parse a document and serialize what ends up in its `<body>`, which
+/// is usually the interesting part of a test.
+#[cfg(feature = "tree_builder")]
+pub fn body_html(input: &str) -> String {
+    let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+    let body = dom.body().expect("no <body>");
+    let mut out = MemWriter::new();
+    serialize(&mut out, &body, Default::default()).unwrap();
+    String::from_utf8(out.unwrap()).unwrap()
+}
+
+/// Parse a document and list the local names of its elements in tree
+/// order, including the `<html>`, `<head>` and `<body>` which the
+/// parser always creates.
+#[cfg(feature = "tree_builder")]
+pub fn element_names(input: &str) -> Vec<String> {
+    let dom: RcDom = parse(one_input(String::from_str(input)), Default::default());
+    dom.document.descendants().filter_map(|(node, _)| match node.borrow().node {
+        Element(ref name, _) => Some(String::from_str(name.local.as_slice())),
+        _ => None,
+    }).collect()
+}
+
+#[cfg(feature = "tree_builder")]
+fn dump_node(buf: &mut String, indent: uint, handle: &Handle) {
+    buf.push('|');
+    buf.grow(indent, ' ');
+
+    let node = handle.borrow();
+    match node.node {
+        Document => fail!("should not reach Document"),
+
+        Doctype(ref name, ref public, ref system) => {
+            buf.push_str("<!DOCTYPE ");
+            buf.push_str(name.as_slice());
+            if !public.is_empty() || !system.is_empty() {
+                buf.push_str(format!(" \"{}\" \"{}\"", public, system).as_slice());
+            }
+            buf.push_str(">\n");
+        }
+
+        Text(ref text) => {
+            buf.push('"');
+            buf.push_str(text.as_slice());
+            buf.push_str("\"\n");
+        }
+
+        Comment(ref text) => {
+            buf.push_str("<!-- ");
+            buf.push_str(text.as_slice());
+            buf.push_str(" -->\n");
+        }
+
+        Element(ref name, ref attrs) => {
+            buf.push('<');
+            match name.ns {
+                ns!(HTML) => (),
+                ns!(SVG) => buf.push_str("svg "),
+                ns!(MathML) => buf.push_str("math "),
+                _ => fail!("unexpected element namespace"),
+            }
+            buf.push_str(name.local.as_slice());
+            buf.push_str(">\n");
+
+            let mut attrs: Vec<(String, String)> = attrs.iter().map(|attr| {
+                let prefix = match attr.name.ns {
+                    ns!("") => "",
+                    ns!(XLink) => "xlink ",
+                    ns!(XML) => "xml ",
+                    ns!(XMLNS) => "xmlns ",
+                    _ => fail!("unexpected attribute namespace"),
+                };
+                (format!("{}{}", prefix, attr.name.local.as_slice()), attr.value.clone())
+            }).collect();
+            attrs.sort();
+            // FIXME: sort by UTF-16 code unit
+
+            for (name, value) in attrs.into_iter() {
+                buf.push('|');
+                buf.grow(indent + 2, ' ');
+                buf.push_str(format!("{}=\"{}\"\n", name, value).as_slice());
+            }
+        }
+    }
+
+    for child in node.children.iter() {
+        dump_node(buf, indent + 2, child);
+    }
+
+    match node.template_contents {
+        Some(ref contents) => {
+            buf.push('|');
+            buf.grow(indent + 2, ' ');
+            buf.push_str("content\n");
+            for child in contents.borrow().children.iter() {
+                dump_node(buf, indent + 4, child);
+            }
+        }
+        None => (),
+    }
+}
+
+/// Write the children of `root` in the indented format of the
+/// html5lib tree construction tests, one line per node or attribute,
+/// each ending in a newline.  Pass the document, or the `<html>`
+/// element holding a parsed fragment.
+#[cfg(feature = "tree_builder")]
+pub fn dump_tree(root: &Handle) -> String {
+    let mut buf = String::new();
+    for child in root.borrow().children.iter() {
+        dump_node(&mut buf, 1, child);
+    }
+    buf
+}
+
+/// Compare `actual` with the snapshot in the file at `path`, and
+/// `fail!()` if they differ or there's no snapshot.  If the
+/// `HTML5EVER_UPDATE_SNAPSHOTS` environment variable is set, write
+/// `actual` to the file instead.
+pub fn check_snapshot(path: &Path, actual: &str) {
+    if os::getenv("HTML5EVER_UPDATE_SNAPSHOTS").is_some() {
+        match File::create(path).write_str(actual) {
+            Ok(()) => return,
+            Err(e) => fail!("couldn't write snapshot {}: {}", path.display(), e),
+        }
+    }
+
+    let expected = match File::open(path).read_to_string() {
+        Ok(s) => s,
+        Err(e) => fail!("couldn't read snapshot {}: {}\n\
+            (set HTML5EVER_UPDATE_SNAPSHOTS to create it)", path.display(), e),
+    };
+    if expected.as_slice() != actual {
+        fail!("\nsnapshot: {}\ngot:\n{}\nexpected:\n{}\n\
+            (set HTML5EVER_UPDATE_SNAPSHOTS to update it)", path.display(), actual, expected);
+    }
+}
+
+/// Assert that a document's `<body>` serializes to the given HTML.
+#[macro_export]
+macro_rules! assert_parses_to ( ($input:expr, $body:expr) => ({
+    let input: &str = $input;
+    let expected: &str = $body;
+    let got = ::html5ever::testing::body_html(input);
+    if got.as_slice() != expected {
+        fail!("\ninput: {}\ngot: {}\nexpected: {}\n", input, got, expected);
+    }
+}))
+
+/// Assert that a document has the given elements, in tree order.
+#[macro_export]
+macro_rules! assert_elements ( ($input:expr, [$($name:expr),*]) => ({
+    let input: &str = $input;
+    let expected: &[&str] = &[$($name),*];
+    let got = ::html5ever::testing::element_names(input);
+    if got.iter().map(|n| n.as_slice()).collect::<Vec<&str>>().as_slice() != expected {
+        fail!("\ninput: {}\ngot: {}\nexpected: {}\n", input, got, expected);
+    }
+}))
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use collections::string::String;
+    use super::{all_splits, random_split, check_tokenizer_chunking, TokenCollector};
+    use super::{body_html, element_names, dump_tree};
+
+    use sink::rcdom::RcDom;
+    use driver::{parse, one_input};
+    use tokenizer::{TokenizerOpts, Tokenizer, TagToken, EndTag, ParseError};
+
+    use core::default::Default;
+
+    #[test]
+    fn all_splits_count() {
+        // "" + "ab", "a" + "b", "ab" + "", and "ab" alone.
+        assert_eq!(all_splits("ab", 2).len(), 4);
+    }
+
+    #[test]
+    fn random_split_rejoins() {
+        let s = "foo <b>\u00e9</b> bar";
+        let pieces = random_split(s, 42);
+        let mut joined = String::new();
+        for p in pieces.iter() {
+            assert!(!p.is_empty());
+            joined.push_str(p.as_slice());
+        }
+        assert_eq!(joined.as_slice(), s);
+    }
+
+    #[test]
+    fn tokenizer_chunking() {
+        let s = "<a href='x&amp;y'>z&lt;</a><!--c-->";
+        check_tokenizer_chunking(s, all_splits(s, 2), Default::default());
+    }
+
+    #[test]
+    fn char_ref_chunking() {
+        let s = "&amp;&notin;b&not c&noti&#x41;&#65&ampx<a title='&amp=1&notit;&lt'>";
+        check_tokenizer_chunking(s, all_splits(s, 3), Default::default());
+    }
+
+    #[test]
+    fn end_tag_keeps_attrs() {
+        let mut tok = Tokenizer::new(TokenCollector::new(), Default::default());
+        tok.feed(String::from_str("</a b=c/>"));
+        tok.end();
+        let tokens = tok.unwrap().unwrap();
+        let errors = tokens.iter().filter(|t| match **t {
+            ParseError(_) => true,
+            _ => false,
+        }).count();
+        assert_eq!(errors, 2);
+        match tokens.iter().find(|t| match **t { TagToken(_) => true, _ => false }) {
+            Some(&TagToken(ref tag)) => {
+                assert_eq!(tag.kind, EndTag);
+                assert!(tag.self_closing);
+                assert_eq!(tag.attrs.len(), 1);
+                assert_eq!(tag.attrs[0].value.as_slice(), "c");
+            }
+            _ => fail!("no tag"),
+        }
+    }
+
+    #[test]
+    fn passthrough_chunking() {
+        let s = "a{b{{ x < y }}<p>{{z}}<{{w";
+        let opts = TokenizerOpts {
+            template_delimiters: vec!((String::from_str("{{"), String::from_str("}}"))),
+            .. Default::default()
+        };
+        check_tokenizer_chunking(s, all_splits(s, 3), opts);
+    }
+
+    #[test]
+    fn processing_instruction_chunking() {
+        let s = "a<?php echo '>' ?>b<?xml?><?x";
+        let opts = TokenizerOpts {
+            processing_instructions: true,
+            .. Default::default()
+        };
+        check_tokenizer_chunking(s, all_splits(s, 3), opts);
+    }
+
+    #[test]
+    fn attr_template_chunking() {
+        let s = "<a href={{ x&amp;y }} title='a{{\"}}'}}'>";
+        let opts = TokenizerOpts {
+            template_delimiters: vec!((String::from_str("{{"), String::from_str("}}"))),
+            template_attr_values: true,
+            .. Default::default()
+        };
+        check_tokenizer_chunking(s, all_splits(s, 2), opts);
+    }
+
+    test_eq!(body, body_html("<title>t</title><p>a<p>b").as_slice(), "<p>a</p><p>b</p>")
+
+    test_eq!(elements, element_names("<table><td>x<svg>"),
+        vec!("html", "head", "body", "table", "tbody", "tr", "td", "svg")
+            .into_iter().map(|n| String::from_str(n)).collect())
+
+    #[test]
+    fn tree_dump() {
+        let dom: RcDom = parse(one_input(String::from_str("<!DOCTYPE html><p id=x>a<!--b-->\
+            <svg xlink:href=y></svg><template>c</template>")), Default::default());
+        assert_eq!(dump_tree(&dom.document).as_slice(), "\
+            | <!DOCTYPE html>\n\
+            | <html>\n\
+            |   <head>\n\
+            |   <body>\n\
+            |     <p>\n\
+            |       id=\"x\"\n\
+            |       \"a\"\n\
+            |       <!-- b -->\n\
+            |       <svg svg>\n\
+            |         xlink href=\"y\"\n\
+            |       <template>\n\
+            |         content\n\
+            |           \"c\"\n");
+    }
+}
@@ -0,0 +1,393 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Text extracted from a document, mapped back to the source.
+//!
+//! `extract_text` parses the document with span tracking on and keeps
+//! each piece of character data with the span of input it came from,
+//! so a search index or annotation tool can highlight a match in the
+//! original HTML.  Words and sentences get a span from their first
+//! character to their last, including any markup in between.
+//!
+//! A character maps to exactly its own bytes of input, unless its run
+//! of text is a different length from the input, as for a character
+//! reference or a `\r\n`; then it maps to the whole run.  Words and
+//! sentences continue across phrasing elements like `<b>` and `<a>`,
+//! but not from one block to another.  The text of `<script>` and
+//! `<style>`, and the contents of `<template>`, are left out.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let text = extract_text(page, Default::default());
+//! for word in text.words().iter() {
+//!     if word.text.as_slice() == query {
+//!         highlight(word.span.start, word.span.end);
+//!     }
+//! }
+//! ```
+
+use core::prelude::*;
+
+use tokenizer::{Attribute, Span};
+use tree_builder::{TreeSink, QuirksMode, NodeOrText, AppendNode, AppendText};
+use tree_builder::Error;
+use driver::{ParseOpts, parse_to, one_input};
+
+use util::str::is_ascii_whitespace;
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+use string_cache::QualName;
+
+/// Some text and the span of input it came from.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct TextSpan {
+    pub text: String,
+    pub span: Span,
+}
+
+/// The text of a document, in source order.
+pub struct ExtractedText {
+    runs: Vec<TextSpan>,
+
+    // For each run, the node it's in, skipping up past phrasing
+    // elements.  Words and sentences don't cross from one to another.
+    blocks: Vec<uint>,
+}
+
+// Builds up words or sentences a character at a time.
+struct Segmenter {
+    out: Vec<TextSpan>,
+    current: Option<TextSpan>,
+
+    // Whitespace was seen inside the current segment.
+    space: bool,
+}
+
+impl Segmenter {
+    fn push(&mut self, c: char, span: Span) {
+        if self.current.is_none() {
+            self.current = Some(TextSpan {
+                text: String::new(),
+                span: span.clone(),
+            });
+            self.space = false;
+        }
+
+        let seg = self.current.as_mut().unwrap();
+        if self.space {
+            seg.text.push(' ');
+            self.space = false;
+        }
+        seg.text.push(c);
+        seg.span.end = span.end;
+    }
+
+    fn space(&mut self) {
+        self.space = self.current.is_some();
+    }
+
+    fn ends_sentence(&self) -> bool {
+        self.current.as_ref().map_or(false, |seg| {
+            let text = seg.text.as_slice();
+            text.ends_with(".") || text.ends_with("!") || text.ends_with("?")
+        })
+    }
+
+    fn finish(&mut self) {
+        match self.current.take() {
+            Some(seg) => self.out.push(seg),
+            None => (),
+        }
+        self.space = false;
+    }
+}
+
+// The input for the character at byte `i` of `run`.
+fn char_span(run: &TextSpan, i: uint, c: char) -> Span {
+    if run.span.end - run.span.start == run.text.len() {
+        let start = run.span.start + i;
+        Span { start: start, end: start + c.len_utf8_bytes() }
+    } else {
+        run.span.clone()
+    }
+}
+
+impl ExtractedText {
+    /// Each piece of character data, in source order.  Text is split
+    /// wherever the tokenizer split it, e.g. around each character
+    /// reference.
+    pub fn runs<'a>(&'a self) -> &'a [TextSpan] {
+        self.runs.as_slice()
+    }
+
+    fn segments(&self, sentences: bool) -> Vec<TextSpan> {
+        let mut seg = Segmenter {
+            out: vec!(),
+            current: None,
+            space: false,
+        };
+        let mut block = None;
+        for (run, &run_block) in self.runs.iter().zip(self.blocks.iter()) {
+            if block != Some(run_block) {
+                seg.finish();
+                block = Some(run_block);
+            }
+            for (i, c) in run.text.as_slice().char_indices() {
+                if !is_ascii_whitespace(c) {
+                    seg.push(c, char_span(run, i, c));
+                } else if !sentences || seg.ends_sentence() {
+                    seg.finish();
+                } else {
+                    seg.space();
+                }
+            }
+        }
+        seg.finish();
+        seg.out
+    }
+
+    /// The words of the text, split at whitespace.
+    pub fn words(&self) -> Vec<TextSpan> {
+        self.segments(false)
+    }
+
+    /// The sentences of the text, which end with `.`, `!` or `?`
+    /// before whitespace, or at the end of a block.  Whitespace inside
+    /// a sentence becomes a single space.
+    pub fn sentences(&self) -> Vec<TextSpan> {
+        self.segments(true)
+    }
+}
+
+struct Node {
+    name: Option<QualName>,
+    parent: Option<uint>,
+}
+
+struct Run {
+    text: TextSpan,
+    parent: uint,
+}
+
+// Records the shape of the tree, and each run of text with its span
+// and parent.  Handles are indices into `nodes`, and the document is 0.
+struct TextSink {
+    nodes: Vec<Node>,
+    runs: Vec<Run>,
+    next_span: Option<Span>,
+}
+
+fn is_phrasing(name: &QualName) -> bool {
+    name.ns == ns!(HTML) && match name.local.as_slice() {
+        "a" | "abbr" | "b" | "bdi" | "bdo" | "big" | "cite" | "code" | "data" | "del"
+            | "dfn" | "em" | "font" | "i" | "ins" | "kbd" | "mark" | "nobr" | "q" | "s"
+            | "samp" | "small" | "span" | "strike" | "strong" | "sub" | "sup" | "time"
+            | "tt" | "u" | "var" => true,
+        _ => false,
+    }
+}
+
+impl TextSink {
+    fn new_node(&mut self, name: Option<QualName>) -> uint {
+        self.nodes.push(Node {
+            name: name,
+            parent: None,
+        });
+        self.nodes.len() - 1
+    }
+
+    fn is_hidden(&self, mut node: uint) -> bool {
+        loop {
+            let hidden = match self.nodes[node].name {
+                Some(ref name) => name.ns == ns!(HTML) && match name.local {
+                    atom!(script) | atom!(style) | atom!(template) => true,
+                    _ => false,
+                },
+                None => false,
+            };
+            match self.nodes[node].parent {
+                _ if hidden => return true,
+                Some(parent) => node = parent,
+                None => return false,
+            }
+        }
+    }
+
+    fn block(&self, mut node: uint) -> uint {
+        loop {
+            let phrasing = self.nodes[node].name.as_ref().map_or(false, is_phrasing);
+            match self.nodes[node].parent {
+                Some(parent) if phrasing => node = parent,
+                _ => return node,
+            }
+        }
+    }
+}
+
+impl TreeSink<uint> for TextSink {
+    fn get_document(&mut self) -> uint {
+        0
+    }
+
+    fn same_node(&self, x: uint, y: uint) -> bool {
+        x == y
+    }
+
+    fn elem_name(&self, target: uint) -> QualName {
+        self.nodes[target].name.clone().expect("not an element")
+    }
+
+    fn create_element(&mut self, name: QualName, _attrs: Vec<Attribute>) -> uint {
+        self.new_node(Some(name))
+    }
+
+    fn create_comment(&mut self, _text: String) -> uint {
+        self.new_node(None)
+    }
+
+    // Contents go under the template itself, which hides their text.
+    fn get_template_contents(&mut self, target: uint) -> uint {
+        target
+    }
+
+    fn append(&mut self, parent: uint, child: NodeOrText<uint>) {
+        match child {
+            AppendNode(node) => self.nodes.get_mut(node).parent = Some(parent),
+            AppendText(text) => {
+                let span = self.next_span.take().expect("text without a span");
+                self.runs.push(Run {
+                    text: TextSpan {
+                        text: text,
+                        span: span,
+                    },
+                    parent: parent,
+                });
+            }
+        }
+    }
+
+    fn append_before_sibling(&mut self,
+            sibling: uint,
+            new_node: NodeOrText<uint>) -> Result<(), NodeOrText<uint>> {
+        let parent = unwrap_or_return!(self.nodes[sibling].parent, Err(new_node));
+        self.append(parent, new_node);
+        Ok(())
+    }
+
+    fn remove_from_parent(&mut self, target: uint) {
+        self.nodes.get_mut(target).parent = None;
+    }
+
+    fn reparent_children(&mut self, node: uint, new_parent: uint) {
+        for i in range(0, self.nodes.len()) {
+            if self.nodes[i].parent == Some(node) {
+                self.nodes.get_mut(i).parent = Some(new_parent);
+            }
+        }
+        for i in range(0, self.runs.len()) {
+            if self.runs[i].parent == node {
+                self.runs.get_mut(i).parent = new_parent;
+            }
+        }
+    }
+
+    fn set_text_span(&mut self, _parent: uint, span: Span) {
+        self.next_span = Some(span);
+    }
+
+    fn parse_error(&mut self, _err: Error) { }
+    fn set_quirks_mode(&mut self, _mode: QuirksMode) { }
+    fn append_doctype_to_document(&mut self, _name: String, _public_id: String, _system_id: String) { }
+    fn add_attrs_if_missing(&mut self, _target: uint, _attrs: Vec<Attribute>) { }
+    fn mark_script_already_started(&mut self, _node: uint) { }
+}
+
+/// Parse a document and extract its text.  The tokenizer's
+/// `track_spans` option is always turned on.
+pub fn extract_text(input: &str, mut opts: ParseOpts) -> ExtractedText {
+    opts.tokenizer.track_spans = true;
+    let document = Node {
+        name: None,
+        parent: None,
+    };
+    let sink = parse_to(TextSink { nodes: vec!(document), runs: vec!(), next_span: None },
+        one_input(String::from_str(input)), opts);
+
+    let mut runs = vec!();
+    let mut blocks = vec!();
+    for run in sink.runs.iter() {
+        if sink.is_hidden(run.parent) {
+            continue;
+        }
+        runs.push(run.text.clone());
+        blocks.push(sink.block(run.parent));
+    }
+
+    ExtractedText {
+        runs: runs,
+        blocks: blocks,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{extract_text, TextSpan};
+
+    use core::default::Default;
+    use collections::vec::Vec;
+    use collections::string::String;
+
+    // Each piece's text, and the input its span covers.
+    fn show(input: &str, pieces: Vec<TextSpan>) -> Vec<(String, String)> {
+        pieces.into_iter().map(|p|
+            (p.text, String::from_str(input.slice(p.span.start, p.span.end)))).collect()
+    }
+
+    fn words(input: &str) -> Vec<(String, String)> {
+        show(input, extract_text(input, Default::default()).words())
+    }
+
+    fn sentences(input: &str) -> Vec<(String, String)> {
+        show(input, extract_text(input, Default::default()).sentences())
+    }
+
+    fn p(text: &str, source: &str) -> (String, String) {
+        (String::from_str(text), String::from_str(source))
+    }
+
+    test_eq!(char_ref, words("<p>caf&eacute; au lait</p>"),
+        vec!(p("café", "caf&eacute;"), p("au", "au"), p("lait", "lait")))
+
+    test_eq!(inline_markup, words("<p><b>Hel</b>lo world"),
+        vec!(p("Hello", "Hel</b>lo"), p("world", "world")))
+
+    test_eq!(blocks, words("<ul><li>Home<li>About</ul>"),
+        vec!(p("Home", "Home"), p("About", "About")))
+
+    test_eq!(script, words("<p>a<script>b c</script><style>d</style>"), vec!(p("a", "a")))
+
+    test_eq!(template, words("<p>a<template><p>b</p></template>"), vec!(p("a", "a")))
+
+    test_eq!(sentence_split, sentences("<p>One <i>two</i>.  Three?</p><p>Four"),
+        vec!(p("One two.", "One <i>two</i>."), p("Three?", "Three?"), p("Four", "Four")))
+
+    #[test]
+    fn runs_in_source_order() {
+        let input = "<table>a<tr><td>b</table>";
+        let text = extract_text(input, Default::default());
+        let runs: Vec<&str> = text.runs().iter().map(|r| r.text.as_slice()).collect();
+        assert_eq!(runs, vec!("a", "b"));
+        assert_eq!(text.runs()[0].span.start, 7);
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Exporting the tokens of many documents as CSV.
+//!
+//! Each token becomes one row of `doc,kind,start,end,text`: the
+//! document's id, the kind of token, its span in the document, and
+//! its text.  This is meant as input for machine learning over web
+//! markup, so it's flat and easy to load, e.g. into a data frame.
+//!
+//! The kinds, and the text given for each, are:
+//!
+//! * `doctype`: the doctype's name;
+//! * `start` and `end`: the tag name, in lower case;
+//! * `text`: character data, with character references decoded.
+//!   A run of text is one row, even if the tokenizer split it;
+//! * `space`: whitespace, if `whitespace_tokens` is set;
+//! * `comment`: the comment's text;
+//! * `pi`: the target and data of a processing instruction;
+//! * `passthrough`: template syntax, if `template_delimiters` is set;
+//! * `null`: a U+0000 in text, with empty text;
+//! * `error`: a parse error's summary, with an empty span.
+//!
+//! There is no tree builder, so raw text elements like `<script>` are
+//! recognized by name alone, as in `highlight`.  Text is quoted as in
+//! RFC 4180 when it contains a comma, quote, or line break.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let docs = pages.into_iter().map(|p| (p.url, p.html));
+//! try!(export_corpus(&mut out, docs, Default::default()));
+//! ```
+
+use core::prelude::*;
+
+use tokenizer::{Tokenizer, TokenizerOpts, TokenSink, Token, Span};
+use tokenizer::{DoctypeToken, TagToken, StartTag, EndTag, CommentToken};
+use tokenizer::{CharacterTokens, WhitespaceTokens, NullCharacterToken};
+use tokenizer::{PassthroughToken, ProcessingInstructionToken, EOFToken, ParseError};
+use tokenizer::states::{State, raw_text_state};
+
+use collections::string::String;
+use std::io::{Writer, IoResult, IoError};
+
+/// Write the header row.
+pub fn write_header<Wr: Writer>(writer: &mut Wr) -> IoResult<()> {
+    writer.write_str("doc,kind,start,end,text\n")
+}
+
+// Write a field, quoting it if necessary.
+fn write_field<Wr: Writer>(writer: &mut Wr, field: &str) -> IoResult<()> {
+    if !field.contains_char(',') && !field.contains_char('"')
+            && !field.contains_char('\n') && !field.contains_char('\r') {
+        return writer.write_str(field);
+    }
+    try!(writer.write_char('"'));
+    for c in field.chars() {
+        if c == '"' {
+            try!(writer.write_char('"'));
+        }
+        try!(writer.write_char(c));
+    }
+    writer.write_char('"')
+}
+
+struct Exporter<'wr, 'doc, Wr: 'wr> {
+    writer: &'wr mut Wr,
+    doc: &'doc str,
+
+    // The run of text so far, not yet written.
+    text: Option<(Span, String)>,
+
+    next_state: Option<State>,
+    error: Option<IoError>,
+}
+
+impl<'wr, 'doc, Wr: Writer> Exporter<'wr, 'doc, Wr> {
+    fn row(&mut self, kind: &str, span: Span, text: &str) -> IoResult<()> {
+        try!(write_field(&mut *self.writer, self.doc));
+        try!(self.writer.write_char(','));
+        try!(self.writer.write_str(kind));
+        try!(self.writer.write_str(format!(",{},{},", span.start, span.end).as_slice()));
+        try!(write_field(&mut *self.writer, text));
+        self.writer.write_char('\n')
+    }
+
+    fn flush_text(&mut self) -> IoResult<()> {
+        match self.text.take() {
+            Some((span, text)) => self.row("text", span, text.as_slice()),
+            None => Ok(()),
+        }
+    }
+
+    fn write_token(&mut self, token: Token, span: Span) -> IoResult<()> {
+        match token {
+            CharacterTokens(text) => {
+                match self.text {
+                    Some((ref mut run, ref mut run_text)) => {
+                        run.end = span.end;
+                        run_text.push_str(text.as_slice());
+                        return Ok(());
+                    }
+                    None => (),
+                }
+                self.text = Some((span, text));
+                return Ok(());
+            }
+
+            // Write the text so far first, so rows stay in input order.
+            ParseError(e) => {
+                try!(self.flush_text());
+                return self.row("error", span, e.code.summary());
+            }
+
+            _ => try!(self.flush_text()),
+        }
+
+        match token {
+            DoctypeToken(doctype) => {
+                let name = doctype.name.unwrap_or(String::new());
+                self.row("doctype", span, name.as_slice())
+            }
+
+            TagToken(tag) => {
+                let kind = match tag.kind {
+                    StartTag => {
+                        self.next_state = raw_text_state(&tag.name, true);
+                        "start"
+                    }
+                    EndTag => "end",
+                };
+                self.row(kind, span, tag.name.as_slice())
+            }
+
+            CommentToken(text) => self.row("comment", span, text.as_slice()),
+            WhitespaceTokens(text) => self.row("space", span, text.as_slice()),
+            NullCharacterToken => self.row("null", span, ""),
+            PassthroughToken(text) => self.row("passthrough", span, text.as_slice()),
+
+            ProcessingInstructionToken(target, data) => {
+                let mut text = target;
+                if !data.is_empty() {
+                    text.push(' ');
+                    text.push_str(data.as_slice());
+                }
+                self.row("pi", span, text.as_slice())
+            }
+
+            EOFToken => Ok(()),
+
+            CharacterTokens(_) | ParseError(_) => unreachable!(),
+        }
+    }
+}
+
+impl<'wr, 'doc, Wr: Writer> TokenSink for Exporter<'wr, 'doc, Wr> {
+    fn process_token(&mut self, _token: Token) {
+        unreachable!();  // we always track spans
+    }
+
+    fn process_token_with_span(&mut self, token: Token, span: Span) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.write_token(token, span) {
+            Ok(()) => (),
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    fn query_state_change(&mut self) -> Option<State> {
+        self.next_state.take()
+    }
+}
+
+/// Tokenize `input` and write a row for each token, with `doc` as its
+/// id.  The `track_spans` option is always turned on.
+pub fn export_tokens<Wr: Writer>(writer: &mut Wr, doc: &str, input: &str,
+        mut opts: TokenizerOpts) -> IoResult<()> {
+    opts.track_spans = true;
+    let exporter = Exporter {
+        writer: writer,
+        doc: doc,
+        text: None,
+        next_state: None,
+        error: None,
+    };
+    let mut tok = Tokenizer::new(exporter, opts);
+    tok.feed(String::from_str(input));
+    tok.end();
+    match tok.unwrap().error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Write the header, then the tokens of each `(id, input)` document.
+/// Stops at the first error from the writer.
+pub fn export_corpus<Wr: Writer, It: Iterator<(String, String)>>(writer: &mut Wr,
+        mut docs: It, opts: TokenizerOpts) -> IoResult<()> {
+    try!(write_header(writer));
+    for (id, input) in docs {
+        try!(export_tokens(writer, id.as_slice(), input.as_slice(), opts.clone()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{export_tokens, export_corpus};
+
+    use core::default::Default;
+    use collections::string::String;
+    use std::io::MemWriter;
+
+    fn export(input: &str) -> String {
+        let mut out = MemWriter::new();
+        export_tokens(&mut out, "d", input, Default::default()).unwrap();
+        String::from_utf8(out.unwrap()).unwrap()
+    }
+
+    test_eq!(tags_and_text, export("<p class=x>a &amp; b</p>").as_slice(),
+        "d,start,0,11,p\nd,text,11,20,a & b\nd,end,20,24,p\n")
+
+    test_eq!(quoting, export("<!--a,\"b\"-->x\r\ny").as_slice(),
+        "d,comment,0,12,\"a,\"\"b\"\"\"\nd,text,12,16,\"x\ny\"\n")
+
+    test_eq!(raw_text, export("<script>a<b</script>").as_slice(),
+        "d,start,0,8,script\nd,text,8,11,a<b\nd,end,11,20,script\n")
+
+    test_eq!(errors, export("a</>b").as_slice(),
+        "d,text,0,1,a\nd,error,4,4,Bad character\nd,text,4,5,b\n")
+
+    #[test]
+    fn corpus() {
+        let docs = vec!(
+            (String::from_str("one"), String::from_str("<b>")),
+            (String::from_str("t,wo"), String::from_str("x")));
+        let mut out = MemWriter::new();
+        export_corpus(&mut out, docs.into_iter(), Default::default()).unwrap();
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(),
+            "doc,kind,start,end,text\none,start,0,3,b\n\"t,wo\",text,0,1,x\n");
+    }
+}
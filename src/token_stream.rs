@@ -0,0 +1,205 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Building a stream of tokens by hand.
+//!
+//! A `TokenStream` lists tokens as a generator or a test means them,
+//! without writing HTML for the tokenizer to take apart.  Any
+//! `TokenSink` can receive them: the tree builder, to get the tree a
+//! browser would build, or a `StreamSink`, to write out the HTML.
+//! Nothing is checked or fixed up on the way, so the stream goes to
+//! the sink exactly as written.
+//!
+//! ## Example
+//!
+//! ```rust
+//! let dom = TokenStream::new()
+//!     .start("a").attr("href", url).text("hi").end("a")
+//!     .send(TreeBuilder::new(RcDom::default(), Default::default()))
+//!     .unwrap();
+//! ```
+
+use core::prelude::*;
+
+use tokenizer::{Token, TokenSink, Doctype, Attribute, Tag, TagKind, StartTag, EndTag};
+use tokenizer::{DoctypeToken, TagToken, CommentToken, CharacterTokens, EOFToken};
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+use collections::string::String;
+
+use string_cache::{Atom, QualName};
+
+/// A list of tokens, built up one call at a time.
+#[deriving(Clone, Show)]
+pub struct TokenStream {
+    tokens: Vec<Token>,
+}
+
+impl TokenStream {
+    /// Create an empty `TokenStream`.
+    pub fn new() -> TokenStream {
+        TokenStream {
+            tokens: vec!(),
+        }
+    }
+
+    /// Add any token.
+    pub fn token(mut self, token: Token) -> TokenStream {
+        self.tokens.push(token);
+        self
+    }
+
+    /// Add `<!DOCTYPE name>`.
+    pub fn doctype(self, name: &str) -> TokenStream {
+        let mut doctype = Doctype::new();
+        doctype.name = Some(String::from_str(name));
+        self.token(DoctypeToken(doctype))
+    }
+
+    fn tag(self, kind: TagKind, name: &str) -> TokenStream {
+        self.token(TagToken(Tag {
+            kind: kind,
+            name: Atom::from_slice(name),
+            self_closing: false,
+            attrs: vec!(),
+        }))
+    }
+
+    /// Add a start tag.  The name should be in lower case, as the
+    /// tokenizer would give it.
+    pub fn start(self, name: &str) -> TokenStream {
+        self.tag(StartTag, name)
+    }
+
+    /// Add an end tag.
+    pub fn end(self, name: &str) -> TokenStream {
+        self.tag(EndTag, name)
+    }
+
+    // The tag just added, which `what` applies to.
+    fn last_tag<'a>(&'a mut self, what: &str) -> &'a mut Tag {
+        match self.tokens.last_mut() {
+            Some(&TagToken(ref mut tag)) => tag,
+            _ => fail!("{} must follow a tag", what),
+        }
+    }
+
+    /// Add an attribute to the tag just added.
+    ///
+    /// ## Failure
+    ///
+    /// Fails if the last token isn't a tag.
+    pub fn attr(mut self, name: &str, value: &str) -> TokenStream {
+        self.last_tag("attr").attrs.push(Attribute {
+            name: QualName::new(ns!(""), Atom::from_slice(name)),
+            value: String::from_str(value),
+            has_template: false,
+        });
+        self
+    }
+
+    /// Mark the tag just added as self-closing, like `<br/>`.
+    ///
+    /// ## Failure
+    ///
+    /// Fails if the last token isn't a tag.
+    pub fn self_closing(mut self) -> TokenStream {
+        self.last_tag("self_closing").self_closing = true;
+        self
+    }
+
+    /// Add text.  Character references aren't decoded, and the text
+    /// shouldn't contain `'\0'`.
+    pub fn text(self, text: &str) -> TokenStream {
+        self.token(CharacterTokens(String::from_str(text)))
+    }
+
+    /// Add a comment.
+    pub fn comment(self, text: &str) -> TokenStream {
+        self.token(CommentToken(String::from_str(text)))
+    }
+
+    /// The tokens so far, without an `EOFToken`.
+    pub fn tokens<'a>(&'a self) -> &'a [Token] {
+        self.tokens.as_slice()
+    }
+
+    /// Send `sink` the tokens, followed by an `EOFToken`.
+    pub fn send<Sink: TokenSink>(self, mut sink: Sink) -> Sink {
+        for token in self.tokens.into_iter() {
+            sink.process_token(token);
+        }
+        sink.process_token(EOFToken);
+        sink
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::TokenStream;
+
+    use tokenizer::{TagToken, StartTag};
+
+    #[cfg(feature = "tree_builder")]
+    use tree_builder::TreeBuilder;
+    #[cfg(feature = "tree_builder")]
+    use sink::rcdom::RcDom;
+    #[cfg(feature = "tree_builder")]
+    use serialize::{serialize, StreamSink};
+
+    #[cfg(feature = "tree_builder")]
+    use core::default::Default;
+    #[cfg(feature = "tree_builder")]
+    use collections::string::String;
+    #[cfg(feature = "tree_builder")]
+    use std::io::MemWriter;
+
+    fn link() -> TokenStream {
+        TokenStream::new().start("a").attr("href", "/x?a=1&b=2").text("hi").end("a")
+    }
+
+    #[test]
+    fn attrs_go_on_last_tag() {
+        match link().tokens()[0] {
+            TagToken(ref tag) => {
+                assert_eq!(tag.kind, StartTag);
+                assert_eq!(tag.attrs[0].value.as_slice(), "/x?a=1&b=2");
+            }
+            _ => fail!("not a tag"),
+        }
+    }
+
+    #[test]
+    #[should_fail]
+    fn attr_without_tag() {
+        TokenStream::new().text("x").attr("a", "b");
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn to_tree() {
+        let dom = link().send(TreeBuilder::new(RcDom::default(), Default::default())).unwrap();
+        let mut out = MemWriter::new();
+        serialize(&mut out, &dom.document, Default::default()).unwrap();
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(),
+            "<html><head></head><body><a href=\"/x?a=1&amp;b=2\">hi</a></body></html>");
+    }
+
+    #[test]
+    #[cfg(feature = "tree_builder")]
+    fn to_stream() {
+        let mut out = MemWriter::new();
+        TokenStream::new().doctype("html").start("br").self_closing().text("a<b")
+            .send(StreamSink::new(&mut out, Default::default())).result().unwrap();
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(),
+            "<!DOCTYPE html><br />a&lt;b");
+    }
+}
@@ -14,7 +14,7 @@ use util::smallcharset::SmallCharSet;
 
 use core::str::CharRange;
 use collections::string::String;
-use collections::{MutableSeq, Deque};
+use collections::{Mutable, MutableSeq, Deque};
 use collections::ringbuf::RingBuf;
 
 struct Buffer {
@@ -31,18 +31,74 @@ pub enum SetResult {
     NotFromSet(String),
 }
 
+// Length in bytes of a typical chunk of input, for `reset`.
+static TYPICAL_CHUNK_LEN: uint = 4096;
+
 /// A queue of owned string buffers, which supports incrementally
 /// consuming characters.
 pub struct BufferQueue {
-    /// Buffers to process.
+    /// Buffers to process.  Only the front buffer can be empty, if
+    /// `take_until` used the rest of it.
     buffers: RingBuf<Buffer>,
+
+    /// Bytes consumed so far, less any pushed back with `push_front`.
+    offset: uint,
+
+    /// Bytes at the front of the queue which were added with
+    /// `insert_front`, and don't count towards `offset`.
+    inserted: uint,
 }
 
 impl BufferQueue {
     /// Create an empty BufferQueue.
     pub fn new() -> BufferQueue {
+        BufferQueue::with_offset(0)
+    }
+
+    /// Create an empty BufferQueue whose input starts at `offset`.
+    pub fn with_offset(offset: uint) -> BufferQueue {
         BufferQueue {
             buffers: RingBuf::with_capacity(3),
+            offset: offset,
+            inserted: 0,
+        }
+    }
+
+    /// Empty the queue for input starting at `offset`, with room for
+    /// about `bytes` of input to be queued up, e.g. while the tokenizer
+    /// is suspended, if it's fed in chunks of a typical read.  Room
+    /// which was already allocated is kept, for the next document.
+    pub fn reset(&mut self, offset: uint, bytes: uint) {
+        let chunks = bytes / TYPICAL_CHUNK_LEN + 1;
+        self.buffers.clear();
+        self.buffers.reserve(chunks);
+        self.offset = offset;
+        self.inserted = 0;
+    }
+
+    /// How many bytes of input have been consumed, plus the starting
+    /// offset?  Bytes dropped by `push_back` count as consumed, and
+    /// bytes returned by `push_front` or added by `insert_front` don't.
+    pub fn offset(&self) -> uint {
+        self.offset
+    }
+
+    // Account for `n` bytes consumed from the front of the queue.
+    fn consumed(&mut self, n: uint) {
+        let inserted = if n < self.inserted { n } else { self.inserted };
+        self.inserted -= inserted;
+        self.offset += n - inserted;
+    }
+
+    // Drop the front buffer if `take_until` used the rest of it.  The
+    // run it returned borrowed the buffer, so it couldn't be dropped then.
+    fn drop_exhausted(&mut self) {
+        let exhausted = match self.buffers.front() {
+            Some(&Buffer { pos, ref buf }) => pos >= buf.len(),
+            None => false,
+        };
+        if exhausted {
+            self.buffers.pop_front();
         }
     }
 
@@ -51,6 +107,23 @@ impl BufferQueue {
         if buf.len() == 0 {
             return;
         }
+        self.drop_exhausted();
+        self.offset -= buf.len();
+        self.buffers.push_front(Buffer {
+            pos: 0,
+            buf: buf,
+        });
+    }
+
+    /// Add a buffer of new input to the beginning of the queue, as for
+    /// `document.write`.  Unlike `push_front`, this is input which was
+    /// never consumed, so consuming it doesn't advance `offset`.
+    pub fn insert_front(&mut self, buf: String) {
+        if buf.len() == 0 {
+            return;
+        }
+        self.drop_exhausted();
+        self.inserted += buf.len();
         self.buffers.push_front(Buffer {
             pos: 0,
             buf: buf,
@@ -61,6 +134,16 @@ impl BufferQueue {
     /// 'pos' can be non-zero to remove that many bytes
     /// from the beginning.
     pub fn push_back(&mut self, buf: String, pos: uint) {
+        self.push_back_from(buf, 0, pos);
+    }
+
+    /// Add a buffer to the end of the queue, whose input starts at byte
+    /// `start`.  The bytes before `start` aren't input at all, so they
+    /// don't count towards `offset`.  Then `skip` more bytes are
+    /// removed, as for `push_back`.
+    pub fn push_back_from(&mut self, buf: String, start: uint, skip: uint) {
+        self.offset += skip;
+        let pos = start + skip;
         if pos >= buf.len() {
             return;
         }
@@ -72,6 +155,7 @@ impl BufferQueue {
 
     /// Look at the next available character, if any.
     pub fn peek(&mut self) -> Option<char> {
+        self.drop_exhausted();
         match self.buffers.front() {
             Some(&Buffer { pos, ref buf }) => Some(buf.as_slice().char_at(pos)),
             None => None,
@@ -80,12 +164,14 @@ impl BufferQueue {
 
     /// Get the next character, if one is available.
     pub fn next(&mut self) -> Option<char> {
+        self.drop_exhausted();
         let (result, now_empty) = match self.buffers.front_mut() {
             None => (None, false),
             Some(&Buffer { ref mut pos, ref buf }) => {
                 let CharRange { ch, next } = buf.as_slice().char_range_at(*pos);
+                let n = next - *pos;
                 *pos = next;
-                (Some(ch), next >= buf.len())
+                (Some((ch, n)), next >= buf.len())
             }
         };
 
@@ -93,37 +179,76 @@ impl BufferQueue {
             self.buffers.pop_front();
         }
 
-        result
+        match result {
+            Some((ch, n)) => {
+                self.consumed(n);
+                Some(ch)
+            }
+            None => None,
+        }
     }
 
-    /// Pops and returns either a single character from the given set, or
-    /// a `String` of characters none of which are in the set.  The set
-    /// is represented as a bitmask and so can only contain the first 64
-    /// ASCII characters.
-    pub fn pop_except_from(&mut self, set: SmallCharSet) -> Option<SetResult> {
-        let (result, now_empty) = match self.buffers.front_mut() {
+    /// Consume and return the run of characters before the first byte
+    /// for which `pred` is true, leaving that byte in the queue.  The
+    /// run is borrowed from a single buffer, so it may stop short at
+    /// the end of that buffer, and it's empty if the next byte matches.
+    /// Returns `None` if the queue is empty.
+    ///
+    /// `pred` should only match ASCII bytes, so that the run ends at a
+    /// character boundary.
+    #[inline]
+    pub fn take_until<'a>(&'a mut self, pred: |u8| -> bool) -> Option<&'a str> {
+        self.drop_exhausted();
+        let (start, end) = match self.buffers.front_mut() {
+            None => return None,
             Some(&Buffer { ref mut pos, ref buf }) => {
-                let n = set.nonmember_prefix_len(buf.as_slice().slice_from(*pos));
-                if n > 0 {
-                    let new_pos = *pos + n;
-                    let out = String::from_str(buf.as_slice().slice(*pos, new_pos));
-                    *pos = new_pos;
-                    (Some(NotFromSet(out)), new_pos >= buf.len())
-                } else {
-                    let CharRange { ch, next } = buf.as_slice().char_range_at(*pos);
-                    *pos = next;
-                    (Some(FromSet(ch)), next >= buf.len())
+                let bytes = buf.as_bytes();
+                let start = *pos;
+                let mut end = start;
+                while end < bytes.len() && !pred(bytes[end]) {
+                    end += 1;
                 }
+                *pos = end;
+                (start, end)
             }
-            _ => (None, false),
         };
 
-        // Unborrow self for this part.
-        if now_empty {
-            self.buffers.pop_front();
+        self.consumed(end - start);
+        self.buffers.front().map(|b| b.buf.as_slice().slice(start, end))
+    }
+
+    /// The length in bytes of the run `take_until` would return, without
+    /// consuming it.  Like that run, it stops at the end of the front
+    /// buffer.
+    pub fn span_until(&mut self, pred: |u8| -> bool) -> uint {
+        self.drop_exhausted();
+        match self.buffers.front() {
+            None => 0,
+            Some(&Buffer { pos, ref buf }) => {
+                let bytes = buf.as_bytes();
+                let mut end = pos;
+                while end < bytes.len() && !pred(bytes[end]) {
+                    end += 1;
+                }
+                end - pos
+            }
         }
+    }
 
-        result
+    /// Pops and returns either a single character from the given set, or
+    /// a `String` of characters none of which are in the set.  The set
+    /// is represented as a bitmask and so can only contain the first 64
+    /// ASCII characters.
+    pub fn pop_except_from(&mut self, set: SmallCharSet) -> Option<SetResult> {
+        let run = match self.take_until(|b| set.contains(b)) {
+            None => return None,
+            Some("") => None,
+            Some(run) => Some(String::from_str(run)),
+        };
+        match run {
+            Some(run) => Some(NotFromSet(run)),
+            None => self.next().map(|c| FromSet(c)),
+        }
     }
 
     // Check if the next characters are an ASCII case-insensitive match for
@@ -133,6 +258,16 @@ impl BufferQueue {
     // If they do not match, return Some(false).
     // If not enough characters are available to know, return None.
     pub fn eat(&mut self, pat: &str) -> Option<bool> {
+        self.eat_matching(pat, false)
+    }
+
+    // Like `eat`, but matching case.
+    pub fn eat_exact(&mut self, pat: &str) -> Option<bool> {
+        self.eat_matching(pat, true)
+    }
+
+    fn eat_matching(&mut self, pat: &str, match_case: bool) -> Option<bool> {
+        self.drop_exhausted();
         let mut buffers_exhausted = 0u;
         let mut consumed_from_last = match self.buffers.front() {
             None => return None,
@@ -147,7 +282,7 @@ impl BufferQueue {
 
             let d = buf.buf.as_slice().char_at(consumed_from_last);
             match (c.to_ascii_opt(), d.to_ascii_opt()) {
-                (Some(c), Some(d)) if c.eq_ignore_case(d) => (),
+                (Some(c), Some(d)) if c == d || (!match_case && c.eq_ignore_case(d)) => (),
                 _ => return Some(false),
             }
 
@@ -169,6 +304,8 @@ impl BufferQueue {
             Some(ref mut buf) => buf.pos = consumed_from_last,
         }
 
+        // Every character matched was ASCII.
+        self.consumed(pat.len());
         Some(true)
     }
 }
@@ -223,6 +360,35 @@ mod test {
         assert_eq!(pop(), None);
     }
 
+    #[test]
+    fn can_take_until() {
+        let mut bq = BufferQueue::new();
+        bq.push_back(String::from_str("ab<c"), 0);
+        bq.push_back(String::from_str("d<"), 0);
+        assert_eq!(bq.take_until(|b| b == b'<'), Some("ab"));
+        assert_eq!(bq.take_until(|b| b == b'<'), Some(""));
+        assert_eq!(bq.next(), Some('<'));
+        assert_eq!(bq.take_until(|b| b == b'<'), Some("c"));
+        assert_eq!(bq.take_until(|b| b == b'<'), Some("d"));
+        assert_eq!(bq.offset(), 5);
+        bq.push_front(String::from_str("d"));
+        assert_eq!(bq.eat("d<"), Some(true));
+        assert_eq!(bq.take_until(|b| b == b'<'), None);
+    }
+
+    #[test]
+    fn can_span_until() {
+        let mut bq = BufferQueue::new();
+        assert_eq!(bq.span_until(|b| b == b'"'), 0);
+        bq.push_back(String::from_str("a&b\"c"), 0);
+        bq.push_back(String::from_str("d\""), 0);
+        assert_eq!(bq.span_until(|b| b == b'"'), 3);
+        assert_eq!(bq.span_until(|b| b == b'x'), 5);
+        assert_eq!(bq.offset(), 0);
+        assert_eq!(bq.take_until(|b| b == b'&'), Some("a"));
+        assert_eq!(bq.span_until(|b| b == b'"'), 2);
+    }
+
     #[test]
     fn can_push_truncated() {
         let mut bq = BufferQueue::new();
@@ -232,6 +398,17 @@ mod test {
         assert_eq!(bq.next(), None);
     }
 
+    #[test]
+    fn can_push_from() {
+        let mut bq = BufferQueue::new();
+        bq.push_back_from(String::from_str("xyabc"), 2, 1);
+        assert_eq!(bq.offset(), 1);
+        assert_eq!(bq.next(), Some('b'));
+        assert_eq!(bq.next(), Some('c'));
+        assert_eq!(bq.next(), None);
+        assert_eq!(bq.offset(), 3);
+    }
+
     #[test]
     fn can_eat() {
         // This is not very comprehensive.  We rely on the tokenizer
@@ -246,4 +423,49 @@ mod test {
         assert_eq!(bq.next(), Some('c'));
         assert_eq!(bq.next(), None);
     }
+
+    #[test]
+    fn can_eat_exact() {
+        let mut bq = BufferQueue::new();
+        bq.push_back(String::from_str("[CDATA[x"), 0);
+        assert_eq!(bq.eat_exact("[cdata["), Some(false));
+        assert_eq!(bq.eat_exact("[CDATA["), Some(true));
+        assert_eq!(bq.next(), Some('x'));
+    }
+
+    #[test]
+    fn offset_counts_bytes() {
+        let mut bq = BufferQueue::new();
+        bq.push_back(String::from_str("\ufeffa\u00e9"), 3);
+        assert_eq!(bq.offset(), 3);
+        assert_eq!(bq.next(), Some('a'));
+        assert_eq!(bq.next(), Some('\u00e9'));
+        assert_eq!(bq.offset(), 6);
+
+        bq.push_front(String::from_str("\u00e9"));
+        assert_eq!(bq.offset(), 4);
+        bq.push_back(String::from_str("bc&d"), 0);
+        {
+            let pop = || bq.pop_except_from(small_char_set!('&'));
+            assert_eq!(pop(), Some(NotFromSet(String::from_str("\u00e9"))));
+            assert_eq!(pop(), Some(NotFromSet(String::from_str("bc"))));
+        }
+        assert_eq!(bq.offset(), 8);
+        assert_eq!(bq.eat("&d"), Some(true));
+        assert_eq!(bq.offset(), 10);
+    }
+
+    #[test]
+    fn inserted_input_is_not_counted() {
+        let mut bq = BufferQueue::new();
+        bq.push_back(String::from_str("abcd"), 0);
+        assert_eq!(bq.next(), Some('a'));
+        bq.insert_front(String::from_str("xyz"));
+        assert_eq!(bq.next(), Some('x'));
+        assert_eq!(bq.offset(), 1);
+        assert_eq!(bq.eat("yzb"), Some(true));
+        assert_eq!(bq.offset(), 2);
+        assert_eq!(bq.next(), Some('c'));
+        assert_eq!(bq.offset(), 3);
+    }
 }
@@ -10,14 +10,31 @@
 use core::prelude::*;
 
 use super::{Tokenizer, TokenSink};
+use super::{NumericCharRefWithoutSemicolon, NumericCharRefWithoutDigits, InvalidNumericCharRef};
+use super::{InvalidCharRef, EqualsAfterCharRef, CharRefWithoutSemicolon};
+use super::{EofInNumericCharRef, EofAfterHashInCharRef};
+use super::buffer_queue::{FromSet, NotFromSet};
 
-use util::str::{is_ascii_alnum, empty_str};
+use util::str::is_ascii_alnum;
+use util::tempbuf::TempBuf;
+use util::smallcharset::SmallCharSet;
 
 use core::char::{to_digit, from_u32};
-use collections::str::Slice;
 use collections::string::String;
 
-mod data;
+pub mod data;
+
+// How far to look for the semicolon after a name which can't match,
+// to decide whether it's a parse error.  This is longer than any name
+// in the table, and keeps a long run of letters from being held back
+// while we wait for more input.  It's also what fits in `name_buf`
+// without allocating.
+static MAX_BOGUS_NAME_LEN: uint = 64;
+
+// The characters below U+0040 which end a name: all but the digits.
+// Letters are above the set's range, so a run of characters outside it
+// can still hold something else which ends the name, like `_` or `é`.
+static NAME_END: SmallCharSet = SmallCharSet { bits: !(0x3FF << 48) };
 
 //§ tokenizing-character-references
 pub struct CharRef {
@@ -54,7 +71,7 @@ pub struct CharRefTokenizer {
     seen_digit: bool,
     hex_marker: Option<char>,
 
-    name_buf_opt: Option<String>,
+    name_buf: TempBuf,
     name_match: Option<&'static [u32, ..2]>,
     name_len: uint,
 }
@@ -71,26 +88,17 @@ impl CharRefTokenizer {
             num_too_big: false,
             seen_digit: false,
             hex_marker: None,
-            name_buf_opt: None,
+            name_buf: TempBuf::new(),
             name_match: None,
             name_len: 0,
         }
     }
 
-    // A CharRefTokenizer can only tokenize one character reference,
-    // so this method consumes the tokenizer.
-    pub fn get_result(self) -> CharRef {
-        self.result.expect("get_result called before done")
-    }
-
-    fn name_buf<'t>(&'t self) -> &'t String {
-        self.name_buf_opt.as_ref()
-            .expect("name_buf missing in named character reference")
-    }
-
-    fn name_buf_mut<'t>(&'t mut self) -> &'t mut String {
-        self.name_buf_opt.as_mut()
-            .expect("name_buf missing in named character reference")
+    // A CharRefTokenizer can only tokenize one character reference.
+    // The caller can reuse its box for the next one, by assigning a
+    // new CharRefTokenizer.
+    pub fn get_result(&mut self) -> CharRef {
+        self.result.take().expect("get_result called before done")
     }
 
     fn finish_none(&mut self) -> Status {
@@ -142,7 +150,6 @@ impl<Sink: TokenSink> CharRefTokenizer {
 
             _ => {
                 self.state = Named;
-                self.name_buf_opt = Some(empty_str());
                 Progress
             }
         }
@@ -193,7 +200,7 @@ impl<Sink: TokenSink> CharRefTokenizer {
     fn do_numeric_semicolon(&mut self, tokenizer: &mut Tokenizer<Sink>) -> Status {
         match unwrap_or_return!(tokenizer.peek(), Stuck) {
             ';' => tokenizer.discard_char(),
-            _   => tokenizer.emit_error(Slice("Semicolon missing after numeric character reference")),
+            _   => tokenizer.emit_error(NumericCharRefWithoutSemicolon),
         };
         self.finish_numeric(tokenizer)
     }
@@ -206,7 +213,7 @@ impl<Sink: TokenSink> CharRefTokenizer {
         }
 
         tokenizer.unconsume(unconsume);
-        tokenizer.emit_error(Slice("Numeric character reference without digits"));
+        tokenizer.emit_error(NumericCharRefWithoutDigits);
         self.finish_none()
     }
 
@@ -234,10 +241,7 @@ impl<Sink: TokenSink> CharRefTokenizer {
         };
 
         if error {
-            let msg = format_if!(tokenizer.opts.exact_errors,
-                "Invalid numeric character reference",
-                "Invalid numeric character reference value 0x{:06X}", self.num);
-            tokenizer.emit_error(msg);
+            tokenizer.emit_error(InvalidNumericCharRef(self.num));
         }
 
         self.finish_one(c)
@@ -245,14 +249,14 @@ impl<Sink: TokenSink> CharRefTokenizer {
 
     fn do_named(&mut self, tokenizer: &mut Tokenizer<Sink>) -> Status {
         let c = unwrap_or_return!(tokenizer.get_char(), Stuck);
-        self.name_buf_mut().push(c);
-        match data::NAMED_ENTITIES.find_equiv(&self.name_buf().as_slice()) {
+        self.name_buf.push(c);
+        match data::NAMED_ENTITIES.find_equiv(&self.name_buf.as_slice()) {
             // We have either a full match or a prefix of one.
             Some(m) => {
                 if m[0] != 0 {
                     // We have a full match, but there might be a longer one to come.
                     self.name_match = Some(m);
-                    self.name_len = self.name_buf().len();
+                    self.name_len = self.name_buf.len();
                 }
                 // Otherwise we just have a prefix match.
                 Progress
@@ -264,14 +268,12 @@ impl<Sink: TokenSink> CharRefTokenizer {
     }
 
     fn emit_name_error(&mut self, tokenizer: &mut Tokenizer<Sink>) {
-        let msg = format_if!(tokenizer.opts.exact_errors,
-            "Invalid character reference",
-            "Invalid character reference &{:s}", self.name_buf().as_slice());
-        tokenizer.emit_error(msg);
+        let name = String::from_str(self.name_buf.as_slice());
+        tokenizer.emit_error(InvalidCharRef(name));
     }
 
     fn unconsume_name(&mut self, tokenizer: &mut Tokenizer<Sink>) {
-        tokenizer.unconsume(self.name_buf_opt.take().unwrap());
+        tokenizer.unconsume(self.name_buf.take());
     }
 
     fn finish_named(&mut self,
@@ -288,7 +290,7 @@ impl<Sink: TokenSink> CharRefTokenizer {
                     }
 
                     // Check length because &; is not a parse error.
-                    Some(';') if self.name_buf().len() > 1
+                    Some(';') if self.name_buf.len() > 1
                         => self.emit_name_error(tokenizer),
 
                     _ => (),
@@ -308,14 +310,14 @@ impl<Sink: TokenSink> CharRefTokenizer {
 
                 let name_len = self.name_len;
                 assert!(name_len > 0);
-                let last_matched = self.name_buf().as_slice().char_at(name_len-1);
+                let last_matched = self.name_buf.as_slice().char_at(name_len-1);
 
                 // There might not be a next character after the match, if
                 // we had a full match and then hit EOF.
-                let next_after = if name_len == self.name_buf().len() {
+                let next_after = if name_len == self.name_buf.len() {
                     None
                 } else {
-                    Some(self.name_buf().as_slice().char_at(name_len))
+                    Some(self.name_buf.as_slice().char_at(name_len))
                 };
 
                 // "If the character reference is being consumed as part of an
@@ -331,12 +333,12 @@ impl<Sink: TokenSink> CharRefTokenizer {
                 let unconsume_all = match (self.addnl_allowed, last_matched, next_after) {
                     (_, ';', _) => false,
                     (Some(_), _, Some('=')) => {
-                        tokenizer.emit_error(Slice("Equals sign after character reference in attribute"));
+                        tokenizer.emit_error(EqualsAfterCharRef);
                         true
                     }
                     (Some(_), _, Some(c)) if is_ascii_alnum(c) => true,
                     _ => {
-                        tokenizer.emit_error(Slice("Character reference does not end with semicolon"));
+                        tokenizer.emit_error(CharRefWithoutSemicolon);
                         false
                     }
                 };
@@ -346,7 +348,7 @@ impl<Sink: TokenSink> CharRefTokenizer {
                     self.finish_none()
                 } else {
                     tokenizer.unconsume(String::from_str(
-                        self.name_buf().as_slice().slice_from(name_len)));
+                        self.name_buf.as_slice().slice_from(name_len)));
                     self.result = Some(CharRef {
                         chars: [from_u32(c1).unwrap(), from_u32(c2).unwrap()],
                         num_chars: if c2 == 0 { 1 } else { 2 },
@@ -358,10 +360,14 @@ impl<Sink: TokenSink> CharRefTokenizer {
     }
 
     fn do_bogus_name(&mut self, tokenizer: &mut Tokenizer<Sink>) -> Status {
-        let c = unwrap_or_return!(tokenizer.get_char(), Stuck);
-        self.name_buf_mut().push(c);
+        let c = match unwrap_or_return!(tokenizer.pop_except_from(NAME_END), Stuck) {
+            FromSet(c) => c,
+            NotFromSet(run) => return self.bogus_name_run(tokenizer, run),
+        };
+        self.name_buf.push(c);
         match c {
-            _ if is_ascii_alnum(c) => return Progress,
+            _ if is_ascii_alnum(c) && self.name_buf.len() < MAX_BOGUS_NAME_LEN
+                => return Progress,
             ';' => self.emit_name_error(tokenizer),
             _ => ()
         }
@@ -369,6 +375,30 @@ impl<Sink: TokenSink> CharRefTokenizer {
         self.finish_none()
     }
 
+    // Take a run of characters which may continue a bogus name, up to
+    // the first which can't or `MAX_BOGUS_NAME_LEN`, and put back the
+    // rest.
+    fn bogus_name_run(&mut self, tokenizer: &mut Tokenizer<Sink>, run: String) -> Status {
+        let room = MAX_BOGUS_NAME_LEN - self.name_buf.len();
+        let (len, take) = {
+            let s = run.as_slice();
+            let len = s.find(|c: char| !is_ascii_alnum(c)).unwrap_or(s.len());
+            let take = if len < room { len } else { room };
+            self.name_buf.push_str(s.slice_to(take));
+            (len, take)
+        };
+        if take < run.len() {
+            tokenizer.unconsume(String::from_str(run.as_slice().slice_from(take)));
+        }
+
+        // Something other than a semicolon ends the name, or it's too long.
+        if len < run.len() || self.name_buf.len() >= MAX_BOGUS_NAME_LEN {
+            self.unconsume_name(tokenizer);
+            return self.finish_none();
+        }
+        Progress
+    }
+
     pub fn end_of_file(&mut self, tokenizer: &mut Tokenizer<Sink>) {
         while self.result.is_none() {
             match self.state {
@@ -378,7 +408,7 @@ impl<Sink: TokenSink> CharRefTokenizer {
                     => drop(self.unconsume_numeric(tokenizer)),
 
                 Numeric(_) | NumericSemicolon => {
-                    tokenizer.emit_error(Slice("EOF in numeric character reference"));
+                    tokenizer.emit_error(EofInNumericCharRef);
                     self.finish_numeric(tokenizer);
                 }
 
@@ -391,7 +421,7 @@ impl<Sink: TokenSink> CharRefTokenizer {
 
                 Octothorpe => {
                     tokenizer.unconsume(String::from_char(1, '#'));
-                    tokenizer.emit_error(Slice("EOF after '#' in character reference"));
+                    tokenizer.emit_error(EofAfterHashInCharRef);
                     self.finish_none();
                 }
             }
@@ -0,0 +1,315 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Decoding bytes for `Tokenizer::feed_bytes`.
+
+use core::prelude::*;
+
+use super::char_ref::data::C1_REPLACEMENTS;
+
+use core::char::from_u32;
+use alloc::boxed::Box;
+use collections::string::String;
+
+/// Decodes a byte stream in some character encoding, one buffer at a
+/// time.  A character may be split between buffers.
+///
+/// Bytes which aren't valid in the encoding become U+FFFD REPLACEMENT
+/// CHARACTER, as in the Encoding Standard.  Implement this to plug in
+/// encodings the parser doesn't know.
+pub trait Decoder {
+    /// Decode the next buffer, appending to `output`.  An incomplete
+    /// character at the end is kept until the next call.
+    fn decode(&mut self, input: &[u8], output: &mut String);
+
+    /// The input has ended.  Append anything left over, which is
+    /// U+FFFD if a character was incomplete.
+    fn finish(&mut self, output: &mut String);
+
+    /// The name of the encoding, as in `decoder_for`, if it has one.
+    fn encoding(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+/// Get a decoder for the encoding with this name, as returned by the
+/// functions in `encoding`.  Only UTF-8, UTF-16 and windows-1252 are
+/// built in.
+pub fn decoder_for(encoding: &str) -> Option<Box<Decoder + Send>> {
+    match encoding {
+        "utf-8" => Some(box Utf8Decoder::new() as Box<Decoder + Send>),
+        "utf-16le" => Some(box Utf16Decoder::little_endian() as Box<Decoder + Send>),
+        "utf-16be" => Some(box Utf16Decoder::big_endian() as Box<Decoder + Send>),
+        "windows-1252" => Some(box Windows1252Decoder as Box<Decoder + Send>),
+        _ => None,
+    }
+}
+
+/// The "UTF-8 decoder" from the Encoding Standard.  Each maximal
+/// invalid sequence becomes one U+FFFD.
+pub struct Utf8Decoder {
+    code_point: u32,
+    needed: uint,
+    seen: uint,
+
+    // Bounds of the next continuation byte.
+    lower: u8,
+    upper: u8,
+}
+
+impl Utf8Decoder {
+    pub fn new() -> Utf8Decoder {
+        Utf8Decoder {
+            code_point: 0,
+            needed: 0,
+            seen: 0,
+            lower: 0x80,
+            upper: 0xBF,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Utf8Decoder::new();
+    }
+}
+
+impl Decoder for Utf8Decoder {
+    fn decode(&mut self, input: &[u8], output: &mut String) {
+        let mut i = 0;
+        while i < input.len() {
+            let b = input[i];
+            if self.needed == 0 {
+                i += 1;
+                match b {
+                    0x00...0x7F => output.push(b as char),
+                    0xC2...0xDF => {
+                        self.needed = 1;
+                        self.code_point = (b & 0x1F) as u32;
+                    }
+                    0xE0...0xEF => {
+                        match b {
+                            0xE0 => self.lower = 0xA0,
+                            0xED => self.upper = 0x9F,
+                            _ => (),
+                        }
+                        self.needed = 2;
+                        self.code_point = (b & 0xF) as u32;
+                    }
+                    0xF0...0xF4 => {
+                        match b {
+                            0xF0 => self.lower = 0x90,
+                            0xF4 => self.upper = 0x8F,
+                            _ => (),
+                        }
+                        self.needed = 3;
+                        self.code_point = (b & 0x7) as u32;
+                    }
+                    _ => output.push('\ufffd'),
+                }
+                continue;
+            }
+
+            if b < self.lower || b > self.upper {
+                // The sequence so far is invalid.  This byte starts
+                // afresh, so don't consume it.
+                self.reset();
+                output.push('\ufffd');
+                continue;
+            }
+
+            i += 1;
+            self.lower = 0x80;
+            self.upper = 0xBF;
+            self.code_point = (self.code_point << 6) | (b & 0x3F) as u32;
+            self.seen += 1;
+            if self.seen == self.needed {
+                output.push(from_u32(self.code_point).expect("UTF-8 decoder produced a bad char"));
+                self.reset();
+            }
+        }
+    }
+
+    fn finish(&mut self, output: &mut String) {
+        if self.needed != 0 {
+            self.reset();
+            output.push('\ufffd');
+        }
+    }
+
+    fn encoding(&self) -> Option<&'static str> {
+        Some("utf-8")
+    }
+}
+
+/// The "shared UTF-16 decoder" from the Encoding Standard.  An
+/// unpaired surrogate becomes U+FFFD.
+pub struct Utf16Decoder {
+    big_endian: bool,
+    lead_byte: Option<u8>,
+    lead_surrogate: Option<u16>,
+}
+
+impl Utf16Decoder {
+    pub fn little_endian() -> Utf16Decoder {
+        Utf16Decoder {
+            big_endian: false,
+            lead_byte: None,
+            lead_surrogate: None,
+        }
+    }
+
+    pub fn big_endian() -> Utf16Decoder {
+        Utf16Decoder {
+            big_endian: true,
+            .. Utf16Decoder::little_endian()
+        }
+    }
+}
+
+impl Decoder for Utf16Decoder {
+    fn decode(&mut self, input: &[u8], output: &mut String) {
+        for &b in input.iter() {
+            let lead = match self.lead_byte.take() {
+                None => {
+                    self.lead_byte = Some(b);
+                    continue;
+                }
+                Some(lead) => lead,
+            };
+            let unit = if self.big_endian {
+                (lead as u16 << 8) | b as u16
+            } else {
+                (b as u16 << 8) | lead as u16
+            };
+
+            match self.lead_surrogate.take() {
+                Some(high) if unit >= 0xDC00 && unit <= 0xDFFF => {
+                    let c = 0x10000 + ((high as u32 - 0xD800) << 10) + (unit as u32 - 0xDC00);
+                    output.push(from_u32(c).expect("UTF-16 decoder produced a bad char"));
+                    continue;
+                }
+                // An unpaired high surrogate.  This unit starts afresh.
+                Some(_) => output.push('\ufffd'),
+                None => (),
+            }
+
+            match unit {
+                0xD800...0xDBFF => self.lead_surrogate = Some(unit),
+                0xDC00...0xDFFF => output.push('\ufffd'),
+                _ => output.push(from_u32(unit as u32).expect("UTF-16 decoder produced a bad char")),
+            }
+        }
+    }
+
+    fn finish(&mut self, output: &mut String) {
+        if self.lead_byte.is_some() || self.lead_surrogate.is_some() {
+            output.push('\ufffd');
+        }
+        self.lead_byte = None;
+        self.lead_surrogate = None;
+    }
+
+    fn encoding(&self) -> Option<&'static str> {
+        Some(if self.big_endian { "utf-16be" } else { "utf-16le" })
+    }
+}
+
+/// The windows-1252 decoder, which is also used for ASCII and
+/// ISO-8859-1.  Every byte is a character.
+pub struct Windows1252Decoder;
+
+impl Decoder for Windows1252Decoder {
+    fn decode(&mut self, input: &[u8], output: &mut String) {
+        for &b in input.iter() {
+            output.push(match b {
+                0x80...0x9F => C1_REPLACEMENTS[(b - 0x80) as uint].unwrap_or(b as char),
+                _ => b as char,
+            });
+        }
+    }
+
+    fn finish(&mut self, _output: &mut String) { }
+
+    fn encoding(&self) -> Option<&'static str> {
+        Some("windows-1252")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{Decoder, Utf8Decoder, Utf16Decoder, decoder_for};
+
+    use collections::string::String;
+
+    fn decode(input: &[u8], chunk_len: uint) -> String {
+        let mut decoder = Utf8Decoder::new();
+        let mut out = String::new();
+        for chunk in input.chunks(chunk_len) {
+            decoder.decode(chunk, &mut out);
+        }
+        decoder.finish(&mut out);
+        out
+    }
+
+    // Decode all at once, and a byte at a time.
+    fn utf8(input: &[u8]) -> String {
+        let out = decode(input, input.len());
+        assert_eq!(decode(input, 1), out);
+        out
+    }
+
+    test_eq!(utf8_valid, utf8(b"a\xc3\xa9\xe2\x82\xac\xf0\x9f\x98\x80").as_slice(),
+        "a\u00e9\u20ac\U0001f600")
+    test_eq!(utf8_bad_lead, utf8(b"a\x80\xffb").as_slice(), "a\ufffd\ufffdb")
+    test_eq!(utf8_truncated, utf8(b"\xe2\x82a").as_slice(), "\ufffda")
+    test_eq!(utf8_surrogate, utf8(b"\xed\xa0\x80").as_slice(), "\ufffd\ufffd\ufffd")
+    test_eq!(utf8_overlong, utf8(b"\xe0\x80\xaf").as_slice(), "\ufffd\ufffd\ufffd")
+    test_eq!(utf8_eof, utf8(b"a\xf0\x9f").as_slice(), "a\ufffd")
+
+    fn utf16_le(input: &[u8]) -> String {
+        let mut decoder = Utf16Decoder::little_endian();
+        let mut out = String::new();
+        for chunk in input.chunks(1) {
+            decoder.decode(chunk, &mut out);
+        }
+        decoder.finish(&mut out);
+        out
+    }
+
+    test_eq!(utf16_valid, utf16_le(b"a\x00\xe9\x00=\xd8\x00\xde").as_slice(),
+        "a\u00e9\U0001f600")
+    test_eq!(utf16_lone_low, utf16_le(b"\x00\xdca\x00").as_slice(), "\ufffda")
+    test_eq!(utf16_lone_high, utf16_le(b"=\xd8a\x00").as_slice(), "\ufffda")
+    test_eq!(utf16_odd_byte, utf16_le(b"a\x00b").as_slice(), "a\ufffd")
+
+    #[test]
+    fn utf16_be() {
+        let mut decoder = decoder_for("utf-16be").unwrap();
+        let mut out = String::new();
+        decoder.decode(b"\x00a\xd8=\xde\x00", &mut out);
+        decoder.finish(&mut out);
+        assert_eq!(out.as_slice(), "a\U0001f600");
+    }
+
+    #[test]
+    fn windows_1252() {
+        let mut decoder = decoder_for("windows-1252").unwrap();
+        let mut out = String::new();
+        decoder.decode(b"a\x80\x81\xe9", &mut out);
+        assert_eq!(out.as_slice(), "a\u20ac\u0081\u00e9");
+    }
+
+    #[test]
+    fn encoding_names() {
+        for &name in ["utf-8", "utf-16le", "utf-16be", "windows-1252"].iter() {
+            assert_eq!(decoder_for(name).unwrap().encoding(), Some(name));
+        }
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use core::prelude::*;
+
+use super::interface::Position;
+use super::states::State;
+
+use collections::string::String;
+use collections::str::{MaybeOwned, Slice};
+
+/// How much a parse error matters, from least to most.
+#[deriving(PartialEq, Eq, PartialOrd, Ord, Clone, Show)]
+pub enum Severity {
+    /// Not a parse error in the spec, but a note that the tokenizer
+    /// cut something off because of one of the length limits.
+    Informational,
+
+    /// A parse error with a fix-up every browser agrees on, which
+    /// leaves what the author most likely meant, e.g. a missing `;`
+    /// after a character reference.
+    Recoverable,
+
+    /// A parse error after which the tokens may well not be what the
+    /// author meant, e.g. a stray character in a tag or EOF in the
+    /// middle of one.  A strict consumer should reject the document.
+    Fatal,
+}
+
+/// What kind of parse error the tokenizer found, with any details
+/// needed to describe it.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum ErrorCode {
+    /// A control character or noncharacter in the input.  Only
+    /// reported with the `exact_errors` option.
+    BadCharacter(char),
+
+    /// A character which isn't allowed in this state.
+    UnexpectedCharacter(char, State),
+
+    /// The input ended in this state.
+    UnexpectedEof(State),
+
+    /// Character data was cut off at `max_text_len` bytes.
+    TextTooLong(uint),
+
+    /// The value of the named attribute was cut off at
+    /// `max_attr_value_len` bytes.
+    AttrValueTooLong(String, uint),
+
+    /// A tag or attribute name was cut off at `max_name_len` bytes.
+    NameTooLong(uint),
+
+    EndTagWithAttributes,
+    SelfClosingEndTag,
+    DuplicateAttribute,
+
+    /// A numeric character reference without `;` after the digits.
+    NumericCharRefWithoutSemicolon,
+
+    /// `&#` or `&#x` without any digits.
+    NumericCharRefWithoutDigits,
+
+    /// A numeric character reference to this value, which isn't
+    /// allowed in HTML.
+    InvalidNumericCharRef(u32),
+
+    /// A named character reference ending in `;`, with this name,
+    /// which isn't in the table.
+    InvalidCharRef(String),
+
+    /// A named character reference without `;` followed by `=` in
+    /// an attribute value.
+    EqualsAfterCharRef,
+
+    /// A named character reference without `;`.
+    CharRefWithoutSemicolon,
+
+    EofInNumericCharRef,
+    EofAfterHashInCharRef,
+}
+
+impl ErrorCode {
+    /// How much this error matters.
+    pub fn severity(&self) -> Severity {
+        match *self {
+            TextTooLong(_) | AttrValueTooLong(..) | NameTooLong(_) => Informational,
+            UnexpectedCharacter(..) | UnexpectedEof(_) => Fatal,
+            _ => Recoverable,
+        }
+    }
+
+    /// A short description, which leaves out any details.
+    pub fn summary(&self) -> &'static str {
+        match *self {
+            BadCharacter(_) | UnexpectedCharacter(..) => "Bad character",
+            UnexpectedEof(_) => "Unexpected EOF",
+            TextTooLong(_) => "Text too long",
+            AttrValueTooLong(..) => "Attribute value too long",
+            NameTooLong(_) => "Name too long",
+            EndTagWithAttributes => "Attributes on an end tag",
+            SelfClosingEndTag => "Self-closing end tag",
+            DuplicateAttribute => "Duplicate attribute",
+            NumericCharRefWithoutSemicolon => "Semicolon missing after numeric character reference",
+            NumericCharRefWithoutDigits => "Numeric character reference without digits",
+            InvalidNumericCharRef(_) => "Invalid numeric character reference",
+            InvalidCharRef(_) => "Invalid character reference",
+            EqualsAfterCharRef => "Equals sign after character reference in attribute",
+            CharRefWithoutSemicolon => "Character reference does not end with semicolon",
+            EofInNumericCharRef => "EOF in numeric character reference",
+            EofAfterHashInCharRef => "EOF after '#' in character reference",
+        }
+    }
+}
+
+/// A parse error from the tokenizer: what went wrong, and where.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct TokenizerError {
+    pub code: ErrorCode,
+
+    /// Where the error was found.  The line and column are 0 unless
+    /// the tokenizer's `track_lines` option is set.
+    pub pos: Position,
+}
+
+impl TokenizerError {
+    /// Describe the error, with any details.  The message is only
+    /// formatted when this is called.  When built as a C library, this
+    /// is the same as the code's `summary`.
+    pub fn message(&self) -> MaybeOwned<'static> {
+        let summary = self.code.summary();
+        match self.code {
+            BadCharacter(c) => format_if!(true, summary, "Bad character {}", c),
+            UnexpectedCharacter(c, state)
+                => format_if!(true, summary, "Saw {} in state {}", c, state),
+            UnexpectedEof(state) => format_if!(true, summary, "Saw EOF in state {}", state),
+            TextTooLong(max)
+                => format_if!(true, summary, "Character data exceeded {} bytes, truncated", max),
+            AttrValueTooLong(ref name, max) => format_if!(true, summary,
+                "Value of attribute {} exceeded {} bytes, truncated", name, max),
+            NameTooLong(max) => format_if!(true, summary,
+                "Tag or attribute name exceeded {} bytes, truncated", max),
+            InvalidNumericCharRef(n) => format_if!(true, summary,
+                "Invalid numeric character reference value 0x{:06X}", n),
+            InvalidCharRef(ref name)
+                => format_if!(true, summary, "Invalid character reference &{:s}", name.as_slice()),
+            _ => Slice(summary),
+        }
+    }
+}
@@ -10,10 +10,10 @@
 use core::prelude::*;
 
 use tokenizer::states;
+use tokenizer::error::TokenizerError;
 
 use collections::vec::Vec;
 use collections::string::String;
-use collections::str::MaybeOwned;
 
 use string_cache::{Atom, QualName};
 
@@ -48,6 +48,10 @@ impl Doctype {
 pub struct Attribute {
     pub name: QualName,
     pub value: String,
+
+    /// Does the value contain template syntax, kept verbatim?  Only
+    /// set if the tokenizer's `template_attr_values` option is on.
+    pub has_template: bool,
 }
 
 #[deriving(PartialEq, Eq, Clone, Show)]
@@ -57,6 +61,10 @@ pub enum TagKind {
 }
 
 /// A tag token.
+///
+/// End tags keep any attributes and the self-closing flag, though the
+/// tokenizer reports a parse error for each and the tree builder then
+/// ignores them.
 #[deriving(PartialEq, Eq, Clone, Show)]
 pub struct Tag {
     pub kind: TagKind,
@@ -88,9 +96,63 @@ pub enum Token {
     TagToken(Tag),
     CommentToken(String),
     CharacterTokens(String),
+
+    /// Characters which are all ASCII whitespace.  Only emitted if the
+    /// `whitespace_tokens` option is set; otherwise these characters
+    /// are part of a `CharacterTokens`.
+    WhitespaceTokens(String),
+
     NullCharacterToken,
+
+    /// Text between a pair of `template_delimiters`, including the
+    /// delimiters themselves.  Only emitted if that option is set.
+    PassthroughToken(String),
+
+    /// A processing instruction `<?target data?>`, with the target and
+    /// data.  Only emitted if the `processing_instructions` option is
+    /// set; otherwise this is tokenized as a bogus comment.
+    ProcessingInstructionToken(String, String),
+
     EOFToken,
-    ParseError(MaybeOwned<'static>),
+    ParseError(TokenizerError),
+}
+
+/// A range of input, as byte offsets from the start of everything
+/// fed to the tokenizer.  A discarded byte order mark counts as input.
+#[deriving(PartialEq, Eq, PartialOrd, Ord, Clone, Show)]
+pub struct Span {
+    pub start: uint,
+    pub end: uint,
+}
+
+/// A point in the input, where a parse error was found.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct Position {
+    /// Byte offset, as in a `Span`.
+    pub offset: uint,
+
+    /// Line number, starting from 1.  `\r\n` counts as one line ending.
+    pub line: uint,
+
+    /// Column number, starting from 1.  This counts bytes, so an editor
+    /// which counts characters should convert it using the line's text.
+    pub column: uint,
+}
+
+/// A part of a token whose span is reported separately.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum TokenPart {
+    /// A tag name, not including `<` or `</`.
+    TagNamePart,
+
+    /// An attribute name.
+    AttrNamePart,
+
+    /// An attribute value, including any quotes.
+    AttrValuePart,
+
+    /// A character reference which was decoded, e.g. `&amp;`.
+    CharRefPart,
 }
 
 /// Types which can receive tokens from the tokenizer.
@@ -98,10 +160,48 @@ pub trait TokenSink {
     /// Process a token.
     fn process_token(&mut self, token: Token);
 
+    /// Process a token along with the span of input it came from.
+    /// Called instead of `process_token` if the tokenizer's
+    /// `track_spans` option is set.  By default the span is ignored.
+    ///
+    /// The spans of successive tokens are adjacent, except that the
+    /// `\n` of a `\r\n` pair may fall between them.  A `ParseError`
+    /// gets an empty span at the position where it was found, and
+    /// doesn't interrupt the spans of other tokens.
+    fn process_token_with_span(&mut self, token: Token, _span: Span) {
+        self.process_token(token);
+    }
+
+    /// Receive the span of part of a token.  Only called if the
+    /// tokenizer's `track_spans` option is set.  Parts are reported as
+    /// they end, before the token which contains them, so a character
+    /// reference in an attribute value comes before the value.  The
+    /// parts of a tag which is cut off by EOF are reported, though
+    /// the tag itself never is.
+    fn process_token_part(&mut self, _part: TokenPart, _span: Span) {
+    }
+
     /// The tokenizer will call this after emitting any start tag.
     /// This allows the tree builder to change the tokenizer's state.
     /// By default no state changes occur.
     fn query_state_change(&mut self) -> Option<states::State> {
         None
     }
+
+    /// The tokenizer will call this after emitting any tag.  Returning
+    /// `true` suspends tokenizing there, e.g. to run a script
+    /// which might call `document.write`, until `Tokenizer::resume` is
+    /// called.  By default the tokenizer is never suspended.
+    fn query_suspend(&mut self) -> bool {
+        false
+    }
+
+    /// The tokenizer will call this on seeing `<![`.  Only if the tree
+    /// builder's adjusted current node is an SVG or MathML element does
+    /// `<![CDATA[` start a CDATA section, whose contents become
+    /// character tokens; otherwise it's a bogus comment.  By default
+    /// this returns `false`.
+    fn adjusted_current_node_is_foreign(&self) -> bool {
+        false
+    }
 }
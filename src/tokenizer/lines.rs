@@ -0,0 +1,146 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use core::prelude::*;
+
+use super::interface::Position;
+
+use collections::MutableSeq;
+use collections::vec::Vec;
+
+// Average length in bytes of a line of HTML, for `reset`.
+static TYPICAL_LINE_LEN: uint = 40;
+
+/// The offset where each line of the input starts, for turning
+/// offsets into line and column numbers.
+///
+/// Lines end at `\n`, `\r`, or `\r\n`, as the tokenizer's input
+/// stream preprocessing sees them.  Input from `Tokenizer::insert`
+/// isn't part of the source, so it's never added.
+pub struct LineTable {
+    // Offsets of the line starts, in increasing order.
+    starts: Vec<uint>,
+
+    // Offset of the end of the input added so far.
+    end: uint,
+
+    // Did the input added so far end with `\r`?
+    after_cr: bool,
+}
+
+impl LineTable {
+    /// Create a `LineTable` for input starting at `offset`.
+    pub fn new(offset: uint) -> LineTable {
+        LineTable {
+            starts: vec!(offset),
+            end: offset,
+            after_cr: false,
+        }
+    }
+
+    /// Empty the table for input starting at `offset`, with room for
+    /// the lines of about `bytes` of typical input.  Room which was
+    /// already allocated is kept, for the next document.
+    pub fn reset(&mut self, offset: uint, bytes: uint) {
+        self.starts.truncate(0);
+        self.starts.reserve(bytes / TYPICAL_LINE_LEN + 1);
+        self.starts.push(offset);
+        self.end = offset;
+        self.after_cr = false;
+    }
+
+    /// Add the next buffer of input.  The first `skip` bytes are
+    /// counted but not scanned, as for `BufferQueue::push_back`.
+    /// Skipped bytes at the very start, i.e. a byte order mark, are
+    /// not part of the first line.
+    pub fn add(&mut self, input: &str, skip: uint) {
+        if self.starts.len() == 1 && self.starts[0] == self.end {
+            *self.starts.get_mut(0) += skip;
+        }
+        let base = self.end;
+        for (i, b) in input.as_bytes().iter().enumerate().skip(skip) {
+            match *b {
+                b'\n' if self.after_cr => *self.starts.get_mut(self.starts.len() - 1) += 1,
+                b'\n' | b'\r' => self.starts.push(base + i + 1),
+                _ => (),
+            }
+            self.after_cr = *b == b'\r';
+        }
+        self.end += input.len();
+    }
+
+    /// The line and column of an offset.  Columns count bytes, so a
+    /// non-ASCII character advances the column by more than one.
+    pub fn position(&self, offset: uint) -> Position {
+        // Errors are found near the end of the input so far, so search
+        // backwards.
+        let line = self.starts.iter().rposition(|&s| s <= offset).map_or(1, |i| i + 1);
+        let start = self.starts[line - 1];
+        Position {
+            offset: offset,
+            line: line,
+            column: if offset > start { offset - start + 1 } else { 1 },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::LineTable;
+
+    fn line_col(table: &LineTable, offset: uint) -> (uint, uint) {
+        let pos = table.position(offset);
+        (pos.line, pos.column)
+    }
+
+    #[test]
+    fn line_endings() {
+        let mut table = LineTable::new(0);
+        table.add("ab\ncd\r", 0);
+        table.add("\nef\rg", 0);
+        assert_eq!(line_col(&table, 0), (1, 1));
+        assert_eq!(line_col(&table, 1), (1, 2));
+        assert_eq!(line_col(&table, 3), (2, 1));
+        assert_eq!(line_col(&table, 5), (2, 3));
+        assert_eq!(line_col(&table, 7), (3, 1));
+        assert_eq!(line_col(&table, 10), (4, 1));
+    }
+
+    #[test]
+    fn offset_and_bom() {
+        let mut table = LineTable::new(10);
+        table.add("\ufeffa\nb", 3);
+        assert_eq!(line_col(&table, 13), (1, 1));
+        assert_eq!(line_col(&table, 15), (2, 1));
+        assert_eq!(table.position(15).offset, 15);
+    }
+
+    #[test]
+    fn reset() {
+        let mut table = LineTable::new(0);
+        table.add("a\nb\nc\r", 0);
+        let cap = table.starts.capacity();
+        table.reset(5, 0);
+        assert_eq!(table.starts.capacity(), cap);
+        table.add("\nd", 0);
+        assert_eq!(line_col(&table, 5), (1, 1));
+        assert_eq!(line_col(&table, 6), (2, 1));
+    }
+
+    #[test]
+    fn size_hint() {
+        let mut table = LineTable::new(0);
+        table.reset(10, 4000);
+        assert!(table.starts.capacity() > 100);
+        table.add("\ufeffa\nb", 3);
+        assert_eq!(line_col(&table, 13), (1, 1));
+        assert_eq!(line_col(&table, 15), (2, 1));
+    }
+}
@@ -13,8 +13,18 @@ use core::prelude::*;
 
 pub use self::interface::{Doctype, Attribute, TagKind, StartTag, EndTag, Tag};
 pub use self::interface::{Token, DoctypeToken, TagToken, CommentToken};
-pub use self::interface::{CharacterTokens, NullCharacterToken, EOFToken, ParseError};
-pub use self::interface::TokenSink;
+pub use self::interface::{CharacterTokens, WhitespaceTokens, NullCharacterToken};
+pub use self::interface::{PassthroughToken, ProcessingInstructionToken, EOFToken, ParseError};
+pub use self::interface::{TokenSink, Span, Position, TokenPart};
+pub use self::error::{Severity, Informational, Recoverable, Fatal};
+pub use self::error::{TokenizerError, ErrorCode, BadCharacter, UnexpectedCharacter, UnexpectedEof};
+pub use self::error::{TextTooLong, AttrValueTooLong, NameTooLong};
+pub use self::error::{EndTagWithAttributes, SelfClosingEndTag, DuplicateAttribute};
+pub use self::error::{NumericCharRefWithoutSemicolon, NumericCharRefWithoutDigits};
+pub use self::error::{InvalidNumericCharRef, InvalidCharRef, EqualsAfterCharRef};
+pub use self::error::{CharRefWithoutSemicolon, EofInNumericCharRef, EofAfterHashInCharRef};
+pub use self::interface::{TagNamePart, AttrNamePart, AttrValuePart, CharRefPart};
+pub use self::decode::{Decoder, Utf8Decoder, Utf16Decoder, Windows1252Decoder, decoder_for};
 
 use self::states::{RawLessThanSign, RawEndTagOpen, RawEndTagName};
 use self::states::{Rcdata, Rawtext, ScriptData, ScriptDataEscaped};
@@ -25,9 +35,13 @@ use self::states::{DoctypeIdKind, Public, System};
 use self::char_ref::{CharRef, CharRefTokenizer};
 
 use self::buffer_queue::{BufferQueue, SetResult, FromSet, NotFromSet};
+use self::lines::LineTable;
 
-use util::str::{lower_ascii, lower_ascii_letter, empty_str};
+use util::str::{lower_ascii, lower_ascii_letter, empty_str, is_ascii_whitespace};
 use util::smallcharset::SmallCharSet;
+use util::tempbuf::TempBuf;
+
+use encoding::sniff_bom;
 
 use core::mem::replace;
 use core::default::Default;
@@ -35,15 +49,17 @@ use alloc::boxed::Box;
 use collections::{MutableSeq, MutableMap};
 use collections::vec::Vec;
 use collections::string::String;
-use collections::str::{MaybeOwned, Slice};
 use collections::treemap::TreeMap;
 
 use string_cache::{Atom, QualName};
 
 pub mod states;
 mod interface;
+mod error;
 mod char_ref;
 mod buffer_queue;
+mod lines;
+mod decode;
 
 fn option_push(opt_str: &mut Option<String>, c: char) {
     match *opt_str {
@@ -52,6 +68,14 @@ fn option_push(opt_str: &mut Option<String>, c: char) {
     }
 }
 
+// Extend a span to `end`, starting it at `start` if there isn't one.
+fn extend_span(span: &mut Option<Span>, start: uint, end: uint) {
+    match *span {
+        Some(ref mut span) => span.end = end,
+        None => *span = Some(Span { start: start, end: end }),
+    }
+}
+
 fn append_strings(lhs: &mut String, rhs: String) {
     if lhs.is_empty() {
         *lhs = rhs;
@@ -60,6 +84,42 @@ fn append_strings(lhs: &mut String, rhs: String) {
     }
 }
 
+// Truncate to at most `max` bytes, backing up to a character boundary.
+// The longest prefix of `s` which is at most `max` bytes and ends at
+// a character boundary.
+fn prefix_within<'a>(s: &'a str, max: uint) -> &'a str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut i = max;
+    while !s.is_char_boundary(i) {
+        i -= 1;
+    }
+    s.slice_to(i)
+}
+
+fn truncate_to(s: &mut String, max: uint) {
+    let len = prefix_within(s.as_slice(), max).len();
+    s.truncate(len);
+}
+
+/// What to do when a tag has two attributes with the same name.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum DuplicateAttrs {
+    /// Keep the first, as the spec requires.
+    KeepFirst,
+
+    /// Keep the last, in the position of the first.
+    KeepLast,
+
+    /// Keep all of them, in source order.  `Tag::attrs` may then
+    /// contain several attributes with the same name.
+    KeepAll,
+
+    /// Stop tokenizing.  See `Tokenizer::aborted`.
+    AbortOnDuplicate,
+}
+
 /// Tokenizer options, with an impl for `Default`.
 #[deriving(Clone)]
 pub struct TokenizerOpts {
@@ -75,13 +135,100 @@ pub struct TokenizerOpts {
     /// when `end()` is called.  Default: false
     pub profile: bool,
 
-    /// Initial state override.  Only the test runner should use
-    /// a non-`None` value!
+    /// The state to start in, instead of the data state.  To tokenize
+    /// the content of a `<script>`, `<style>` or `<title>` on its own,
+    /// use the state from `raw_text_state`, and set
+    /// `last_start_tag_name` so that the element's end tag is
+    /// recognized.  Default: None
     pub initial_state: Option<states::State>,
 
-    /// Last start tag.  Only the test runner should use a
-    /// non-`None` value!
+    /// The name of the last start tag, as if it had already been seen.
+    /// An end tag in a raw text state only ends the text if it matches
+    /// this.  Default: None
     pub last_start_tag_name: Option<String>,
+
+    /// Maximum length in bytes of a single attribute value.  Longer
+    /// values are truncated and a parse error is emitted.  Default: None
+    pub max_attr_value_len: Option<uint>,
+
+    /// Maximum length in bytes of a run of character data between two
+    /// pieces of markup.  This bounds the size of text nodes.  Longer
+    /// runs are truncated and a parse error is emitted.  Default: None
+    pub max_text_len: Option<uint>,
+
+    /// Maximum length in bytes of a tag or attribute name.  The spec
+    /// sets no limit.  Further characters are dropped and a parse error
+    /// is emitted.  Default: None
+    pub max_name_len: Option<uint>,
+
+    /// Emit character data consisting only of ASCII whitespace as
+    /// `WhitespaceTokens` rather than `CharacterTokens`?  Character data
+    /// is not split to make this happen, so some whitespace may still
+    /// appear in `CharacterTokens`.  Default: false
+    pub whitespace_tokens: bool,
+
+    /// Pairs of opening and closing delimiters, such as `("{{", "}}")`
+    /// or `("<?", "?>")`, for template syntax embedded in the document.
+    /// In the data state, text from an opening delimiter through the
+    /// matching closing delimiter is emitted as a `PassthroughToken`
+    /// rather than being tokenized as HTML.  Delimiters must be ASCII
+    /// and are matched case-insensitively.  A pair with an empty
+    /// opening delimiter is ignored.  Default: empty
+    pub template_delimiters: Vec<(String, String)>,
+
+    /// Also recognize `template_delimiters` inside attribute values?
+    /// Text between the delimiters is kept verbatim, without decoding
+    /// character references, and the attribute's `has_template` flag
+    /// is set.  Default: false
+    pub template_attr_values: bool,
+
+    /// Emit `<?target data?>` as a `ProcessingInstructionToken`, ending
+    /// at `?>`, rather than as a bogus comment ending at `>`?  This is
+    /// still a parse error.  Useful for server-side template files,
+    /// e.g. PHP.  Default: false
+    pub processing_instructions: bool,
+
+    /// What to do with duplicate attributes.  Every policy emits a
+    /// parse error.  Default: KeepFirst
+    pub duplicate_attrs: DuplicateAttrs,
+
+    /// Track where each token came from in the input?  The sink's
+    /// `process_token_with_span` and `process_token_part` methods are
+    /// then called.  Default: false
+    pub track_spans: bool,
+
+    /// Only report parse errors at least this severe, or none at all
+    /// if `None`.  A sanitizer might want none, and a validator all.
+    /// Default: `Some(Informational)`, i.e. every error
+    pub min_error_severity: Option<Severity>,
+
+    /// Track the line and column of each parse error?  Otherwise only
+    /// the offset is given.  This keeps the offset of every line start,
+    /// so it uses memory in proportion to the number of lines.
+    /// Default: false
+    pub track_lines: bool,
+
+    /// Offset of the input in some larger buffer.  Spans start from
+    /// here rather than from zero.  Default: 0
+    pub span_offset: uint,
+
+    /// Is the input expected to be cut off, e.g. the first few kilobytes
+    /// of a page?  Reaching EOF inside a tag, comment or doctype is then
+    /// not a parse error, though `eof_truncated()` still reports it.
+    /// The tree builder has an option of the same name.  Default: false
+    pub truncated_input: bool,
+
+    /// Drop character data rather than emitting it, for sinks which
+    /// only care about markup.  Character references in text aren't
+    /// decoded.  Default: false
+    pub discard_text: bool,
+
+    /// Roughly how many bytes of input to expect, if known.  The queue
+    /// of input chunks, and the line table from `track_lines`, are
+    /// sized for it up front.  With a hint, each attribute value's
+    /// buffer is also sized from the value's span in the input, which
+    /// costs a look ahead.  Default: None
+    pub input_size_hint: Option<uint>,
 }
 
 impl Default for TokenizerOpts {
@@ -92,6 +239,185 @@ impl Default for TokenizerOpts {
             profile: false,
             initial_state: None,
             last_start_tag_name: None,
+            max_attr_value_len: None,
+            max_text_len: None,
+            max_name_len: None,
+            whitespace_tokens: false,
+            template_delimiters: vec!(),
+            template_attr_values: false,
+            processing_instructions: false,
+            duplicate_attrs: KeepFirst,
+            track_spans: false,
+            min_error_severity: Some(Informational),
+            track_lines: false,
+            span_offset: 0,
+            truncated_input: false,
+            discard_text: false,
+            input_size_hint: None,
+        }
+    }
+}
+
+impl TokenizerOpts {
+    /// Start building options from the defaults, one setting at a
+    /// time.  Code written this way keeps compiling when options are
+    /// added, unlike a struct literal.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// let opts = TokenizerOpts::builder()
+    ///     .exact_errors(true)
+    ///     .initial_state(states::RawData(states::Rawtext))
+    ///     .build();
+    /// ```
+    pub fn builder() -> TokenizerOptsBuilder {
+        TokenizerOptsBuilder {
+            opts: Default::default(),
+        }
+    }
+}
+
+/// Builds `TokenizerOpts`.  Each method sets the option of the same
+/// name; see there for what it does.
+#[deriving(Clone)]
+pub struct TokenizerOptsBuilder {
+    opts: TokenizerOpts,
+}
+
+impl TokenizerOptsBuilder {
+    pub fn exact_errors(mut self, x: bool) -> TokenizerOptsBuilder {
+        self.opts.exact_errors = x;
+        self
+    }
+
+    pub fn discard_bom(mut self, x: bool) -> TokenizerOptsBuilder {
+        self.opts.discard_bom = x;
+        self
+    }
+
+    pub fn profile(mut self, x: bool) -> TokenizerOptsBuilder {
+        self.opts.profile = x;
+        self
+    }
+
+    pub fn initial_state(mut self, state: states::State) -> TokenizerOptsBuilder {
+        self.opts.initial_state = Some(state);
+        self
+    }
+
+    pub fn last_start_tag_name(mut self, name: &str) -> TokenizerOptsBuilder {
+        self.opts.last_start_tag_name = Some(String::from_str(name));
+        self
+    }
+
+    pub fn max_attr_value_len(mut self, max: uint) -> TokenizerOptsBuilder {
+        self.opts.max_attr_value_len = Some(max);
+        self
+    }
+
+    pub fn max_text_len(mut self, max: uint) -> TokenizerOptsBuilder {
+        self.opts.max_text_len = Some(max);
+        self
+    }
+
+    pub fn max_name_len(mut self, max: uint) -> TokenizerOptsBuilder {
+        self.opts.max_name_len = Some(max);
+        self
+    }
+
+    pub fn whitespace_tokens(mut self, x: bool) -> TokenizerOptsBuilder {
+        self.opts.whitespace_tokens = x;
+        self
+    }
+
+    /// Add one pair to `template_delimiters`.
+    pub fn template_delimiter(mut self, open: &str, close: &str) -> TokenizerOptsBuilder {
+        self.opts.template_delimiters.push((String::from_str(open), String::from_str(close)));
+        self
+    }
+
+    pub fn template_attr_values(mut self, x: bool) -> TokenizerOptsBuilder {
+        self.opts.template_attr_values = x;
+        self
+    }
+
+    pub fn processing_instructions(mut self, x: bool) -> TokenizerOptsBuilder {
+        self.opts.processing_instructions = x;
+        self
+    }
+
+    pub fn duplicate_attrs(mut self, policy: DuplicateAttrs) -> TokenizerOptsBuilder {
+        self.opts.duplicate_attrs = policy;
+        self
+    }
+
+    pub fn track_spans(mut self, x: bool) -> TokenizerOptsBuilder {
+        self.opts.track_spans = x;
+        self
+    }
+
+    /// `None` turns off error reporting.
+    pub fn min_error_severity(mut self, min: Option<Severity>) -> TokenizerOptsBuilder {
+        self.opts.min_error_severity = min;
+        self
+    }
+
+    pub fn track_lines(mut self, x: bool) -> TokenizerOptsBuilder {
+        self.opts.track_lines = x;
+        self
+    }
+
+    pub fn span_offset(mut self, offset: uint) -> TokenizerOptsBuilder {
+        self.opts.span_offset = offset;
+        self
+    }
+
+    pub fn truncated_input(mut self, x: bool) -> TokenizerOptsBuilder {
+        self.opts.truncated_input = x;
+        self
+    }
+
+    pub fn discard_text(mut self, x: bool) -> TokenizerOptsBuilder {
+        self.opts.discard_text = x;
+        self
+    }
+
+    pub fn input_size_hint(mut self, bytes: uint) -> TokenizerOptsBuilder {
+        self.opts.input_size_hint = Some(bytes);
+        self
+    }
+
+    pub fn build(self) -> TokenizerOpts {
+        self.opts
+    }
+}
+
+/// Buffers which a tokenizer has finished with, for the next one to
+/// reuse rather than growing its own.  This is for parsing many
+/// documents in turn, as `BatchParser` does.  Nothing in the buffers
+/// carries over from one document to the next but their capacity.
+pub struct TokenizerBuffers {
+    input_buffers: BufferQueue,
+    temp_buf: TempBuf,
+    lines: LineTable,
+    char_ref_tokenizer: Option<Box<CharRefTokenizer>>,
+    tag_name: String,
+    attr_name: String,
+    template_starts: Vec<u8>,
+}
+
+impl TokenizerBuffers {
+    /// Buffers which haven't been used yet.
+    pub fn new() -> TokenizerBuffers {
+        TokenizerBuffers {
+            input_buffers: BufferQueue::new(),
+            temp_buf: TempBuf::new(),
+            lines: LineTable::new(0),
+            char_ref_tokenizer: None,
+            tag_name: empty_str(),
+            attr_name: empty_str(),
+            template_starts: vec!(),
         }
     }
 }
@@ -114,10 +440,23 @@ pub struct Tokenizer<Sink> {
     /// completely? This affects whether we will wait for lookahead or not.
     at_eof: bool,
 
+    /// Has the sink suspended tokenizing?
+    suspended: bool,
+
+    /// Input to insert at the insertion point when we resume.
+    pending_insertion: String,
+
+    /// Was `end` called while we were suspended?
+    end_pending: bool,
+
     /// Tokenizer for character references, if we're tokenizing
     /// one at the moment.
     char_ref_tokenizer: Option<Box<CharRefTokenizer>>,
 
+    /// A finished tokenizer for character references, whose box is
+    /// reused for the next one.
+    spare_char_ref_tokenizer: Option<Box<CharRefTokenizer>>,
+
     /// Current input character.  Just consumed, may reconsume.
     current_char: char,
 
@@ -144,34 +483,106 @@ pub struct Tokenizer<Sink> {
     /// Current tag attributes.
     current_tag_attrs: Vec<Attribute>,
 
+    /// Did we truncate the current tag or attribute name?
+    name_truncated: bool,
+
     /// Current attribute name.
     current_attr_name: String,
 
     /// Current attribute value.
     current_attr_value: String,
 
+    /// Did we truncate the current attribute value?
+    attr_value_truncated: bool,
+
+    /// Does the current attribute value contain template syntax?
+    current_attr_has_template: bool,
+
     /// Current comment.
     current_comment: String,
 
     /// Current doctype token.
     current_doctype: Doctype,
 
+    /// Text of the current template passthrough.
+    current_passthrough: String,
+
     /// Last start tag name, for use in checking "appropriate end tag".
     last_start_tag_name: Option<Atom>,
 
-    /// The "temporary buffer" mentioned in the spec.
-    temp_buf: String,
+    /// The "temporary buffer" mentioned in the spec.  It holds at most
+    /// the last start tag name plus one letter, so it only allocates
+    /// for very long names.
+    temp_buf: TempBuf,
+
+    /// Bytes of character data emitted since the last non-character token.
+    text_len: uint,
+
+    /// Did we truncate the current run of character data?
+    text_truncated: bool,
+
+    /// Have we truncated anything because of a length limit?
+    truncated: bool,
+
+    /// Did we stop early because of `AbortOnDuplicate`?
+    aborted: bool,
+
+    /// Did we reach EOF inside some markup?
+    eof_truncated: bool,
 
     /// Record of how many ns we spent in each state, if profiling is enabled.
     state_profile: TreeMap<states::State, u64>,
 
     /// Record of how many ns we spent in the token sink.
     time_in_sink: u64,
+
+    /// How many strings and vectors we allocated for tokens.
+    allocations: u64,
+
+    /// The first bytes of the opening template delimiters, where a run
+    /// of text must stop.  These are never UTF-8 continuation bytes, so
+    /// a run still ends at a character boundary.
+    template_starts: Vec<u8>,
+
+    /// Offset of the current input character.
+    char_start: uint,
+
+    /// Offset where the next token starts, if tracking spans.
+    token_start: uint,
+
+    /// Span of the current tag name, until it's reported.
+    tag_name_span: Option<Span>,
+
+    /// Span of the current attribute name, until it's reported.
+    attr_name_span: Option<Span>,
+
+    /// Span of the current attribute value, until it's reported.
+    attr_value_span: Option<Span>,
+
+    /// Offset of the `&` starting the current character reference.
+    char_ref_start: uint,
+
+    /// Where each line starts, if tracking lines.
+    lines: LineTable,
+
+    /// Decoder for `feed_bytes`, once there is one.
+    decoder: Option<Box<Decoder + Send>>,
+
+    /// The first few bytes from `feed_bytes`, until we know whether
+    /// they start with a byte order mark.
+    bom_bytes: Option<Vec<u8>>,
 }
 
 impl<Sink: TokenSink> Tokenizer<Sink> {
     /// Create a new tokenizer which feeds tokens to a particular `TokenSink`.
-    pub fn new(sink: Sink, mut opts: TokenizerOpts) -> Tokenizer<Sink> {
+    pub fn new(sink: Sink, opts: TokenizerOpts) -> Tokenizer<Sink> {
+        Tokenizer::new_with_buffers(sink, opts, TokenizerBuffers::new())
+    }
+
+    /// Create a new tokenizer which reuses the buffers of a finished
+    /// one, from `unwrap_with_buffers`.
+    pub fn new_with_buffers(sink: Sink, mut opts: TokenizerOpts, buffers: TokenizerBuffers)
+            -> Tokenizer<Sink> {
         if opts.profile && cfg!(for_c) {
             fail!("Can't profile tokenizer when built as a C library");
         }
@@ -180,29 +591,71 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
             .map(|s| Atom::from_slice(s.as_slice()));
         let state = *opts.initial_state.as_ref().unwrap_or(&states::Data);
         let discard_bom = opts.discard_bom;
+        let offset = opts.span_offset;
+        let TokenizerBuffers {
+            mut input_buffers, mut temp_buf, mut lines, char_ref_tokenizer,
+            mut tag_name, mut attr_name, mut template_starts,
+        } = buffers;
+        template_starts.truncate(0);
+        for &(ref open, _) in opts.template_delimiters.iter() {
+            match open.as_bytes().head() {
+                Some(&b) => template_starts.push(b),
+                None => (),
+            }
+        }
+        let size_hint = opts.input_size_hint.unwrap_or(0);
+        input_buffers.reset(offset, size_hint);
+        lines.reset(offset, if opts.track_lines { size_hint } else { 0 });
+        temp_buf.reset();
+        tag_name.truncate(0);
+        attr_name.truncate(0);
         Tokenizer {
             opts: opts,
             sink: sink,
             state: state,
             char_ref_tokenizer: None,
-            input_buffers: BufferQueue::new(),
+            spare_char_ref_tokenizer: char_ref_tokenizer,
+            input_buffers: input_buffers,
             at_eof: false,
+            suspended: false,
+            pending_insertion: empty_str(),
+            end_pending: false,
             current_char: '\0',
             reconsume: false,
             ignore_lf: false,
             discard_bom: discard_bom,
             current_tag_kind: StartTag,
-            current_tag_name: empty_str(),
+            current_tag_name: tag_name,
             current_tag_self_closing: false,
             current_tag_attrs: vec!(),
-            current_attr_name: empty_str(),
+            name_truncated: false,
+            current_attr_name: attr_name,
             current_attr_value: empty_str(),
+            attr_value_truncated: false,
+            current_attr_has_template: false,
             current_comment: empty_str(),
             current_doctype: Doctype::new(),
+            current_passthrough: empty_str(),
             last_start_tag_name: start_tag_name,
-            temp_buf: empty_str(),
+            temp_buf: temp_buf,
+            text_len: 0,
+            text_truncated: false,
+            truncated: false,
+            aborted: false,
+            eof_truncated: false,
             state_profile: TreeMap::new(),
             time_in_sink: 0,
+            allocations: 0,
+            template_starts: template_starts,
+            char_start: offset,
+            token_start: offset,
+            tag_name_span: None,
+            attr_name_span: None,
+            attr_value_span: None,
+            char_ref_start: offset,
+            lines: lines,
+            decoder: None,
+            bom_bytes: Some(vec!()),
         }
     }
 
@@ -210,6 +663,38 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         self.sink
     }
 
+    /// Like `unwrap`, but also return the options, and the buffers for
+    /// `new_with_buffers` to reuse.
+    pub fn unwrap_with_buffers(self) -> (Sink, TokenizerOpts, TokenizerBuffers) {
+        let Tokenizer {
+            sink, opts, input_buffers, temp_buf, lines, char_ref_tokenizer,
+            spare_char_ref_tokenizer, current_tag_name, current_attr_name,
+            template_starts, ..
+        } = self;
+        let buffers = TokenizerBuffers {
+            input_buffers: input_buffers,
+            temp_buf: temp_buf,
+            lines: lines,
+            char_ref_tokenizer: spare_char_ref_tokenizer.or(char_ref_tokenizer),
+            tag_name: current_tag_name,
+            attr_name: current_attr_name,
+            template_starts: template_starts,
+        };
+        (sink, opts, buffers)
+    }
+
+    /// How many strings and vectors has the tokenizer allocated for
+    /// tokens so far?  This counts the buffers which make up the
+    /// tokens, e.g. a run of characters or an attribute list, but not
+    /// what the sink does with them.  Once the tokenizer's own buffers
+    /// have grown, a run of tags without attributes or text allocates
+    /// nothing.
+    ///
+    /// Counting costs an integer add, so it's always on.
+    pub fn allocations(&self) -> u64 {
+        self.allocations
+    }
+
     pub fn sink<'a>(&'a self) -> &'a Sink {
         &self.sink
     }
@@ -218,29 +703,252 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         &mut self.sink
     }
 
+    /// Has any content been truncated because of one of the length
+    /// limits in `TokenizerOpts`?
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Did the input end inside a tag, comment, doctype or other
+    /// markup?  Only known after `end()`.
+    pub fn eof_truncated(&self) -> bool {
+        self.eof_truncated
+    }
+
+    /// Did we stop tokenizing early because of a duplicate attribute,
+    /// with the `AbortOnDuplicate` policy?
+    pub fn aborted(&self) -> bool {
+        self.aborted
+    }
+
+    /// The tokenizer's current state.
+    pub fn state(&self) -> states::State {
+        self.state
+    }
+
+    /// The kind of the tag being tokenized.  Only meaningful in the
+    /// tag name and attribute states.
+    pub fn current_tag_kind(&self) -> TagKind {
+        self.current_tag_kind
+    }
+
+    /// The name of the tag being tokenized, lowercased, as far as
+    /// we've read it.  Only meaningful in the tag name and attribute
+    /// states.
+    pub fn current_tag_name<'a>(&'a self) -> &'a str {
+        self.current_tag_name.as_slice()
+    }
+
+    /// The name of the attribute being tokenized, lowercased, as far
+    /// as we've read it.  Only meaningful from the attribute name
+    /// state up to the end of the attribute's value.
+    pub fn current_attr_name<'a>(&'a self) -> &'a str {
+        self.current_attr_name.as_slice()
+    }
+
     /// Feed an input string into the tokenizer.
     pub fn feed(&mut self, input: String) {
-        if input.len() == 0 {
+        self.feed_from(input, 0);
+    }
+
+    /// Feed the part of `input` from byte `start` on.  The bytes before
+    /// `start` aren't input at all, so nothing is copied and they don't
+    /// count towards offsets.  With `span_offset` set to `start`, spans
+    /// are offsets into the whole of `input`.
+    ///
+    /// ## Failure
+    ///
+    /// Fails if `start` isn't at a character boundary.
+    pub fn feed_from(&mut self, input: String, start: uint) {
+        if start >= input.len() || self.aborted {
             return;
         }
 
-        let pos = if self.discard_bom && input.as_slice().char_at(0) == '\ufeff' {
+        let skip = if self.discard_bom && input.as_slice().char_at(start) == '\ufeff' {
             self.discard_bom = false;
             3  // length of BOM in UTF-8
         } else {
             0
         };
 
-        self.input_buffers.push_back(input, pos);
+        if self.opts.track_lines {
+            self.lines.add(input.as_slice().slice_from(start), skip);
+        }
+        self.input_buffers.push_back_from(input, start, skip);
         self.run();
     }
 
+    /// Use `decoder` for `feed_bytes`, instead of UTF-8.  Call this
+    /// before feeding any bytes.  A byte order mark still takes
+    /// precedence.
+    pub fn set_decoder(&mut self, decoder: Box<Decoder + Send>) {
+        self.decoder = Some(decoder);
+    }
+
+    /// The encoding `feed_bytes` is decoding from, once it knows, or
+    /// `None` if only text has been fed in.  A decoder from
+    /// `set_decoder` may not have a name.
+    pub fn encoding_used(&self) -> Option<&'static str> {
+        self.decoder.as_ref().and_then(|d| d.encoding())
+    }
+
+    /// Feed bytes into the tokenizer, decoding them as UTF-8 or with
+    /// the decoder from `set_decoder`.  A character may be split
+    /// between calls.  Invalid bytes become U+FFFD, which isn't a parse
+    /// error.  Spans and positions are offsets into the decoded text.
+    ///
+    /// If the bytes start with a UTF-8, UTF-16LE or UTF-16BE byte order
+    /// mark, it's removed and the matching decoder is used instead.  The
+    /// first three bytes are held back until this is known.
+    pub fn feed_bytes(&mut self, input: Vec<u8>) {
+        let input = match self.bom_bytes.take() {
+            None => input,
+            Some(mut start) => {
+                start.push_all(input.as_slice());
+                if start.len() < 3 {
+                    self.bom_bytes = Some(start);
+                    return;
+                }
+                self.strip_bom(start)
+            }
+        };
+        self.decode_bytes(input.as_slice());
+    }
+
+    fn strip_bom(&mut self, bytes: Vec<u8>) -> Vec<u8> {
+        match sniff_bom(bytes.as_slice()) {
+            Some((encoding, len)) => {
+                self.decoder = decoder_for(encoding);
+                // A U+FEFF after the BOM is content.
+                self.discard_bom = false;
+                bytes.slice_from(len).to_vec()
+            }
+            None => bytes,
+        }
+    }
+
+    fn decode_bytes(&mut self, input: &[u8]) {
+        if self.decoder.is_none() {
+            self.decoder = Some(box Utf8Decoder::new() as Box<Decoder + Send>);
+        }
+        let mut text = String::with_capacity(input.len());
+        self.decoder.as_mut().unwrap().decode(input, &mut text);
+        self.feed(text);
+    }
+
+    /// Insert input at the insertion point, ahead of any input which
+    /// hasn't been tokenized yet, as `document.write` does.  While the
+    /// tokenizer is suspended the input waits for `resume`, and input
+    /// from successive calls stays in order.  Otherwise it's tokenized
+    /// straight away.
+    ///
+    /// Inserted input isn't part of the source, so tokens from it get
+    /// empty spans at the insertion point.
+    pub fn insert(&mut self, input: String) {
+        if input.len() == 0 || self.aborted {
+            return;
+        }
+
+        if self.suspended {
+            self.pending_insertion.push_str(input.as_slice());
+        } else {
+            self.input_buffers.insert_front(input);
+            self.run();
+        }
+    }
+
+    /// Has the sink suspended tokenizing, through `query_suspend`?
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Continue tokenizing after the sink suspended it, starting with
+    /// any input from `insert`.  If `end` was called meanwhile, the end
+    /// of input is processed once the rest of the input has been,
+    /// unless the sink suspends tokenizing again first.
+    pub fn resume(&mut self) {
+        if !self.suspended {
+            return;
+        }
+        self.suspended = false;
+
+        let input = replace(&mut self.pending_insertion, empty_str());
+        self.input_buffers.insert_front(input);
+        if self.end_pending {
+            self.end_pending = false;
+            self.end();
+        } else {
+            self.run();
+        }
+    }
+
     fn process_token(&mut self, token: Token) {
+        let token = match token {
+            CharacterTokens(b) => match self.limit_text(b) {
+                Some(b) => if self.opts.whitespace_tokens
+                        && b.as_slice().chars().all(is_ascii_whitespace) {
+                    WhitespaceTokens(b)
+                } else {
+                    CharacterTokens(b)
+                },
+                None => return,
+            },
+            t @ NullCharacterToken | t @ ParseError(_) => t,
+            t => {
+                self.text_len = 0;
+                self.text_truncated = false;
+                t
+            }
+        };
+
+        let span = if self.opts.track_spans {
+            Some(self.token_span(&token))
+        } else {
+            None
+        };
+
         if self.opts.profile {
-            let (_, dt) = time!(self.sink.process_token(token));
+            let (_, dt) = time!(self.send_token(token, span));
             self.time_in_sink += dt;
         } else {
-            self.sink.process_token(token);
+            self.send_token(token, span);
+        }
+    }
+
+    fn send_token(&mut self, token: Token, span: Option<Span>) {
+        match span {
+            Some(span) => self.sink.process_token_with_span(token, span),
+            None => self.sink.process_token(token),
+        }
+    }
+
+    // Offset of the next input character, which is the current
+    // character if we're going to reconsume it.
+    fn position(&self) -> uint {
+        if self.reconsume {
+            self.char_start
+        } else {
+            self.input_buffers.offset()
+        }
+    }
+
+    // The span of a token we're about to emit, which ends at the
+    // current position.
+    fn token_span(&mut self, token: &Token) -> Span {
+        let end = self.position();
+        match *token {
+            ParseError(_) => Span { start: end, end: end },
+            _ => {
+                let start = replace(&mut self.token_start, end);
+                Span { start: start, end: end }
+            }
+        }
+    }
+
+    fn report_part(&mut self, part: TokenPart, span: Option<Span>) {
+        match span {
+            Some(span) => self.sink.process_token_part(part, span),
+            None => (),
         }
     }
 
@@ -251,6 +959,11 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         if self.ignore_lf {
             self.ignore_lf = false;
             if c == '\n' {
+                // If no token has started since the \r, start the
+                // next one after this \n.
+                if self.token_start + 1 == self.input_buffers.offset() {
+                    self.token_start += 1;
+                }
                 c = unwrap_or_return!(self.input_buffers.next(), None);
             }
         }
@@ -265,14 +978,12 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
             n if (n & 0xFFFE) == 0xFFFE => true,
             _ => false,
         } {
-            // format_if!(true) will still use the static error when built for C.
-            let msg = format_if!(true, "Bad character",
-                "Bad character {}", c);
-            self.emit_error(msg);
+            self.emit_error(BadCharacter(c));
         }
 
         h5e_debug!("got character {}", c);
         self.current_char = c;
+        self.char_start = self.input_buffers.offset() - c.len_utf8_bytes();
         Some(c)
     }
 
@@ -302,6 +1013,12 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         match d {
             Some(FromSet(c)) => self.get_preprocessed_char(c).map(|x| FromSet(x)),
 
+            // The buffer queue copied the run into a new string.
+            Some(NotFromSet(_)) => {
+                self.allocations += 1;
+                d
+            }
+
             // NB: We don't set self.current_char for a run of characters not
             // in the set.  It shouldn't matter for the codepaths that use
             // this.
@@ -309,6 +1026,97 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         }
     }
 
+    // The fast path of the data, RCDATA and attribute value states:
+    // take the run of characters before the next one in `set`, or
+    // before anything which could start a template delimiter, straight
+    // from the input.  In an attribute value the run is appended without
+    // copying it first.  Otherwise it's emitted as text.  Returns false
+    // if there's no such run, and the caller should take one character.
+    fn take_run(&mut self, set: SmallCharSet) -> bool {
+        // As for `pop_except_from`.
+        if self.opts.exact_errors || self.reconsume || self.ignore_lf {
+            return false;
+        }
+
+        let (in_attr, templates) = match self.state {
+            states::AttributeValue(_) => (true, self.templates_enabled()),
+            states::Data => (false, self.templates_enabled()),
+            _ => (false, false),
+        };
+        // Only as much of the run as the length limits leave room for
+        // is copied, so a huge value or run of text is never held.
+        let room = if in_attr {
+            match self.opts.max_attr_value_len {
+                _ if self.attr_value_truncated => Some(0),
+                Some(max) => Some(max - self.current_attr_value.len()),
+                None => None,
+            }
+        } else {
+            match self.opts.max_text_len {
+                _ if self.text_truncated => Some(0),
+                Some(max) => Some(max - self.text_len),
+                None => None,
+            }
+        };
+        if in_attr {
+            self.size_attr_value(0);
+        }
+        let (text, over) = {
+            let starts = self.template_starts.as_slice();
+            let run = match self.input_buffers.take_until(
+                    |b| set.contains(b) || (templates && starts.contains(&b))) {
+                None | Some("") => return false,
+                Some(run) => run,
+            };
+            h5e_debug!("got characters {}", run);
+            let part = match room {
+                Some(room) => prefix_within(run, room),
+                None => run,
+            };
+            let over = part.len() < run.len();
+            if in_attr {
+                let cap = self.current_attr_value.capacity();
+                self.current_attr_value.push_str(part);
+                if self.current_attr_value.capacity() != cap {
+                    self.allocations += 1;
+                }
+                (None, over)
+            } else if part.is_empty() {
+                (None, over)
+            } else {
+                (Some(String::from_str(part)), over)
+            }
+        };
+
+        if in_attr {
+            if over {
+                self.attr_value_too_long();
+            }
+            self.end_attr_value();
+            return true;
+        }
+
+        // The error comes before the text which was kept, as it does
+        // from `limit_text`.
+        let first_over = over && !self.text_truncated;
+        if first_over {
+            let max = self.opts.max_text_len.unwrap();
+            self.emit_error(TextTooLong(max));
+        }
+        match text {
+            Some(b) => {
+                self.allocations += 1;
+                self.emit_chars(b);
+            }
+            None => (),
+        }
+        if first_over {
+            self.text_truncated = true;
+            self.truncated = true;
+        }
+        true
+    }
+
     // Check if the next characters are an ASCII case-insensitive match.  See
     // BufferQueue::eat.
     //
@@ -321,6 +1129,13 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         }
     }
 
+    fn eat_exact(&mut self, pat: &str) -> Option<bool> {
+        match self.input_buffers.eat_exact(pat) {
+            None if self.at_eof => Some(false),
+            r => r,
+        }
+    }
+
     // Run the state machine for as long as we can.
     fn run(&mut self) {
         if self.opts.profile {
@@ -348,39 +1163,164 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         }
     }
 
+    // Apply `max_text_len` to some character data we're about to emit.
+    fn limit_text(&mut self, mut b: String) -> Option<String> {
+        let max = unwrap_or_return!(self.opts.max_text_len, Some(b));
+        if self.text_truncated {
+            return None;
+        }
+
+        if self.text_len + b.len() > max {
+            truncate_to(&mut b, max - self.text_len);
+            self.text_truncated = true;
+            self.truncated = true;
+            self.emit_error(TextTooLong(max));
+        }
+
+        self.text_len += b.len();
+        if b.is_empty() { None } else { Some(b) }
+    }
+
+    // With a size hint, size a new attribute value's buffer for the
+    // whole value, as far as it's in the front input chunk, plus `extra`
+    // bytes already consumed.  Then the runs after a character reference
+    // don't grow it again.
+    fn size_attr_value(&mut self, extra: uint) {
+        if self.opts.input_size_hint.is_none() || self.current_attr_value.capacity() != 0 {
+            return;
+        }
+        // An unquoted value's first character is pushed before we
+        // switch to its state.
+        let span = extra + match self.state {
+            states::AttributeValue(DoubleQuoted)
+                => self.input_buffers.span_until(|b| b == b'"'),
+            states::AttributeValue(SingleQuoted)
+                => self.input_buffers.span_until(|b| b == b'\''),
+            _ => self.input_buffers.span_until(|b| match b {
+                b'\t' | b'\n' | b'\x0C' | b' ' | b'>' => true,
+                _ => false,
+            }),
+        };
+        let span = match self.opts.max_attr_value_len {
+            Some(max) if max < span => max,
+            _ => span,
+        };
+        if span > 0 {
+            self.current_attr_value.reserve(span);
+            self.allocations += 1;
+        }
+    }
+
+    // Once a value is truncated, the rest of it is dropped, even if
+    // a shorter character would still fit.
+    fn push_attr_value(&mut self, c: char) {
+        if !self.attr_value_truncated {
+            self.size_attr_value(c.len_utf8_bytes());
+            let cap = self.current_attr_value.capacity();
+            self.current_attr_value.push(c);
+            if self.current_attr_value.capacity() != cap {
+                self.allocations += 1;
+            }
+            self.limit_attr_value();
+        }
+        self.end_attr_value();
+    }
+
+    fn append_attr_value(&mut self, b: String) {
+        if !self.attr_value_truncated {
+            append_strings(&mut self.current_attr_value, b);
+            self.limit_attr_value();
+        }
+        self.end_attr_value();
+    }
+
+    // The current character, which may be a quote, starts an
+    // attribute value.
+    fn start_attr_value(&mut self) {
+        if self.opts.track_spans {
+            let name = self.attr_name_span.take();
+            self.report_part(AttrNamePart, name);
+            let end = self.position();
+            self.attr_value_span = Some(Span { start: self.char_start, end: end });
+        }
+    }
+
+    // The attribute value extends to the current position.
+    fn end_attr_value(&mut self) {
+        if self.opts.track_spans {
+            let end = self.position();
+            extend_span(&mut self.attr_value_span, end, end);
+        }
+    }
+
+    // Apply `max_attr_value_len` to the current attribute value.
+    fn limit_attr_value(&mut self) {
+        let max = unwrap_or_return!(self.opts.max_attr_value_len, ());
+        if self.current_attr_value.len() <= max {
+            return;
+        }
+
+        truncate_to(&mut self.current_attr_value, max);
+        self.attr_value_too_long();
+    }
+
+    // Report that the current attribute value was cut off at
+    // `max_attr_value_len`, if we haven't yet.
+    fn attr_value_too_long(&mut self) {
+        if !self.attr_value_truncated {
+            self.attr_value_truncated = true;
+            self.truncated = true;
+            let name = self.current_attr_name.clone();
+            let max = self.opts.max_attr_value_len.unwrap();
+            self.emit_error(AttrValueTooLong(name, max));
+        }
+    }
+
     fn bad_char_error(&mut self) {
-        let msg = format_if!(
-            self.opts.exact_errors,
-            "Bad character",
-            "Saw {} in state {}", self.current_char, self.state);
-        self.emit_error(msg);
+        let (c, state) = (self.current_char, self.state);
+        self.emit_error(UnexpectedCharacter(c, state));
     }
 
     fn bad_eof_error(&mut self) {
-        let msg = format_if!(
-            self.opts.exact_errors,
-            "Unexpected EOF",
-            "Saw EOF in state {}", self.state);
-        self.emit_error(msg);
+        self.eof_truncated = true;
+        if self.opts.truncated_input {
+            return;
+        }
+        let state = self.state;
+        self.emit_error(UnexpectedEof(state));
     }
 
     fn emit_char(&mut self, c: char) {
+        if self.opts.discard_text {
+            return;
+        }
         self.process_token(match c {
             '\0' => NullCharacterToken,
-            _ => CharacterTokens(String::from_char(1, c)),
+            _ => {
+                self.allocations += 1;
+                CharacterTokens(String::from_char(1, c))
+            }
         });
     }
 
     // The string must not contain '\0'!
     fn emit_chars(&mut self, b: String) {
+        if self.opts.discard_text {
+            return;
+        }
         self.process_token(CharacterTokens(b));
     }
 
     fn emit_current_tag(&mut self) {
+        self.finish_tag_name();
         self.finish_attribute();
+        if self.aborted {
+            return;
+        }
 
-        let name = replace(&mut self.current_tag_name, String::new());
-        let name = Atom::from_slice(name.as_slice());
+        // Keep the buffer for the next tag.
+        let name = Atom::from_slice(self.current_tag_name.as_slice());
+        self.current_tag_name.truncate(0);
 
         match self.current_tag_kind {
             StartTag => {
@@ -388,10 +1328,10 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
             }
             EndTag => {
                 if !self.current_tag_attrs.is_empty() {
-                    self.emit_error(Slice("Attributes on an end tag"));
+                    self.emit_error(EndTagWithAttributes);
                 }
                 if self.current_tag_self_closing {
-                    self.emit_error(Slice("Self-closing end tag"));
+                    self.emit_error(SelfClosingEndTag);
                 }
             }
         }
@@ -409,34 +1349,175 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
                 Some(s) => self.state = s,
             }
         }
+        // A self-closing SVG <script/> completes a script too.
+        if self.sink.query_suspend() {
+            self.suspended = true;
+        }
+    }
+
+    // A new buffer to replace one whose contents we're emitting.
+    fn new_buffer(&mut self) -> String {
+        self.allocations += 1;
+        empty_str()
     }
 
     fn emit_temp_buf(&mut self) {
+        if self.temp_buf.is_empty() {
+            return;
+        }
         // FIXME: Make sure that clearing on emit is spec-compatible.
-        let buf = replace(&mut self.temp_buf, empty_str());
+        self.allocations += 1;
+        let buf = self.temp_buf.take();
         self.emit_chars(buf);
     }
 
     fn clear_temp_buf(&mut self) {
-        // Do this without a new allocation.
-        self.temp_buf.truncate(0);
+        self.temp_buf.clear();
+    }
+
+    fn emit_cdata(&mut self) {
+        if self.current_comment.is_empty() {
+            return;
+        }
+        let fresh = self.new_buffer();
+        let text = replace(&mut self.current_comment, fresh);
+        self.emit_chars(text);
     }
 
     fn emit_current_comment(&mut self) {
-        let comment = replace(&mut self.current_comment, empty_str());
+        let fresh = self.new_buffer();
+        let comment = replace(&mut self.current_comment, fresh);
         self.process_token(CommentToken(comment));
     }
 
+    fn emit_current_passthrough(&mut self) {
+        let fresh = self.new_buffer();
+        let text = replace(&mut self.current_passthrough, fresh);
+        self.process_token(PassthroughToken(text));
+    }
+
+    fn emit_current_processing_instruction(&mut self) {
+        let fresh = self.new_buffer();
+        let text = replace(&mut self.current_comment, fresh);
+        let (target, data) = {
+            let s = text.as_slice();
+            let end = s.find(|c: char| is_ascii_whitespace(c)).unwrap_or(s.len());
+            let data = s.slice_from(end).trim_left_chars(|c: char| is_ascii_whitespace(c));
+            (String::from_str(s.slice_to(end)), String::from_str(data))
+        };
+        self.process_token(ProcessingInstructionToken(target, data));
+    }
+
+    fn is_template_start(&self, c: char) -> bool {
+        self.opts.template_delimiters.iter()
+            .any(|&(ref open, _)| open.as_slice().chars().next() == Some(c))
+    }
+
+    // Should we look for template delimiters in the current state?
+    fn templates_enabled(&self) -> bool {
+        !self.opts.template_delimiters.is_empty() && match self.state {
+            states::AttributeValue(_) => self.opts.template_attr_values,
+            _ => true,
+        }
+    }
+
+    // Look for an opening template delimiter at the current position,
+    // and consume it if found.  Returns the index of the delimiter pair,
+    // or `None` inside the `Option` if we need more input to decide.
+    fn open_template(&mut self) -> Option<Option<uint>> {
+        if self.reconsume {
+            if !self.is_template_start(self.current_char) {
+                return Some(None);
+            }
+            self.reconsume = false;
+            self.unconsume(String::from_char(1, self.current_char));
+        }
+
+        let mut found = None;
+        for (i, &(ref open, _)) in self.opts.template_delimiters.iter().enumerate() {
+            if open.is_empty() {
+                continue;
+            }
+            match self.input_buffers.eat(open.as_slice()) {
+                Some(true) => {
+                    found = Some(i);
+                    break;
+                }
+                None if !self.at_eof => return None,
+                _ => (),
+            }
+        }
+
+        let i = unwrap_or_return!(found, Some(None));
+        let open = match self.opts.template_delimiters[i] {
+            (ref open, _) => open.clone(),
+        };
+        self.ignore_lf = false;
+        match self.state {
+            states::AttributeValue(_) => {
+                self.current_attr_has_template = true;
+                self.append_attr_value(open);
+            }
+            _ => self.current_passthrough = open,
+        }
+        Some(Some(i))
+    }
+
+    // Check for an opening template delimiter at the top of the loop
+    // in a state which allows them.  Returns `Some(r)` if `step` should
+    // return `r`.
+    fn step_template_open(&mut self) -> Option<bool> {
+        if !self.templates_enabled() {
+            return None;
+        }
+        match self.open_template() {
+            None => Some(false),
+            Some(None) => None,
+            Some(Some(i)) => {
+                self.state = match self.state {
+                    states::AttributeValue(kind) => states::AttributeValueTemplate(kind, i),
+                    _ => states::Passthrough(i),
+                };
+                Some(true)
+            }
+        }
+    }
+
+    // The closing delimiter for the template delimiter pair `i`.
+    fn template_close(&self, i: uint) -> String {
+        match self.opts.template_delimiters[i] {
+            (_, ref close) => close.clone(),
+        }
+    }
+
     fn discard_tag(&mut self) {
-        self.current_tag_name = String::new();
+        self.name_truncated = false;
+        self.current_tag_name.truncate(0);
         self.current_tag_self_closing = false;
         self.current_tag_attrs = vec!();
+        self.tag_name_span = None;
+        self.attr_name_span = None;
+        self.attr_value_span = None;
     }
 
     fn create_tag(&mut self, kind: TagKind, c: char) {
         self.discard_tag();
         self.current_tag_name.push(c);
         self.current_tag_kind = kind;
+        if self.opts.track_spans {
+            let end = self.position();
+            self.tag_name_span = Some(Span { start: self.char_start, end: end });
+        }
+    }
+
+    // Report the span of the tag name, if we haven't yet.
+    fn finish_tag_name(&mut self) {
+        let span = self.tag_name_span.take();
+        self.report_part(TagNamePart, span);
+    }
+
+    fn last_start_tag_len(&self) -> uint {
+        self.last_start_tag_name.as_ref().map_or(0, |n| n.as_slice().len())
     }
 
     fn have_appropriate_end_tag(&self) -> bool {
@@ -449,12 +1530,65 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
     }
 
     fn create_attribute(&mut self, c: char) {
+        self.finish_tag_name();
         self.finish_attribute();
 
+        self.name_truncated = false;
         self.current_attr_name.push(c);
+        if self.opts.track_spans {
+            let end = self.position();
+            self.attr_name_span = Some(Span { start: self.char_start, end: end });
+        }
+    }
+
+    fn push_tag_name(&mut self, c: char) {
+        if self.opts.track_spans {
+            let end = self.position();
+            extend_span(&mut self.tag_name_span, end, end);
+        }
+        let len = self.current_tag_name.len();
+        if self.name_fits(len, c) {
+            self.current_tag_name.push(c);
+        }
+    }
+
+    fn push_attr_name(&mut self, c: char) {
+        if self.opts.track_spans {
+            let end = self.position();
+            extend_span(&mut self.attr_name_span, end, end);
+        }
+        let len = self.current_attr_name.len();
+        if self.name_fits(len, c) {
+            self.current_attr_name.push(c);
+        }
+    }
+
+    // Apply `max_name_len` before adding a character to a name.  Once
+    // a name is cut off, the rest of it is dropped, even characters
+    // which would fit.
+    fn name_fits(&mut self, len: uint, c: char) -> bool {
+        let max = unwrap_or_return!(self.opts.max_name_len, true);
+        if self.name_truncated {
+            return false;
+        }
+        if len + c.len_utf8_bytes() <= max {
+            return true;
+        }
+
+        self.name_truncated = true;
+        self.truncated = true;
+        self.emit_error(NameTooLong(max));
+        false
     }
 
     fn finish_attribute(&mut self) {
+        let name_span = self.attr_name_span.take();
+        self.report_part(AttrNamePart, name_span);
+        let value_span = self.attr_value_span.take();
+        self.report_part(AttrValuePart, value_span);
+
+        self.attr_value_truncated = false;
+        let has_template = replace(&mut self.current_attr_has_template, false);
         if self.current_attr_name.len() == 0 {
             return;
         }
@@ -464,24 +1598,55 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         // FIXME: linear time search, do we care?
         let dup = {
             let name = self.current_attr_name.as_slice();
-            self.current_tag_attrs.iter().any(|a| a.name.local.as_slice() == name)
+            self.current_tag_attrs.iter().position(|a| a.name.local.as_slice() == name)
         };
 
-        if dup {
-            self.emit_error(Slice("Duplicate attribute"));
-            self.current_attr_name.truncate(0);
-            self.current_attr_value.truncate(0);
-        } else {
-            let name = replace(&mut self.current_attr_name, String::new());
-            self.current_tag_attrs.push(Attribute {
-                // The tree builder will adjust the namespace if necessary.
-                // This only happens in foreign elements.
-                name: QualName::new(ns!(""), Atom::from_slice(name.as_slice())),
-                value: replace(&mut self.current_attr_value, empty_str()),
-            });
+        if dup.is_some() {
+            self.emit_error(DuplicateAttribute);
+        }
+
+        match (dup, self.opts.duplicate_attrs) {
+            (Some(_), KeepFirst) => {
+                self.current_attr_name.truncate(0);
+                self.current_attr_value.truncate(0);
+            }
+            (Some(i), KeepLast) => {
+                self.current_attr_name.truncate(0);
+                let value = replace(&mut self.current_attr_value, String::new());
+                let attr = self.current_tag_attrs.get_mut(i);
+                attr.value = value;
+                attr.has_template = has_template;
+            }
+            (Some(_), AbortOnDuplicate) => self.abort(),
+            (None, _) | (Some(_), KeepAll) => {
+                // Keep the name buffer for the next attribute.
+                let name = Atom::from_slice(self.current_attr_name.as_slice());
+                self.current_attr_name.truncate(0);
+                if self.current_tag_attrs.is_empty() {
+                    self.allocations += 1;
+                }
+                self.current_tag_attrs.push(Attribute {
+                    // The tree builder will adjust the namespace if necessary.
+                    // This only happens in foreign elements.
+                    name: QualName::new(ns!(""), name),
+                    // Don't allocate here: the next value's buffer is
+                    // allocated when its first run of characters arrives.
+                    value: replace(&mut self.current_attr_value, String::new()),
+                    has_template: has_template,
+                });
+            }
         }
     }
 
+    // Stop tokenizing, for `AbortOnDuplicate`.  The rest of the input
+    // is discarded, and `end()` will emit only an `EOFToken`.
+    fn abort(&mut self) {
+        self.aborted = true;
+        self.input_buffers = BufferQueue::new();
+        self.char_ref_tokenizer = None;
+        self.reconsume = false;
+    }
+
     fn emit_current_doctype(&mut self) {
         let doctype = replace(&mut self.current_doctype, Doctype::new());
         self.process_token(DoctypeToken(doctype));
@@ -505,7 +1670,14 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
     fn consume_char_ref(&mut self, addnl_allowed: Option<char>) {
         // NB: The char ref tokenizer assumes we have an additional allowed
         // character iff we're tokenizing in an attribute value.
-        self.char_ref_tokenizer = Some(box CharRefTokenizer::new(addnl_allowed));
+        self.char_ref_tokenizer = match self.spare_char_ref_tokenizer.take() {
+            Some(mut tok) => {
+                *tok = CharRefTokenizer::new(addnl_allowed);
+                Some(tok)
+            }
+            None => Some(box CharRefTokenizer::new(addnl_allowed)),
+        };
+        self.char_ref_start = self.char_start;
     }
 
     fn emit_eof(&mut self) {
@@ -529,8 +1701,18 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         self.input_buffers.push_front(buf);
     }
 
-    fn emit_error(&mut self, error: MaybeOwned<'static>) {
-        self.process_token(ParseError(error));
+    fn emit_error(&mut self, code: ErrorCode) {
+        match self.opts.min_error_severity {
+            Some(min) if code.severity() >= min => (),
+            _ => return,
+        }
+        let offset = self.position();
+        let pos = if self.opts.track_lines {
+            self.lines.position(offset)
+        } else {
+            Position { offset: offset, line: 0, column: 0 }
+        };
+        self.process_token(ParseError(TokenizerError { code: code, pos: pos }));
     }
 }
 //§ END
@@ -539,15 +1721,18 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
 macro_rules! shorthand (
     ( $me:expr : emit $c:expr                    ) => ( $me.emit_char($c);                                   );
     ( $me:expr : create_tag $kind:expr $c:expr   ) => ( $me.create_tag($kind, $c);                           );
-    ( $me:expr : push_tag $c:expr                ) => ( $me.current_tag_name.push($c);                       );
+    ( $me:expr : push_tag $c:expr                ) => ( $me.push_tag_name($c);                               );
     ( $me:expr : discard_tag                     ) => ( $me.discard_tag();                                   );
     ( $me:expr : push_temp $c:expr               ) => ( $me.temp_buf.push($c);                               );
     ( $me:expr : emit_temp                       ) => ( $me.emit_temp_buf();                                 );
     ( $me:expr : clear_temp                      ) => ( $me.clear_temp_buf();                                );
     ( $me:expr : create_attr $c:expr             ) => ( $me.create_attribute($c);                            );
-    ( $me:expr : push_name $c:expr               ) => ( $me.current_attr_name.push($c);                      );
-    ( $me:expr : push_value $c:expr              ) => ( $me.current_attr_value.push($c);                     );
-    ( $me:expr : append_value $c:expr            ) => ( append_strings(&mut $me.current_attr_value, $c);     );
+    ( $me:expr : push_name $c:expr               ) => ( $me.push_attr_name($c);                              );
+    ( $me:expr : push_value $c:expr              ) => ( $me.push_attr_value($c);                             );
+    ( $me:expr : append_value $c:expr            ) => ( $me.append_attr_value($c);                           );
+    ( $me:expr : start_value                     ) => ( $me.start_attr_value();                              );
+    ( $me:expr : end_value                       ) => ( $me.end_attr_value();                                );
+    ( $me:expr : put_back                        ) => ( $me.reconsume = true;                                );
     ( $me:expr : push_comment $c:expr            ) => ( $me.current_comment.push($c);                        );
     ( $me:expr : append_comment $c:expr          ) => ( $me.current_comment.push_str($c);                    );
     ( $me:expr : emit_comment                    ) => ( $me.emit_current_comment();                          );
@@ -558,6 +1743,9 @@ macro_rules! shorthand (
     ( $me:expr : clear_doctype_id $k:expr        ) => ( $me.clear_doctype_id($k);                            );
     ( $me:expr : force_quirks                    ) => ( $me.current_doctype.force_quirks = true;             );
     ( $me:expr : emit_doctype                    ) => ( $me.emit_current_doctype();                          );
+    ( $me:expr : emit_passthrough                ) => ( $me.emit_current_passthrough();                      );
+    ( $me:expr : emit_pi                         ) => ( $me.emit_current_processing_instruction();           );
+    ( $me:expr : emit_cdata                      ) => ( $me.emit_cdata();                                    );
     ( $me:expr : error                           ) => ( $me.bad_char_error();                                );
     ( $me:expr : error_eof                       ) => ( $me.bad_eof_error();                                 );
 )
@@ -629,15 +1817,36 @@ macro_rules! pop_except_from ( ($me:expr, $set:expr) => (
     unwrap_or_return!($me.pop_except_from($set), false)
 ))
 
+macro_rules! take_run ( ($me:expr, $set:expr) => (
+    if $me.take_run($set) {
+        continue;
+    }
+))
+
 macro_rules! eat ( ($me:expr, $pat:expr) => (
     unwrap_or_return!($me.eat($pat), false)
 ))
 
+macro_rules! eat_exact ( ($me:expr, $pat:expr) => (
+    unwrap_or_return!($me.eat_exact($pat), false)
+))
+
+macro_rules! template_open ( ($me:expr) => (
+    match $me.step_template_open() {
+        Some(r) => return r,
+        None => (),
+    }
+))
+
 impl<Sink: TokenSink> Tokenizer<Sink> {
     // Run the state machine for a while.
     // Return true if we should be immediately re-invoked
     // (this just simplifies control flow vs. break / continue).
     fn step(&mut self) -> bool {
+        if self.aborted || self.suspended {
+            return false;
+        }
+
         if self.char_ref_tokenizer.is_some() {
             return self.step_char_ref_tokenizer();
         }
@@ -646,23 +1855,61 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         match self.state {
             //§ data-state
             states::Data => loop {
-                match pop_except_from!(self, small_char_set!('\r' '\0' '&' '<')) {
-                    FromSet('\0') => go!(self: error; emit '\0'),
-                    FromSet('&')  => go!(self: consume_char_ref),
-                    FromSet('<')  => go!(self: to TagOpen),
-                    FromSet(c)    => go!(self: emit c),
-                    NotFromSet(b) => self.emit_chars(b),
+                template_open!(self);
+                take_run!(self, small_char_set!('\r' '\0' '&' '<'));
+                match get_char!(self) {
+                    '\0' => go!(self: error; emit '\0'),
+                    '&' if !self.opts.discard_text
+                         => go!(self: consume_char_ref),
+                    '<'  => go!(self: to TagOpen),
+                    c    => go!(self: emit c),
+                }
+            },
+
+            // Not in the spec: text between template delimiters.
+            states::Passthrough(i) => {
+                let close = self.template_close(i);
+                loop {
+                    if eat!(self, close.as_slice()) {
+                        self.current_passthrough.push_str(close.as_slice());
+                        go!(self: emit_passthrough; to Data);
+                    }
+                    let c = get_char!(self);
+                    self.current_passthrough.push(c);
+                }
+            },
+
+            // Not in the spec: a processing instruction, if enabled.
+            states::ProcessingInstruction => loop {
+                if eat!(self, "?>") {
+                    go!(self: emit_pi; to Data);
+                }
+                match get_char!(self) {
+                    '\0' => go!(self: error; push_comment '\ufffd'),
+                    c    => go!(self: push_comment c),
+                }
+            },
+
+            // Not in the spec: template syntax in an attribute value.
+            states::AttributeValueTemplate(kind, i) => {
+                let close = self.template_close(i);
+                loop {
+                    if eat!(self, close.as_slice()) {
+                        go!(self: append_value close; to AttributeValue kind);
+                    }
+                    let c = get_char!(self);
+                    go!(self: push_value c);
                 }
             },
 
             //§ rcdata-state
             states::RawData(Rcdata) => loop {
-                match pop_except_from!(self, small_char_set!('\r' '\0' '&' '<')) {
-                    FromSet('\0') => go!(self: error; emit '\ufffd'),
-                    FromSet('&') => go!(self: consume_char_ref),
-                    FromSet('<') => go!(self: to RawLessThanSign Rcdata),
-                    FromSet(c) => go!(self: emit c),
-                    NotFromSet(b) => self.emit_chars(b),
+                take_run!(self, small_char_set!('\r' '\0' '&' '<'));
+                match get_char!(self) {
+                    '\0' => go!(self: error; emit '\ufffd'),
+                    '&' => go!(self: consume_char_ref),
+                    '<' => go!(self: to RawLessThanSign Rcdata),
+                    c => go!(self: emit c),
                 }
             },
 
@@ -721,10 +1968,16 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
             states::TagOpen => loop { match get_char!(self) {
                 '!' => go!(self: to MarkupDeclarationOpen),
                 '/' => go!(self: to EndTagOpen),
-                '?' => go!(self: error; clear_comment; push_comment '?'; to BogusComment),
+                '?' => if self.opts.processing_instructions {
+                    go!(self: error; clear_comment; to ProcessingInstruction);
+                } else {
+                    go!(self: error; clear_comment; push_comment '?'; to BogusComment);
+                },
                 c => match lower_ascii_letter(c) {
                     Some(cl) => go!(self: create_tag StartTag cl; to TagName),
-                    None     => go!(self: error; emit '<'; reconsume Data),
+                    // Put c back first, so that the span of the '<'
+                    // doesn't include it.
+                    None     => go!(self: put_back; error; emit '<'; to Data),
                 }
             }},
 
@@ -754,7 +2007,7 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
                 c => match lower_ascii_letter(c) {
                     Some(cl) => go!(self: clear_temp; push_temp cl; emit '<'; emit c;
                                     to ScriptDataEscapeStart DoubleEscaped),
-                    None => go!(self: emit '<'; reconsume RawData ScriptDataEscaped Escaped),
+                    None => go!(self: put_back; emit '<'; to RawData ScriptDataEscaped Escaped),
                 }
             }},
 
@@ -769,7 +2022,7 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
             states::RawLessThanSign(kind) => loop { match get_char!(self) {
                 '/' => go!(self: clear_temp; to RawEndTagOpen kind),
                 '!' if kind == ScriptData => go!(self: emit '<'; emit '!'; to ScriptDataEscapeStart Escaped),
-                _   => go!(self: emit '<'; reconsume RawData kind),
+                _   => go!(self: put_back; emit '<'; to RawData kind),
             }},
 
             //§ rcdata-end-tag-open-state rawtext-end-tag-open-state script-data-end-tag-open-state script-data-escaped-end-tag-open-state
@@ -777,7 +2030,7 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
                 let c = get_char!(self);
                 match lower_ascii_letter(c) {
                     Some(cl) => go!(self: create_tag EndTag cl; push_temp c; to RawEndTagName kind),
-                    None     => go!(self: emit '<'; emit '/'; reconsume RawData kind),
+                    None     => go!(self: put_back; emit '<'; emit '/'; to RawData kind),
                 }
             },
 
@@ -794,9 +2047,13 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
                     }
                 }
 
+                // Once the name is longer than the last start tag's, it
+                // can't be appropriate, so stop collecting it.
                 match lower_ascii_letter(c) {
-                    Some(cl) => go!(self: push_tag cl; push_temp c),
-                    None     => go!(self: discard_tag; emit '<'; emit '/'; emit_temp; reconsume RawData kind),
+                    Some(cl) if self.temp_buf.len() < self.last_start_tag_len()
+                             => go!(self: push_tag cl; push_temp c),
+                    _        => go!(self: put_back; discard_tag; emit '<'; emit '/'; emit_temp;
+                                    to RawData kind),
                 }
             },
 
@@ -809,6 +2066,8 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
                         go!(self: emit c; to RawData ScriptDataEscaped esc);
                     }
                     _ => match lower_ascii_letter(c) {
+                        // Only whether this is "script" matters.
+                        Some(_) if self.temp_buf.len() > 6 => go!(self: emit c),
                         Some(cl) => go!(self: push_temp cl; emit c),
                         None     => go!(self: reconsume RawData ScriptDataEscaped Escaped),
                     }
@@ -859,6 +2118,8 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
                         go!(self: emit c; to RawData ScriptDataEscaped esc);
                     }
                     _ => match lower_ascii_letter(c) {
+                        // Only whether this is "script" matters.
+                        Some(_) if self.temp_buf.len() > 6 => go!(self: emit c),
                         Some(cl) => go!(self: push_temp cl; emit c),
                         None     => go!(self: reconsume RawData ScriptDataEscaped DoubleEscaped),
                     }
@@ -919,54 +2180,57 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
             //§ before-attribute-value-state
             states::BeforeAttributeValue => loop { match get_char!(self) {
                 '\t' | '\n' | '\x0C' | ' ' => (),
-                '"'  => go!(self: to AttributeValue DoubleQuoted),
-                '&'  => go!(self: reconsume AttributeValue Unquoted),
-                '\'' => go!(self: to AttributeValue SingleQuoted),
-                '\0' => go!(self: error; push_value '\ufffd'; to AttributeValue Unquoted),
+                '"'  => go!(self: start_value; to AttributeValue DoubleQuoted),
+                '&'  => go!(self: start_value; reconsume AttributeValue Unquoted),
+                '\'' => go!(self: start_value; to AttributeValue SingleQuoted),
+                '\0' => go!(self: error; start_value; push_value '\ufffd'; to AttributeValue Unquoted),
                 '>'  => go!(self: error; emit_tag Data),
                 c => {
                     go_match!(self: c,
                         '<' | '=' | '`' => error);
-                    go!(self: push_value c; to AttributeValue Unquoted);
+                    go!(self: start_value; push_value c; to AttributeValue Unquoted);
                 }
             }},
 
             //§ attribute-value-(double-quoted)-state
             states::AttributeValue(DoubleQuoted) => loop {
-                match pop_except_from!(self, small_char_set!('\r' '"' '&' '\0')) {
-                    FromSet('"')  => go!(self: to AfterAttributeValueQuoted),
-                    FromSet('&')  => go!(self: consume_char_ref '"'),
-                    FromSet('\0') => go!(self: error; push_value '\ufffd'),
-                    FromSet(c)    => go!(self: push_value c),
-                    NotFromSet(b) => go!(self: append_value b),
+                template_open!(self);
+                take_run!(self, small_char_set!('\r' '"' '&' '\0'));
+                match get_char!(self) {
+                    '"'  => go!(self: end_value; to AfterAttributeValueQuoted),
+                    '&'  => go!(self: consume_char_ref '"'),
+                    '\0' => go!(self: error; push_value '\ufffd'),
+                    c    => go!(self: push_value c),
                 }
             },
 
             //§ attribute-value-(single-quoted)-state
             states::AttributeValue(SingleQuoted) => loop {
-                match pop_except_from!(self, small_char_set!('\r' '\'' '&' '\0')) {
-                    FromSet('\'') => go!(self: to AfterAttributeValueQuoted),
-                    FromSet('&')  => go!(self: consume_char_ref '\''),
-                    FromSet('\0') => go!(self: error; push_value '\ufffd'),
-                    FromSet(c)    => go!(self: push_value c),
-                    NotFromSet(b) => go!(self: append_value b),
+                template_open!(self);
+                take_run!(self, small_char_set!('\r' '\'' '&' '\0'));
+                match get_char!(self) {
+                    '\'' => go!(self: end_value; to AfterAttributeValueQuoted),
+                    '&'  => go!(self: consume_char_ref '\''),
+                    '\0' => go!(self: error; push_value '\ufffd'),
+                    c    => go!(self: push_value c),
                 }
             },
 
             //§ attribute-value-(unquoted)-state
             states::AttributeValue(Unquoted) => loop {
-                match pop_except_from!(self, small_char_set!('\r' '\t' '\n' '\x0C' ' ' '&' '>' '\0')) {
-                    FromSet('\t') | FromSet('\n') | FromSet('\x0C') | FromSet(' ')
-                     => go!(self: to BeforeAttributeName),
-                    FromSet('&')  => go!(self: consume_char_ref '>'),
-                    FromSet('>')  => go!(self: emit_tag Data),
-                    FromSet('\0') => go!(self: error; push_value '\ufffd'),
-                    FromSet(c) => {
+                template_open!(self);
+                take_run!(self, small_char_set!('\r' '\t' '\n' '\x0C' ' ' '&' '>' '\0'));
+                match get_char!(self) {
+                    '\t' | '\n' | '\x0C' | ' '
+                         => go!(self: to BeforeAttributeName),
+                    '&'  => go!(self: consume_char_ref '>'),
+                    '>'  => go!(self: emit_tag Data),
+                    '\0' => go!(self: error; push_value '\ufffd'),
+                    c => {
                         go_match!(self: c,
                             '"' | '\'' | '<' | '=' | '`' => error);
                         go!(self: push_value c);
                     }
-                    NotFromSet(b) => go!(self: append_value b),
                 }
             },
 
@@ -1154,16 +2418,25 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
                     go!(self: clear_comment; to CommentStart);
                 } else if eat!(self, "doctype") {
                     go!(self: to Doctype);
+                } else if self.sink.adjusted_current_node_is_foreign()
+                        && eat_exact!(self, "[CDATA[") {
+                    go!(self: clear_comment; to CdataSection);
                 } else {
-                    // FIXME: CDATA, requires "adjusted current node" from tree builder
                     // FIXME: 'error' gives wrong message
                     go!(self: error; to BogusComment);
                 }
             },
 
             //§ cdata-section-state
-            states::CdataSection
-                => fail!("FIXME: state {} not implemented", self.state),
+            states::CdataSection => loop {
+                if eat!(self, "]]>") {
+                    go!(self: emit_cdata; to Data);
+                }
+                match get_char!(self) {
+                    '\0' => go!(self: emit_cdata; emit '\0'),
+                    c    => go!(self: push_comment c),
+                }
+            },
             //§ END
         }
     }
@@ -1177,6 +2450,7 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         let progress = match outcome {
             char_ref::Done => {
                 self.process_char_ref(tok.get_result());
+                self.spare_char_ref_tokenizer = Some(tok);
                 return true;
             }
 
@@ -1191,6 +2465,11 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
     fn process_char_ref(&mut self, char_ref: CharRef) {
         let CharRef { mut chars, mut num_chars } = char_ref;
 
+        if self.opts.track_spans && num_chars > 0 {
+            let span = Span { start: self.char_ref_start, end: self.position() };
+            self.report_part(CharRefPart, Some(span));
+        }
+
         if num_chars == 0 {
             chars[0] = '&';
             num_chars = 1;
@@ -1210,8 +2489,31 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         }
     }
 
-    /// Indicate that we have reached the end of the input.
+    /// Indicate that we have reached the end of the input.  If the sink
+    /// has suspended tokenizing, this waits for `resume`.
     pub fn end(&mut self) {
+        // Bytes too short to rule out a BOM.
+        match self.bom_bytes.take() {
+            Some(start) => {
+                let start = self.strip_bom(start);
+                self.decode_bytes(start.as_slice());
+            }
+            None => (),
+        }
+
+        // An incomplete character at the end of the bytes.
+        let mut rest = String::new();
+        match self.decoder {
+            Some(ref mut decoder) => decoder.finish(&mut rest),
+            None => (),
+        }
+        self.feed(rest);
+
+        if self.suspended {
+            self.end_pending = true;
+            return;
+        }
+
         // Handle EOF in the char ref sub-tokenizer, if there is one.
         // Do this first because it might un-consume stuff.
         match self.char_ref_tokenizer.take() {
@@ -1219,6 +2521,7 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
             Some(mut tok) => {
                 tok.end_of_file(self);
                 self.process_char_ref(tok.get_result());
+                self.spare_char_ref_tokenizer = Some(tok);
             }
         }
 
@@ -1226,6 +2529,10 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         // If we're waiting for lookahead, we're not gonna get it.
         self.at_eof = true;
         self.run();
+        if self.suspended {
+            self.end_pending = true;
+            return;
+        }
 
         while self.eof_step() {
             // loop
@@ -1253,6 +2560,7 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
         println!("\nTokenizer profile, in nanoseconds");
         println!("\n{:12u}         total in token sink", self.time_in_sink);
         println!("\n{:12u}         total in tokenizer", total);
+        println!("\n{:12u}         allocations for tokens", self.allocations);
 
         for (k, v) in results.into_iter() {
             let pct = 100.0 * (v as f64) / (total as f64);
@@ -1262,6 +2570,10 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
 
     fn eof_step(&mut self) -> bool {
         h5e_debug!("processing EOF in state {}", self.state);
+        if self.aborted {
+            go!(self: eof);
+        }
+
         match self.state {
             states::Data | states::RawData(Rcdata) | states::RawData(Rawtext)
             | states::RawData(ScriptData) | states::Plaintext
@@ -1278,6 +2590,15 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
             states::TagOpen
                 => go!(self: error_eof; emit '<'; to Data),
 
+            states::Passthrough(_)
+                => go!(self: error_eof; emit_passthrough; to Data),
+
+            states::AttributeValueTemplate(..)
+                => go!(self: error_eof; to Data),
+
+            states::ProcessingInstruction
+                => go!(self: error_eof; emit_pi; to Data),
+
             states::EndTagOpen
                 => go!(self: error_eof; emit '<'; emit '/'; to Data),
 
@@ -1293,8 +2614,11 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
             states::RawEndTagName(kind)
                 => go!(self: emit '<'; emit '/'; emit_temp; to RawData kind),
 
-            states::ScriptDataEscapeStart(kind)
-                => go!(self: to RawData ScriptDataEscaped kind),
+            states::ScriptDataEscapeStart(Escaped)
+                => go!(self: to RawData ScriptData),
+
+            states::ScriptDataEscapeStart(DoubleEscaped)
+                => go!(self: to RawData ScriptDataEscaped Escaped),
 
             states::ScriptDataEscapeStartDash
                 => go!(self: to RawData ScriptData),
@@ -1326,7 +2650,7 @@ impl<Sink: TokenSink> Tokenizer<Sink> {
                 => go!(self: error; to BogusComment),
 
             states::CdataSection
-                => fail!("FIXME: state {} not implemented in EOF", self.state),
+                => go!(self: error_eof; emit_cdata; to Data),
         }
     }
 }
@@ -1338,7 +2662,19 @@ mod test {
     use collections::vec::Vec;
     use collections::string::String;
     use collections::slice::CloneableVector;
-    use super::{option_push, append_strings}; // private items
+    use super::{option_push, append_strings, truncate_to}; // private items
+    use super::{Tokenizer, TokenizerOpts, TokenSink, Token, Span, ParseError};
+    use super::{TokenizerError, ErrorCode, DuplicateAttribute, UnexpectedEof, InvalidCharRef};
+    use super::{Severity, Informational, Recoverable, Fatal};
+    use super::{Tag, TagToken, StartTag, EndTag, CharacterTokens, EOFToken};
+    use super::PassthroughToken;
+    use super::{DuplicateAttrs, KeepFirst, KeepLast, KeepAll, AbortOnDuplicate};
+    use super::{TextTooLong, AttrValueTooLong, NameTooLong};
+    use super::decoder_for;
+    use super::states::{RawData, Rawtext, ScriptData, TagName};
+    use driver::tokenize_region_to;
+
+    use core::default::Default;
 
     #[test]
     fn push_to_None_gives_singleton() {
@@ -1380,4 +2716,627 @@ mod test {
         let ptr_new = lhs.into_bytes()[0] as *const u8;
         assert_eq!(ptr_old, ptr_new);
     }
+
+    #[test]
+    fn truncate_short_is_noop() {
+        let mut s = String::from_str("foo");
+        truncate_to(&mut s, 5);
+        assert_eq!(s, String::from_str("foo"));
+    }
+
+    #[test]
+    fn truncate_ascii() {
+        let mut s = String::from_str("foobar");
+        truncate_to(&mut s, 3);
+        assert_eq!(s, String::from_str("foo"));
+    }
+
+    #[test]
+    fn truncate_backs_up_to_char_boundary() {
+        // U+00E9 is two bytes in UTF-8.
+        let mut s = String::from_str("a\u00e9b");
+        truncate_to(&mut s, 2);
+        assert_eq!(s, String::from_str("a"));
+    }
+
+    struct Discard;
+
+    impl TokenSink for Discard {
+        fn process_token(&mut self, _: Token) { }
+    }
+
+    fn allocations(input: &str) -> u64 {
+        allocations_with(input, Default::default())
+    }
+
+    fn allocations_with(input: &str, opts: TokenizerOpts) -> u64 {
+        let mut tok = Tokenizer::new(Discard, opts);
+        tok.feed(String::from_str(input));
+        tok.end();
+        tok.allocations()
+    }
+
+    #[test]
+    fn tags_dont_allocate() {
+        assert_eq!(allocations("<p><b></b></p>"), 0);
+        assert_eq!(allocations("<p><b></b></p><p><b></b></p>"), 0);
+    }
+
+    #[test]
+    fn allocations_per_token() {
+        // The attribute list, the attribute value, and the text.
+        assert_eq!(allocations("<a href='x'>y"), 3);
+    }
+
+    #[test]
+    fn attr_value_run_not_copied() {
+        // The attribute list, and one buffer for each value.
+        assert_eq!(allocations("<a href='abcdef' title=xyz>"), 3);
+    }
+
+    #[test]
+    fn size_hint_sizes_attr_values() {
+        let input = "<a title='one &amp; two &amp; three' href=a&amp;b&amp;c>";
+        let hinted = TokenizerOpts {
+            input_size_hint: Some(input.len()),
+            .. Default::default()
+        };
+        // The attribute list, and one buffer for each value.
+        assert_eq!(allocations_with(input, hinted), 3);
+        assert!(allocations(input) > 3);
+    }
+
+    struct Spans(Vec<(uint, uint)>);
+
+    impl TokenSink for Spans {
+        fn process_token(&mut self, _: Token) { }
+
+        fn process_token_with_span(&mut self, token: Token, span: Span) {
+            let Spans(ref mut spans) = *self;
+            match token {
+                ParseError(_) => (),
+                _ => spans.push((span.start, span.end)),
+            }
+        }
+    }
+
+    fn spans(chunks: &[&str]) -> Vec<(uint, uint)> {
+        let opts = TokenizerOpts {
+            track_spans: true,
+            .. Default::default()
+        };
+        let mut tok = Tokenizer::new(Spans(vec!()), opts);
+        for chunk in chunks.iter() {
+            tok.feed(String::from_str(*chunk));
+        }
+        tok.end();
+        let Spans(spans) = tok.unwrap();
+        spans
+    }
+
+    #[test]
+    fn token_spans() {
+        // Text, a tag, a character reference, a comment, and EOF.
+        let expected = vec!((0, 2), (2, 9), (9, 14), (14, 22), (22, 22));
+        assert_eq!(spans(&["ab<p x=y>&amp;<!--c-->"]), expected);
+        assert_eq!(spans(&["ab<p x", "=y>&am", "p;<!-", "-c-->"]), expected);
+    }
+
+    #[test]
+    fn region_spans() {
+        let Spans(spans) = tokenize_region_to(Spans(vec!()),
+            String::from_str("xx<b>yy</b>zz"), 2, 9, Default::default());
+        assert_eq!(spans, vec!((2, 5), (5, 7), (7, 11), (11, 11)));
+
+        // The region's own offsets still come after `span_offset`.
+        let Spans(spans) = tokenize_region_to(Spans(vec!()),
+            String::from_str("\ufeffx<b>"), 3, 4, TokenizerOpts {
+                span_offset: 100,
+                .. Default::default()
+            });
+        assert_eq!(spans, vec!((103, 104), (104, 107), (107, 107)));
+    }
+
+    #[test]
+    fn token_spans_skip_lf_after_cr() {
+        assert_eq!(spans(&["a\r", "\nb"]), vec!((0, 1), (1, 2), (3, 4), (4, 4)));
+    }
+
+    #[test]
+    fn token_spans_cover_input() {
+        // Splicing the spans back together gives the input, less the
+        // `\n` of each `\r\n`.
+        let input = "<!DOCTYPE html>\r\n<p class=a&amp;b>x &lt; y<!-- c --><br/>\r\n</p>z";
+        let mut spliced = String::new();
+        for &(start, end) in spans(&[input.slice_to(20), input.slice_from(20)]).iter() {
+            spliced.push_str(input.slice(start, end));
+        }
+        assert_eq!(spliced.as_slice(), input.replace("\r\n", "\r").as_slice());
+    }
+
+    #[test]
+    fn token_span_excludes_reconsumed() {
+        // The '<' is emitted on seeing '3', which is then reconsumed.
+        assert_eq!(spans(&["<3"]), vec!((0, 1), (1, 2), (2, 2)));
+    }
+
+    struct Errors(Vec<TokenizerError>);
+
+    impl TokenSink for Errors {
+        fn process_token(&mut self, token: Token) {
+            let Errors(ref mut errors) = *self;
+            match token {
+                ParseError(e) => errors.push(e),
+                _ => (),
+            }
+        }
+    }
+
+    fn errors(chunks: &[&str], track_lines: bool) -> Vec<TokenizerError> {
+        errors_with(chunks, TokenizerOpts {
+            track_lines: track_lines,
+            .. Default::default()
+        })
+    }
+
+    fn errors_with(chunks: &[&str], opts: TokenizerOpts) -> Vec<TokenizerError> {
+        let mut tok = Tokenizer::new(Errors(vec!()), opts);
+        for chunk in chunks.iter() {
+            tok.feed(String::from_str(*chunk));
+        }
+        tok.end();
+        let Errors(errors) = tok.unwrap();
+        errors
+    }
+
+    #[test]
+    fn error_lines() {
+        let lines: Vec<(uint, uint)> = errors(&["a\r", "\n<p>\n</p x=1>\n<b"], true)
+            .iter().map(|e| (e.pos.line, e.pos.column)).collect();
+        assert_eq!(lines, vec!((3, 9), (4, 3)));
+    }
+
+    #[test]
+    fn error_codes() {
+        let errs = errors(&["<p a=1 a=2><b"], false);
+        let codes: Vec<ErrorCode> = errs.iter().map(|e| e.code.clone()).collect();
+        assert_eq!(codes, vec!(DuplicateAttribute, UnexpectedEof(TagName)));
+        assert_eq!(errs[0].pos.offset, 11);
+        assert_eq!(errs[1].message().as_slice(), "Saw EOF in state TagName");
+    }
+
+    #[test]
+    fn bogus_char_ref_names() {
+        let codes = |chunks: &[&str]| errors(chunks, false).into_iter()
+            .map(|e| e.code).collect::<Vec<ErrorCode>>();
+        let bogus = vec!(InvalidCharRef(String::from_str("xyzzy123;")));
+        assert_eq!(codes(&["a&xyzzy123;b"]), bogus);
+        assert_eq!(codes(&["a&xyz", "zy1", "23;b"]), bogus);
+        assert_eq!(codes(&["a&xyzzy_1;b &xyzzy\u00e9;"]), vec!());
+
+        // Too long to look for the semicolon.
+        let long = format!("&{};", String::from_char(70, 'q'));
+        assert_eq!(codes(&[long.as_slice()]), vec!());
+
+        let mut tok = writes(false, Default::default());
+        tok.feed(String::from_str("a&xyz"));
+        tok.feed(String::from_str("zy_1;b"));
+        tok.feed(long.clone());
+        tok.end();
+        assert_eq!(tok.unwrap().out, format!("a&xyzzy_1;b{}$", long));
+    }
+
+    #[test]
+    fn error_severity() {
+        let input = ["</p/>a&ampb<p a=1 a=2><b"];
+        let severities = |min| {
+            let errs = errors_with(&input, TokenizerOpts {
+                min_error_severity: min,
+                max_text_len: Some(1),
+                .. Default::default()
+            });
+            errs.iter().map(|e| e.code.severity()).collect::<Vec<Severity>>()
+        };
+        assert_eq!(severities(Some(Informational)),
+            vec!(Recoverable, Recoverable, Informational, Recoverable, Fatal));
+        assert_eq!(severities(Some(Fatal)), vec!(Fatal));
+        assert_eq!(severities(None), vec!());
+    }
+
+    // Start tags, with their attributes.
+    struct StartTags(Vec<String>);
+
+    impl TokenSink for StartTags {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                TagToken(Tag { kind: StartTag, name, attrs, .. }) => {
+                    let StartTags(ref mut tags) = *self;
+                    let mut tag = String::from_str(name.as_slice());
+                    for attr in attrs.iter() {
+                        tag.push_str(format!(" {}={}",
+                            attr.name.local.as_slice(), attr.value).as_slice());
+                    }
+                    tags.push(tag);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // Returns the start tags, and whether the tokenizer aborted.
+    fn duplicate_attrs(policy: DuplicateAttrs) -> (Vec<String>, bool) {
+        let mut tok = Tokenizer::new(StartTags(vec!()), TokenizerOpts {
+            duplicate_attrs: policy,
+            .. Default::default()
+        });
+        tok.feed(String::from_str("<b><p a=1 b=2 a=3><i x=y>"));
+        tok.end();
+        let aborted = tok.aborted();
+        let StartTags(tags) = tok.unwrap();
+        (tags, aborted)
+    }
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| String::from_str(*s)).collect()
+    }
+
+    #[test]
+    fn duplicate_attrs_keep_first() {
+        assert_eq!(duplicate_attrs(KeepFirst), (strings(&["b", "p a=1 b=2", "i x=y"]), false));
+    }
+
+    #[test]
+    fn duplicate_attrs_keep_last() {
+        assert_eq!(duplicate_attrs(KeepLast), (strings(&["b", "p a=3 b=2", "i x=y"]), false));
+    }
+
+    #[test]
+    fn duplicate_attrs_keep_all() {
+        assert_eq!(duplicate_attrs(KeepAll),
+            (strings(&["b", "p a=1 b=2 a=3", "i x=y"]), false));
+    }
+
+    #[test]
+    fn duplicate_attrs_abort() {
+        assert_eq!(duplicate_attrs(AbortOnDuplicate), (strings(&["b"]), true));
+    }
+
+    fn error_codes_with(input: &str, opts: TokenizerOpts) -> Vec<ErrorCode> {
+        errors_with(&[input], opts).into_iter().map(|e| e.code).collect()
+    }
+
+    #[test]
+    fn max_attr_value_len() {
+        let opts = TokenizerOpts {
+            max_attr_value_len: Some(3),
+            .. Default::default()
+        };
+        let input = "<p a=abcdef b='xy\u00e9z' c=\"ok\">";
+        let mut tok = Tokenizer::new(StartTags(vec!()), opts.clone());
+        tok.feed(String::from_str(input));
+        tok.end();
+        assert!(tok.truncated());
+        let StartTags(tags) = tok.unwrap();
+        // U+00E9 would straddle the limit, so it's dropped, and so is
+        // everything after it.
+        assert_eq!(tags, strings(&["p a=abc b=xy c=ok"]));
+        assert_eq!(error_codes_with(input, opts), vec!(
+            AttrValueTooLong(String::from_str("a"), 3),
+            AttrValueTooLong(String::from_str("b"), 3)));
+    }
+
+    #[test]
+    fn max_text_len() {
+        let opts = TokenizerOpts {
+            max_text_len: Some(4),
+            .. Default::default()
+        };
+        let input = "abc&amp;def<p>ghijkl";
+        let mut tok = writes(false, opts.clone());
+        tok.feed(String::from_str(input));
+        tok.end();
+        assert!(tok.truncated());
+        // The limit applies to each run of text between markup.
+        assert_eq!(tok.unwrap().out.as_slice(), "abc&<p>ghij$");
+        assert_eq!(error_codes_with(input, opts), vec!(TextTooLong(4), TextTooLong(4)));
+    }
+
+    // The largest capacity of any string of characters.
+    struct TextCapacity(uint);
+
+    impl TokenSink for TextCapacity {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                CharacterTokens(b) => {
+                    let TextCapacity(ref mut cap) = *self;
+                    if b.capacity() > *cap {
+                        *cap = b.capacity();
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn long_runs_not_copied() {
+        let big = String::from_char(100000, 'x');
+        let mut tok = Tokenizer::new(TextCapacity(0), TokenizerOpts {
+            max_attr_value_len: Some(3),
+            max_text_len: Some(4),
+            .. Default::default()
+        });
+        tok.feed(format!("<p a={}", big));
+        assert!(tok.current_attr_value.capacity() < 100);
+        tok.feed(format!(">{}", big));
+        tok.end();
+        assert!(tok.truncated());
+        let TextCapacity(cap) = tok.unwrap();
+        assert!(cap < 100);
+    }
+
+    #[test]
+    fn max_name_len() {
+        let opts = TokenizerOpts {
+            max_name_len: Some(3),
+            .. Default::default()
+        };
+        let input = "<abcdef ab\u00e9cd=1 ok=2>";
+        let mut tok = Tokenizer::new(StartTags(vec!()), opts.clone());
+        tok.feed(String::from_str(input));
+        tok.end();
+        assert!(tok.truncated());
+        let StartTags(tags) = tok.unwrap();
+        // "c" would fit after U+00E9 is dropped, but the name was
+        // already cut off.
+        assert_eq!(tags, strings(&["abc ab=1 ok=2"]));
+        assert_eq!(error_codes_with(input, opts), vec!(NameTooLong(3), NameTooLong(3)));
+    }
+
+    #[test]
+    fn opts_builder() {
+        let opts = TokenizerOpts::builder()
+            .exact_errors(true)
+            .initial_state(RawData(Rawtext))
+            .last_start_tag_name("title")
+            .template_delimiter("{{", "}}")
+            .build();
+        assert!(opts.exact_errors);
+        assert_eq!(opts.initial_state, Some(RawData(Rawtext)));
+        assert_eq!(opts.last_start_tag_name, Some(String::from_str("title")));
+        assert_eq!(opts.template_delimiters,
+            vec!((String::from_str("{{"), String::from_str("}}"))));
+        assert!(opts.discard_bom);
+        assert_eq!(opts.min_error_severity, Some(Informational));
+    }
+
+    // Writes out tags and text, and suspends after `</script>` if
+    // `suspend` is set.
+    struct Writes {
+        out: String,
+        suspend: bool,
+    }
+
+    impl TokenSink for Writes {
+        fn process_token(&mut self, token: Token) {
+            match token {
+                TagToken(Tag { kind: StartTag, name, .. }) => {
+                    self.out.push_str(format!("<{}>", name.as_slice()).as_slice());
+                }
+                TagToken(Tag { kind: EndTag, name, .. }) => {
+                    self.out.push_str(format!("</{}>", name.as_slice()).as_slice());
+                }
+                CharacterTokens(text) | PassthroughToken(text)
+                    => self.out.push_str(text.as_slice()),
+                EOFToken => self.out.push_str("$"),
+                _ => (),
+            }
+        }
+
+        fn query_suspend(&mut self) -> bool {
+            self.suspend && self.out.as_slice().ends_with("</script>")
+        }
+    }
+
+    fn writes(suspend: bool, opts: TokenizerOpts) -> Tokenizer<Writes> {
+        Tokenizer::new(Writes { out: String::new(), suspend: suspend }, opts)
+    }
+
+    #[test]
+    fn insert_while_suspended() {
+        let mut tok = writes(true, Default::default());
+        tok.feed(String::from_str("<p>a</script>b"));
+        assert!(tok.is_suspended());
+        tok.end();
+        tok.insert(String::from_str("<i>"));
+        tok.insert(String::from_str("w</i>"));
+        tok.resume();
+        assert!(!tok.is_suspended());
+        assert_eq!(tok.unwrap().out.as_slice(), "<p>a</script><i>w</i>b$");
+    }
+
+    #[test]
+    fn insert_when_not_suspended() {
+        let mut tok = writes(true, Default::default());
+        tok.feed(String::from_str("<p>a"));
+        tok.insert(String::from_str("<i>"));
+        tok.feed(String::from_str("b"));
+        tok.end();
+        assert_eq!(tok.unwrap().out.as_slice(), "<p>a<i>b$");
+    }
+
+    #[test]
+    fn empty_template_delimiter_ignored() {
+        let mut tok = writes(false, TokenizerOpts {
+            template_delimiters: vec!((String::new(), String::new()),
+                (String::from_str("{{"), String::from_str("}}"))),
+            template_attr_values: true,
+            .. Default::default()
+        });
+        tok.feed(String::from_str("a{{b}}<p x='{{c}}'>d"));
+        tok.end();
+        assert_eq!(tok.unwrap().out.as_slice(), "a{{b}}<p>d$");
+    }
+
+    #[test]
+    fn reuse_buffers() {
+        let opts = TokenizerOpts {
+            template_delimiters: vec!((String::from_str("{{"), String::from_str("}}"))),
+            .. Default::default()
+        };
+        let mut tok = writes(false, opts);
+        tok.feed(String::from_str("<blockquote data-attribute=1>a&amp;b{{c}}"));
+        tok.end();
+        let (_, opts, buffers) = tok.unwrap_with_buffers();
+        let tag_cap = buffers.tag_name.capacity();
+        let attr_cap = buffers.attr_name.capacity();
+        assert!(tag_cap >= 10);
+        assert!(buffers.char_ref_tokenizer.is_some());
+
+        let mut tok = Tokenizer::new_with_buffers(Writes { out: String::new(), suspend: false },
+            opts, buffers);
+        assert!(tok.current_tag_name.is_empty());
+        assert_eq!(tok.current_tag_name.capacity(), tag_cap);
+        assert_eq!(tok.current_attr_name.capacity(), attr_cap);
+        assert!(tok.spare_char_ref_tokenizer.is_some());
+        tok.feed(String::from_str("<p>{{x}}&lt;"));
+        tok.end();
+        assert_eq!(tok.unwrap().out.as_slice(), "<p>{{x}}<$");
+    }
+
+    #[test]
+    fn long_bogus_char_ref_not_held_back() {
+        let name = String::from_char(100, 'a');
+        let mut tok = writes(false, Default::default());
+        tok.feed(format!("x&{}", name));
+        assert_eq!(tok.unwrap().out, format!("x&{}", name));
+    }
+
+    #[test]
+    fn feed_bytes() {
+        let mut tok = writes(false, Default::default());
+        tok.feed_bytes(b"\xef\xbb\xbf<p>caf\xc3".to_vec());
+        tok.feed_bytes(b"\xa9 \xff</p>\xe2\x82".to_vec());
+        tok.end();
+        assert_eq!(tok.unwrap().out.as_slice(), "<p>caf\u00e9 \ufffd</p>\ufffd$");
+    }
+
+    #[test]
+    fn feed_bytes_with_decoder() {
+        let mut tok = writes(false, Default::default());
+        tok.set_decoder(decoder_for("windows-1252").unwrap());
+        tok.feed_bytes(b"<b>\x93hi\x94</b>".to_vec());
+        tok.end();
+        assert_eq!(tok.encoding_used(), Some("windows-1252"));
+        assert_eq!(tok.unwrap().out.as_slice(), "<b>\u201chi\u201d</b>$");
+    }
+
+    #[test]
+    fn feed_bytes_utf16_bom() {
+        let mut tok = writes(false, Default::default());
+        for &b in b"\xff\xfe<\x00b\x00>\x00\xe9\x00".iter() {
+            tok.feed_bytes(vec!(b));
+        }
+        tok.end();
+        assert_eq!(tok.encoding_used(), Some("utf-16le"));
+        assert_eq!(tok.unwrap().out.as_slice(), "<b>\u00e9$");
+    }
+
+    #[test]
+    fn feed_bytes_bom_overrides_decoder() {
+        let mut tok = writes(false, Default::default());
+        tok.set_decoder(decoder_for("windows-1252").unwrap());
+        tok.feed_bytes(b"\xef\xbb\xbf\xef\xbb\xbfcaf\xc3\xa9".to_vec());
+        tok.end();
+        assert_eq!(tok.encoding_used(), Some("utf-8"));
+        // The second U+FEFF isn't a BOM.
+        assert_eq!(tok.unwrap().out.as_slice(), "\ufeffcaf\u00e9$");
+    }
+
+    #[test]
+    fn feed_bytes_short() {
+        let mut tok = writes(false, Default::default());
+        assert_eq!(tok.encoding_used(), None);
+        tok.feed_bytes(b"\xfe\xff".to_vec());
+        tok.end();
+        assert_eq!(tok.unwrap().out.as_slice(), "$");
+
+        let mut tok = writes(false, Default::default());
+        tok.feed_bytes(b"a".to_vec());
+        tok.end();
+        assert_eq!(tok.unwrap().out.as_slice(), "a$");
+    }
+
+    #[test]
+    fn script_content_alone() {
+        let mut tok = writes(false, TokenizerOpts {
+            initial_state: Some(RawData(ScriptData)),
+            last_start_tag_name: Some(String::from_str("script")),
+            .. Default::default()
+        });
+        tok.feed(String::from_str("a<b></style></script>c"));
+        tok.end();
+        assert_eq!(tok.unwrap().out.as_slice(), "a<b></style></script>c$");
+    }
+
+    fn script(input: &str, split: bool) -> String {
+        let mut tok = writes(false, TokenizerOpts {
+            initial_state: Some(RawData(ScriptData)),
+            last_start_tag_name: Some(String::from_str("script")),
+            .. Default::default()
+        });
+        if split {
+            for c in input.chars() {
+                tok.feed(String::from_char(1, c));
+            }
+        } else {
+            tok.feed(String::from_str(input));
+        }
+        tok.end();
+        tok.unwrap().out
+    }
+
+    #[test]
+    fn script_escapes() {
+        // An end tag comes out in lower case, and text as written.
+        for &(input, expected) in [
+                ("<!--<script>x</SCRIPT>y</SCRIPT>z", "<!--<script>x</SCRIPT>y</script>z$"),
+                ("<!--x-->y</SCRIPT>z", "<!--x-->y</script>z$"),
+                ("<!--<script>--></SCRIPT>", "<!--<script>--></script>$"),
+                ("<!--<scripts></SCRIPT>", "<!--<scripts></script>$"),
+                ("<!-<script></SCRIPT>", "<!-<script></script>$"),
+                ("<!--<script/a</SCRIPT-->b</SCRIPT>", "<!--<script/a</SCRIPT-->b</script>$"),
+            ].iter() {
+            assert_eq!(script(input, false).as_slice(), expected);
+            assert_eq!(script(input, true).as_slice(), expected);
+        }
+    }
+
+    #[test]
+    fn script_escape_eof() {
+        let count = |input: &str| errors_with(&[input], TokenizerOpts {
+            initial_state: Some(RawData(ScriptData)),
+            last_start_tag_name: Some(String::from_str("script")),
+            .. Default::default()
+        }).len();
+        // EOF before `<!--` is complete isn't an error.
+        assert_eq!(count("<!"), 0);
+        assert_eq!(count("<!-"), 0);
+        assert_eq!(count("<!--"), 1);
+        assert_eq!(count("<!--<s"), 1);
+        assert_eq!(count("<!--<script>-"), 1);
+    }
+
+    #[test]
+    fn long_end_tag_name_in_raw_text() {
+        let xs = String::from_char(100, 'x');
+        let mut tok = writes(false, TokenizerOpts {
+            initial_state: Some(RawData(Rawtext)),
+            last_start_tag_name: Some(String::from_str("style")),
+            .. Default::default()
+        });
+        tok.feed(format!("a</{}></styles></STYLE>", xs));
+        tok.end();
+        assert_eq!(tok.unwrap().out, format!("a</{}></styles></style>$", xs));
+    }
 }
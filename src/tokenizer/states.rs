@@ -14,6 +14,8 @@
 
 use core::prelude::*;
 
+use string_cache::Atom;
+
 #[deriving(PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Show)]
 pub enum ScriptEscapeKind {
     Escaped,
@@ -84,4 +86,24 @@ pub enum State {
     BetweenDoctypePublicAndSystemIdentifiers,
     BogusDoctype,
     CdataSection,
+    Passthrough(uint),
+    ProcessingInstruction,
+    AttributeValueTemplate(AttrValueKind, uint),
+}
+
+/// The state the tokenizer should switch to after a start tag with
+/// this name, going by the name alone.  The tree builder would decide
+/// differently in a few corner cases, e.g. a `<title>` inside an
+/// `<svg>`.  `scripting_enabled` should match the tree builder's
+/// option of the same name.
+pub fn raw_text_state(name: &Atom, scripting_enabled: bool) -> Option<State> {
+    match *name {
+        atom!(title) | atom!(textarea) => Some(RawData(Rcdata)),
+        atom!(style) | atom!(xmp) | atom!(iframe)
+            | atom!(noembed) | atom!(noframes) => Some(RawData(Rawtext)),
+        atom!(noscript) if scripting_enabled => Some(RawData(Rawtext)),
+        atom!(script) => Some(RawData(ScriptData)),
+        atom!(plaintext) => Some(Plaintext),
+        _ => None,
+    }
 }
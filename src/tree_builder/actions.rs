@@ -17,26 +17,28 @@ use core::prelude::*;
 use tree_builder::types::*;
 use tree_builder::tag_sets::*;
 use tree_builder::interface::{TreeSink, QuirksMode, NodeOrText, AppendNode, AppendText};
+use tree_builder::kind::{ElementKind, is_valid_custom_element_name};
 use tree_builder::rules::TreeBuilderStep;
-
-use tokenizer::{Attribute, Tag};
+use tree_builder::error::{ErrorCode, TreeBuilderError, TreeBuilderErr, UnexpectedToken};
+use tree_builder::error::{FormattingElementNotOpen, FormattingElementNotInScope};
+use tree_builder::error::{FormattingElementNotCurrentNode, UnclosedElementAtBodyEnd};
+use tree_builder::error::{UnexpectedOpenElement, UnexpectedCharsInTable};
+use tree_builder::error::{SpecialElementWhileClosing, CellNotClosed, InvalidCustomElementName};
+use tree_builder::data::{svg_tag_name, svg_attr_name, mathml_attr_name, foreign_attr_name};
+
+use tokenizer::{Attribute, Tag, Span, StartTag, EndTag};
 use tokenizer::states::{RawData, RawKind};
 
 use util::str::AsciiExt;
 
-#[cfg(not(for_c))]
-use util::str::to_escaped_string;
-
-use core::mem::replace;
 use core::iter::{Rev, Enumerate};
 use core::slice;
 use core::fmt::Show;
 use collections::MutableSeq;
 use collections::vec::Vec;
 use collections::string::String;
-use collections::str::Slice;
 
-use string_cache::{Atom, QualName};
+use string_cache::{Atom, QualName, Namespace};
 
 pub struct ActiveFormattingIter<'a, Handle: 'a> {
     iter: Rev<Enumerate<slice::Items<'a, FormatEntry<Handle>>>>,
@@ -56,17 +58,50 @@ pub enum PushFlag {
     NoPush,
 }
 
+// Where the adoption agency algorithm puts the new formatting element
+// in the list of active formatting elements.
+enum Bookmark<Handle> {
+    Replace(Handle),
+    InsertAfter(Handle),
+}
+
+// The name of an attribute on a foreign element, in its proper case and
+// namespace.
+fn adjust_attr_name(name: QualName, ns: &Namespace) -> QualName {
+    if name.ns != ns!("") {
+        return name;
+    }
+    let local = match *ns {
+        ns!(SVG) => svg_attr_name(&name.local),
+        ns!(MathML) => mathml_attr_name(&name.local),
+        _ => None,
+    };
+    match local {
+        Some(local) => return QualName::new(ns!(""), local),
+        None => (),
+    }
+    foreign_attr_name(&name.local).unwrap_or(name)
+}
+
 // These go in a trait so that we can control visibility.
 pub trait TreeBuilderActions<Handle> {
     fn unexpected<T: Show>(&mut self, thing: &T) -> ProcessResult;
+    fn parse_error(&mut self, code: ErrorCode);
+    fn unclosed_error(&mut self, code: ErrorCode);
+    fn unexpected_eof(&mut self, token: &Token);
     fn assert_named(&mut self, node: Handle, name: Atom);
     fn clear_active_formatting_to_marker(&mut self);
     fn create_formatting_element_for(&mut self, tag: Tag) -> Handle;
     fn append_text(&mut self, text: String) -> ProcessResult;
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle;
+    fn elem_name(&self, elem: Handle) -> QualName;
+    fn remember_elem_name(&self, elem: Handle, name: QualName);
     fn append_comment(&mut self, text: String) -> ProcessResult;
     fn append_comment_to_doc(&mut self, text: String) -> ProcessResult;
     fn append_comment_to_html(&mut self, text: String) -> ProcessResult;
     fn insert_appropriately(&mut self, child: NodeOrText<Handle>);
+    fn insert_appropriately_in(&mut self, target: Handle, child: NodeOrText<Handle>);
+    fn append_to_template_contents(&mut self, idx: uint, child: NodeOrText<Handle>);
     fn insert_phantom(&mut self, name: Atom) -> Handle;
     fn insert_and_pop_element_for(&mut self, tag: Tag) -> Handle;
     fn insert_element_for(&mut self, tag: Tag) -> Handle;
@@ -88,15 +123,24 @@ pub trait TreeBuilderActions<Handle> {
     fn in_scope_named(&self, scope: TagSet, name: Atom) -> bool;
     fn current_node_named(&self, name: Atom) -> bool;
     fn html_elem_named(&self, elem: Handle, name: Atom) -> bool;
+    fn in_html_elem_named(&self, name: Atom) -> bool;
     fn elem_in(&self, elem: Handle, set: TagSet) -> bool;
     fn in_scope(&self, scope: TagSet, pred: |Handle| -> bool) -> bool;
+    fn any_in_scope(&self, scope: TagSet, set: TagSet) -> bool;
     fn check_body_end(&mut self);
     fn body_elem(&mut self) -> Option<Handle>;
     fn html_elem(&self) -> Handle;
     fn reconstruct_formatting(&mut self);
+    fn is_marker_or_open(&self, entry: &FormatEntry<Handle>) -> bool;
+    fn position_in_active_formatting(&self, elem: &Handle) -> Option<uint>;
+    fn clone_formatting_element(&mut self, tag: &Tag) -> Handle;
+    fn process_end_tag_in_body(&mut self, tag: Tag);
     fn remove_from_stack(&mut self, elem: &Handle);
     fn pop(&mut self) -> Handle;
     fn push(&mut self, elem: &Handle);
+    fn report_start_span(&mut self, node: Handle, name: &QualName);
+    fn report_end_span(&mut self, elem: Handle);
+    fn create_comment(&mut self, text: String) -> Handle;
     fn adoption_agency(&mut self, subject: Atom);
     fn current_node_in(&self, set: TagSet) -> bool;
     fn current_node(&self) -> Handle;
@@ -105,20 +149,58 @@ pub trait TreeBuilderActions<Handle> {
     fn stop_parsing(&mut self) -> ProcessResult;
     fn set_quirks_mode(&mut self, mode: QuirksMode);
     fn active_formatting_end_to_marker<'a>(&'a self) -> ActiveFormattingIter<'a, Handle>;
+    fn adjusted_current_node(&self) -> Handle;
+    fn is_html_integration_point(&self, elem: Handle) -> bool;
+    fn is_foreign(&self, token: &Token) -> bool;
+    fn insert_foreign_element_for(&mut self, tag: Tag, ns: Namespace) -> ProcessResult;
+    fn foreign_start_tag(&mut self, tag: Tag) -> ProcessResult;
+    fn unexpected_start_tag_in_foreign_content(&mut self, tag: Tag) -> ProcessResult;
 }
 
 #[doc(hidden)]
 impl<Handle: Clone, Sink: TreeSink<Handle>>
     TreeBuilderActions<Handle> for super::TreeBuilder<Handle, Sink> {
 
-    fn unexpected<T: Show>(&mut self, _thing: &T) -> ProcessResult {
-        self.sink.parse_error(format_if!(
-            self.opts.exact_errors,
-            "Unexpected token",
-            "Unexpected token {} in insertion mode {}", to_escaped_string(_thing), self.mode));
+    fn unexpected<T: Show>(&mut self, thing: &T) -> ProcessResult {
+        let details = self.describe(thing);
+        self.parse_error(UnexpectedToken(details));
         Done
     }
 
+    /// Report a parse error.  With `exact_errors`, the error includes
+    /// the insertion mode and the stack of open elements.
+    fn parse_error(&mut self, code: ErrorCode) {
+        if !self.reports(code.severity()) {
+            return;
+        }
+
+        let err = TreeBuilderError {
+            code: code,
+            span: self.token_span.clone(),
+            context: self.error_context(),
+        };
+        self.errors_count += 1;
+        self.sink.parse_error(TreeBuilderErr(err));
+    }
+
+    /// Report an element left open.  At EOF this means the input was
+    /// cut off, which isn't an error with the `truncated_input` option.
+    fn unclosed_error(&mut self, code: ErrorCode) {
+        if self.at_eof {
+            self.eof_truncated = true;
+            if self.opts.truncated_input {
+                return;
+            }
+        }
+        self.parse_error(code);
+    }
+
+    /// Report EOF in an insertion mode which has elements to close.
+    fn unexpected_eof(&mut self, token: &Token) {
+        let details = self.describe(token);
+        self.unclosed_error(UnexpectedToken(details));
+    }
+
     fn assert_named(&mut self, node: Handle, name: Atom) {
         assert!(self.html_elem_named(node, name));
     }
@@ -137,7 +219,10 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
     }
 
     fn stop_parsing(&mut self) -> ProcessResult {
-        h5e_warn!("stop_parsing not implemented, full speed ahead!");
+        // The spec pops everything off the stack of open elements here,
+        // which only matters to us for the sink's end spans.  Elements
+        // still open at EOF were never closed, so they don't get one.
+        // The rest of "stop parsing" is about scripts and events.
         Done
     }
 
@@ -165,71 +250,382 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
     }
 
     fn current_node_in(&self, set: TagSet) -> bool {
-        set(self.sink.elem_name(self.current_node()))
+        set(self.current_kind().name())
+    }
+
+    fn current_kind<'a>(&'a self) -> &'a ElementKind {
+        self.open_elem_kinds.last().expect("no current element")
     }
 
     // Insert at the "appropriate place for inserting a node".
     fn insert_appropriately(&mut self, child: NodeOrText<Handle>) {
-        declare_tag_set!(foster_target = table tbody tfoot thead tr)
         let target = self.current_node();
+        self.insert_appropriately_in(target, child);
+    }
+
+    // The same, with an override target instead of the current node.
+    fn insert_appropriately_in(&mut self, target: Handle, child: NodeOrText<Handle>) {
+        declare_tag_set!(foster_target = table tbody tfoot thead tr)
         if !(self.foster_parenting && self.elem_in(target.clone(), foster_target)) {
             // No foster parenting (the common case).
+            if self.html_elem_named(target.clone(), atom!(template)) {
+                let contents = self.sink.get_template_contents(target);
+                return self.sink.append(contents, child);
+            }
             return self.sink.append(target, child);
         }
 
         // Foster parenting
-        // FIXME: <template>
+        let last_template = self.open_elems.iter()
+            .rposition(|e| self.html_elem_named(e.clone(), atom!(template)));
         let last_table = self.open_elems.iter()
-            .enumerate()
-            .rev()
-            .filter(|&(_, e)| self.html_elem_named(e.clone(), atom!(table)))
-            .next();
+            .rposition(|e| self.html_elem_named(e.clone(), atom!(table)));
 
-        match last_table {
-            None => {
+        match (last_template, last_table) {
+            (None, None) => {
                 let html_elem = self.html_elem();
                 self.sink.append(html_elem, child);
             }
-            Some((idx, last_table)) => {
-                // Try inserting "inside last table's parent node, immediately before last table"
-                match self.sink.append_before_sibling(last_table.clone(), child) {
-                    Ok(()) => (),
-
-                    // If last_table has no parent, we regain ownership of the child.
-                    // Insert "inside previous element, after its last child (if any)"
-                    Err(child) => {
-                        let previous_element = self.open_elems[idx-1].clone();
-                        self.sink.append(previous_element, child);
-                    }
+
+            // A template above the last table on the stack, or with no
+            // table at all, takes the child into its contents.
+            (Some(idx), None) => self.append_to_template_contents(idx, child),
+            (Some(idx), Some(table_idx)) if idx > table_idx
+                => self.append_to_template_contents(idx, child),
+
+            (_, Some(idx)) => {
+                // Insert "inside last table's parent node, immediately before last table",
+                // or if it has no parent, "inside previous element, after its last child".
+                let last_table = self.open_elems[idx].clone();
+                let mut previous_element = self.open_elems[idx-1].clone();
+                if self.html_elem_named(previous_element.clone(), atom!(template)) {
+                    previous_element = self.sink.get_template_contents(previous_element);
                 }
+                self.sink.append_based_on_parent_node(last_table, previous_element, child);
             }
         }
     }
 
+    fn append_to_template_contents(&mut self, idx: uint, child: NodeOrText<Handle>) {
+        let template = self.open_elems[idx].clone();
+        let contents = self.sink.get_template_contents(template);
+        self.sink.append(contents, child);
+    }
+
+    //§ adoption-agency-algorithm
     fn adoption_agency(&mut self, subject: Atom) {
-        // FIXME: this is not right
-        if self.current_node_named(subject) {
+        // 1.
+        if self.current_node_named(subject.clone())
+            && self.position_in_active_formatting(&self.current_node()).is_none() {
             self.pop();
+            return;
+        }
+
+        // 2. 3. 4.
+        for _ in range(0u, 8) {
+            // 5.
+            let maybe_fmt_entry = self.active_formatting_end_to_marker()
+                .filter(|&(_, _, tag)| tag.name == subject)
+                .next()
+                .map(|(i, h, t)| (i, h.clone(), t.clone()));
+
+            let (fmt_elem_index, fmt_elem, fmt_elem_tag) = match maybe_fmt_entry {
+                None => return self.process_end_tag_in_body(Tag {
+                    kind: EndTag,
+                    name: subject,
+                    self_closing: false,
+                    attrs: vec!(),
+                }),
+                Some(x) => x,
+            };
+
+            // 6.
+            let fmt_elem_stack_index = match self.open_elems.iter()
+                    .position(|n| self.sink.same_node(n.clone(), fmt_elem.clone())) {
+                None => {
+                    self.parse_error(FormattingElementNotOpen);
+                    self.active_formatting.remove(fmt_elem_index);
+                    return;
+                }
+                Some(i) => i,
+            };
+
+            // 7.
+            if !self.in_scope(default_scope, |n| self.sink.same_node(n, fmt_elem.clone())) {
+                self.parse_error(FormattingElementNotInScope);
+                return;
+            }
+
+            // 8.
+            if !self.sink.same_node(self.current_node(), fmt_elem.clone()) {
+                self.parse_error(FormattingElementNotCurrentNode);
+            }
+
+            // 9.
+            let maybe_furthest_block = self.open_elem_kinds.iter()
+                .enumerate()
+                .skip(fmt_elem_stack_index + 1)
+                .find(|&(_, kind)| kind.is_special())
+                .map(|(i, _)| (i, self.open_elems[i].clone()));
+
+            let (furthest_block_index, furthest_block) = match maybe_furthest_block {
+                // 10.
+                None => {
+                    while self.open_elems.len() > fmt_elem_stack_index {
+                        self.pop();
+                    }
+                    self.active_formatting.remove(fmt_elem_index);
+                    return;
+                }
+                Some(x) => x,
+            };
+
+            // 11.
+            let common_ancestor = self.open_elems[fmt_elem_stack_index - 1].clone();
+
+            // 12.
+            let mut bookmark = Replace(fmt_elem.clone());
+
+            // 13.
+            let mut node_index = furthest_block_index;
+            let mut last_node = furthest_block.clone();
+            let mut inner_counter = 0u;
+            loop {
+                // 13.1.
+                inner_counter += 1;
+
+                // 13.2.  If the previous node was removed from the stack,
+                // the one above it has moved into its place.
+                node_index -= 1;
+                let node = self.open_elems[node_index].clone();
+
+                // 13.3.
+                if self.sink.same_node(node.clone(), fmt_elem.clone()) {
+                    break;
+                }
+
+                // 13.4.
+                let mut node_formatting_index = self.position_in_active_formatting(&node);
+                if inner_counter > 3 {
+                    match node_formatting_index {
+                        Some(i) => {
+                            self.active_formatting.remove(i);
+                            node_formatting_index = None;
+                        }
+                        None => (),
+                    }
+                }
+
+                // 13.5.
+                let node_formatting_index = match node_formatting_index {
+                    None => {
+                        self.remove_from_stack(&node);
+                        continue;
+                    }
+                    Some(i) => i,
+                };
+
+                // 13.6.
+                let tag = match self.active_formatting[node_formatting_index] {
+                    Element(_, ref t) => t.clone(),
+                    Marker => fail!("Found marker during adoption agency"),
+                };
+                // The clone has the same name, so its kind is unchanged.
+                let new_elem = self.clone_formatting_element(&tag);
+                *self.open_elems.get_mut(node_index) = new_elem.clone();
+                *self.active_formatting.get_mut(node_formatting_index)
+                    = Element(new_elem.clone(), tag);
+                self.report_end_span(node);
+                let node = new_elem;
+
+                // 13.7.
+                if self.sink.same_node(last_node.clone(), furthest_block.clone()) {
+                    bookmark = InsertAfter(node.clone());
+                }
+
+                // 13.8.
+                self.sink.remove_from_parent(last_node.clone());
+                self.sink.append(node.clone(), AppendNode(last_node));
+
+                // 13.9.
+                last_node = node;
+            }
+
+            // 14.
+            self.sink.remove_from_parent(last_node.clone());
+            self.insert_appropriately_in(common_ancestor, AppendNode(last_node));
+
+            // 15.
+            let new_elem = self.clone_formatting_element(&fmt_elem_tag);
+            let new_kind = ElementKind::from_name(
+                &QualName::new(ns!(HTML), fmt_elem_tag.name.clone()));
+            let new_entry = Element(new_elem.clone(), fmt_elem_tag);
+
+            // 16.
+            self.sink.reparent_children(furthest_block.clone(), new_elem.clone());
+
+            // 17.
+            self.sink.append(furthest_block.clone(), AppendNode(new_elem.clone()));
+
+            // 18.
+            match bookmark {
+                Replace(to_replace) => {
+                    let index = self.position_in_active_formatting(&to_replace)
+                        .expect("bookmark not found in active formatting elements");
+                    *self.active_formatting.get_mut(index) = new_entry;
+                }
+                InsertAfter(previous) => {
+                    let index = self.position_in_active_formatting(&previous)
+                        .expect("bookmark not found in active formatting elements");
+                    self.active_formatting.insert(index + 1, new_entry);
+                    let old_index = self.position_in_active_formatting(&fmt_elem)
+                        .expect("formatting element not found in active formatting elements");
+                    self.active_formatting.remove(old_index);
+                }
+            }
+
+            // 19.
+            self.remove_from_stack(&fmt_elem);
+            let new_furthest_block_index = self.open_elems.iter()
+                .position(|n| self.sink.same_node(n.clone(), furthest_block.clone()))
+                .expect("furthest block missing from open element stack");
+            self.open_elems.insert(new_furthest_block_index + 1, new_elem);
+            self.open_elem_kinds.insert(new_furthest_block_index + 1, new_kind);
+
+            // 20.
         }
     }
+    //§ END
 
     fn push(&mut self, elem: &Handle) {
+        let kind = ElementKind::from_name(&self.elem_name(elem.clone()));
         self.open_elems.push(elem.clone());
+        self.open_elem_kinds.push(kind);
     }
 
     fn pop(&mut self) -> Handle {
-        self.open_elems.pop().expect("no current element")
+        let elem = self.open_elems.pop().expect("no current element");
+        self.open_elem_kinds.pop();
+        self.report_end_span(elem.clone());
+        elem
     }
 
     fn remove_from_stack(&mut self, elem: &Handle) {
-        let mut open_elems = replace(&mut self.open_elems, vec!());
-        open_elems.retain(|x| !self.sink.same_node(elem.clone(), x.clone()));
-        self.open_elems = open_elems;
+        let index = self.open_elems.iter()
+            .position(|x| self.sink.same_node(elem.clone(), x.clone()));
+        match index {
+            Some(i) => {
+                self.open_elems.remove(i);
+                self.open_elem_kinds.remove(i);
+            }
+            None => (),
+        }
+        self.report_end_span(elem.clone());
+    }
+
+    // Tell the sink where a new element or comment came from.  An
+    // element which isn't for the current start tag was implied, and
+    // gets an empty span.
+    fn report_start_span(&mut self, node: Handle, name: &QualName) {
+        let span = unwrap_or_return!(self.token_span.clone(), ());
+        let for_tag = self.token_elem.is_none() && match self.token_tag {
+            Some((StartTag, ref tag)) => *tag == name.local
+                || (name.ns != ns!(HTML)
+                    && tag.as_slice().eq_ignore_ascii_case(name.local.as_slice())),
+            _ => false,
+        };
+        if for_tag {
+            self.token_elem = Some(node.clone());
+            self.sink.set_node_span(node, span);
+        } else {
+            self.sink.set_node_span(node, Span { start: span.start, end: span.start });
+        }
+    }
+
+    // Tell the sink where an element was closed: by its own end tag,
+    // by its own start tag if it's never pushed, or else implicitly
+    // where the current token starts.
+    fn report_end_span(&mut self, elem: Handle) {
+        let span = unwrap_or_return!(self.token_span.clone(), ());
+        let own_tag = match self.token_elem {
+            Some(ref e) => self.sink.same_node(e.clone(), elem.clone()),
+            None => false,
+        } || match self.token_tag {
+            Some((EndTag, ref tag)) => {
+                let name = self.elem_name(elem.clone());
+                name.local == *tag
+                    || (name.ns != ns!(HTML)
+                        && tag.as_slice().eq_ignore_ascii_case(name.local.as_slice()))
+            }
+            _ => false,
+        };
+        if own_tag {
+            self.sink.set_end_span(elem, span);
+        } else {
+            self.sink.set_end_span(elem, Span { start: span.start, end: span.start });
+        }
     }
 
+    //§ reconstruct-the-active-formatting-elements
     /// Reconstruct the active formatting elements.
     fn reconstruct_formatting(&mut self) {
-        // FIXME
+        {
+            let last = unwrap_or_return!(self.active_formatting.last(), ());
+            if self.is_marker_or_open(last) {
+                return;
+            }
+        }
+
+        // Rewind to just after the last entry which is a marker or is
+        // still open.
+        let mut entry_index = self.active_formatting.len() - 1;
+        while entry_index > 0 {
+            entry_index -= 1;
+            if self.is_marker_or_open(&self.active_formatting[entry_index]) {
+                entry_index += 1;
+                break;
+            }
+        }
+
+        // Create the rest again.
+        while entry_index < self.active_formatting.len() {
+            let tag = match self.active_formatting[entry_index] {
+                Element(_, ref t) => t.clone(),
+                Marker => fail!("Found marker during formatting element reconstruction"),
+            };
+            let elem = self.clone_formatting_element(&tag);
+            self.insert_appropriately(AppendNode(elem.clone()));
+            self.push(&elem);
+            *self.active_formatting.get_mut(entry_index) = Element(elem, tag);
+            entry_index += 1;
+        }
+    }
+    //§ END
+
+    fn is_marker_or_open(&self, entry: &FormatEntry<Handle>) -> bool {
+        match *entry {
+            Marker => true,
+            Element(ref node, _) => self.open_elems.iter()
+                .any(|n| self.sink.same_node(n.clone(), node.clone())),
+        }
+    }
+
+    fn position_in_active_formatting(&self, elem: &Handle) -> Option<uint> {
+        self.active_formatting.iter().position(|n| match *n {
+            Marker => false,
+            Element(ref handle, _) => self.sink.same_node(handle.clone(), elem.clone()),
+        })
+    }
+
+    // Create another element like one from the list of active formatting
+    // elements.  It's implied, never for the current tag, so it gets an
+    // empty span even if the current tag has the same name.
+    fn clone_formatting_element(&mut self, tag: &Tag) -> Handle {
+        let token_tag = self.token_tag.take();
+        let elem = self.create_element(QualName::new(ns!(HTML), tag.name.clone()),
+            tag.attrs.clone());
+        self.token_tag = token_tag;
+        elem
     }
 
     /// Get the first element on the stack, which will be the <html> element.
@@ -259,11 +655,9 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
             thead tr body html)
 
         for elem in self.open_elems.iter() {
-            let name = self.sink.elem_name(elem.clone());
+            let name = self.elem_name(elem.clone());
             if !body_end_ok(name.clone()) {
-                self.sink.parse_error(format_if!(self.opts.exact_errors,
-                    "Unexpected open tag at end of body",
-                    "Unexpected open tag {} at end of body", name));
+                self.unclosed_error(UnclosedElementAtBodyEnd(name));
                 // FIXME: Do we keep checking after finding one bad tag?
                 // The spec suggests not.
                 return;
@@ -272,11 +666,11 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
     }
 
     fn in_scope(&self, scope: TagSet, pred: |Handle| -> bool) -> bool {
-        for node in self.open_elems.iter().rev() {
+        for (node, kind) in self.open_elems.iter().zip(self.open_elem_kinds.iter()).rev() {
             if pred(node.clone()) {
                 return true;
             }
-            if scope(self.sink.elem_name(node.clone())) {
+            if scope(kind.name()) {
                 return false;
             }
         }
@@ -287,27 +681,48 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
     }
 
     fn elem_in(&self, elem: Handle, set: TagSet) -> bool {
-        set(self.sink.elem_name(elem))
+        set(self.elem_name(elem))
     }
 
     fn html_elem_named(&self, elem: Handle, name: Atom) -> bool {
-        self.sink.elem_name(elem) == QualName::new(ns!(HTML), name)
+        self.elem_name(elem) == QualName::new(ns!(HTML), name)
+    }
+
+    fn in_html_elem_named(&self, name: Atom) -> bool {
+        let name = QualName::new(ns!(HTML), name);
+        self.open_elem_kinds.iter().any(|kind| kind.name() == name)
     }
 
     fn current_node_named(&self, name: Atom) -> bool {
-        self.html_elem_named(self.current_node(), name)
+        self.current_kind().name() == QualName::new(ns!(HTML), name)
     }
 
     fn in_scope_named(&self, scope: TagSet, name: Atom) -> bool {
-        self.in_scope(scope, |elem|
-            self.html_elem_named(elem, name.clone()))
+        let name = QualName::new(ns!(HTML), name);
+        self.any_in_scope(scope, |n| n == name)
+    }
+
+    // Like `in_scope`, but matching elements by name, which only needs
+    // the kinds cached alongside the stack.
+    fn any_in_scope(&self, scope: TagSet, set: TagSet) -> bool {
+        for kind in self.open_elem_kinds.iter().rev() {
+            if set(kind.name()) {
+                return true;
+            }
+            if scope(kind.name()) {
+                return false;
+            }
+        }
+
+        // supposed to be impossible, because <html> is always in scope
+
+        false
     }
 
     //§ closing-elements-that-have-implied-end-tags
     fn generate_implied_end(&mut self, set: TagSet) {
         loop {
-            let elem = unwrap_or_return!(self.open_elems.last(), ()).clone();
-            let nsname = self.sink.elem_name(elem);
+            let nsname = unwrap_or_return!(self.open_elem_kinds.last(), ()).name();
             if !set(nsname) { return; }
             self.pop();
         }
@@ -327,7 +742,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
             if self.current_node_in(|x| pred(x)) {
                 break;
             }
-            self.open_elems.pop();
+            self.pop();
         }
     }
 
@@ -337,9 +752,13 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
         let mut n = 0;
         loop {
             n += 1;
-            match self.open_elems.pop() {
+            let name = match self.open_elem_kinds.last() {
                 None => break,
-                Some(elem) => if pred(self.sink.elem_name(elem)) { break; },
+                Some(kind) => kind.name(),
+            };
+            self.pop();
+            if pred(name) {
+                break;
             }
         }
         n
@@ -353,9 +772,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
     // Signal an error if it was not the first one.
     fn expect_to_close(&mut self, name: Atom) {
         if self.pop_until_named(name.clone()) != 1 {
-            self.sink.parse_error(format_if!(self.opts.exact_errors,
-                "Unexpected open element",
-                "Unexpected open element while closing {}", name));
+            self.parse_error(UnexpectedOpenElement(name));
         }
     }
 
@@ -379,37 +796,46 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
         }
     }
 
+    // Process a token misplaced in a table by the "in body" rules, with
+    // anything it inserts into the table moved to before the table.
+    //
+    // The tokens which get here (character data, and tags the table
+    // modes don't handle) never make the "in body" rules reprocess, so
+    // foster parenting only has to cover this one step.
     fn foster_parent_in_body(&mut self, token: Token) -> ProcessResult {
-        h5e_warn!("foster parenting not implemented");
         self.foster_parenting = true;
         let res = self.step(InBody, token);
-        // FIXME: what if res is Reprocess?
         self.foster_parenting = false;
-        res
+        match res {
+            Done | DoneAckSelfClosing => res,
+            _ => fail!("foster parented token was not completely processed"),
+        }
     }
 
     fn process_chars_in_table(&mut self, token: Token) -> ProcessResult {
-        declare_tag_set!(table_outer = table tbody tfoot thead tr)
+        declare_tag_set!(table_outer = table tbody template tfoot thead tr)
         if self.current_node_in(table_outer) {
             assert!(self.pending_table_text.is_empty());
             self.orig_mode = Some(self.mode);
             Reprocess(InTableText, token)
         } else {
-            self.sink.parse_error(format_if!(self.opts.exact_errors,
-                "Unexpected characters in table",
-                "Unexpected characters {} in table", to_escaped_string(&token)));
+            let details = self.describe(&token);
+            self.parse_error(UnexpectedCharsInTable(details));
             self.foster_parent_in_body(token)
         }
     }
 
     fn reset_insertion_mode(&mut self) -> InsertionMode {
         for (i, node) in self.open_elems.iter().enumerate().rev() {
-            let name = match self.sink.elem_name(node.clone()) {
+            let last = i == 0u;
+            let node = match (last, &self.context_elem) {
+                (true, &Some(ref ctx)) => ctx,
+                _ => node,
+            };
+            let name = match self.elem_name(node.clone()) {
                 QualName { ns: ns!(HTML), local } => local,
                 _ => continue,
             };
-            let last = i == 0u;
-            // FIXME: fragment case context element
             match name {
                 // FIXME: <select> sub-steps
                 atom!(select) => return InSelect,
@@ -428,7 +854,10 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                     Some(_) => return AfterHead,
                 },
 
-                atom!(template) => fail!("FIXME: <template> not implemented"),
+                atom!(template) => match self.template_modes.last() {
+                    Some(&mode) => return mode,
+                    None => (),
+                },
 
                 _ => (),
             }
@@ -436,41 +865,137 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
         InBody
     }
 
+    // The rules for "any other end tag" in the "in body" insertion mode.
+    fn process_end_tag_in_body(&mut self, tag: Tag) {
+        // Look back for a matching open element.
+        let mut match_idx = None;
+        let name = QualName::new(ns!(HTML), tag.name.clone());
+        for (i, kind) in self.open_elem_kinds.iter().enumerate().rev() {
+            if kind.name() == name {
+                match_idx = Some(i);
+                break;
+            }
+
+            if kind.is_special() {
+                self.parse_error(SpecialElementWhileClosing);
+                return;
+            }
+        }
+
+        // Can't use unwrap_or_return!() due to rust-lang/rust#16617.
+        let match_idx = match match_idx {
+            None => {
+                // I believe this is impossible, because the root
+                // <html> element is in special_tag.
+                self.unexpected(&tag);
+                return;
+            }
+            Some(x) => x,
+        };
+
+        self.generate_implied_end_except(tag.name.clone());
+
+        if match_idx != self.open_elems.len() - 1 {
+            // mis-nested tags
+            self.unexpected(&tag);
+        }
+        while self.open_elems.len() > match_idx {
+            self.pop();
+        }
+    }
+
     fn close_the_cell(&mut self) {
         self.generate_implied_end(cursory_implied_end);
         if self.pop_until(td_th) != 1 {
-            self.sink.parse_error(Slice("expected to close <td> or <th> with cell"));
+            self.parse_error(CellNotClosed);
         }
     }
 
     fn append_text(&mut self, text: String) -> ProcessResult {
+        let parent = self.open_elems.last().unwrap_or(&self.doc_handle).clone();
+        match self.token_span.clone() {
+            Some(span) => self.sink.set_text_span(parent.clone(), span),
+            None => (),
+        }
+        self.sink.account_text(parent, text.len());
         self.insert_appropriately(AppendText(text));
         Done
     }
 
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle {
+        if self.opts.check_custom_element_names && name.ns == ns!(HTML) {
+            let local = name.local.as_slice();
+            if local.contains_char('-') && !is_valid_custom_element_name(local) {
+                self.parse_error(InvalidCustomElementName(name.local.clone()));
+            }
+        }
+
+        let bytes = attrs.iter().fold(0, |n, a| n + a.value.len());
+        self.sink.account_attrs(&name, bytes);
+        let kind = ElementKind::from_name(&name);
+        let is = attrs.iter()
+            .find(|a| a.name.ns == ns!("") && a.name.local.as_slice() == "is")
+            .map(|a| a.value.clone());
+        let elem = self.sink.create_element_of_kind(kind, is, name.clone(), attrs);
+        self.report_start_span(elem.clone(), &name);
+        self.remember_elem_name(elem.clone(), name);
+        elem
+    }
+
+    // Get the name of an element, from our cache if possible.
+    fn elem_name(&self, elem: Handle) -> QualName {
+        if self.opts.elem_name_cache_size == 0 {
+            return self.sink.elem_name(elem);
+        }
+
+        let cached = self.elem_names.borrow()
+            .find(|h| self.sink.same_node(h.clone(), elem.clone()));
+        match cached {
+            Some(name) => name,
+            None => {
+                let name = self.sink.elem_name(elem.clone());
+                self.remember_elem_name(elem, name.clone());
+                name
+            }
+        }
+    }
+
+    fn remember_elem_name(&self, elem: Handle, name: QualName) {
+        self.elem_names.borrow_mut().insert(elem, name);
+    }
+
     fn append_comment(&mut self, text: String) -> ProcessResult {
-        let comment = self.sink.create_comment(text);
+        let comment = self.create_comment(text);
         self.insert_appropriately(AppendNode(comment));
         Done
     }
 
+    fn create_comment(&mut self, text: String) -> Handle {
+        let comment = self.sink.create_comment(text);
+        match self.token_span.clone() {
+            Some(span) => self.sink.set_node_span(comment.clone(), span),
+            None => (),
+        }
+        comment
+    }
+
     fn append_comment_to_doc(&mut self, text: String) -> ProcessResult {
         let target = self.doc_handle.clone();
-        let comment = self.sink.create_comment(text);
+        let comment = self.create_comment(text);
         self.sink.append(target, AppendNode(comment));
         Done
     }
 
     fn append_comment_to_html(&mut self, text: String) -> ProcessResult {
         let target = self.html_elem();
-        let comment = self.sink.create_comment(text);
+        let comment = self.create_comment(text);
         self.sink.append(target, AppendNode(comment));
         Done
     }
 
     //§ creating-and-inserting-nodes
     fn create_root(&mut self, attrs: Vec<Attribute>) {
-        let elem = self.sink.create_element(qualname!(HTML, html), attrs);
+        let elem = self.create_element(qualname!(HTML, html), attrs);
         self.push(&elem);
         self.sink.append(self.doc_handle.clone(), AppendNode(elem));
         // FIXME: application cache selection algorithm
@@ -478,11 +1003,11 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
 
     fn insert_element(&mut self, push: PushFlag, name: Atom, attrs: Vec<Attribute>)
             -> Handle {
-        let elem = self.sink.create_element(QualName::new(ns!(HTML), name), attrs);
+        let elem = self.create_element(QualName::new(ns!(HTML), name), attrs);
         self.insert_appropriately(AppendNode(elem.clone()));
         match push {
             Push => self.push(&elem),
-            NoPush => (),
+            NoPush => self.report_end_span(elem.clone()),
         }
         // FIXME: Remove from the stack if we can't append?
         elem
@@ -529,4 +1054,138 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
             }
         }
     }
+
+    //§ tree-construction
+    // The current node, except that a fragment's context element stands
+    // in for the root.
+    fn adjusted_current_node(&self) -> Handle {
+        if self.open_elems.len() == 1 {
+            match self.context_elem {
+                Some(ref ctx) => return ctx.clone(),
+                None => (),
+            }
+        }
+        self.current_node()
+    }
+
+    fn is_html_integration_point(&self, elem: Handle) -> bool {
+        if self.annotation_xml_integration_points.iter()
+                .any(|e| self.sink.same_node(e.clone(), elem.clone())) {
+            return true;
+        }
+        svg_html_integration_point(self.elem_name(elem))
+    }
+
+    // Should this token be processed by the rules for foreign content,
+    // rather than those of the insertion mode?
+    fn is_foreign(&self, token: &Token) -> bool {
+        match *token {
+            EOFToken => return false,
+            _ => (),
+        }
+        if self.open_elems.is_empty() {
+            return false;
+        }
+
+        let node = self.adjusted_current_node();
+        let name = self.elem_name(node.clone());
+        if name.ns == ns!(HTML) {
+            return false;
+        }
+
+        let start = match *token {
+            TagToken(Tag { kind: StartTag, ref name, .. }) => Some(name.as_slice()),
+            _ => None,
+        };
+        let chars = match *token {
+            CharacterTokens(..) | NullCharacterToken => true,
+            _ => false,
+        };
+
+        if mathml_text_integration_point(name.clone()) {
+            match start {
+                Some("mglyph") | Some("malignmark") => (),
+                Some(_) => return false,
+                None => if chars { return false; },
+            }
+        }
+
+        if name.ns == ns!(MathML) && name.local.as_slice() == "annotation-xml"
+                && start == Some("svg") {
+            return false;
+        }
+
+        !((start.is_some() || chars) && self.is_html_integration_point(node))
+    }
+    //§ END
+
+    //§ creating-and-inserting-nodes
+    // Insert an element in a foreign namespace, first restoring the case
+    // of SVG and MathML names, and putting attributes like `xlink:href`
+    // in their namespaces.
+    fn insert_foreign_element_for(&mut self, tag: Tag, ns: Namespace) -> ProcessResult {
+        let Tag { name, self_closing, attrs, .. } = tag;
+        let name = match ns {
+            ns!(SVG) => svg_tag_name(&name).unwrap_or(name),
+            _ => name,
+        };
+        let attrs: Vec<Attribute> = attrs.into_iter().map(|attr| Attribute {
+            name: adjust_attr_name(attr.name.clone(), &ns),
+            ..attr
+        }).collect();
+
+        let integration_point = ns == ns!(MathML)
+            && name.as_slice() == "annotation-xml"
+            && attrs.iter().any(|a| a.name.ns == ns!("")
+                && a.name.local.as_slice() == "encoding"
+                && (a.value.as_slice().eq_ignore_ascii_case("text/html")
+                    || a.value.as_slice().eq_ignore_ascii_case("application/xhtml+xml")));
+        let is_svg_script = ns == ns!(SVG) && name == atom!(script);
+
+        let elem = self.create_element(QualName::new(ns, name), attrs);
+        if integration_point {
+            self.annotation_xml_integration_points.push(elem.clone());
+        }
+        self.insert_appropriately(AppendNode(elem.clone()));
+
+        if self_closing {
+            self.report_end_span(elem.clone());
+            if is_svg_script {
+                self.sink.complete_script(elem);
+                self.suspend_tokenizer = self.opts.suspend_after_scripts;
+            }
+            DoneAckSelfClosing
+        } else {
+            self.push(&elem);
+            Done
+        }
+    }
+    //§ END
+
+    //§ parsing-main-inforeign
+    fn foreign_start_tag(&mut self, tag: Tag) -> ProcessResult {
+        let ns = self.elem_name(self.adjusted_current_node()).ns;
+        self.insert_foreign_element_for(tag, ns)
+    }
+
+    // An HTML start tag which breaks out of foreign content.
+    fn unexpected_start_tag_in_foreign_content(&mut self, tag: Tag) -> ProcessResult {
+        self.unexpected(&tag);
+        if self.opts.fragment {
+            return self.foreign_start_tag(tag);
+        }
+
+        self.pop();
+        loop {
+            let current = self.current_node();
+            let name = self.elem_name(current.clone());
+            if name.ns == ns!(HTML) || mathml_text_integration_point(name)
+                    || self.is_html_integration_point(current) {
+                break;
+            }
+            self.pop();
+        }
+        Reprocess(self.mode, TagToken(tag))
+    }
+    //§ END
 }
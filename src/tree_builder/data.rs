@@ -15,8 +15,11 @@ use util::str::AsciiExt;
 
 use collections::string::String;
 
+use string_cache::{Atom, QualName};
+
 // These should all be lowercase, for ASCII-case-insensitive matching.
 static QUIRKY_PUBLIC_PREFIXES: &'static [&'static str] = &[
+    "+//silmaril//dtd html pro v0r11 19970101//",
     "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
     "-//as//dtd html 3.0 aswedit + extensions//",
     "-//ietf//dtd html 2.0 level 1//",
@@ -131,11 +134,12 @@ pub fn doctype_error_and_quirks(doctype: &Doctype, iframe_srcdoc: bool) -> (bool
     let system = opt_to_ascii_lower(system);
 
     let quirk = match (opt_as_slice(&public), opt_as_slice(&system)) {
+        // An iframe srcdoc document is never in quirks mode.
+        _ if iframe_srcdoc => NoQuirks,
+
         _ if doctype.force_quirks => Quirks,
         _ if name != Some("html") => Quirks,
 
-        _ if iframe_srcdoc => NoQuirks,
-
         (Some(ref p), _) if QUIRKY_PUBLIC_MATCHES.contains(p) => Quirks,
         (_, Some(ref s)) if QUIRKY_SYSTEM_MATCHES.contains(s) => Quirks,
 
@@ -152,3 +156,219 @@ pub fn doctype_error_and_quirks(doctype: &Doctype, iframe_srcdoc: bool) -> (bool
 
     (err, quirk)
 }
+
+//§ creating-and-inserting-nodes
+// SVG names which the tokenizer has lowercased, and their proper case.
+static SVG_TAG_NAMES: &'static [(&'static str, &'static str)] = &[
+    ("altglyph", "altGlyph"),
+    ("altglyphdef", "altGlyphDef"),
+    ("altglyphitem", "altGlyphItem"),
+    ("animatecolor", "animateColor"),
+    ("animatemotion", "animateMotion"),
+    ("animatetransform", "animateTransform"),
+    ("clippath", "clipPath"),
+    ("feblend", "feBlend"),
+    ("fecolormatrix", "feColorMatrix"),
+    ("fecomponenttransfer", "feComponentTransfer"),
+    ("fecomposite", "feComposite"),
+    ("feconvolvematrix", "feConvolveMatrix"),
+    ("fediffuselighting", "feDiffuseLighting"),
+    ("fedisplacementmap", "feDisplacementMap"),
+    ("fedistantlight", "feDistantLight"),
+    ("feflood", "feFlood"),
+    ("fefunca", "feFuncA"),
+    ("fefuncb", "feFuncB"),
+    ("fefuncg", "feFuncG"),
+    ("fefuncr", "feFuncR"),
+    ("fegaussianblur", "feGaussianBlur"),
+    ("feimage", "feImage"),
+    ("femerge", "feMerge"),
+    ("femergenode", "feMergeNode"),
+    ("femorphology", "feMorphology"),
+    ("feoffset", "feOffset"),
+    ("fepointlight", "fePointLight"),
+    ("fespecularlighting", "feSpecularLighting"),
+    ("fespotlight", "feSpotLight"),
+    ("fetile", "feTile"),
+    ("feturbulence", "feTurbulence"),
+    ("foreignobject", "foreignObject"),
+    ("glyphref", "glyphRef"),
+    ("lineargradient", "linearGradient"),
+    ("radialgradient", "radialGradient"),
+    ("textpath", "textPath"),
+];
+
+static SVG_ATTR_NAMES: &'static [(&'static str, &'static str)] = &[
+    ("attributename", "attributeName"),
+    ("attributetype", "attributeType"),
+    ("basefrequency", "baseFrequency"),
+    ("baseprofile", "baseProfile"),
+    ("calcmode", "calcMode"),
+    ("clippathunits", "clipPathUnits"),
+    ("contentscripttype", "contentScriptType"),
+    ("contentstyletype", "contentStyleType"),
+    ("diffuseconstant", "diffuseConstant"),
+    ("edgemode", "edgeMode"),
+    ("externalresourcesrequired", "externalResourcesRequired"),
+    ("filterres", "filterRes"),
+    ("filterunits", "filterUnits"),
+    ("glyphref", "glyphRef"),
+    ("gradienttransform", "gradientTransform"),
+    ("gradientunits", "gradientUnits"),
+    ("kernelmatrix", "kernelMatrix"),
+    ("kernelunitlength", "kernelUnitLength"),
+    ("keypoints", "keyPoints"),
+    ("keysplines", "keySplines"),
+    ("keytimes", "keyTimes"),
+    ("lengthadjust", "lengthAdjust"),
+    ("limitingconeangle", "limitingConeAngle"),
+    ("markerheight", "markerHeight"),
+    ("markerunits", "markerUnits"),
+    ("markerwidth", "markerWidth"),
+    ("maskcontentunits", "maskContentUnits"),
+    ("maskunits", "maskUnits"),
+    ("numoctaves", "numOctaves"),
+    ("pathlength", "pathLength"),
+    ("patterncontentunits", "patternContentUnits"),
+    ("patterntransform", "patternTransform"),
+    ("patternunits", "patternUnits"),
+    ("pointsatx", "pointsAtX"),
+    ("pointsaty", "pointsAtY"),
+    ("pointsatz", "pointsAtZ"),
+    ("preservealpha", "preserveAlpha"),
+    ("preserveaspectratio", "preserveAspectRatio"),
+    ("primitiveunits", "primitiveUnits"),
+    ("refx", "refX"),
+    ("refy", "refY"),
+    ("repeatcount", "repeatCount"),
+    ("repeatdur", "repeatDur"),
+    ("requiredextensions", "requiredExtensions"),
+    ("requiredfeatures", "requiredFeatures"),
+    ("specularconstant", "specularConstant"),
+    ("specularexponent", "specularExponent"),
+    ("spreadmethod", "spreadMethod"),
+    ("startoffset", "startOffset"),
+    ("stddeviation", "stdDeviation"),
+    ("stitchtiles", "stitchTiles"),
+    ("surfacescale", "surfaceScale"),
+    ("systemlanguage", "systemLanguage"),
+    ("tablevalues", "tableValues"),
+    ("targetx", "targetX"),
+    ("targety", "targetY"),
+    ("textlength", "textLength"),
+    ("viewbox", "viewBox"),
+    ("viewtarget", "viewTarget"),
+    ("xchannelselector", "xChannelSelector"),
+    ("ychannelselector", "yChannelSelector"),
+    ("zoomandpan", "zoomAndPan"),
+];
+
+static MATHML_ATTR_NAMES: &'static [(&'static str, &'static str)] = &[
+    ("definitionurl", "definitionURL"),
+];
+
+fn lookup(table: &'static [(&'static str, &'static str)], name: &Atom) -> Option<Atom> {
+    let name = name.as_slice();
+    table.iter().find(|&&(from, _)| from == name).map(|&(_, to)| Atom::from_slice(to))
+}
+
+/// The proper case of an SVG element name, if it isn't all lowercase.
+pub fn svg_tag_name(name: &Atom) -> Option<Atom> {
+    lookup(SVG_TAG_NAMES, name)
+}
+
+/// The proper case of an SVG attribute name, if it isn't all lowercase.
+pub fn svg_attr_name(name: &Atom) -> Option<Atom> {
+    lookup(SVG_ATTR_NAMES, name)
+}
+
+/// The proper case of a MathML attribute name, if it isn't all lowercase.
+pub fn mathml_attr_name(name: &Atom) -> Option<Atom> {
+    lookup(MATHML_ATTR_NAMES, name)
+}
+
+/// The namespaced name of an attribute like `xlink:href` on a foreign
+/// element.
+pub fn foreign_attr_name(name: &Atom) -> Option<QualName> {
+    let (ns, local) = match name.as_slice() {
+        "xlink:actuate" | "xlink:arcrole" | "xlink:href" | "xlink:role"
+            | "xlink:show" | "xlink:title" | "xlink:type"
+            => (ns!(XLink), name.as_slice().slice_from(6)),
+        "xml:base" | "xml:lang" | "xml:space" => (ns!(XML), name.as_slice().slice_from(4)),
+        "xmlns" => (ns!(XMLNS), "xmlns"),
+        "xmlns:xlink" => (ns!(XMLNS), "xlink"),
+        _ => return None,
+    };
+    Some(QualName::new(ns, Atom::from_slice(local)))
+}
+//§ END
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::doctype_error_and_quirks;
+
+    use super::{svg_tag_name, svg_attr_name, foreign_attr_name};
+
+    use tokenizer::Doctype;
+    use tree_builder::interface::{QuirksMode, Quirks, LimitedQuirks, NoQuirks};
+
+    use collections::string::String;
+
+    use string_cache::{Atom, QualName};
+
+    fn quirks(name: Option<&str>, public: Option<&str>, system: Option<&str>) -> QuirksMode {
+        let doctype = Doctype {
+            name: name.map(|x| String::from_str(x)),
+            public_id: public.map(|x| String::from_str(x)),
+            system_id: system.map(|x| String::from_str(x)),
+            force_quirks: false,
+        };
+        let (_, mode) = doctype_error_and_quirks(&doctype, false);
+        mode
+    }
+
+    test_eq!(html5, quirks(Some("html"), None, None), NoQuirks)
+    test_eq!(not_html, quirks(Some("svg"), None, None), Quirks)
+    test_eq!(public_match, quirks(Some("html"), Some("HTML"), None), Quirks)
+    test_eq!(public_prefix, quirks(Some("html"),
+        Some("+//Silmaril//DTD HTML Pro v0r11 19970101//EN"), None), Quirks)
+    test_eq!(system_match, quirks(Some("html"), None,
+        Some("http://www.IBM.com/data/dtd/v11/ibmxhtml1-transitional.dtd")), Quirks)
+    test_eq!(xhtml_transitional, quirks(Some("html"),
+        Some("-//W3C//DTD XHTML 1.0 Transitional//EN"), None), LimitedQuirks)
+    test_eq!(html4_no_system, quirks(Some("html"),
+        Some("-//W3C//DTD HTML 4.01 Transitional//EN"), None), Quirks)
+    test_eq!(html4_system, quirks(Some("html"), Some("-//W3C//DTD HTML 4.01 Transitional//EN"),
+        Some("http://www.w3.org/TR/html4/loose.dtd")), LimitedQuirks)
+    test_eq!(html4_strict, quirks(Some("html"), Some("-//W3C//DTD HTML 4.01//EN"), None),
+        NoQuirks)
+
+    #[test]
+    fn forced() {
+        let mut doctype = Doctype::new();
+        doctype.name = Some(String::from_str("html"));
+        doctype.force_quirks = true;
+        assert_eq!(doctype_error_and_quirks(&doctype, false), (false, Quirks));
+        assert_eq!(doctype_error_and_quirks(&doctype, true), (false, NoQuirks));
+    }
+
+    #[test]
+    fn srcdoc_never_quirks() {
+        let mut doctype = Doctype::new();
+        doctype.name = Some(String::from_str("foo"));
+        assert_eq!(doctype_error_and_quirks(&doctype, false), (true, Quirks));
+        assert_eq!(doctype_error_and_quirks(&doctype, true), (true, NoQuirks));
+    }
+
+    test_eq!(svg_tag, svg_tag_name(&Atom::from_slice("foreignobject")),
+        Some(Atom::from_slice("foreignObject")))
+    test_eq!(svg_tag_lower, svg_tag_name(&Atom::from_slice("path")), None)
+    test_eq!(svg_attr, svg_attr_name(&Atom::from_slice("viewbox")),
+        Some(Atom::from_slice("viewBox")))
+    test_eq!(xlink_href, foreign_attr_name(&Atom::from_slice("xlink:href")),
+        Some(QualName::new(ns!(XLink), Atom::from_slice("href"))))
+    test_eq!(xmlns, foreign_attr_name(&Atom::from_slice("xmlns")),
+        Some(QualName::new(ns!(XMLNS), Atom::from_slice("xmlns"))))
+    test_eq!(not_foreign, foreign_attr_name(&Atom::from_slice("xlink")), None)
+}
@@ -0,0 +1,293 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use core::prelude::*;
+
+use tokenizer::{TokenizerError, Span, Severity, Informational, Recoverable, Fatal};
+
+use collections::string::String;
+use collections::str::{MaybeOwned, Slice};
+
+use string_cache::{Atom, QualName};
+
+/// What kind of parse error the tree builder found, with any details
+/// needed to describe it.  Details which take formatting to produce,
+/// like a description of the offending token, are only filled in with
+/// the `exact_errors` option.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum ErrorCode {
+    /// A token which isn't allowed in the current insertion mode.
+    UnexpectedToken(Option<String>),
+
+    /// A self-closing flag on a start tag which isn't a void element.
+    UnacknowledgedSelfClosingTag,
+
+    /// A DOCTYPE which isn't one of the allowed forms.
+    BadDoctype(Option<String>),
+
+    /// A DOCTYPE after the start of the document.
+    MisplacedDoctype(Option<String>),
+
+    /// An end tag with this name, for `<body>` or `<html>`, when no
+    /// `<body>` is in scope.
+    BodyNotInScope(Atom),
+
+    NestedHeading,
+    NestedForm,
+    NestedButton,
+    NestedNobr,
+
+    /// `</form>` without a form element pointer.
+    NoFormElement,
+
+    /// `</form>` when the form element isn't in scope.
+    FormNotInScope,
+
+    /// `</form>` when the form element isn't the current node.
+    FormNotCurrentNode,
+
+    /// An end tag with this name, with no such element in scope.
+    NoElementToClose(Atom),
+
+    /// A heading end tag with no heading element in scope.
+    NoHeadingToClose,
+
+    /// A heading end tag which closed a different heading element.
+    WrongHeadingClosed,
+
+    /// An `<isindex>` tag, whose rules aren't implemented, with the
+    /// `ignore_missing_rules` option.
+    UnimplementedIsindex,
+
+    /// Text other than whitespace in a table.
+    NonSpaceTableText,
+
+    /// Characters in a table where text isn't allowed.
+    UnexpectedCharsInTable(Option<String>),
+
+    FormattingElementNotOpen,
+    FormattingElementNotInScope,
+    FormattingElementNotCurrentNode,
+
+    /// An element with this name still open at the end of the body.
+    UnclosedElementAtBodyEnd(QualName),
+
+    /// Other elements were open above the one with this name, which
+    /// was being closed.
+    UnexpectedOpenElement(Atom),
+
+    /// A special element was open above the one an end tag closes.
+    SpecialElementWhileClosing,
+
+    /// Closing a cell closed more than the `<td>` or `<th>`.
+    CellNotClosed,
+
+    /// An element name with a hyphen which isn't a valid custom
+    /// element name.
+    InvalidCustomElementName(Atom),
+}
+
+impl ErrorCode {
+    /// How much this error matters.  Errors after which the tree may
+    /// well not be what the author meant, like misnested formatting
+    /// elements or text foster parented out of a table, are `Fatal`.
+    pub fn severity(&self) -> Severity {
+        match *self {
+            InvalidCustomElementName(_) | UnimplementedIsindex => Informational,
+            FormattingElementNotOpen | FormattingElementNotInScope
+            | FormattingElementNotCurrentNode | NonSpaceTableText
+            | UnexpectedCharsInTable(_) | NestedForm | FormNotInScope
+            | FormNotCurrentNode | SpecialElementWhileClosing
+            | UnexpectedOpenElement(_) | UnclosedElementAtBodyEnd(_) => Fatal,
+            _ => Recoverable,
+        }
+    }
+
+    /// A short description, which leaves out any details.
+    pub fn summary(&self) -> &'static str {
+        match *self {
+            UnexpectedToken(_) => "Unexpected token",
+            UnacknowledgedSelfClosingTag => "Unacknowledged self-closing tag",
+            BadDoctype(_) => "Bad DOCTYPE",
+            MisplacedDoctype(_) => "DOCTYPE in body",
+            BodyNotInScope(_) => "End tag with no <body> in scope",
+            NestedHeading => "nested heading tags",
+            NestedForm => "nested forms",
+            NestedButton => "nested buttons",
+            NestedNobr => "Nested <nobr>",
+            NoFormElement => "Null form element pointer on </form>",
+            FormNotInScope => "Form element not in scope on </form>",
+            FormNotCurrentNode => "Bad open element on </form>",
+            NoElementToClose(_) => "No matching tag to close",
+            NoHeadingToClose => "No heading tag to close",
+            WrongHeadingClosed => "Closing wrong heading tag",
+            UnimplementedIsindex => "Ignoring unimplemented rules for <isindex>",
+            NonSpaceTableText => "Non-space table text",
+            UnexpectedCharsInTable(_) => "Unexpected characters in table",
+            FormattingElementNotOpen => "Formatting element not open",
+            FormattingElementNotInScope => "Formatting element not in scope",
+            FormattingElementNotCurrentNode => "Formatting element not current node",
+            UnclosedElementAtBodyEnd(_) => "Unexpected open tag at end of body",
+            UnexpectedOpenElement(_) => "Unexpected open element",
+            SpecialElementWhileClosing => "Found special tag while closing generic tag",
+            CellNotClosed => "expected to close <td> or <th> with cell",
+            InvalidCustomElementName(_) => "Invalid custom element name",
+        }
+    }
+
+    /// Describe the error, with any details.
+    pub fn message(&self) -> MaybeOwned<'static> {
+        let summary = self.summary();
+        match *self {
+            UnexpectedToken(Some(ref t)) => format_if!(true, summary, "Unexpected token {}", t),
+            BadDoctype(Some(ref d)) => format_if!(true, summary, "Bad DOCTYPE: {}", d),
+            MisplacedDoctype(Some(ref m))
+                => format_if!(true, summary, "DOCTYPE in insertion mode {}", m),
+            BodyNotInScope(ref name)
+                => format_if!(true, summary, "</{}> with no <body> in scope", name),
+            NoElementToClose(ref name)
+                => format_if!(true, summary, "No <{}> tag to close", name),
+            UnexpectedCharsInTable(Some(ref t))
+                => format_if!(true, summary, "Unexpected characters {} in table", t),
+            UnclosedElementAtBodyEnd(ref name)
+                => format_if!(true, summary, "Unexpected open tag {} at end of body", name),
+            UnexpectedOpenElement(ref name)
+                => format_if!(true, summary, "Unexpected open element while closing {}", name),
+            InvalidCustomElementName(ref name)
+                => format_if!(true, summary, "Invalid custom element name {}", name),
+            _ => Slice(summary),
+        }
+    }
+}
+
+/// A parse error from the tree builder: what went wrong, and where.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub struct TreeBuilderError {
+    pub code: ErrorCode,
+
+    /// The span of the token being processed, if the tokenizer's
+    /// `track_spans` option is set.
+    pub span: Option<Span>,
+
+    /// With the `exact_errors` option, the insertion mode and the
+    /// names of the open elements when the error was found.
+    pub context: Option<String>,
+}
+
+impl TreeBuilderError {
+    /// Describe the error, with any details and context.  The message
+    /// is only formatted when this is called.
+    pub fn message(&self) -> MaybeOwned<'static> {
+        match self.context {
+            None => self.code.message(),
+            Some(ref c) => format_if!(true, self.code.summary(),
+                "{} ({})", self.code.message(), c),
+        }
+    }
+}
+
+/// A parse error passed to `TreeSink::parse_error`, from either the
+/// tokenizer or the tree builder.
+#[deriving(PartialEq, Eq, Clone, Show)]
+pub enum Error {
+    TokenizerErr(TokenizerError),
+    TreeBuilderErr(TreeBuilderError),
+}
+
+impl Error {
+    /// How much this error matters.
+    pub fn severity(&self) -> Severity {
+        match *self {
+            TokenizerErr(ref e) => e.code.severity(),
+            TreeBuilderErr(ref e) => e.code.severity(),
+        }
+    }
+
+    /// A short description, which leaves out any details.
+    pub fn summary(&self) -> &'static str {
+        match *self {
+            TokenizerErr(ref e) => e.code.summary(),
+            TreeBuilderErr(ref e) => e.code.summary(),
+        }
+    }
+
+    /// Describe the error, with any details, in English.  The message
+    /// is only formatted when this is called.
+    pub fn message(&self) -> MaybeOwned<'static> {
+        self.message_in(&English)
+    }
+
+    /// Describe the error using a message catalog.
+    pub fn message_in<C: MessageCatalog>(&self, catalog: &C) -> MaybeOwned<'static> {
+        match *self {
+            TokenizerErr(ref e) => catalog.tokenizer_message(e),
+            TreeBuilderErr(ref e) => catalog.tree_builder_message(e),
+        }
+    }
+}
+
+/// Text for parse errors, so that a validator can show them in another
+/// language.  Each method defaults to the English message, so a catalog
+/// need only override the codes it translates.
+pub trait MessageCatalog {
+    /// Describe an error from the tokenizer.
+    fn tokenizer_message(&self, err: &TokenizerError) -> MaybeOwned<'static> {
+        err.message()
+    }
+
+    /// Describe an error from the tree builder.
+    fn tree_builder_message(&self, err: &TreeBuilderError) -> MaybeOwned<'static> {
+        err.message()
+    }
+}
+
+/// The built-in English messages.
+pub struct English;
+
+impl MessageCatalog for English { }
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{MessageCatalog, English, Error, TokenizerErr, TreeBuilderErr};
+    use super::{TreeBuilderError, NestedForm, NoElementToClose};
+    use tokenizer::{TokenizerError, DuplicateAttribute, Position};
+
+    use collections::str::{MaybeOwned, Slice};
+
+    struct French;
+
+    impl MessageCatalog for French {
+        fn tree_builder_message(&self, err: &TreeBuilderError) -> MaybeOwned<'static> {
+            match err.code {
+                NestedForm => Slice("formulaires imbriqués"),
+                _ => err.message(),
+            }
+        }
+    }
+
+    fn tree_builder_err(code: super::ErrorCode) -> Error {
+        TreeBuilderErr(TreeBuilderError { code: code, span: None, context: None })
+    }
+
+    test_eq!(translated, tree_builder_err(NestedForm).message_in(&French).as_slice(),
+        "formulaires imbriqués")
+
+    test_eq!(untranslated,
+        tree_builder_err(NoElementToClose(atom!(p))).message_in(&French).as_slice(),
+        "No <p> tag to close")
+
+    test_eq!(tokenizer_default, TokenizerErr(TokenizerError {
+            code: DuplicateAttribute,
+            pos: Position { offset: 0, line: 0, column: 0 },
+        }).message_in(&French).as_slice(), "Duplicate attribute")
+
+    test_eq!(english, tree_builder_err(NestedForm).message_in(&English).as_slice(),
+        tree_builder_err(NestedForm).message().as_slice())
+}
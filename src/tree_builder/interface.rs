@@ -12,11 +12,12 @@
 
 use core::prelude::*;
 
-use tokenizer::Attribute;
+use tokenizer::{Attribute, Span};
+use tree_builder::kind::ElementKind;
+use tree_builder::error::Error;
 
 use collections::vec::Vec;
 use collections::string::String;
-use collections::str::MaybeOwned;
 
 use string_cache::QualName;
 
@@ -42,9 +43,26 @@ pub enum NodeOrText<Handle> {
 /// `Handle` is a reference to a DOM node.  The tree builder requires
 /// that a `Handle` implements `Clone` to get another reference to
 /// the same node.
+///
+/// ## Deferred sinks
+///
+/// A sink may queue operations and apply them later, for example in
+/// another task, with `Handle`s that are just identifiers allocated by
+/// the sink.  Almost every method only tells the sink what to do.  The
+/// exceptions, which need an answer right away, are:
+///
+/// * `same_node`, which only compares handles, not the nodes themselves;
+/// * `elem_name`, which is only asked about elements the tree builder
+///   created, so the answer never changes;
+/// * `get_template_contents`, whose handle the sink can allocate when
+///   it creates the `<template>`;
+/// * `append_before_sibling`, whose result depends on the tree.  The
+///   tree builder only calls it through `append_based_on_parent_node`,
+///   which a deferred sink should override to queue a single operation.
 pub trait TreeSink<Handle> {
-    /// Signal a parse error.
-    fn parse_error(&mut self, msg: MaybeOwned<'static>);
+    /// Signal a parse error, from the tokenizer or the tree builder.
+    /// Call `message()` on the error for a description.
+    fn parse_error(&mut self, err: Error);
 
     /// Get a handle to the `Document` node.
     fn get_document(&mut self) -> Handle;
@@ -56,6 +74,9 @@ pub trait TreeSink<Handle> {
     ///
     /// Should never be called on a non-element node;
     /// feel free to `fail!`.
+    ///
+    /// The tree builder asks this often.  If answering is expensive, set
+    /// `TreeBuilderOpts::elem_name_cache_size`.
     fn elem_name(&self, target: Handle) -> QualName;
 
     /// Set the document's quirks mode.
@@ -64,14 +85,36 @@ pub trait TreeSink<Handle> {
     /// Create an element.
     fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> Handle;
 
+    /// Create an element, given its `ElementKind` as computed by the
+    /// tree builder, and the value of its `is` attribute if any, for
+    /// customized built-in elements.  The tree builder always calls this
+    /// rather than `create_element`, which is what the default
+    /// implementation does.
+    fn create_element_of_kind(&mut self, _kind: ElementKind, _is: Option<String>,
+            name: QualName, attrs: Vec<Attribute>) -> Handle {
+        self.create_element(name, attrs)
+    }
+
     /// Create a comment node.
     fn create_comment(&mut self, text: String) -> Handle;
 
+    /// Get a handle to the template contents of a `<template>`, a
+    /// document fragment which holds the template's children instead
+    /// of the element itself.  The same handle should be returned each
+    /// time.
+    ///
+    /// Will only be called on HTML `<template>` elements.
+    fn get_template_contents(&mut self, target: Handle) -> Handle;
+
     /// Append a node as the last child of the given node.  If this would
     /// produce adjacent sibling text nodes, it should concatenate the text
     /// instead.
     ///
-    /// The child node will not already have a parent.
+    /// The child node will not already have a parent, and will not be
+    /// `parent` or one of its ancestors.  Adoption agency and foster
+    /// parenting rely on this to move nodes with `remove_from_parent`
+    /// followed by `append`.  `sink::validating::ValidatingSink`
+    /// checks these promises around any sink.
     fn append(&mut self, parent: Handle, child: NodeOrText<Handle>);
 
     /// Append a node as the sibling immediately before the given node.  If that node
@@ -87,6 +130,23 @@ pub trait TreeSink<Handle> {
         sibling: Handle,
         new_node: NodeOrText<Handle>) -> Result<(), NodeOrText<Handle>>;
 
+    /// If `element` has a parent, insert `child` immediately before it,
+    /// as in `append_before_sibling`.  Otherwise append `child` to
+    /// `prev_element`.  This is used for foster parenting.
+    ///
+    /// Whether `element` has a parent can depend on earlier operations
+    /// and on scripts, so a deferred sink should decide this when it
+    /// applies the operation.
+    fn append_based_on_parent_node(&mut self,
+            element: Handle,
+            prev_element: Handle,
+            child: NodeOrText<Handle>) {
+        match self.append_before_sibling(element, child) {
+            Ok(()) => (),
+            Err(child) => self.append(prev_element, child),
+        }
+    }
+
     /// Append a `DOCTYPE` element to the `Document` node.
     fn append_doctype_to_document(&mut self, name: String, public_id: String, system_id: String);
 
@@ -94,14 +154,63 @@ pub trait TreeSink<Handle> {
     /// with that name already exists.
     fn add_attrs_if_missing(&mut self, target: Handle, attrs: Vec<Attribute>);
 
-    /// Detach the given node from its parent.
+    /// Detach the given node from its parent.  Afterwards it has no
+    /// parent, and may be appended elsewhere.  If it has no parent
+    /// already, do nothing.
     fn remove_from_parent(&mut self, target: Handle);
 
+    /// Remove all the children from `node` and append them to
+    /// `new_parent`, keeping their order.  `new_parent` will not be
+    /// `node` or one of its descendants.
+    fn reparent_children(&mut self, node: Handle, new_parent: Handle);
+
     /// Mark a HTML `<script>` element as "already started".
     fn mark_script_already_started(&mut self, node: Handle);
 
     /// Indicate that a `<script>` element is complete.
     fn complete_script(&mut self, _node: Handle) { }
+
+    /// Called before parsing with roughly how many bytes of input to
+    /// expect, if the `input_size_hint` option is set, so that the sink
+    /// can size its storage up front.
+    fn input_size_hint(&mut self, _bytes: uint) { }
+
+    /// Called just before an element is created, with the total length
+    /// in bytes of its attribute values.
+    ///
+    /// Together with `account_text`, this lets a sink enforce limits on
+    /// resource use (for example, rejecting huge `data:` URIs or inline
+    /// SVG) while parsing is still in progress.
+    fn account_attrs(&mut self, _name: &QualName, _bytes: uint) { }
+
+    /// Called just before character data is inserted, with its length
+    /// in bytes and the current node.  Because of foster parenting, the
+    /// text may end up somewhere other than under `parent`.
+    fn account_text(&mut self, _parent: Handle, _bytes: uint) { }
+
+    /// Called just before character data is inserted, with the current
+    /// node and the span of input the text came from, if the tokenizer's
+    /// `track_spans` option is set.  Because of character references
+    /// and newline normalization, the span's length may differ from the
+    /// text's.  Where the tree builder splits a token, as with
+    /// whitespace before a table, each piece gets the whole span.
+    fn set_text_span(&mut self, _parent: Handle, _span: Span) { }
+
+    /// Called after creating an element or comment, with the span of
+    /// input it came from, if the tokenizer's `track_spans` option is
+    /// set.  For an element that's the span of its start tag.  An
+    /// element the tree builder implied, such as a `<tbody>` with no
+    /// tag of its own, gets an empty span where it was implied.
+    fn set_node_span(&mut self, _node: Handle, _span: Span) { }
+
+    /// Called when an element is closed, with the span of its end tag,
+    /// if the tokenizer's `track_spans` option is set.  An element
+    /// which was closed without an end tag, e.g. by another start tag,
+    /// gets an empty span where it was closed.  An element that's
+    /// closed as soon as it's created, such as `<br>`, gets the span
+    /// of its start tag.  Elements still open at the end of input are
+    /// never closed.
+    fn set_end_span(&mut self, _elem: Handle, _span: Span) { }
 }
 
 /// Trace hooks for a garbage-collected DOM.
@@ -0,0 +1,183 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Classification of elements by name, computed once when the
+//! element is created.
+
+use core::prelude::*;
+
+use tree_builder::tag_sets::special_tag;
+
+use string_cache::{Atom, QualName};
+
+macro_rules! element_kinds ( ($($variant:ident $tag:ident)+) => (
+    /// The kind of an element.  Common HTML elements get their own
+    /// variant, so that DOM backends can match on them cheaply.
+    #[deriving(PartialEq, Eq, Clone, Hash, Show)]
+    pub enum ElementKind {
+        $( $variant, )+
+
+        /// Some other element in the HTML namespace.
+        OtherHtmlElement(Atom),
+
+        /// An element in some other namespace, e.g. SVG.
+        ForeignElement(QualName),
+    }
+
+    impl ElementKind {
+        /// Classify an element by its name.
+        pub fn from_name(name: &QualName) -> ElementKind {
+            match *name {
+                QualName { ns: ns!(HTML), ref local } => match *local {
+                    $( atom!($tag) => $variant, )+
+                    _ => OtherHtmlElement(local.clone()),
+                },
+                _ => ForeignElement(name.clone()),
+            }
+        }
+
+        /// The element's name.
+        pub fn name(&self) -> QualName {
+            match *self {
+                $( $variant => qualname!(HTML, $tag), )+
+                OtherHtmlElement(ref local) => QualName::new(ns!(HTML), local.clone()),
+                ForeignElement(ref name) => name.clone(),
+            }
+        }
+    }
+))
+
+element_kinds!(
+    HtmlElement html
+    HeadElement head
+    BodyElement body
+    TitleElement title
+    MetaElement meta
+    LinkElement link
+    ScriptElement script
+    StyleElement style
+    DivElement div
+    SpanElement span
+    PElement p
+    AElement a
+    BElement b
+    IElement i
+    EmElement em
+    StrongElement strong
+    UlElement ul
+    OlElement ol
+    LiElement li
+    ImgElement img
+    BrElement br
+    InputElement input
+    FormElement form
+    ButtonElement button
+    SelectElement select
+    OptionElement option
+    TableElement table
+    TbodyElement tbody
+    TrElement tr
+    TdElement td
+    ThElement th
+    TemplateElement template
+)
+
+declare_tag_set!(formatting_tag =
+    a b big code em font i nobr s small strike strong tt u)
+
+impl ElementKind {
+    /// Is this in the spec's "special" category?
+    pub fn is_special(&self) -> bool {
+        match *self {
+            HtmlElement | HeadElement | BodyElement | TitleElement | MetaElement
+            | LinkElement | ScriptElement | StyleElement | DivElement | PElement
+            | UlElement | OlElement | LiElement | ImgElement | BrElement
+            | InputElement | FormElement | ButtonElement | SelectElement
+            | TableElement | TbodyElement | TrElement | TdElement | ThElement
+            | TemplateElement => true,
+            SpanElement | AElement | BElement | IElement | EmElement
+            | StrongElement | OptionElement => false,
+            OtherHtmlElement(_) | ForeignElement(_) => special_tag(self.name()),
+        }
+    }
+
+    /// Is this in the spec's "formatting" category?
+    pub fn is_formatting(&self) -> bool {
+        match *self {
+            AElement | BElement | IElement | EmElement | StrongElement => true,
+            OtherHtmlElement(_) => formatting_tag(self.name()),
+            _ => false,
+        }
+    }
+}
+
+static RESERVED_CUSTOM_NAMES: &'static [&'static str] = &[
+    "annotation-xml", "color-profile", "font-face", "font-face-src",
+    "font-face-uri", "font-face-format", "font-face-name", "missing-glyph"];
+
+// The spec's PCENChar production.
+fn is_pcen_char(c: char) -> bool {
+    match c {
+        '-' | '.' | '0'...'9' | '_' | 'a'...'z' | '\u00b7'
+        | '\u00c0'...'\u00d6' | '\u00d8'...'\u00f6' | '\u00f8'...'\u037d'
+        | '\u037f'...'\u1fff' | '\u200c'...'\u200d' | '\u203f'...'\u2040'
+        | '\u2070'...'\u218f' | '\u2c00'...'\u2fef' | '\u3001'...'\ud7ff'
+        | '\uf900'...'\ufdcf' | '\ufdf0'...'\ufffd' | '\U00010000'...'\U000effff'
+            => true,
+        _ => false,
+    }
+}
+
+/// Is this a valid custom element name, as defined by the Custom
+/// Elements spec?  It must start with a lowercase ASCII letter, contain
+/// a hyphen, and not be one of the reserved names like `font-face`.
+pub fn is_valid_custom_element_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some('a'...'z') => (),
+        _ => return false,
+    }
+    name.contains_char('-')
+        && chars.all(is_pcen_char)
+        && !RESERVED_CUSTOM_NAMES.contains(&name)
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{ElementKind, is_valid_custom_element_name};
+    use tree_builder::tag_sets::special_tag;
+
+    use string_cache::{Atom, QualName};
+
+    fn special_agrees(name: QualName) -> bool {
+        ElementKind::from_name(&name).is_special() == special_tag(name)
+    }
+
+    #[test]
+    fn special_matches_tag_set() {
+        for local in ["html", "head", "body", "title", "meta", "link", "script",
+                "style", "div", "span", "p", "a", "b", "i", "em", "strong", "ul",
+                "ol", "li", "img", "br", "input", "form", "button", "select",
+                "option", "table", "tbody", "tr", "td", "th", "template",
+                "address", "xmp", "foo"].iter() {
+            let name = QualName::new(ns!(HTML), Atom::from_slice(*local));
+            assert!(special_agrees(name), "{}", local);
+        }
+        assert!(special_agrees(qualname!(SVG, title)));
+        assert!(special_agrees(qualname!(SVG, a)));
+    }
+
+    test_eq!(custom_simple, is_valid_custom_element_name("x-foo"), true)
+    test_eq!(custom_unicode, is_valid_custom_element_name("math-\u03b1"), true)
+    test_eq!(custom_no_hyphen, is_valid_custom_element_name("xfoo"), false)
+    test_eq!(custom_upper, is_valid_custom_element_name("x-Foo"), false)
+    test_eq!(custom_leading_hyphen, is_valid_custom_element_name("-foo"), false)
+    test_eq!(custom_reserved, is_valid_custom_element_name("font-face"), false)
+}
@@ -14,30 +14,41 @@ use core::prelude::*;
 pub use self::interface::{QuirksMode, Quirks, LimitedQuirks, NoQuirks};
 pub use self::interface::{NodeOrText, AppendNode, AppendText};
 pub use self::interface::{TreeSink, Tracer};
+pub use self::kind::*;
+pub use self::error::*;
 
 use self::types::*;
 use self::actions::TreeBuilderActions;
 use self::rules::TreeBuilderStep;
+use self::name_cache::ElemNameCache;
 
 use tokenizer;
-use tokenizer::{Doctype, Tag};
+use tokenizer::{Doctype, Tag, TagKind, Span};
 use tokenizer::TokenSink;
+use tokenizer::{Severity, Informational};
+use tokenizer::states::raw_text_state;
 
 use util::str::{is_ascii_whitespace, char_run};
 
 use core::default::Default;
+use core::fmt::Show;
 use core::mem::replace;
+use core::cell::RefCell;
 use collections::vec::Vec;
 use collections::string::String;
-use collections::str::Slice;
 use collections::{MutableSeq, Deque, RingBuf};
 
+use string_cache::{Atom, QualName};
+
 mod interface;
 mod tag_sets;
+mod kind;
+mod error;
 mod data;
 mod types;
 mod actions;
 mod rules;
+mod name_cache;
 
 /// Tree builder options, with an impl for Default.
 #[deriving(Clone)]
@@ -52,21 +63,58 @@ pub struct TreeBuilderOpts {
     /// Is this an `iframe srcdoc` document?
     pub iframe_srcdoc: bool,
 
-    /// Are we parsing a HTML fragment?
+    /// Are we parsing a HTML fragment?  `TreeBuilder::new_for_fragment`
+    /// sets this.
+    ///
+    /// Without a context element, a fragment is parsed much like the
+    /// content of a `<body>`.  With an `<svg>` or `<math>` context
+    /// element, e.g. for a standalone SVG icon, the fragment is parsed
+    /// as foreign content: its elements are in that namespace, and
+    /// names like `viewBox` get their case back.
     pub fragment: bool,
 
     /// Should we drop the DOCTYPE (if any) from the tree?
     pub drop_doctype: bool,
 
-    /// The `<svg>`, `<math>`, and `<template>` tags have special
-    /// parsing rules that are currently unimplemented.  By default
-    /// we `fail!()` if any of these tags is encountered.  If this
-    /// option is enabled, we will instead attempt to parse them
-    /// using the ordinary HTML parsing rules.
+    /// The `<isindex>` tag has special parsing rules that are currently
+    /// unimplemented.  By default we signal a parse error and attempt
+    /// to parse it using the ordinary HTML parsing rules.  If this
+    /// option is disabled, we will instead `fail!()` if this tag is
+    /// encountered.
     ///
-    /// **Warning**: This may produce extremely incorrect results
-    /// on some documents!
+    /// **Warning**: Ignoring the missing rules may produce extremely
+    /// incorrect results on some documents!  Default: true
     pub ignore_missing_rules: bool,
+
+    /// Signal a parse error when creating an HTML element whose name
+    /// contains a hyphen but is not a valid custom element name?  This
+    /// is not an error in the HTML spec.  Default: false
+    pub check_custom_element_names: bool,
+
+    /// Remember the names of up to this many recently created or
+    /// queried elements, rather than asking `TreeSink::elem_name` each
+    /// time.  Worthwhile if the sink's DOM is remote or deferred; for an
+    /// in-memory DOM the lookups cost more than they save.  Default: 0
+    pub elem_name_cache_size: uint,
+
+    /// Is the input expected to be cut off, e.g. the first few kilobytes
+    /// of a page?  Elements left open at EOF are then not a parse error,
+    /// though `eof_truncated()` still reports them.  The tokenizer has
+    /// an option of the same name.  Default: false
+    pub truncated_input: bool,
+
+    /// Suspend the tokenizer after each tag which completes a script,
+    /// so the embedder can run the script, pass anything it writes to
+    /// `Tokenizer::insert`, and then call `Tokenizer::resume`.  The
+    /// functions in `driver` don't run scripts, so they resume straight
+    /// away.  Default: false
+    pub suspend_after_scripts: bool,
+
+    /// Only report parse errors at least this severe, or none at all
+    /// if `None`.  This applies to the tree builder's own errors and to
+    /// those passed on from the tokenizer, which has an option of the
+    /// same name.  Default: `Some(Informational)`, i.e. every error
+    pub min_error_severity: Option<Severity>,
 }
 
 impl Default for TreeBuilderOpts {
@@ -77,7 +125,41 @@ impl Default for TreeBuilderOpts {
             iframe_srcdoc: false,
             fragment: false,
             drop_doctype: false,
-            ignore_missing_rules: false,
+            ignore_missing_rules: true,
+            check_custom_element_names: false,
+            elem_name_cache_size: 0,
+            truncated_input: false,
+            suspend_after_scripts: false,
+            min_error_severity: Some(Informational),
+        }
+    }
+}
+
+/// Buffers which a tree builder has finished with, for the next one to
+/// reuse rather than growing its own.  This is for parsing many
+/// documents in turn, as `BatchParser` does.  The buffers are empty,
+/// so they don't keep any nodes of the last document alive.
+pub struct TreeBuilderBuffers<Handle> {
+    template_modes: Vec<InsertionMode>,
+    pending_table_text: Vec<(SplitStatus, String, Option<Span>)>,
+    open_elems: Vec<Handle>,
+    open_elem_kinds: Vec<ElementKind>,
+    active_formatting: Vec<FormatEntry<Handle>>,
+    annotation_xml_integration_points: Vec<Handle>,
+    elem_names: ElemNameCache<Handle>,
+}
+
+impl<Handle> TreeBuilderBuffers<Handle> {
+    /// Buffers which haven't been used yet.
+    pub fn new() -> TreeBuilderBuffers<Handle> {
+        TreeBuilderBuffers {
+            template_modes: vec!(),
+            pending_table_text: vec!(),
+            open_elems: vec!(),
+            open_elem_kinds: vec!(),
+            active_formatting: vec!(),
+            annotation_xml_integration_points: vec!(),
+            elem_names: ElemNameCache::new(0),
         }
     }
 }
@@ -99,8 +181,8 @@ pub struct TreeBuilder<Handle, Sink> {
     /// Stack of template insertion modes.
     template_modes: Vec<InsertionMode>,
 
-    /// Pending table character tokens.
-    pending_table_text: Vec<(SplitStatus, String)>,
+    /// Pending table character tokens, with their spans.
+    pending_table_text: Vec<(SplitStatus, String, Option<Span>)>,
 
     /// Quirks mode as set by the parser.
     /// FIXME: can scripts etc. change this?
@@ -112,6 +194,10 @@ pub struct TreeBuilder<Handle, Sink> {
     /// Stack of open elements, most recently added at end.
     open_elems: Vec<Handle>,
 
+    /// The kind of each element in `open_elems`, so that scope checks
+    /// don't have to ask the sink for names.
+    open_elem_kinds: Vec<ElementKind>,
+
     /// List of active formatting elements.
     active_formatting: Vec<FormatEntry<Handle>>,
 
@@ -123,9 +209,19 @@ pub struct TreeBuilder<Handle, Sink> {
     form_elem: Option<Handle>,
     //§ END
 
+    /// Context element, when parsing a fragment.
+    context_elem: Option<Handle>,
+
+    /// MathML `<annotation-xml>` elements whose `encoding` makes them
+    /// HTML integration points.  The sink isn't asked for attributes.
+    annotation_xml_integration_points: Vec<Handle>,
+
     /// Next state change for the tokenizer, if any.
     next_tokenizer_state: Option<tokenizer::states::State>,
 
+    /// Should the tokenizer be suspended after the current token?
+    suspend_tokenizer: bool,
+
     /// Frameset-ok flag.
     frameset_ok: bool,
 
@@ -135,6 +231,30 @@ pub struct TreeBuilder<Handle, Sink> {
     /// Is foster parenting enabled?
     foster_parenting: bool,
 
+    /// Number of parse errors reported to the sink.
+    errors_count: uint,
+
+    /// Are we processing the EOF token?
+    at_eof: bool,
+
+    /// Did the document end with elements open which need closing?
+    eof_truncated: bool,
+
+    /// Recently seen element names.  See
+    /// `TreeBuilderOpts::elem_name_cache_size`.
+    elem_names: RefCell<ElemNameCache<Handle>>,
+
+    /// Span of the token being processed, if the tokenizer is
+    /// tracking spans.
+    token_span: Option<Span>,
+
+    /// Kind and name of the tag being processed, if tracking spans.
+    token_tag: Option<(TagKind, Atom)>,
+
+    /// The element created for the start tag being processed, if
+    /// tracking spans.
+    token_elem: Option<Handle>,
+
     // WARNING: If you add new fields that contain Handles, you
     // must add them to trace_handles() below to preserve memory
     // safety!
@@ -146,32 +266,119 @@ impl<Handle: Clone, Sink: TreeSink<Handle>> TreeBuilder<Handle, Sink> {
     /// Create a new tree builder which sends tree modifications to a particular `TreeSink`.
     ///
     /// The tree builder is also a `TokenSink`.
-    pub fn new(mut sink: Sink, opts: TreeBuilderOpts) -> TreeBuilder<Handle, Sink> {
+    pub fn new(sink: Sink, opts: TreeBuilderOpts) -> TreeBuilder<Handle, Sink> {
+        TreeBuilder::new_with_buffers(sink, opts, TreeBuilderBuffers::new())
+    }
+
+    /// Create a new tree builder which reuses the buffers of a finished
+    /// one, from `unwrap_with_buffers`.
+    pub fn new_with_buffers(mut sink: Sink, opts: TreeBuilderOpts,
+            buffers: TreeBuilderBuffers<Handle>) -> TreeBuilder<Handle, Sink> {
         let doc_handle = sink.get_document();
+        let mut elem_names = buffers.elem_names;
+        elem_names.reset(opts.elem_name_cache_size);
         TreeBuilder {
             opts: opts,
             sink: sink,
             mode: Initial,
             orig_mode: None,
-            template_modes: vec!(),
-            pending_table_text: vec!(),
+            template_modes: buffers.template_modes,
+            pending_table_text: buffers.pending_table_text,
             quirks_mode: NoQuirks,
             doc_handle: doc_handle,
-            open_elems: vec!(),
-            active_formatting: vec!(),
+            open_elems: buffers.open_elems,
+            open_elem_kinds: buffers.open_elem_kinds,
+            active_formatting: buffers.active_formatting,
             head_elem: None,
             form_elem: None,
+            context_elem: None,
+            annotation_xml_integration_points: buffers.annotation_xml_integration_points,
             next_tokenizer_state: None,
+            suspend_tokenizer: false,
             frameset_ok: true,
             ignore_lf: false,
             foster_parenting: false,
+            errors_count: 0,
+            at_eof: false,
+            eof_truncated: false,
+            elem_names: RefCell::new(elem_names),
+            token_span: None,
+            token_tag: None,
+            token_elem: None,
+        }
+    }
+
+    /// Create a new tree builder for the HTML fragment parsing
+    /// algorithm, as used by `innerHTML`.  The content will be parsed
+    /// as if it were inside `context_elem`, which is not itself added to
+    /// the tree.  `form_elem` is the nearest `<form>` around the context
+    /// element, if any.
+    ///
+    /// The sink's document gets a single `<html>` element, and the
+    /// fragment's nodes become the children of that.  Start the
+    /// tokenizer in `tokenizer_state_for_context_elem()`.
+    pub fn new_for_fragment(sink: Sink,
+            context_elem: Handle,
+            form_elem: Option<Handle>,
+            mut opts: TreeBuilderOpts) -> TreeBuilder<Handle, Sink> {
+        opts.fragment = true;
+        let mut tb = TreeBuilder::new(sink, opts);
+        let root = tb.sink.create_element(qualname!(HTML, html), vec!());
+        tb.sink.append(tb.doc_handle.clone(), AppendNode(root.clone()));
+        tb.push(&root);
+
+        let context_name = tb.elem_name(context_elem.clone());
+        if context_name == qualname!(HTML, template) {
+            tb.template_modes.push(InTemplate);
+        }
+        tb.context_elem = Some(context_elem);
+        tb.form_elem = form_elem;
+        tb.mode = tb.reset_insertion_mode();
+        tb
+    }
+
+    /// The state the tokenizer should start in for a fragment, e.g.
+    /// RCDATA inside a `<textarea>`.  `Data` when not parsing a
+    /// fragment.
+    pub fn tokenizer_state_for_context_elem(&self) -> tokenizer::states::State {
+        let name = match self.context_elem {
+            Some(ref elem) => self.elem_name(elem.clone()),
+            None => return tokenizer::states::Data,
+        };
+        if name.ns != ns!(HTML) {
+            return tokenizer::states::Data;
         }
+        raw_text_state(&name.local, self.opts.scripting_enabled)
+            .unwrap_or(tokenizer::states::Data)
     }
 
     pub fn unwrap(self) -> Sink {
         self.sink
     }
 
+    /// Like `unwrap`, but also return the buffers, emptied, for
+    /// `new_with_buffers` to reuse.
+    pub fn unwrap_with_buffers(self) -> (Sink, TreeBuilderBuffers<Handle>) {
+        let mut elem_names = replace(&mut *self.elem_names.borrow_mut(), ElemNameCache::new(0));
+        elem_names.clear();
+        let mut buffers = TreeBuilderBuffers {
+            template_modes: self.template_modes,
+            pending_table_text: self.pending_table_text,
+            open_elems: self.open_elems,
+            open_elem_kinds: self.open_elem_kinds,
+            active_formatting: self.active_formatting,
+            annotation_xml_integration_points: self.annotation_xml_integration_points,
+            elem_names: elem_names,
+        };
+        buffers.template_modes.truncate(0);
+        buffers.pending_table_text.truncate(0);
+        buffers.open_elems.truncate(0);
+        buffers.open_elem_kinds.truncate(0);
+        buffers.active_formatting.truncate(0);
+        buffers.annotation_xml_integration_points.truncate(0);
+        (self.sink, buffers)
+    }
+
     pub fn sink<'a>(&'a self) -> &'a Sink {
         &self.sink
     }
@@ -180,6 +387,28 @@ impl<Handle: Clone, Sink: TreeSink<Handle>> TreeBuilder<Handle, Sink> {
         &mut self.sink
     }
 
+    /// The document's quirks mode, as determined so far.
+    pub fn quirks_mode(&self) -> QuirksMode {
+        self.quirks_mode
+    }
+
+    /// The number of parse errors reported to the sink so far,
+    /// including those from the tokenizer.
+    pub fn errors_count(&self) -> uint {
+        self.errors_count
+    }
+
+    /// Did the document end with elements open which need an end tag,
+    /// or in the middle of e.g. a `<script>`?  Only known after EOF.
+    pub fn eof_truncated(&self) -> bool {
+        self.eof_truncated
+    }
+
+    /// The stack of open elements, outermost first.
+    pub fn open_elems<'a>(&'a self) -> &'a [Handle] {
+        self.open_elems.as_slice()
+    }
+
     /// Call the `Tracer`'s `trace_handle` method on every `Handle` in the tree builder's
     /// internal state.  This is intended to support garbage-collected DOMs.
     pub fn trace_handles(&self, tracer: &Tracer<Handle>) {
@@ -195,26 +424,80 @@ impl<Handle: Clone, Sink: TreeSink<Handle>> TreeBuilder<Handle, Sink> {
         }
         self.head_elem.as_ref().map(|h| tracer.trace_handle(h.clone()));
         self.form_elem.as_ref().map(|h| tracer.trace_handle(h.clone()));
+        self.context_elem.as_ref().map(|h| tracer.trace_handle(h.clone()));
+        for e in self.annotation_xml_integration_points.iter() {
+            tracer.trace_handle(e.clone());
+        }
+        for &(ref h, _) in self.elem_names.borrow().iter() {
+            tracer.trace_handle(h.clone());
+        }
+        self.token_elem.as_ref().map(|h| tracer.trace_handle(h.clone()));
     }
 
     // Debug helper
     #[cfg(not(for_c))]
     #[allow(dead_code)]
     fn dump_state(&self, label: String) {
-        use string_cache::QualName;
-
         println!("dump_state on {}", label);
         print!("    open_elems:");
         for node in self.open_elems.iter() {
-            let QualName { ns, local } = self.sink.elem_name(node.clone());
-            match ns {
-                ns!(HTML) => print!(" {}", local),
-                _ => fail!(),
+            match self.elem_name(node.clone()) {
+                QualName { ns: ns!(HTML), local } => print!(" {}", local),
+                name => print!(" {}", name),
             }
         }
         println!("");
     }
 
+    /// Should errors of this severity go to the sink?
+    fn reports(&self, severity: Severity) -> bool {
+        match self.opts.min_error_severity {
+            Some(min) => severity >= min,
+            None => false,
+        }
+    }
+
+    #[cfg(for_c)]
+    fn error_context(&self) -> Option<String> {
+        None
+    }
+
+    /// Describe the insertion mode and the names of the open elements,
+    /// for an error with the `exact_errors` option.
+    #[cfg(not(for_c))]
+    fn error_context(&self) -> Option<String> {
+        if !self.opts.exact_errors {
+            return None;
+        }
+
+        let mut s = format!("insertion mode {}, open elements:", self.mode);
+        for kind in self.open_elem_kinds.iter() {
+            s.push_str(match kind.name() {
+                QualName { ns: ns!(HTML), local } => format!(" {}", local),
+                name => format!(" {}", name),
+            }.as_slice());
+        }
+        Some(s)
+    }
+
+    #[cfg(for_c)]
+    fn describe<T: Show>(&self, _thing: &T) -> Option<String> {
+        None
+    }
+
+    /// Describe a token or the like for an error's details, with the
+    /// `exact_errors` option.
+    #[cfg(not(for_c))]
+    fn describe<T: Show>(&self, thing: &T) -> Option<String> {
+        use util::str::to_escaped_string;
+
+        if self.opts.exact_errors {
+            Some(to_escaped_string(thing))
+        } else {
+            None
+        }
+    }
+
     #[cfg(for_c)]
     fn debug_step(&self, _mode: InsertionMode, _token: &Token) {
     }
@@ -236,10 +519,15 @@ impl<Handle: Clone, Sink: TreeSink<Handle>> TreeBuilder<Handle, Sink> {
                 _ => false,
             };
             let mode = self.mode;
-            match self.step(mode, token) {
+            let result = if self.is_foreign(&token) {
+                self.step_foreign(token)
+            } else {
+                self.step(mode, token)
+            };
+            match result {
                 Done => {
                     if is_self_closing {
-                        self.sink.parse_error(Slice("Unacknowledged self-closing tag"));
+                        self.parse_error(UnacknowledgedSelfClosingTag);
                     }
                     token = unwrap_or_return!(more_tokens.pop_front(), ());
                 }
@@ -277,17 +565,19 @@ impl<Handle: Clone, Sink: TreeSink<Handle>> TokenSink for TreeBuilder<Handle, Si
         // Handle `ParseError` and `DoctypeToken`; convert everything else to the local `Token` type.
         let token = match token {
             tokenizer::ParseError(e) => {
-                self.sink.parse_error(e);
+                if !self.reports(e.code.severity()) {
+                    return;
+                }
+                self.errors_count += 1;
+                self.sink.parse_error(TokenizerErr(e));
                 return;
             }
 
             tokenizer::DoctypeToken(dt) => if self.mode == Initial {
                 let (err, quirk) = data::doctype_error_and_quirks(&dt, self.opts.iframe_srcdoc);
                 if err {
-                    self.sink.parse_error(format_if!(
-                        self.opts.exact_errors,
-                        "Bad DOCTYPE",
-                        "Bad DOCTYPE: {}", dt));
+                    let details = self.describe(&dt);
+                    self.parse_error(BadDoctype(details));
                 }
                 let Doctype { name, public_id, system_id, force_quirks: _ } = dt;
                 if !self.opts.drop_doctype {
@@ -302,17 +592,32 @@ impl<Handle: Clone, Sink: TreeSink<Handle>> TokenSink for TreeBuilder<Handle, Si
                 self.mode = BeforeHtml;
                 return;
             } else {
-                self.sink.parse_error(format_if!(
-                    self.opts.exact_errors,
-                    "DOCTYPE in body",
-                    "DOCTYPE in insertion mode {}", self.mode));
+                let details = self.describe(&self.mode);
+                self.parse_error(MisplacedDoctype(details));
                 return;
             },
 
             tokenizer::TagToken(x) => TagToken(x),
             tokenizer::CommentToken(x) => CommentToken(x),
             tokenizer::NullCharacterToken => NullCharacterToken,
-            tokenizer::EOFToken => EOFToken,
+            tokenizer::PassthroughToken(x) => CharacterTokens(NotSplit, x),
+
+            // Build the bogus comment we would have got without the
+            // tokenizer's `processing_instructions` option.
+            tokenizer::ProcessingInstructionToken(target, data) => {
+                let mut text = String::from_str("?");
+                text.push_str(target.as_slice());
+                if !data.is_empty() {
+                    text.push(' ');
+                    text.push_str(data.as_slice());
+                }
+                text.push('?');
+                CommentToken(text)
+            }
+            tokenizer::EOFToken => {
+                self.at_eof = true;
+                EOFToken
+            }
 
             tokenizer::CharacterTokens(mut x) => {
                 if ignore_lf && x.len() >= 1 && x.as_slice().char_at(0) == '\n' {
@@ -323,12 +628,86 @@ impl<Handle: Clone, Sink: TreeSink<Handle>> TokenSink for TreeBuilder<Handle, Si
                 }
                 CharacterTokens(NotSplit, x)
             }
+
+            // The tokenizer already checked that this is all whitespace.
+            tokenizer::WhitespaceTokens(mut x) => {
+                if ignore_lf && x.len() >= 1 && x.as_slice().char_at(0) == '\n' {
+                    x.remove(0);
+                }
+                if x.is_empty() {
+                    return;
+                }
+                CharacterTokens(Whitespace, x)
+            }
         };
 
         self.process_to_completion(token);
     }
 
+    fn process_token_with_span(&mut self, token: tokenizer::Token, span: Span) {
+        match token {
+            // Errors arrive in the middle of other tokens.
+            tokenizer::ParseError(_) => (),
+            _ => {
+                self.token_span = Some(span);
+                self.token_tag = match token {
+                    tokenizer::TagToken(ref tag) => Some((tag.kind, tag.name.clone())),
+                    _ => None,
+                };
+                self.token_elem = None;
+            }
+        }
+        self.process_token(token);
+    }
+
     fn query_state_change(&mut self) -> Option<tokenizer::states::State> {
         self.next_tokenizer_state.take()
     }
+
+    fn query_suspend(&mut self) -> bool {
+        replace(&mut self.suspend_tokenizer, false)
+    }
+
+    fn adjusted_current_node_is_foreign(&self) -> bool {
+        !self.open_elems.is_empty()
+            && self.sink.elem_name(self.adjusted_current_node()).ns != ns!(HTML)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::TreeBuilder;
+
+    use tokenizer::Tokenizer;
+    use sink::rcdom::RcDom;
+
+    use core::default::Default;
+    use collections::string::String;
+
+    #[test]
+    fn reuse_buffers() {
+        let tb = TreeBuilder::new(RcDom::default(), Default::default());
+        let mut tok = Tokenizer::new(tb, Default::default());
+        tok.feed(String::from_str("<div><div><div><b><i><table>x"));
+        tok.end();
+        let (_, buffers) = tok.unwrap().unwrap_with_buffers();
+        let open_cap = buffers.open_elems.capacity();
+        let formatting_cap = buffers.active_formatting.capacity();
+        assert!(open_cap >= 8);
+        assert!(formatting_cap >= 2);
+        assert!(buffers.open_elems.is_empty());
+        assert!(buffers.open_elem_kinds.is_empty());
+        assert!(buffers.active_formatting.is_empty());
+        assert!(buffers.pending_table_text.is_empty());
+
+        let tb = TreeBuilder::new_with_buffers(RcDom::default(), Default::default(), buffers);
+        assert_eq!(tb.open_elems.capacity(), open_cap);
+        assert_eq!(tb.active_formatting.capacity(), formatting_cap);
+        let mut tok = Tokenizer::new(tb, Default::default());
+        tok.feed(String::from_str("<p>a"));
+        tok.end();
+        let dom = tok.unwrap().unwrap();
+        assert_eq!(dom.body().unwrap().borrow().children.len(), 1);
+    }
 }
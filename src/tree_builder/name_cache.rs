@@ -0,0 +1,132 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The names of recently seen elements.  See
+//! `TreeBuilderOpts::elem_name_cache_size`.
+
+use core::prelude::*;
+
+use collections::{Mutable, MutableSeq, Deque};
+use collections::ringbuf::{RingBuf, Items};
+
+use string_cache::QualName;
+
+/// A fixed number of element names, dropping the oldest when full.
+pub struct ElemNameCache<Handle> {
+    names: RingBuf<(Handle, QualName)>,
+    max: uint,
+}
+
+impl<Handle> ElemNameCache<Handle> {
+    pub fn new(max: uint) -> ElemNameCache<Handle> {
+        ElemNameCache {
+            names: RingBuf::with_capacity(max),
+            max: max,
+        }
+    }
+
+    /// The name of the most recently remembered element for which
+    /// `same` is true.  Handles can only be compared by the sink, so
+    /// this checks each one.
+    pub fn find(&self, same: |&Handle| -> bool) -> Option<QualName> {
+        for &(ref h, ref name) in self.names.iter().rev() {
+            if same(h) {
+                return Some(name.clone());
+            }
+        }
+        None
+    }
+
+    pub fn insert(&mut self, elem: Handle, name: QualName) {
+        if self.max == 0 {
+            return;
+        }
+        if self.names.len() >= self.max {
+            self.names.pop_front();
+        }
+        self.names.push((elem, name));
+    }
+
+    /// Forget every name, keeping the room allocated for them.
+    pub fn clear(&mut self) {
+        self.names.clear();
+    }
+
+    /// Forget every name, and remember up to `max` from now on.
+    pub fn reset(&mut self, max: uint) {
+        self.names.clear();
+        self.names.reserve(max);
+        self.max = max;
+    }
+
+    pub fn iter<'a>(&'a self) -> Items<'a, (Handle, QualName)> {
+        self.names.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::ElemNameCache;
+
+    fn find(cache: &ElemNameCache<uint>, elem: uint) -> Option<&'static str> {
+        cache.find(|&h| h == elem).map(|n| match n.local.as_slice() {
+            "p" => "p",
+            "b" => "b",
+            _ => "other",
+        })
+    }
+
+    #[test]
+    fn hit_and_miss() {
+        let mut cache = ElemNameCache::new(4);
+        cache.insert(1u, qualname!(HTML, p));
+        assert_eq!(find(&cache, 1), Some("p"));
+        assert_eq!(find(&cache, 2), None);
+    }
+
+    #[test]
+    fn most_recent_first() {
+        let mut cache = ElemNameCache::new(4);
+        cache.insert(1u, qualname!(HTML, p));
+        cache.insert(1u, qualname!(HTML, b));
+        assert_eq!(find(&cache, 1), Some("b"));
+    }
+
+    #[test]
+    fn evicts_oldest() {
+        let mut cache = ElemNameCache::new(2);
+        cache.insert(1u, qualname!(HTML, p));
+        cache.insert(2u, qualname!(HTML, b));
+        cache.insert(3u, qualname!(HTML, p));
+        assert_eq!(find(&cache, 1), None);
+        assert_eq!(find(&cache, 2), Some("b"));
+        assert_eq!(find(&cache, 3), Some("p"));
+        assert_eq!(cache.iter().count(), 2);
+    }
+
+    #[test]
+    fn reset() {
+        let mut cache = ElemNameCache::new(0);
+        cache.insert(1u, qualname!(HTML, p));
+        cache.reset(1);
+        cache.insert(2u, qualname!(HTML, b));
+        assert_eq!(find(&cache, 2), Some("b"));
+        cache.clear();
+        assert_eq!(find(&cache, 2), None);
+        assert_eq!(cache.iter().count(), 0);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut cache = ElemNameCache::new(0);
+        cache.insert(1u, qualname!(HTML, p));
+        assert_eq!(find(&cache, 1), None);
+    }
+}
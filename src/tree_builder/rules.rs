@@ -15,25 +15,37 @@ use tree_builder::types::*;
 use tree_builder::tag_sets::*;
 use tree_builder::actions::TreeBuilderActions;
 use tree_builder::interface::{TreeSink, Quirks, AppendNode};
+use tree_builder::error::{BodyNotInScope, NestedHeading, NestedForm, NestedButton, NestedNobr};
+use tree_builder::error::{NoFormElement, FormNotInScope, FormNotCurrentNode, NoElementToClose};
+use tree_builder::error::{NoHeadingToClose, WrongHeadingClosed, UnimplementedIsindex};
+use tree_builder::error::NonSpaceTableText;
 
 use tokenizer::{Tag, StartTag, EndTag};
 use tokenizer::states::{Rcdata, Rawtext, ScriptData, Plaintext};
 
-use util::str::is_ascii_whitespace;
+use util::str::{is_ascii_whitespace, AsciiExt};
 
 use core::mem::replace;
 use collections::MutableSeq;
 use collections::string::String;
-use collections::str::Slice;
+
+use string_cache::{Atom, QualName};
 
 fn any_not_whitespace(x: &String) -> bool {
     // FIXME: this might be much faster as a byte scan
     x.as_slice().chars().any(|c| !is_ascii_whitespace(c))
 }
 
+// Does an end tag in foreign content close this element?  SVG names
+// have their case restored, but the tag's name is lowercase.
+fn closes_foreign(name: QualName, tag: &Atom) -> bool {
+    name.local.as_slice().eq_ignore_ascii_case(tag.as_slice())
+}
+
 // This goes in a trait so that we can control visibility.
 pub trait TreeBuilderStep<Handle> {
     fn step(&mut self, mode: InsertionMode, token: Token) -> ProcessResult;
+    fn step_foreign(&mut self, token: Token) -> ProcessResult;
 }
 
 #[doc(hidden)]
@@ -134,12 +146,12 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                 }
 
                 tag @ <script> => {
-                    let elem = self.sink.create_element(qualname!(HTML, script), tag.attrs);
+                    let elem = self.create_element(qualname!(HTML, script), tag.attrs);
                     if self.opts.fragment {
                         self.sink.mark_script_already_started(elem.clone());
                     }
                     self.insert_appropriately(AppendNode(elem.clone()));
-                    self.open_elems.push(elem);
+                    self.push(&elem);
                     self.to_raw_text_mode(ScriptData);
                     Done
                 }
@@ -162,7 +174,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                 }
 
                 tag @ </template> => {
-                    if !self.in_scope_named(default_scope, atom!(template)) {
+                    if !self.in_html_elem_named(atom!(template)) {
                         self.unexpected(&tag);
                     } else {
                         self.generate_implied_end(thorough_implied_end);
@@ -273,9 +285,10 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
 
                 tag @ <html> => {
                     self.unexpected(&tag);
-                    // FIXME: <template>
-                    let top = self.html_elem();
-                    self.sink.add_attrs_if_missing(top, tag.attrs);
+                    if !self.in_html_elem_named(atom!(template)) {
+                        let top = self.html_elem();
+                        self.sink.add_attrs_if_missing(top, tag.attrs);
+                    }
                     Done
                 }
 
@@ -286,8 +299,8 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
 
                 tag @ <body> => {
                     self.unexpected(&tag);
-                    // FIXME: <template>
                     match self.body_elem() {
+                        Some(_) if self.in_html_elem_named(atom!(template)) => (),
                         None => (),
                         Some(node) => {
                             self.frameset_ok = false;
@@ -310,14 +323,18 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
 
                     // FIXME: can we get here in the fragment case?
                     // What to do with the first element then?
-                    self.open_elems.truncate(1);
+                    while self.open_elems.len() > 1 {
+                        self.pop();
+                    }
                     self.insert_element_for(tag);
                     self.mode = InFrameset;
                     Done
                 }
 
                 EOFToken => {
-                    // FIXME: <template>
+                    if !self.template_modes.is_empty() {
+                        return self.step(InTemplate, token);
+                    }
                     self.check_body_end();
                     self.stop_parsing()
                 }
@@ -327,7 +344,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                         self.check_body_end();
                         self.mode = AfterBody;
                     } else {
-                        self.sink.parse_error(Slice("</body> with no <body> in scope"));
+                        self.parse_error(BodyNotInScope(atom!(body)));
                     }
                     Done
                 }
@@ -337,7 +354,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                         self.check_body_end();
                         Reprocess(AfterBody, token)
                     } else {
-                        self.sink.parse_error(Slice("</html> with no <body> in scope"));
+                        self.parse_error(BodyNotInScope(atom!(html)));
                         Done
                     }
                 }
@@ -353,7 +370,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                 tag @ <h1> <h2> <h3> <h4> <h5> <h6> => {
                     self.close_p_element_in_button_scope();
                     if self.current_node_in(heading_tag) {
-                        self.sink.parse_error(Slice("nested heading tags"));
+                        self.parse_error(NestedHeading);
                         self.pop();
                     }
                     self.insert_element_for(tag);
@@ -369,14 +386,15 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                 }
 
                 tag @ <form> => {
-                    // FIXME: <template>
-                    if self.form_elem.is_some() {
-                        self.sink.parse_error(Slice("nested forms"));
+                    let in_template = self.in_html_elem_named(atom!(template));
+                    if self.form_elem.is_some() && !in_template {
+                        self.parse_error(NestedForm);
                     } else {
                         self.close_p_element_in_button_scope();
                         let elem = self.insert_element_for(tag);
-                        // FIXME: <template>
-                        self.form_elem = Some(elem);
+                        if !in_template {
+                            self.form_elem = Some(elem);
+                        }
                     }
                     Done
                 }
@@ -394,8 +412,8 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                     self.frameset_ok = false;
 
                     let mut to_close = None;
-                    for node in self.open_elems.iter().rev() {
-                        let name = self.sink.elem_name(node.clone());
+                    for kind in self.open_elem_kinds.iter().rev() {
+                        let name = kind.name();
                         if can_close(name.clone()) {
                             to_close = Some(name.local);
                             break;
@@ -427,7 +445,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
 
                 tag @ <button> => {
                     if self.in_scope_named(default_scope, atom!(button)) {
-                        self.sink.parse_error(Slice("nested buttons"));
+                        self.parse_error(NestedButton);
                         self.generate_implied_end(cursory_implied_end);
                         self.pop_until_named(atom!(button));
                     }
@@ -450,33 +468,42 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                     Done
                 }
 
-                </form> => {
-                    // FIXME: <template>
+                tag @ </form> => {
+                    if self.in_html_elem_named(atom!(template)) {
+                        if !self.in_scope_named(default_scope, atom!(form)) {
+                            self.unexpected(&tag);
+                        } else {
+                            self.generate_implied_end(cursory_implied_end);
+                            self.expect_to_close(atom!(form));
+                        }
+                        return Done;
+                    }
+
                     // Can't use unwrap_or_return!() due to rust-lang/rust#16617.
                     let node = match self.form_elem.take() {
                         None => {
-                            self.sink.parse_error(Slice("Null form element pointer on </form>"));
+                            self.parse_error(NoFormElement);
                             return Done;
                         }
                         Some(x) => x,
                     };
                     if !self.in_scope(default_scope,
                         |n| self.sink.same_node(node.clone(), n)) {
-                        self.sink.parse_error(Slice("Form element not in scope on </form>"));
+                        self.parse_error(FormNotInScope);
                         return Done;
                     }
                     self.generate_implied_end(cursory_implied_end);
                     let current = self.current_node();
                     self.remove_from_stack(&node);
                     if !self.sink.same_node(current, node) {
-                        self.sink.parse_error(Slice("Bad open element on </form>"));
+                        self.parse_error(FormNotCurrentNode);
                     }
                     Done
                 }
 
                 </p> => {
                     if !self.in_scope_named(button_scope, atom!(p)) {
-                        self.sink.parse_error(Slice("No <p> tag to close"));
+                        self.parse_error(NoElementToClose(atom!(p)));
                         self.insert_phantom(atom!(p));
                     }
                     self.close_p_element();
@@ -492,43 +519,44 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                         self.generate_implied_end_except(tag.name.clone());
                         self.expect_to_close(tag.name);
                     } else {
-                        self.sink.parse_error(Slice("No matching tag to close"));
+                        self.parse_error(NoElementToClose(tag.name));
                     }
                     Done
                 }
 
                 tag @ </h1> </h2> </h3> </h4> </h5> </h6> => {
-                    if self.in_scope(default_scope, |n| self.elem_in(n.clone(), heading_tag)) {
+                    if self.any_in_scope(default_scope, heading_tag) {
                         self.generate_implied_end(cursory_implied_end);
                         if !self.current_node_named(tag.name) {
-                            self.sink.parse_error(Slice("Closing wrong heading tag"));
+                            self.parse_error(WrongHeadingClosed);
                         }
                         self.pop_until(heading_tag);
                     } else {
-                        self.sink.parse_error(Slice("No heading tag to close"));
+                        self.parse_error(NoHeadingToClose);
                     }
                     Done
                 }
 
                 tag @ <a> => {
-                    let mut to_remove = vec!();
-                    for (i, handle, _) in self.active_formatting_end_to_marker() {
-                        if self.html_elem_named(handle.clone(), atom!(a)) {
-                            to_remove.push((i, handle.clone()));
-                        }
-                    }
+                    let open_a = self.active_formatting_end_to_marker()
+                        .find(|&(_, handle, _)| self.html_elem_named(handle.clone(), atom!(a)))
+                        .map(|(_, handle, _)| handle.clone());
 
-                    if !to_remove.is_empty() {
-                        self.unexpected(&tag);
-                        self.adoption_agency(atom!(a));
-                        // FIXME: quadratic time
-                        for (i, handle) in to_remove.into_iter() {
-                            self.remove_from_stack(&handle);
-                            self.active_formatting.remove(i);
-                            // We iterated backwards from the end above, so
-                            // we don't need to adjust the indices after each
-                            // removal.
+                    match open_a {
+                        Some(handle) => {
+                            self.unexpected(&tag);
+                            self.adoption_agency(atom!(a));
+                            // The adoption agency may have left it in place.
+                            match self.position_in_active_formatting(&handle) {
+                                Some(i) => { self.active_formatting.remove(i); }
+                                None => (),
+                            }
+                            if self.open_elems.iter()
+                                    .any(|n| self.sink.same_node(n.clone(), handle.clone())) {
+                                self.remove_from_stack(&handle);
+                            }
                         }
+                        None => (),
                     }
 
                     self.reconstruct_formatting();
@@ -545,7 +573,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                 tag @ <nobr> => {
                     self.reconstruct_formatting();
                     if self.in_scope_named(default_scope, atom!(nobr)) {
-                        self.sink.parse_error(Slice("Nested <nobr>"));
+                        self.parse_error(NestedNobr);
                         self.adoption_agency(atom!(nobr));
                         self.reconstruct_formatting();
                     }
@@ -630,7 +658,16 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                     }))
                 }
 
-                <isindex> => fail!("FIXME: <isindex> not implemented"),
+                tag @ <isindex> => {
+                    if self.opts.ignore_missing_rules {
+                        self.parse_error(UnimplementedIsindex);
+                        self.reconstruct_formatting();
+                        self.insert_element_for(tag);
+                        Done
+                    } else {
+                        fail!("FIXME: <isindex> not implemented");
+                    }
+                }
 
                 tag @ <textarea> => {
                     self.ignore_lf = true;
@@ -695,14 +732,12 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                 }
 
                 tag @ <math> <svg> => {
-                    if self.opts.ignore_missing_rules {
-                        self.sink.parse_error(Slice("Ignoring unimplemented rules for <math> or <svg>"));
-                        self.reconstruct_formatting();
-                        self.insert_element_for(tag);
-                        Done
-                    } else {
-                        fail!("FIXME: MathML and SVG are not implemented");
-                    }
+                    self.reconstruct_formatting();
+                    let ns = match tag.name {
+                        atom!(math) => ns!(MathML),
+                        _ => ns!(SVG),
+                    };
+                    self.insert_foreign_element_for(tag, ns)
                 }
 
                 <caption> <col> <colgroup> <frame> <head>
@@ -722,38 +757,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                 }
 
                 tag @ </_> => {
-                    // Look back for a matching open element.
-                    let mut match_idx = None;
-                    for (i, elem) in self.open_elems.iter().enumerate().rev() {
-                        if self.html_elem_named(elem.clone(), tag.name.clone()) {
-                            match_idx = Some(i);
-                            break;
-                        }
-
-                        if self.elem_in(elem.clone(), special_tag) {
-                            self.sink.parse_error(Slice("Found special tag while closing generic tag"));
-                            return Done;
-                        }
-                    }
-
-                    // Can't use unwrap_or_return!() due to rust-lang/rust#16617.
-                    let match_idx = match match_idx {
-                        None => {
-                            // I believe this is impossible, because the root
-                            // <html> element is in special_tag.
-                            self.unexpected(&tag);
-                            return Done;
-                        }
-                        Some(x) => x,
-                    };
-
-                    self.generate_implied_end_except(tag.name.clone());
-
-                    if match_idx != self.open_elems.len() - 1 {
-                        // mis-nested tags
-                        self.unexpected(&tag);
-                    }
-                    self.open_elems.truncate(match_idx);
+                    self.process_end_tag_in_body(tag);
                     Done
                 }
 
@@ -767,7 +771,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                 CharacterTokens(_, text) => self.append_text(text),
 
                 EOFToken => {
-                    self.unexpected(&token);
+                    self.unexpected_eof(&token);
                     if self.current_node_named(atom!(script)) {
                         let current = self.current_node();
                         self.sink.mark_script_already_started(current);
@@ -781,6 +785,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                     if tag.name == atom!(script) {
                         h5e_warn!("FIXME: </script> not fully implemented");
                         self.sink.complete_script(node);
+                        self.suspend_tokenizer = self.opts.suspend_after_scripts;
                     }
                     self.mode = self.orig_mode.take().unwrap();
                     Done
@@ -873,8 +878,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
 
                 tag @ <form> => {
                     self.unexpected(&tag);
-                    // FIXME: <template>
-                    if self.form_elem.is_none() {
+                    if self.form_elem.is_none() && !self.in_html_elem_named(atom!(template)) {
                         self.form_elem = Some(self.insert_and_pop_element_for(tag));
                     }
                     Done
@@ -893,13 +897,14 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                 NullCharacterToken => self.unexpected(&token),
 
                 CharacterTokens(split, text) => {
-                    self.pending_table_text.push((split, text));
+                    let span = self.token_span.clone();
+                    self.pending_table_text.push((split, text, span));
                     Done
                 }
 
                 token => {
                     let pending = replace(&mut self.pending_table_text, vec!());
-                    let contains_nonspace = pending.iter().any(|&(split, ref text)| {
+                    let contains_nonspace = pending.iter().any(|&(split, ref text, _)| {
                         match split {
                             Whitespace => false,
                             NotWhitespace => true,
@@ -907,19 +912,21 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                         }
                     });
 
+                    // The text keeps its own spans, not this token's.
+                    let token_span = self.token_span.take();
                     if contains_nonspace {
-                        self.sink.parse_error(Slice("Non-space table text"));
-                        for (split, text) in pending.into_iter() {
-                            match self.foster_parent_in_body(CharacterTokens(split, text)) {
-                                Done => (),
-                                _ => fail!("not prepared to handle this!"),
-                            }
+                        self.parse_error(NonSpaceTableText);
+                        for (split, text, span) in pending.into_iter() {
+                            self.token_span = span;
+                            self.foster_parent_in_body(CharacterTokens(split, text));
                         }
                     } else {
-                        for (_, text) in pending.into_iter() {
+                        for (_, text, span) in pending.into_iter() {
+                            self.token_span = span;
                             self.append_text(text);
                         }
                     }
+                    self.token_span = token_span;
 
                     Reprocess(self.orig_mode.take().unwrap(), token)
                 }
@@ -984,10 +991,10 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                 token => {
                     if self.current_node_named(atom!(colgroup)) {
                         self.pop();
+                        Reprocess(InTable, token)
                     } else {
-                        self.unexpected(&token);
+                        self.unexpected(&token)
                     }
-                    Reprocess(InTable, token)
                 }
             }),
 
@@ -1020,7 +1027,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
 
                 <caption> <col> <colgroup> <tbody> <tfoot> <thead> </table> => {
                     declare_tag_set!(table_outer = table tbody tfoot)
-                    if self.in_scope(table_scope, |e| self.elem_in(e, table_outer)) {
+                    if self.any_in_scope(table_scope, table_outer) {
                         self.pop_until_current(table_body_context);
                         self.pop();
                         Reprocess(InTable, token)
@@ -1104,7 +1111,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
                 }
 
                 <caption> <col> <colgroup> <tbody> <td> <tfoot> <th> <thead> <tr> => {
-                    if self.in_scope(table_scope, |n| self.elem_in(n.clone(), td_th)) {
+                    if self.any_in_scope(table_scope, td_th) {
                         self.close_the_cell();
                         Reprocess(InRow, token)
                     } else {
@@ -1231,18 +1238,60 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
             }),
 
             //§ parsing-main-intemplate
-            InTemplate => {
-                // NB: Implementing <template> requires not just adding
-                // the InTemplate rules here, but also inserting various
-                // extra logic at other points noted throughout the
-                // parser.
-
-                if self.opts.ignore_missing_rules {
-                    self.step(InBody, token)
-                } else {
-                    fail!("FIXME: <template> not implemented");
+            InTemplate => match_token!(token {
+                CharacterTokens(_, _) => self.step(InBody, token),
+                NullCharacterToken => self.step(InBody, token),
+                CommentToken(_) => self.step(InBody, token),
+
+                <base> <basefont> <bgsound> <link> <meta> <noframes> <script>
+                  <style> <template> <title> </template> => {
+                    self.step(InHead, token)
+                }
+
+                <caption> <colgroup> <tbody> <tfoot> <thead> => {
+                    self.template_modes.pop();
+                    self.template_modes.push(InTable);
+                    Reprocess(InTable, token)
+                }
+
+                <col> => {
+                    self.template_modes.pop();
+                    self.template_modes.push(InColumnGroup);
+                    Reprocess(InColumnGroup, token)
+                }
+
+                <tr> => {
+                    self.template_modes.pop();
+                    self.template_modes.push(InTableBody);
+                    Reprocess(InTableBody, token)
                 }
-            }
+
+                <td> <th> => {
+                    self.template_modes.pop();
+                    self.template_modes.push(InRow);
+                    Reprocess(InRow, token)
+                }
+
+                EOFToken => {
+                    if !self.in_html_elem_named(atom!(template)) {
+                        self.stop_parsing()
+                    } else {
+                        self.unexpected_eof(&token);
+                        self.pop_until_named(atom!(template));
+                        self.clear_active_formatting_to_marker();
+                        self.template_modes.pop();
+                        Reprocess(self.reset_insertion_mode(), token)
+                    }
+                }
+
+                tag @ <_> => {
+                    self.template_modes.pop();
+                    self.template_modes.push(InBody);
+                    Reprocess(InBody, TagToken(tag))
+                }
+
+                token => self.unexpected(&token),
+            }),
 
             //§ parsing-main-afterbody
             AfterBody => match_token!(token {
@@ -1303,7 +1352,7 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
 
                 EOFToken => {
                     if self.open_elems.len() != 1 {
-                        self.unexpected(&token);
+                        self.unexpected_eof(&token);
                     }
                     self.stop_parsing()
                 }
@@ -1364,4 +1413,85 @@ impl<Handle: Clone, Sink: TreeSink<Handle>>
             //§ END
         }
     }
+
+    //§ parsing-main-inforeign
+    fn step_foreign(&mut self, token: Token) -> ProcessResult {
+        self.debug_step(self.mode, &token);
+
+        match_token!(token {
+            NullCharacterToken => {
+                self.unexpected(&token);
+                self.append_text(String::from_str("\ufffd"))
+            }
+
+            CharacterTokens(NotSplit, text) => SplitWhitespace(text),
+            CharacterTokens(Whitespace, text) => self.append_text(text),
+            CharacterTokens(NotWhitespace, text) => {
+                self.frameset_ok = false;
+                self.append_text(text)
+            }
+
+            CommentToken(text) => self.append_comment(text),
+
+            tag @ <b> <big> <blockquote> <body> <br> <center> <code> <dd> <div> <dl>
+              <dt> <em> <embed> <h1> <h2> <h3> <h4> <h5> <h6> <head> <hr> <i> <img>
+              <li> <listing> <menu> <meta> <nobr> <ol> <p> <pre> <ruby> <s> <small>
+              <span> <strong> <strike> <sub> <sup> <table> <tt> <u> <ul> <var>
+                => self.unexpected_start_tag_in_foreign_content(tag),
+
+            tag @ <font> => {
+                let presentational = tag.attrs.iter().any(|attr| {
+                    attr.name.ns == ns!("") && match attr.name.local.as_slice() {
+                        "color" | "face" | "size" => true,
+                        _ => false,
+                    }
+                });
+                if presentational {
+                    self.unexpected_start_tag_in_foreign_content(tag)
+                } else {
+                    self.foreign_start_tag(tag)
+                }
+            }
+
+            tag @ <_> => self.foreign_start_tag(tag),
+
+            tag @ </_> => {
+                let mut idx = self.open_elems.len() - 1;
+                if !closes_foreign(self.elem_name(self.open_elems[idx].clone()), &tag.name) {
+                    self.unexpected(&tag);
+                }
+
+                loop {
+                    // Only in the fragment case.
+                    if idx == 0 {
+                        return Done;
+                    }
+
+                    let node = self.open_elems[idx].clone();
+                    let name = self.elem_name(node.clone());
+                    if closes_foreign(name.clone(), &tag.name) {
+                        while self.open_elems.len() > idx {
+                            self.pop();
+                        }
+                        if name.ns == ns!(SVG) && tag.name == atom!(script) {
+                            self.sink.complete_script(node);
+                            self.suspend_tokenizer = self.opts.suspend_after_scripts;
+                        }
+                        return Done;
+                    }
+
+                    idx -= 1;
+                    if self.elem_name(self.open_elems[idx].clone()).ns == ns!(HTML) {
+                        let mode = self.mode;
+                        return self.step(mode, TagToken(tag));
+                    }
+                }
+            }
+
+            // FIXME: This should be unreachable, but match_token! requires a
+            // catch-all case.
+            _ => fail!("impossible case in foreign content"),
+        })
+    }
+    //§ END
 }
@@ -11,6 +11,8 @@
 
 #![macro_escape]
 
+use core::prelude::*;
+
 use string_cache::QualName;
 
 //§ the-stack-of-open-elements
@@ -51,8 +53,37 @@ macro_rules! declare_tag_set (
 #[inline(always)] pub fn empty_set(_: QualName) -> bool { false }
 #[inline(always)] pub fn full_set(_: QualName) -> bool { true }
 
-// FIXME: MathML, SVG
-declare_tag_set!(pub default_scope = applet caption html table td th marquee object template)
+declare_tag_set!(html_default_scope =
+    applet caption html table td th marquee object template)
+
+/// MathML elements whose content is parsed as HTML, for text and most
+/// start tags.
+pub fn mathml_text_integration_point(p: QualName) -> bool {
+    p.ns == ns!(MathML) && match p.local.as_slice() {
+        "mi" | "mo" | "mn" | "ms" | "mtext" => true,
+        _ => false,
+    }
+}
+
+/// SVG elements whose content is parsed as HTML.  A MathML
+/// `<annotation-xml>` can be one too, depending on its `encoding`.
+pub fn svg_html_integration_point(p: QualName) -> bool {
+    p.ns == ns!(SVG) && match p.local.as_slice() {
+        "foreignObject" | "desc" | "title" => true,
+        _ => false,
+    }
+}
+
+// Foreign elements which bound the default scope, and are special.
+fn foreign_boundary(p: QualName) -> bool {
+    (p.ns == ns!(MathML) && p.local.as_slice() == "annotation-xml")
+        || mathml_text_integration_point(p.clone())
+        || svg_html_integration_point(p)
+}
+
+pub fn default_scope(p: QualName) -> bool {
+    html_default_scope(p.clone()) || foreign_boundary(p)
+}
 
 declare_tag_set!(pub list_item_scope = default_scope + ol ul)
 declare_tag_set!(pub button_scope = default_scope + button)
@@ -70,11 +101,15 @@ declare_tag_set!(pub thorough_implied_end = cursory_implied_end
 
 declare_tag_set!(pub heading_tag = h1 h2 h3 h4 h5 h6)
 
-declare_tag_set!(pub special_tag =
+declare_tag_set!(html_special_tag =
     address applet area article aside base basefont bgsound blockquote body br button caption
     center col colgroup dd details dir div dl dt embed fieldset figcaption figure footer form
     frame frameset h1 h2 h3 h4 h5 h6 head header hgroup hr html iframe img input isindex li
     link listing main marquee menu menuitem meta nav noembed noframes noscript object ol p
     param plaintext pre script section select source style summary table tbody td template
     textarea tfoot th thead title tr track ul wbr xmp)
+
+pub fn special_tag(p: QualName) -> bool {
+    html_special_tag(p.clone()) || foreign_boundary(p)
+}
 //§ END
@@ -0,0 +1,59 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use core::prelude::*;
+
+use string_cache::{QualName, Namespace};
+
+/// 64-bit FNV-1a, for hashes which must be the same between runs and
+/// platforms.  Not cryptographic.
+pub struct Fnv(u64);
+
+impl Fnv {
+    pub fn new() -> Fnv {
+        Fnv(0xcbf29ce484222325)
+    }
+
+    pub fn finish(&self) -> u64 {
+        let Fnv(h) = *self;
+        h
+    }
+
+    pub fn byte(&mut self, b: u8) {
+        let Fnv(ref mut h) = *self;
+        *h = (*h ^ (b as u64)) * 0x100000001b3;
+    }
+
+    pub fn u64(&mut self, n: u64) {
+        let mut n = n;
+        for _ in range(0u, 8) {
+            self.byte(n as u8);
+            n >>= 8;
+        }
+    }
+
+    pub fn len(&mut self, n: uint) {
+        self.u64(n as u64);
+    }
+
+    /// Strings are length-prefixed, so that adjacent ones can't run
+    /// together.
+    pub fn str(&mut self, s: &str) {
+        self.len(s.len());
+        for &b in s.as_bytes().iter() {
+            self.byte(b);
+        }
+    }
+
+    pub fn name(&mut self, name: &QualName) {
+        let Namespace(ref ns) = name.ns;
+        self.str(ns.as_slice());
+        self.str(name.local.as_slice());
+    }
+}
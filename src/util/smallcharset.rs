@@ -13,23 +13,30 @@ use core::prelude::*;
 
 /// Represents a set of "small characters", those with Unicode scalar
 /// values less than 64.
+///
+/// This is the fast "find the first of these bytes" test used by the
+/// tokenizer's input buffers, the character reference tokenizer, and
+/// the serializer's escaper.
 pub struct SmallCharSet {
     pub bits: u64,
 }
 
 impl SmallCharSet {
+    /// Is this byte in the set?
     #[inline]
-    fn contains(self, n: u8) -> bool {
-        0 != (self.bits & (1 << (n as uint)))
+    pub fn contains(self, n: u8) -> bool {
+        n < 64 && 0 != (self.bits & (1 << (n as uint)))
     }
 
     /// Count the number of bytes of characters at the beginning
     /// of `buf` which are not in the set.
-    /// See `tokenizer::buffer_queue::pop_except_from`.
+    ///
+    /// Members are ASCII, so the result is always at a character
+    /// boundary.
     pub fn nonmember_prefix_len(&self, buf: &str) -> uint {
         let mut n = 0;
         for b in buf.bytes() {
-            if b >= 64 || !self.contains(b) {
+            if !self.contains(b) {
                 n += 1;
             } else {
                 break;
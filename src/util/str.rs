@@ -205,11 +205,40 @@ pub fn char_run<Pred: CharEq>(mut pred: Pred, buf: &str) -> Option<(uint, bool)>
     Some((buf.len(), matches))
 }
 
+/// Iterator over the tokens of a "set of space-separated tokens", such
+/// as the value of a `class` attribute.  Empty tokens are skipped.
+pub struct SpaceSeparated<'a> {
+    rest: &'a str,
+}
+
+/// Split a string on ASCII whitespace, as the spec defines it for
+/// space-separated tokens.
+pub fn split_html_space<'a>(s: &'a str) -> SpaceSeparated<'a> {
+    SpaceSeparated {
+        rest: s,
+    }
+}
+
+impl<'a> Iterator<&'a str> for SpaceSeparated<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let s = self.rest.trim_left_chars(is_ascii_whitespace);
+        let end = s.find(is_ascii_whitespace).unwrap_or(s.len());
+        self.rest = s.slice_from(end);
+        if end == 0 {
+            None
+        } else {
+            Some(s.slice_to(end))
+        }
+    }
+}
+
 #[cfg(test)]
 #[allow(non_snake_case)]
 mod test {
     use core::prelude::*;
+    use collections::vec::Vec;
     use super::{char_run, is_ascii_whitespace, is_ascii_alnum, lower_ascii, lower_ascii_letter};
+    use super::split_html_space;
 
     test_eq!(lower_letter_a_is_a, lower_ascii_letter('a'), Some('a'))
     test_eq!(lower_letter_A_is_a, lower_ascii_letter('A'), Some('a'))
@@ -244,4 +273,15 @@ mod test {
     test_char_run!(run_multibyte_1, " 中 ", Some((1, true)))
     test_char_run!(run_multibyte_2, "  中 ", Some((2, true)))
     test_char_run!(run_multibyte_3, "   中 ", Some((3, true)))
+
+    macro_rules! test_split ( ($name:ident, $input:expr, $expect:expr) => (
+        test_eq!($name, split_html_space($input).collect::<Vec<&str>>(), $expect)
+    ))
+
+    test_split!(split_empty, "", vec!())
+    test_split!(split_only_space, " \t\n ", vec!())
+    test_split!(split_one, "foo", vec!("foo"))
+    test_split!(split_padded, "  foo\x0C", vec!("foo"))
+    test_split!(split_many, "foo\tbar\r\nbaz", vec!("foo", "bar", "baz"))
+    test_split!(split_nbsp_is_not_space, "a\u00a0b c", vec!("a\u00a0b", "c"))
 }
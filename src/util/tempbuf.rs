@@ -0,0 +1,166 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use core::prelude::*;
+
+use core::str;
+use core::mem::replace;
+use collections::string::String;
+
+/// How many bytes a `TempBuf` holds without allocating.
+pub static INLINE_LEN: uint = 64;
+
+/// A short-lived string buffer, for the tokenizer's temporary buffer
+/// and character reference names.
+///
+/// ASCII text up to `INLINE_LEN` bytes is kept inline.  A longer
+/// string, or any non-ASCII character, moves the whole contents to
+/// the heap.  The callers keep their buffers under that size for all
+/// but pathological input, so the worst case is one heap string as
+/// long as the run of input which filled it; `clear` and `take` free
+/// it again.
+pub struct TempBuf {
+    inline: [u8, ..64],
+    len: uint,
+
+    // The whole contents, once they don't fit inline.
+    heap: Option<String>,
+}
+
+impl TempBuf {
+    pub fn new() -> TempBuf {
+        TempBuf {
+            inline: [0, ..64],
+            len: 0,
+            heap: None,
+        }
+    }
+
+    pub fn push(&mut self, c: char) {
+        if self.heap.is_none() {
+            if c < '\x80' && self.len < INLINE_LEN {
+                self.inline[self.len] = c as u8;
+                self.len += 1;
+                return;
+            }
+            self.heap = Some(String::from_str(self.as_slice()));
+        }
+        self.heap.as_mut().unwrap().push(c);
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        if self.heap.is_none() {
+            if self.len + s.len() <= INLINE_LEN && s.bytes().all(|b| b < 0x80) {
+                for b in s.bytes() {
+                    self.inline[self.len] = b;
+                    self.len += 1;
+                }
+                return;
+            }
+            self.heap = Some(String::from_str(self.as_slice()));
+        }
+        self.heap.as_mut().unwrap().push_str(s);
+    }
+
+    pub fn as_slice<'t>(&'t self) -> &'t str {
+        match self.heap {
+            Some(ref s) => s.as_slice(),
+            // Only ASCII is kept inline.
+            None => unsafe { str::raw::from_utf8(self.inline.slice_to(self.len)) },
+        }
+    }
+
+    pub fn len(&self) -> uint {
+        self.as_slice().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.heap = None;
+    }
+
+    /// Empty the buffer, but unlike `clear`, keep any heap string to
+    /// grow into again.
+    pub fn reset(&mut self) {
+        self.len = 0;
+        match self.heap {
+            Some(ref mut s) => s.truncate(0),
+            None => (),
+        }
+    }
+
+    /// Get the contents as a `String`, and clear the buffer.
+    pub fn take(&mut self) -> String {
+        let s = match replace(&mut self.heap, None) {
+            Some(s) => s,
+            None => String::from_str(self.as_slice()),
+        };
+        self.len = 0;
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::prelude::*;
+    use super::{TempBuf, INLINE_LEN};
+
+    #[test]
+    fn inline() {
+        let mut buf = TempBuf::new();
+        for _ in range(0, INLINE_LEN) {
+            buf.push('a');
+        }
+        assert!(buf.heap.is_none());
+        assert_eq!(buf.len(), INLINE_LEN);
+        assert_eq!(buf.take().len(), INLINE_LEN);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn spill() {
+        let mut buf = TempBuf::new();
+        buf.push('a');
+        buf.push('é');
+        buf.push('b');
+        assert!(buf.heap.is_some());
+        assert_eq!(buf.as_slice(), "aéb");
+        buf.clear();
+        assert!(buf.heap.is_none());
+        buf.push('c');
+        assert_eq!(buf.as_slice(), "c");
+    }
+
+    #[test]
+    fn reset_keeps_heap() {
+        let mut buf = TempBuf::new();
+        buf.push_str("aé");
+        buf.reset();
+        assert!(buf.is_empty());
+        assert!(buf.heap.as_ref().unwrap().capacity() > 0);
+        buf.push('b');
+        assert_eq!(buf.as_slice(), "b");
+    }
+
+    #[test]
+    fn push_str() {
+        let mut buf = TempBuf::new();
+        buf.push_str("ab");
+        buf.push_str("cd");
+        assert!(buf.heap.is_none());
+        assert_eq!(buf.as_slice(), "abcd");
+        buf.push_str("é");
+        assert!(buf.heap.is_some());
+        assert_eq!(buf.as_slice(), "abcdé");
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Run html5lib's encoding detection tests against the prescan.
+
+use util::foreach_html5lib_test;
+
+use std::path::Path;
+use std::vec::MoveItems;
+use test::{TestDesc, TestDescAndFn, DynTestName, DynTestFn};
+
+use html5ever::encoding::{prescan, encoding_for_label};
+
+// The tests are like the tree construction tests, but `#data` can
+// contain bytes which aren't UTF-8, so we split them up ourselves.
+// Returns the data and expected encoding for each test.
+fn parse_tests(bytes: &[u8]) -> Vec<(Vec<u8>, String)> {
+    let mut tests = vec!();
+    let mut data = vec!();
+    let mut encoding = String::new();
+    let mut section = None;
+
+    for line in bytes.split(|&b| b == b'\n') {
+        if line == b"#data" {
+            if section.is_some() {
+                tests.push((data, encoding));
+            }
+            data = vec!();
+            encoding = String::new();
+            section = Some("data");
+        } else if line == b"#encoding" {
+            section = Some("encoding");
+        } else {
+            match section {
+                Some("data") => {
+                    data.push_all(line);
+                    data.push(b'\n');
+                }
+                Some("encoding") if encoding.is_empty() =>
+                    encoding = String::from_utf8_lossy(line).into_string(),
+                _ => (),
+            }
+        }
+    }
+    if section.is_some() {
+        tests.push((data, encoding));
+    }
+    tests
+}
+
+pub fn tests(src_dir: Path) -> MoveItems<TestDescAndFn> {
+    let mut tests = vec!();
+
+    foreach_html5lib_test(src_dir, "encoding", ".dat", |path_str, mut file| {
+        let bytes = file.read_to_end().ok().expect("can't read");
+        for (i, (data, encoding)) in parse_tests(bytes.as_slice()).into_iter().enumerate() {
+            tests.push(TestDescAndFn {
+                desc: TestDesc {
+                    name: DynTestName(format!("enc: {}-{}", path_str, i)),
+                    ignore: false,
+                    should_fail: false,
+                },
+                testfn: DynTestFn(proc() {
+                    // The tests expect the default encoding if there's
+                    // no declaration.
+                    let result = prescan(data.as_slice()).unwrap_or("windows-1252");
+                    let expected = encoding_for_label(encoding.as_slice())
+                        .expect("unknown expected encoding");
+                    if result != expected {
+                        fail!("\ninput: {}\ngot: {}\nexpected: {}\n",
+                            String::from_utf8_lossy(data.as_slice()), result, expected);
+                    }
+                }),
+            });
+        }
+    });
+
+    tests.into_iter()
+}
@@ -25,6 +25,9 @@ use test::test_main;
 
 mod tokenizer;
 mod tree_builder;
+mod serializer;
+mod encoding;
+mod no_panic;
 mod util;
 
 fn main() {
@@ -39,7 +42,19 @@ fn main() {
     }
 
     if os::getenv("HTML5EVER_NO_TB_TEST").is_none() {
-        tests.extend(tree_builder::tests(src_dir));
+        tests.extend(tree_builder::tests(src_dir.clone()));
+    }
+
+    if os::getenv("HTML5EVER_NO_SER_TEST").is_none() {
+        tests.extend(serializer::tests(src_dir.clone()));
+    }
+
+    if os::getenv("HTML5EVER_NO_ENC_TEST").is_none() {
+        tests.extend(encoding::tests(src_dir.clone()));
+    }
+
+    if os::getenv("HTML5EVER_NO_FUZZ_TEST").is_none() {
+        tests.extend(no_panic::tests(src_dir));
     }
 
     let args: Vec<String> = os::args().into_iter().collect();
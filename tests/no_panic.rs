@@ -0,0 +1,185 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Check that parsing never fails, on the html5lib tree builder
+//! inputs, on randomly mutated copies of them, and on inputs which
+//! have made it fail before.
+
+use util::foreach_html5lib_test;
+use tree_builder::parse_tests;
+
+use std::io;
+use std::default::Default;
+use std::path::Path;
+use std::vec::MoveItems;
+use test::{TestDesc, TestDescAndFn, DynTestName, DynTestFn};
+
+use html5ever::sink::rcdom::RcDom;
+use html5ever::tokenizer::TokenizerOpts;
+use html5ever::tree_builder::TreeBuilderOpts;
+use html5ever::{parse, ParseOpts};
+
+// Number of mutated copies of each input.
+static MUTATIONS: uint = 20;
+
+// Bytes which are likely to change what the parser does.
+static INTERESTING: &'static [u8]
+    = b"<>&/!-'\"=?;#[]\0\r\n\t aZ\xc3\xa9\xef\xbb\xbf\xff";
+
+// Inputs which have made the parser fail before, or which reach the
+// corners most likely to: character references cut off at EOF, in text
+// and in attribute values, template delimiters, and length limits which
+// land inside a multibyte character.
+static REGRESSIONS: &'static [&'static str] = &[
+    "", "&", "&#", "&#x", "&#x;", "&not", "&notin", "&noti;", "&;",
+    "<a b=&", "<a b='&not", "<a b=\"&#", "<a b=&notit=", "<a b='&#x1F600",
+    "{{", "{{x", "a{{b}}c", "<a b='{{'>", "<a b={{}}>", "<a b='x{{y}}z'>",
+    "\u00e9\u00e9{{\u00e9}}", "<a \u00e9\u00e9=\u00e9\u00e9>\u00e9\u00e9",
+    "<template><tr></template><td>", "<svg><template><td>", "<table><template>",
+];
+
+// A small xorshift PRNG, so that failures are reproducible.
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        let Rng(mut x) = *self;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        *self = Rng(x);
+        x
+    }
+
+    fn below(&mut self, n: uint) -> uint {
+        (self.next() as uint) % n
+    }
+}
+
+fn mutate(rng: &mut Rng, input: &[u8]) -> Vec<u8> {
+    let mut bytes = input.to_vec();
+    for _ in range(0, 1 + rng.below(4)) {
+        let pos = if bytes.is_empty() { 0 } else { rng.below(bytes.len()) };
+        let b = INTERESTING[rng.below(INTERESTING.len())];
+        match rng.below(4) {
+            0 if !bytes.is_empty() => *bytes.get_mut(pos) = b,
+            1 => bytes.insert(pos, b),
+            2 if !bytes.is_empty() => { bytes.remove(pos); }
+            _ => bytes.truncate(pos),
+        }
+    }
+    bytes
+}
+
+// Split a string into chunks at random character boundaries.
+fn chunks(rng: &mut Rng, s: &str) -> Vec<String> {
+    let mut out = vec!();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let mut n = 1 + rng.below(rest.len());
+        while !rest.is_char_boundary(n) {
+            n += 1;
+        }
+        out.push(rest.slice_to(n).to_string());
+        rest = rest.slice_from(n);
+    }
+    out
+}
+
+// The options to parse each input with.
+fn all_opts() -> Vec<ParseOpts> {
+    let mut out = vec!();
+    for &exact in [false, true].iter() {
+        out.push(ParseOpts {
+            tokenizer: TokenizerOpts {
+                exact_errors: exact,
+                .. Default::default()
+            },
+            tree_builder: TreeBuilderOpts {
+                exact_errors: exact,
+                .. Default::default()
+            },
+            .. Default::default()
+        });
+    }
+
+    // Template delimiters, including an empty one which should be
+    // ignored, and length limits which cut multibyte characters.
+    out.push(ParseOpts {
+        tokenizer: TokenizerOpts {
+            template_delimiters: vec!(("{{".to_string(), "}}".to_string()),
+                ("".to_string(), "".to_string())),
+            template_attr_values: true,
+            max_text_len: Some(1),
+            max_attr_value_len: Some(1),
+            max_name_len: Some(1),
+            .. Default::default()
+        },
+        .. Default::default()
+    });
+    out
+}
+
+fn parse_bytes(rng: &mut Rng, input: &[u8]) {
+    let input = String::from_utf8_lossy(input).into_string();
+    for opts in all_opts().into_iter() {
+        let _: RcDom = parse(chunks(rng, input.as_slice()).into_iter(), opts);
+    }
+}
+
+pub fn tests(src_dir: Path) -> MoveItems<TestDescAndFn> {
+    let mut tests = vec!();
+
+    tests.push(TestDescAndFn {
+        desc: TestDesc {
+            name: DynTestName("no panic: regressions".to_string()),
+            ignore: false,
+            should_fail: false,
+        },
+        testfn: DynTestFn(proc() {
+            let mut rng = Rng(0x9e3779b9);
+            for input in REGRESSIONS.iter() {
+                parse_bytes(&mut rng, input.as_bytes());
+            }
+        }),
+    });
+
+    foreach_html5lib_test(src_dir, "tree-construction", ".dat", |path_str, file| {
+        let mut buf = io::BufferedReader::new(file);
+        let lines = buf.lines()
+            .map(|res| res.ok().expect("couldn't read"));
+        let data = parse_tests(lines);
+
+        for (i, fields) in data.into_iter().enumerate() {
+            let input = match fields.find_equiv(&"data") {
+                Some(input) => input.clone(),
+                None => continue,
+            };
+
+            tests.push(TestDescAndFn {
+                desc: TestDesc {
+                    name: DynTestName(format!("no panic: {}-{}", path_str, i)),
+                    ignore: false,
+                    should_fail: false,
+                },
+                testfn: DynTestFn(proc() {
+                    let mut rng = Rng(0x9e3779b9 ^ (i as u32));
+                    let bytes = input.as_bytes();
+                    parse_bytes(&mut rng, bytes);
+                    for _ in range(0, MUTATIONS) {
+                        let mutated = mutate(&mut rng, bytes);
+                        parse_bytes(&mut rng, mutated.as_slice());
+                    }
+                }),
+            });
+        }
+    });
+
+    tests.into_iter()
+}
@@ -0,0 +1,289 @@
+// Copyright 2014 The html5ever Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Run html5lib's serializer tests.
+//!
+//! Each test serializes a token stream with some options.  The tests
+//! assume the defaults of html5lib's own serializer, which differ
+//! from ours.  An option runs if it maps onto `SerializeOpts`, if it
+//! matches what we always do, or if it makes no difference to the
+//! test's tokens, e.g. quoting in a test with no attributes.  The rest
+//! are marked as ignored.  Add to `supported` as the serializer gains
+//! options.
+//!
+//! The files in `SKIPPED_FILES` are about features we don't have, so
+//! none of their tests run.  If fewer than `MIN_TESTS_RUN` tests run
+//! overall, that's a failure, so that a change to this file can't
+//! quietly ignore everything.
+
+use util::foreach_html5lib_test;
+use tokenizer::JsonExt;
+
+use std::io::MemWriter;
+use std::default::Default;
+use std::path::Path;
+use std::collections::treemap::TreeMap;
+use std::vec::MoveItems;
+use test::{TestDesc, TestDescAndFn, DynTestName, DynTestFn};
+use serialize::json;
+use serialize::json::Json;
+
+use html5ever::serialize::{Serializer, SerializeOpts};
+
+use string_cache::{Atom, QualName, Namespace};
+
+static XHTML_NS: &'static str = "http://www.w3.org/1999/xhtml";
+
+// Files we don't run: `optionaltags.test` is all about omitting
+// optional tags, and `injectmeta.test` about adding a `<meta charset>`.
+static SKIPPED_FILES: &'static [&'static str] = &["optionaltags.test", "injectmeta.test"];
+
+// Raise this as more options are supported.
+static MIN_TESTS_RUN: uint = 20;
+
+// Elements whose tags html5lib's serializer may leave out.
+static OPTIONAL_TAGS: &'static [&'static str] = &["html", "head", "body", "p", "li", "dt",
+    "dd", "rt", "rp", "optgroup", "option", "colgroup", "caption", "thead", "tbody",
+    "tfoot", "tr", "td", "th"];
+
+// The options of html5lib's serializer, and their default values.
+fn html5lib_defaults() -> TreeMap<String, Json> {
+    let string = |s: &str| json::String(s.to_string());
+    let defaults = vec!(
+        ("quote_attr_values", string("legacy")),
+        ("quote_char", string("\"")),
+        ("use_best_quote_char", json::Boolean(true)),
+        ("omit_optional_tags", json::Boolean(true)),
+        ("minimize_boolean_attributes", json::Boolean(true)),
+        ("use_trailing_solidus", json::Boolean(false)),
+        ("space_before_trailing_solidus", json::Boolean(true)),
+        ("escape_lt_in_attrs", json::Boolean(false)),
+        ("escape_rcdata", json::Boolean(false)),
+        ("resolve_entities", json::Boolean(true)),
+        ("alphabetical_attributes", json::Boolean(false)),
+        ("strip_whitespace", json::Boolean(false)));
+
+    let mut map = TreeMap::new();
+    for (k, v) in defaults.into_iter() {
+        map.insert(k.to_string(), v);
+    }
+    map
+}
+
+// The names of the tags in a token stream.
+fn tag_names(input: &Vec<Json>) -> Vec<String> {
+    input.iter().filter_map(|token| {
+        let parts = token.get_list();
+        match parts[0].get_str().as_slice() {
+            "StartTag" | "EndTag" => Some(parts[2].get_str()),
+            "EmptyTag" => Some(parts[1].get_str()),
+            _ => None,
+        }
+    }).collect()
+}
+
+// Does any tag in the token stream have attributes?
+fn has_attrs(input: &Vec<Json>) -> bool {
+    input.iter().any(|token| {
+        let parts = token.get_list();
+        match parts[0].get_str().as_slice() {
+            "StartTag" => !get_attrs(&parts[3]).is_empty(),
+            "EmptyTag" => !get_attrs(&parts[2]).is_empty(),
+            _ => false,
+        }
+    })
+}
+
+// Can we run a test on this input which sets this html5lib option to
+// this value?  If so, update `opts` to match.
+fn supported(key: &str, value: &Json, input: &Vec<Json>, opts: &mut SerializeOpts) -> bool {
+    match (key, value) {
+        ("alphabetical_attributes", &json::Boolean(b)) => {
+            opts.sort_attrs = b;
+            true
+        }
+
+        // We always quote with `"`, escaping it inside values, and never
+        // minimize.  That's only seen if there are attributes.
+        ("quote_attr_values", &json::Boolean(true)) => true,
+        ("quote_attr_values", &json::String(ref s)) if s.as_slice() == "always" => true,
+        ("quote_char", &json::String(ref s)) if s.as_slice() == "\"" => true,
+        ("use_best_quote_char", &json::Boolean(false)) => true,
+        ("minimize_boolean_attributes", &json::Boolean(false)) => true,
+        ("quote_attr_values", _) | ("quote_char", _) | ("use_best_quote_char", _)
+        | ("minimize_boolean_attributes", _) => !has_attrs(input),
+
+        // We always write every tag.
+        ("omit_optional_tags", &json::Boolean(true)) => !tag_names(input).iter()
+            .any(|n| OPTIONAL_TAGS.iter().any(|t| *t == n.as_slice())),
+
+        ("omit_optional_tags", &json::Boolean(b))
+        | ("use_trailing_solidus", &json::Boolean(b))
+        | ("escape_lt_in_attrs", &json::Boolean(b))
+        | ("escape_rcdata", &json::Boolean(b))
+        | ("strip_whitespace", &json::Boolean(b))
+            => !b,
+
+        // Only matters with use_trailing_solidus.
+        ("space_before_trailing_solidus", _) => true,
+
+        // We write characters as they are, except for U+00A0.
+        ("resolve_entities", &json::Boolean(b)) => b,
+
+        _ => false,
+    }
+}
+
+fn field<'t>(js: &'t Json, key: &str) -> &'t Json {
+    js.get_obj().find(&key.to_string()).expect("missing field")
+}
+
+fn get_attrs(js: &Json) -> Vec<(QualName, String)> {
+    let one = |attr: &Json| {
+        let ns = match *field(attr, "namespace") {
+            json::Null => ns!(""),
+            ref ns => Namespace(Atom::from_slice(ns.get_str().as_slice())),
+        };
+        let name = Atom::from_slice(field(attr, "name").get_str().as_slice());
+        (QualName::new(ns, name), field(attr, "value").get_str())
+    };
+
+    match *js {
+        json::List(ref attrs) => attrs.iter().map(one).collect(),
+        json::Object(ref attrs) => attrs.iter().map(|(k, v)| {
+            (QualName::new(ns!(""), Atom::from_slice(k.as_slice())), v.get_str())
+        }).collect(),
+        _ => fail!("don't understand attributes {}", js),
+    }
+}
+
+fn html_name(ns: &Json, name: &Json) -> Option<QualName> {
+    if ns.get_str().as_slice() != XHTML_NS {
+        return None;
+    }
+    Some(QualName::new(ns!(HTML), Atom::from_slice(name.get_str().as_slice())))
+}
+
+// Can our serializer handle everything in this token stream?
+// Namespaced elements and attributes aren't supported yet.
+fn tokens_supported(input: &Vec<Json>) -> bool {
+    input.iter().all(|token| {
+        let parts = token.get_list();
+        match parts[0].get_str().as_slice() {
+            "StartTag" => html_name(&parts[1], &parts[2]).is_some()
+                && get_attrs(&parts[3]).iter().all(|&(ref n, _)| n.ns == ns!("")),
+            "EndTag" => html_name(&parts[1], &parts[2]).is_some(),
+            "EmptyTag" => get_attrs(&parts[2]).iter().all(|&(ref n, _)| n.ns == ns!("")),
+            _ => true,
+        }
+    })
+}
+
+fn serialize_tokens(input: &Vec<Json>, opts: SerializeOpts) -> String {
+    let mut out = MemWriter::new();
+    {
+        let mut ser = Serializer::new(&mut out, opts);
+        for token in input.iter() {
+            let parts = token.get_list();
+            match parts[0].get_str().as_slice() {
+                "StartTag" => {
+                    let name = html_name(&parts[1], &parts[2]).unwrap();
+                    let attrs = get_attrs(&parts[3]);
+                    ser.start_elem(name, attrs.iter().map(|&(ref n, ref v)| (n, v.as_slice())))
+                }
+                "EndTag" => ser.end_elem(html_name(&parts[1], &parts[2]).unwrap()),
+                "EmptyTag" => {
+                    let name = QualName::new(ns!(HTML),
+                        Atom::from_slice(parts[1].get_str().as_slice()));
+                    let attrs = get_attrs(&parts[2]);
+                    ser.start_elem(name.clone(),
+                        attrs.iter().map(|&(ref n, ref v)| (n, v.as_slice())))
+                        .and_then(|_| ser.end_elem(name))
+                }
+                "Characters" => ser.write_text(parts[1].get_str().as_slice()),
+                "Comment" => ser.write_comment(parts[1].get_str().as_slice()),
+                "Doctype" => ser.write_doctype(parts[1].get_str().as_slice()),
+                t => fail!("don't understand token type {}", t),
+            }.ok().expect("serialization failed");
+        }
+    }
+    String::from_utf8(out.unwrap()).unwrap()
+}
+
+// Returns whether the test will run.
+fn make_test(tests: &mut Vec<TestDescAndFn>, path_str: &str, idx: uint, js: &Json) -> bool {
+    let obj = js.get_obj();
+    let desc = obj.find(&"description".to_string()).map_or(String::new(), |d| d.get_str());
+    let input = field(js, "input").get_list().clone();
+    let expected: Vec<String> = field(js, "expected").get_list()
+        .iter().map(|e| e.get_str()).collect();
+
+    let mut options = html5lib_defaults();
+    match obj.find(&"options".to_string()) {
+        Some(o) => for (k, v) in o.get_obj().iter() {
+            options.insert(k.clone(), v.clone());
+        },
+        None => (),
+    }
+
+    let mut opts: SerializeOpts = Default::default();
+    let ignore = !tokens_supported(&input)
+        || !options.iter().all(|(k, v)| supported(k.as_slice(), v, &input, &mut opts));
+
+    tests.push(TestDescAndFn {
+        desc: TestDesc {
+            name: DynTestName(format!("ser: {}-{}: {}", path_str, idx, desc)),
+            ignore: ignore,
+            should_fail: false,
+        },
+        testfn: DynTestFn(proc() {
+            let result = serialize_tokens(&input, opts);
+            if !expected.iter().any(|e| *e == result) {
+                fail!("\ninput: {}\ngot: {}\nexpected one of: {}\n",
+                    json::List(input), result, expected);
+            }
+        }),
+    });
+    !ignore
+}
+
+pub fn tests(src_dir: Path) -> MoveItems<TestDescAndFn> {
+    let mut tests = vec!();
+    let mut run = 0u;
+
+    foreach_html5lib_test(src_dir, "serializer", ".test", |path_str, mut file| {
+        if SKIPPED_FILES.iter().any(|f| *f == path_str) {
+            return;
+        }
+
+        let js = json::from_reader(&mut file as &mut Reader)
+            .ok().expect("json parse error");
+
+        for (i, test) in field(&js, "tests").get_list().iter().enumerate() {
+            if make_test(&mut tests, path_str, i, test) {
+                run += 1;
+            }
+        }
+    });
+
+    tests.push(TestDescAndFn {
+        desc: TestDesc {
+            name: DynTestName("ser: enough tests run".to_string()),
+            ignore: false,
+            should_fail: false,
+        },
+        testfn: DynTestFn(proc() {
+            if run < MIN_TESTS_RUN {
+                fail!("only {} serializer tests run, expected at least {}", run, MIN_TESTS_RUN);
+            }
+        }),
+    });
+
+    tests.into_iter()
+}
@@ -17,14 +17,14 @@ use test::{TestDesc, TestDescAndFn, DynTestName, DynTestFn};
 use serialize::json;
 use serialize::json::Json;
 use std::collections::treemap::TreeMap;
-use std::str::Slice;
 use std::vec::MoveItems;
 
 use html5ever::tokenizer::{Doctype, Attribute, StartTag, EndTag, Tag};
 use html5ever::tokenizer::{Token, DoctypeToken, TagToken, CommentToken};
 use html5ever::tokenizer::{CharacterTokens, NullCharacterToken, EOFToken, ParseError};
 use html5ever::tokenizer::{TokenSink, Tokenizer, TokenizerOpts};
-use html5ever::tokenizer::states::{Plaintext, RawData, Rcdata, Rawtext};
+use html5ever::tokenizer::{TokenizerError, Position, UnexpectedEof};
+use html5ever::tokenizer::states::{Plaintext, RawData, Rcdata, Rawtext, Data};
 
 use string_cache::{Atom, QualName};
 
@@ -98,7 +98,7 @@ impl TokenSink for TokenLogger {
             }
 
             ParseError(_) => if self.exact_errors {
-                self.push(ParseError(Slice("")));
+                self.push(any_error());
             },
 
             TagToken(mut t) => {
@@ -132,7 +132,7 @@ fn tokenize(input: Vec<String>, opts: TokenizerOpts) -> Vec<Token> {
     tok.unwrap().get_tokens()
 }
 
-trait JsonExt {
+pub trait JsonExt {
     fn get_str(&self) -> String;
     fn get_nullable_str(&self) -> Option<String>;
     fn get_bool(&self) -> bool;
@@ -202,7 +202,8 @@ fn json_to_token(js: &Json) -> Token {
             attrs: attrs.get_obj().iter().map(|(k,v)| {
                 Attribute {
                     name: QualName::new(ns!(""), Atom::from_slice(k.as_slice())),
-                    value: v.get_str()
+                    value: v.get_str(),
+                    has_template: false,
                 }
             }).collect(),
             self_closing: match rest {
@@ -229,6 +230,15 @@ fn json_to_token(js: &Json) -> Token {
     }
 }
 
+// The tests only say where errors come in the token stream, so every
+// error is logged as this one.
+fn any_error() -> Token {
+    ParseError(TokenizerError {
+        code: UnexpectedEof(Data),
+        pos: Position { offset: 0, line: 0, column: 0 },
+    })
+}
+
 // Parse the "output" field of the test case into a vector of tokens.
 fn json_to_tokens(js: &Json, exact_errors: bool) -> Vec<Token> {
     // Use a TokenLogger so that we combine character tokens separated
@@ -237,7 +247,7 @@ fn json_to_tokens(js: &Json, exact_errors: bool) -> Vec<Token> {
     for tok in js.get_list().iter() {
         match *tok {
             json::String(ref s)
-                if s.as_slice() == "ParseError" => sink.process_token(ParseError(Slice(""))),
+                if s.as_slice() == "ParseError" => sink.process_token(any_error()),
             _ => sink.process_token(json_to_token(tok)),
         }
     }
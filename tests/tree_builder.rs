@@ -7,7 +7,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use util::foreach_html5lib_test;
+use util::{foreach_html5lib_test, foreach_local_test};
 
 use std::io;
 use std::mem::replace;
@@ -17,11 +17,13 @@ use std::collections::hashmap::HashMap;
 use std::vec::MoveItems;
 use test::{TestDesc, TestDescAndFn, DynTestName, DynTestFn};
 
-use html5ever::sink::common::{Document, Doctype, Text, Comment, Element};
-use html5ever::sink::rcdom::{RcDom, Handle};
-use html5ever::{parse, one_input};
+use html5ever::sink::rcdom::RcDom;
+use html5ever::{parse, parse_fragment, one_input};
+use html5ever::testing::dump_tree;
 
-fn parse_tests<It: Iterator<String>>(mut lines: It) -> Vec<HashMap<String, String>> {
+use string_cache::{Atom, QualName};
+
+pub fn parse_tests<It: Iterator<String>>(mut lines: It) -> Vec<HashMap<String, String>> {
     let mut tests = vec!();
     let mut test = HashMap::new();
     let mut key = None;
@@ -63,63 +65,9 @@ fn parse_tests<It: Iterator<String>>(mut lines: It) -> Vec<HashMap<String, Strin
     tests
 }
 
-fn serialize(buf: &mut String, indent: uint, handle: Handle) {
-    buf.push_str("|");
-    buf.grow(indent, ' ');
-
-    let node = handle.borrow();
-    match node.node {
-        Document => fail!("should not reach Document"),
-
-        Doctype(ref name, ref public, ref system) => {
-            buf.push_str("<!DOCTYPE ");
-            buf.push_str(name.as_slice());
-            if !public.is_empty() || !system.is_empty() {
-                buf.push_str(format!(" \"{}\" \"{}\"", public, system).as_slice());
-            }
-            buf.push_str(">\n");
-        }
-
-        Text(ref text) => {
-            buf.push_str("\"");
-            buf.push_str(text.as_slice());
-            buf.push_str("\"\n");
-        }
-
-        Comment(ref text) => {
-            buf.push_str("<!-- ");
-            buf.push_str(text.as_slice());
-            buf.push_str(" -->\n");
-        }
-
-        Element(ref name, ref attrs) => {
-            assert!(name.ns == ns!(HTML));
-            buf.push_str("<");
-            buf.push_str(name.local.as_slice());
-            buf.push_str(">\n");
-
-            let mut attrs = attrs.clone();
-            attrs.sort_by(|x, y| x.name.local.cmp(&y.name.local));
-            // FIXME: sort by UTF-16 code unit
-
-            for attr in attrs.into_iter() {
-                assert!(attr.name.ns == ns!(""));
-                buf.push_str("|");
-                buf.grow(indent+2, ' ');
-                buf.push_str(format!("{}=\"{}\"\n",
-                    attr.name.local.as_slice(), attr.value).as_slice());
-            }
-        }
-    }
-
-    for child in node.children.iter() {
-        serialize(buf, indent+2, child.clone());
-    }
-}
-
 // Ignore tests containing these strings; we don't support these features yet.
 static IGNORE_SUBSTRS: &'static [&'static str]
-    = &["<math", "<svg", "<template"];
+    = &[];
 
 fn make_test(
         tests: &mut Vec<TestDescAndFn>,
@@ -132,10 +80,19 @@ fn make_test(
         field.as_slice().trim_right_chars('\n').to_string()
     };
 
-    if fields.find_equiv(&"document-fragment").is_some() {
-        // FIXME
-        return;
-    }
+    // Fragment tests name the context element, with a prefix like
+    // "svg path" for foreign elements.
+    let context = fields.find_equiv(&"document-fragment").map(|field| {
+        let field = field.as_slice().trim_right_chars('\n');
+        let (ns, local) = if field.starts_with("svg ") {
+            (ns!(SVG), field.slice_from(4))
+        } else if field.starts_with("math ") {
+            (ns!(MathML), field.slice_from(5))
+        } else {
+            (ns!(HTML), field)
+        };
+        QualName::new(ns, Atom::from_slice(local))
+    });
 
     let data = get_field("data");
     let expected = get_field("document");
@@ -146,14 +103,21 @@ fn make_test(
             should_fail: false,
         },
         testfn: DynTestFn(proc() {
-            let dom: RcDom = parse(one_input(data.clone()), Default::default());
+            let mut result = match context {
+                None => {
+                    let dom: RcDom = parse(one_input(data.clone()), Default::default());
+                    dump_tree(&dom.document)
+                }
+                Some(ref context) => {
+                    let dom: RcDom = parse_fragment(one_input(data.clone()), context.clone(),
+                        Default::default());
 
-            let mut result = String::new();
-            for child in dom.document.borrow().children.iter() {
-                serialize(&mut result, 1, child.clone());
-            }
-            let len = result.len();
-            result.truncate(len - 1);  // drop the trailing newline
+                    // The fragment is the children of the <html> root.
+                    let root = dom.document.borrow().children[0].clone();
+                    dump_tree(&root)
+                }
+            };
+            result.pop();  // drop the trailing newline
 
             if result != expected {
                 fail!("\ninput: {}\ngot:\n{}\nexpected:\n{}\n",
@@ -163,18 +127,26 @@ fn make_test(
     });
 }
 
+fn add_tests(tests: &mut Vec<TestDescAndFn>, path_str: &str, file: io::File) {
+    let mut buf = io::BufferedReader::new(file);
+    let lines = buf.lines()
+        .map(|res| res.ok().expect("couldn't read"));
+    let data = parse_tests(lines);
+
+    for (i, test) in data.into_iter().enumerate() {
+        make_test(tests, path_str, i, test);
+    }
+}
+
 pub fn tests(src_dir: Path) -> MoveItems<TestDescAndFn> {
     let mut tests = vec!();
 
-    foreach_html5lib_test(src_dir, "tree-construction", ".dat", |path_str, file| {
-        let mut buf = io::BufferedReader::new(file);
-        let lines = buf.lines()
-            .map(|res| res.ok().expect("couldn't read"));
-        let data = parse_tests(lines);
+    foreach_html5lib_test(src_dir.clone(), "tree-construction", ".dat", |path_str, file| {
+        add_tests(&mut tests, path_str, file);
+    });
 
-        for (i, test) in data.into_iter().enumerate() {
-            make_test(&mut tests, path_str, i, test);
-        }
+    foreach_local_test(src_dir, "tree-construction", ".dat", |path_str, file| {
+        add_tests(&mut tests, path_str, file);
     });
 
     tests.into_iter()
@@ -15,7 +15,23 @@ pub fn foreach_html5lib_test(
         subdir: &'static str,
         ext: &'static str,
         mk: |path_str: &str, file: io::File|) {
-    let test_dir_path = src_dir.join_many(["html5lib-tests", subdir]);
+    foreach_test_file(src_dir.join_many(["html5lib-tests", subdir]), ext, mk);
+}
+
+/// Like `foreach_html5lib_test`, but for our own tests, which are in
+/// the same format and live in `tests/data`.
+pub fn foreach_local_test(
+        src_dir: Path,
+        subdir: &'static str,
+        ext: &'static str,
+        mk: |path_str: &str, file: io::File|) {
+    foreach_test_file(src_dir.join_many(["tests", "data", subdir]), ext, mk);
+}
+
+fn foreach_test_file(
+        test_dir_path: Path,
+        ext: &'static str,
+        mk: |path_str: &str, file: io::File|) {
     let test_files = io::fs::readdir(&test_dir_path).ok().expect("can't open dir");
     for path in test_files.into_iter() {
         let path_str = path.filename_str().unwrap();